@@ -0,0 +1,249 @@
+//! Streams an infinigen world inside a Bevy app.
+//!
+//! Add `InfinigenPlugin::<W>::default()` and insert the world as a
+//! `ChunkWorld` resource. Every update, the world's observer is moved to the
+//! entity marked with `ChunkObserver`, chunks are loaded and unloaded, and
+//! every loaded chunk gets an entity holding its data. Errors from updating
+//! the world are sent as `ChunkStreamError` events instead of stopping the
+//! app.
+
+use std::fmt::Debug;
+use std::hash::Hash;
+use std::marker::PhantomData;
+
+use bevy_app::{App, Plugin, Update};
+use bevy_ecs::prelude::*;
+use bevy_transform::components::Transform;
+use infinigen::{FxHashMap, FxHashSet, Index, SerialError, SerialResult};
+
+/// A world that can be streamed by `InfinigenPlugin`. This is usually
+/// implemented by forwarding to the world's `ChunkedWorld` implementation.
+pub trait BevyWorld: Send + Sync + 'static {
+    type Index: Index + Debug + Send + Sync + 'static;
+    /// The component a chunk entity holds its chunk's data in.
+    type Chunk: Component;
+
+    /// Moves the world's observer to the position of the observer entity.
+    fn move_observer(&mut self, transform: &Transform);
+
+    /// Loads and unloads chunks around the observer, like
+    /// `ChunkedWorld::update_chunks`.
+    fn update(&mut self) -> SerialResult<()>;
+
+    fn chunk_indices(&self) -> Vec<Self::Index>;
+
+    /// Returns the data of a loaded chunk for its entity.
+    fn chunk_component(&self, index: &Self::Index) -> Option<Self::Chunk>;
+}
+
+/// The streamed world.
+#[derive(Resource)]
+pub struct ChunkWorld<W: BevyWorld>(pub W);
+
+/// Marks the entity chunks are loaded around, such as the player or camera.
+#[derive(Component, Debug, Default, Clone, Copy)]
+pub struct ChunkObserver;
+
+/// The index of the chunk an entity was spawned for.
+#[derive(Component, Debug, Clone, PartialEq, Eq)]
+pub struct ChunkAt<I: Index + Send + Sync + 'static>(pub I);
+
+/// Sent after a chunk was loaded and its entity spawned.
+#[derive(Event, Debug, Clone)]
+pub struct ChunkLoaded<I: Index + Send + Sync + 'static> {
+    pub index: I,
+    pub entity: Entity,
+}
+
+/// Sent after a chunk was unloaded and its entity despawned.
+#[derive(Event, Debug, Clone)]
+pub struct ChunkUnloaded<I: Index + Send + Sync + 'static> {
+    pub index: I,
+}
+
+/// Sent when updating the world failed, such as when the disk is full. The
+/// chunks that couldn't be saved stay loaded and the update is tried again
+/// on the next frame, so a game only has to tell the player or stop.
+#[derive(Event, Debug)]
+pub struct ChunkStreamError(pub SerialError);
+
+/// The entity of every loaded chunk.
+#[derive(Resource)]
+pub struct ChunkEntities<I: Index + Send + Sync + 'static> {
+    entities: FxHashMap<I, Entity>,
+}
+
+impl<I: Index + Send + Sync + 'static> Default for ChunkEntities<I> {
+    fn default() -> Self {
+        ChunkEntities {
+            entities: FxHashMap::default(),
+        }
+    }
+}
+
+impl<I: Index + Send + Sync + 'static> ChunkEntities<I> {
+    pub fn get(&self, index: &I) -> Option<Entity> {
+        self.entities.get(index).cloned()
+    }
+
+    pub fn len(&self) -> usize {
+        self.entities.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entities.is_empty()
+    }
+}
+
+/// Streams the `ChunkWorld<W>` resource during `Update`.
+pub struct InfinigenPlugin<W: BevyWorld> {
+    _world: PhantomData<fn() -> W>,
+}
+
+impl<W: BevyWorld> Default for InfinigenPlugin<W> {
+    fn default() -> Self {
+        InfinigenPlugin {
+            _world: PhantomData,
+        }
+    }
+}
+
+impl<W: BevyWorld> Plugin for InfinigenPlugin<W>
+where
+    W::Index: Hash + Eq,
+{
+    fn build(&self, app: &mut App) {
+        app.add_event::<ChunkLoaded<W::Index>>()
+            .add_event::<ChunkUnloaded<W::Index>>()
+            .add_event::<ChunkStreamError>()
+            .init_resource::<ChunkEntities<W::Index>>()
+            .add_systems(Update, stream_chunks::<W>);
+    }
+}
+
+/// Moves the observer, updates the world and spawns or despawns chunk
+/// entities to match the chunks that are loaded.
+pub fn stream_chunks<W: BevyWorld>(
+    mut commands: Commands,
+    mut world: ResMut<ChunkWorld<W>>,
+    mut entities: ResMut<ChunkEntities<W::Index>>,
+    observers: Query<&Transform, With<ChunkObserver>>,
+    mut loaded: EventWriter<ChunkLoaded<W::Index>>,
+    mut unloaded: EventWriter<ChunkUnloaded<W::Index>>,
+    mut errors: EventWriter<ChunkStreamError>,
+) {
+    let world = &mut world.0;
+    if let Some(transform) = observers.iter().next() {
+        world.move_observer(transform);
+    }
+    // Chunks may have been loaded or unloaded before the error, so the
+    // entities are still brought up to date.
+    if let Err(e) = world.update() {
+        errors.send(ChunkStreamError(e));
+    }
+
+    let resident: FxHashSet<W::Index> = world.chunk_indices().into_iter().collect();
+
+    let gone: Vec<W::Index> = entities.entities.keys()
+        .filter(|index| !resident.contains(index))
+        .cloned()
+        .collect();
+    for index in gone.into_iter() {
+        if let Some(entity) = entities.entities.remove(&index) {
+            commands.entity(entity).despawn();
+        }
+        unloaded.send(ChunkUnloaded { index: index });
+    }
+
+    for index in resident.into_iter() {
+        if entities.entities.contains_key(&index) {
+            continue;
+        }
+        let chunk = match world.chunk_component(&index) {
+            Some(c) => c,
+            None    => continue,
+        };
+        let entity = commands.spawn((ChunkAt(index.clone()), chunk)).id();
+        entities.entities.insert(index.clone(), entity);
+        loaded.send(ChunkLoaded { index: index, entity: entity });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[derive(Debug, Clone, Hash, PartialEq, Eq)]
+    struct Pos(i32, i32);
+
+    impl Index for Pos {
+        fn x(&self) -> i32 { self.0 }
+        fn y(&self) -> i32 { self.1 }
+        fn from_xy(x: i32, y: i32) -> Self { Pos(x, y) }
+    }
+
+    #[derive(Component, Debug, PartialEq)]
+    struct Cells(i32);
+
+    /// A world whose loaded chunks are set directly by the test.
+    struct StubWorld {
+        loaded: Vec<Pos>,
+        fail: bool,
+    }
+
+    impl BevyWorld for StubWorld {
+        type Index = Pos;
+        type Chunk = Cells;
+
+        fn move_observer(&mut self, _transform: &Transform) {}
+
+        fn update(&mut self) -> SerialResult<()> {
+            if self.fail { Err(SerialError::DiskFull) } else { Ok(()) }
+        }
+
+        fn chunk_indices(&self) -> Vec<Pos> {
+            self.loaded.clone()
+        }
+
+        fn chunk_component(&self, index: &Pos) -> Option<Cells> {
+            Some(Cells(index.0))
+        }
+    }
+
+    fn app(loaded: Vec<Pos>, fail: bool) -> App {
+        let mut app = App::new();
+        app.add_plugins(InfinigenPlugin::<StubWorld>::default())
+            .insert_resource(ChunkWorld(StubWorld { loaded: loaded, fail: fail }));
+        app
+    }
+
+    #[test]
+    fn test_stream_chunks() {
+        let mut app = app(vec![Pos(0, 0), Pos(1, 0)], false);
+        app.update();
+        let entity = app.world().resource::<ChunkEntities<Pos>>().get(&Pos(1, 0)).unwrap();
+        assert_eq!(app.world().get::<Cells>(entity), Some(&Cells(1)));
+        assert_eq!(app.world().get::<ChunkAt<Pos>>(entity), Some(&ChunkAt(Pos(1, 0))));
+        assert_eq!(app.world().resource::<Events<ChunkLoaded<Pos>>>().len(), 2);
+
+        app.world_mut().resource_mut::<ChunkWorld<StubWorld>>().0.loaded = vec![Pos(0, 0)];
+        app.update();
+        assert!(app.world().get_entity(entity).is_none());
+        assert_eq!(app.world().resource::<ChunkEntities<Pos>>().len(), 1);
+        let unloaded: Vec<Pos> = app.world().resource::<Events<ChunkUnloaded<Pos>>>()
+            .iter_current_update_events()
+            .map(|e| e.index.clone())
+            .collect();
+        assert_eq!(unloaded, vec![Pos(1, 0)]);
+    }
+
+    #[test]
+    fn test_update_error() {
+        let mut app = app(vec![Pos(0, 0)], true);
+        app.update();
+        app.update();
+        // The app keeps running and the chunks that are loaded get entities.
+        assert_eq!(app.world().resource::<Events<ChunkStreamError>>().len(), 2);
+        assert_eq!(app.world().resource::<ChunkEntities<Pos>>().len(), 1);
+    }
+}
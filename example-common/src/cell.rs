@@ -0,0 +1,67 @@
+use color::Color;
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub enum CellKind {
+    Wall,
+    Floor,
+    Tree,
+    Nothing,
+}
+
+impl CellKind {
+    /// The name this kind is saved under. Changing it breaks existing saves
+    /// unless a `CellRemap` maps the old name to the new one.
+    pub fn name(&self) -> &'static str {
+        match *self {
+            CellKind::Wall    => "Wall",
+            CellKind::Floor   => "Floor",
+            CellKind::Tree    => "Tree",
+            CellKind::Nothing => "Nothing",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<CellKind> {
+        match name {
+            "Wall"    => Some(CellKind::Wall),
+            "Floor"   => Some(CellKind::Floor),
+            "Tree"    => Some(CellKind::Tree),
+            "Nothing" => Some(CellKind::Nothing),
+            _         => None,
+        }
+    }
+
+    pub fn can_walk(&self) -> bool {
+        match *self {
+            CellKind::Floor => true,
+            _               => false,
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct Cell {
+    pub color: Color,
+    pub kind: CellKind,
+}
+
+impl Cell {
+    pub fn new(kind: CellKind, color: Color) -> Self{
+        Cell {
+            color: color,
+            kind: kind
+        }
+    }
+
+    pub fn to_char(&self) -> char {
+        match self.kind {
+            CellKind::Wall => '#',
+            CellKind::Floor => '.',
+            CellKind::Tree => '%',
+            CellKind::Nothing => ' ',
+        }
+    }
+
+    pub fn can_walk(&self) -> bool {
+        self.kind.can_walk()
+    }
+}
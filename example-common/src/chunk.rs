@@ -0,0 +1,269 @@
+use std::fmt;
+use std::io;
+
+use infinigen::{AttachedChunk, BufferPool, CellRemap, DiffableChunk, ManagedChunk, Occupancy, Palette, WorldSeed};
+use noise::{NoiseModule, Perlin};
+
+use color::Color;
+use config::GenConfig;
+use cell::*;
+use point::Point;
+use world::WorldPosition;
+
+pub const CHUNK_WIDTH: i32 = 32;
+
+#[derive(Debug, Clone)]
+pub struct ChunkPosition(pub Point);
+
+impl From<Point> for ChunkPosition {
+    fn from(pos: Point) -> ChunkPosition {
+        assert!(pos.x >= 0);
+        assert!(pos.y >= 0);
+        assert!(pos.x < CHUNK_WIDTH);
+        assert!(pos.y < CHUNK_WIDTH);
+        ChunkPosition(pos)
+    }
+}
+
+impl ChunkPosition {
+    pub fn from_world(pos: &WorldPosition) -> ChunkPosition {
+        let conv = |i: i32| {
+            let i_new = i % CHUNK_WIDTH;
+            if i_new < 0 {
+                CHUNK_WIDTH + i_new
+            } else {
+                i_new
+            }
+        };
+        ChunkPosition(Point::new(conv(pos.x), conv(pos.y)))
+    }
+}
+
+impl fmt::Display for ChunkPosition {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct Chunk {
+    cells: Vec<Cell>,
+}
+
+const COS_THETA: f32 = 0.99854;
+const SIN_THETA: f32 = 0.05408;
+
+/// The number of cells in a chunk.
+pub const CHUNK_CELLS: usize = (CHUNK_WIDTH * CHUNK_WIDTH) as usize;
+
+impl Chunk {
+    /// Generates a chunk, filling a buffer from `pool`.
+    pub fn new(index: &ChunkIndex, gen: &Perlin, config: &GenConfig, pool: &mut BufferPool<Cell>) -> Self {
+        let mut cells = pool.take(CHUNK_CELLS);
+        let center = WorldPosition::from_chunk_index(*index);
+
+        let mut rng = config.seed.chunk_rng(index, "colors");
+        let fg_color = Color::from_rng(&mut rng);
+        let bg_color = Color::from_rng(&mut rng);
+        let mut trees = config.seed.chunk_rng(index, "trees");
+
+        for j in 0..(CHUNK_WIDTH) {
+            for i in 0..(CHUNK_WIDTH) {
+                let ax = (center.x + i) as f32;
+                let ay = (center.y + j) as f32;
+                let az = 0.2333333333;
+
+                // Perlin doesn't work on integer values, so rotate slightly.
+                let conv = |a: f32, b| config.noise_scale * (a * COS_THETA + b * SIN_THETA);
+                let res = gen.get([conv(ay, -ax), conv(ax, ay), az]);
+
+                if res > config.threshold || trees.chance(config.tree_density as f64) {
+                    cells.push(Cell::new(CellKind::Tree, bg_color));
+                } else {
+                    cells.push(Cell::new(CellKind::Floor, bg_color));
+                }
+
+                let res = gen.get([conv(ay, -ax), conv(ax, ay), az + 4.555555555]);
+                let index = Chunk::cell_index(ChunkPosition(Point::new(i, j)));
+                if res > config.threshold {
+                    cells[index].color = fg_color;
+                }
+            }
+        }
+
+        Chunk {
+            cells: cells
+        }
+    }
+
+    /// Creates a chunk from its cells, row by row.
+    pub fn from_cells(cells: Vec<Cell>) -> Self {
+        assert_eq!(cells.len(), CHUNK_CELLS);
+        Chunk {
+            cells: cells
+        }
+    }
+
+    /// A sparse grid of walls shown in place of a chunk that is still being
+    /// loaded. Nothing can walk on it.
+    pub fn placeholder() -> Self {
+        let cells = (0..CHUNK_WIDTH * CHUNK_WIDTH)
+            .map(|i| {
+                let (x, y) = (i % CHUNK_WIDTH, i / CHUNK_WIDTH);
+                if x % 4 == 0 && y % 4 == 0 {
+                    Cell::new(CellKind::Wall, Color::WHITE)
+                } else {
+                    Cell::new(CellKind::Nothing, Color::WHITE)
+                }
+            })
+            .collect();
+        Chunk {
+            cells: cells
+        }
+    }
+
+    /// Returns the buffer of cells, to give back to a `BufferPool`.
+    pub fn into_cells(self) -> Vec<Cell> {
+        self.cells
+    }
+
+    fn cell_index(pos: ChunkPosition) -> usize {
+        (pos.0.y * CHUNK_WIDTH + pos.0.x) as usize
+    }
+
+    /// Gets an immutable cell reference relative to within this Chunk.
+    pub fn cell(&self, pos: ChunkPosition) -> &Cell {
+        let index = Chunk::cell_index(pos.into());
+        &self.cells[index]
+    }
+
+    /// Gets an mutable cell reference relative to within this Chunk.
+    pub fn cell_mut(&mut self, pos: ChunkPosition) -> &mut Cell {
+        let index = Chunk::cell_index(pos.into());
+        &mut self.cells[index]
+    }
+
+    /// Converts this chunk to the form it is saved in, with the kinds of its
+    /// cells stored by name.
+    pub fn to_saved(&self) -> SavedChunk {
+        let mut palette = Palette::new();
+        let kinds = self.cells.iter().map(|c| palette.insert(c.kind.name())).collect();
+        SavedChunk {
+            palette: palette,
+            kinds: kinds,
+            colors: self.cells.iter().map(|c| c.color).collect(),
+        }
+    }
+
+    /// Restores a saved chunk into a buffer from `pool`, first renaming the
+    /// kinds of its cells with `remap`. Fails if a kind is still unknown
+    /// afterwards.
+    pub fn from_saved(mut saved: SavedChunk, index: &ChunkIndex, remap: &CellRemap, seed: WorldSeed,
+                      pool: &mut BufferPool<Cell>) -> io::Result<Self> {
+        let seed = seed.chunk(index, "remap");
+        remap.apply(&mut saved.palette, &mut saved.kinds, seed);
+
+        let mut cells = pool.take(saved.kinds.len());
+        for (&id, &color) in saved.kinds.iter().zip(saved.colors.iter()) {
+            let kind = match saved.palette.name(id).and_then(CellKind::from_name) {
+                Some(kind) => kind,
+                None => {
+                    let e = io::Error::new(io::ErrorKind::InvalidData,
+                                           format!("unknown cell kind {:?} in chunk {}", saved.palette.name(id), index));
+                    pool.give(cells);
+                    return Err(e);
+                },
+            };
+            cells.push(Cell::new(kind, color));
+        }
+
+        Ok(Chunk {
+            cells: cells
+        })
+    }
+
+    /// Calculates the position in the world the point in the chunk represents.
+    pub fn world_position_at(index: &ChunkIndex, pos: &ChunkPosition) -> Point {
+        Point::new(pos.0.x + index.0.x * CHUNK_WIDTH, pos.0.y + index.0.y * CHUNK_WIDTH)
+    }
+}
+
+// Because a world position and chunk index are different quantities, newtype to
+// enforce correct usage
+#[derive(Serialize, Deserialize, Copy, Clone, Debug, Hash, Eq, PartialEq)]
+pub struct ChunkIndex(pub Point);
+
+impl ChunkIndex {
+    pub fn new(x: i32, y: i32) -> Self {
+        ChunkIndex(Point::new(x, y))
+    }
+
+    pub fn from_world_pos(pos: Point) -> ChunkIndex {
+        let conv = |i: i32| {
+            if i < 0 {
+                // [-1, -chunk_width] = -1
+                ((i + 1) / CHUNK_WIDTH) - 1
+            } else {
+                // [0, chunk_width-1] = 0
+                i / CHUNK_WIDTH
+            }
+        };
+
+        ChunkIndex::new(conv(pos.x), conv(pos.y))
+    }
+
+}
+
+impl fmt::Display for ChunkIndex {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+
+/// A chunk as it is saved to disk.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct SavedChunk {
+    pub(crate) palette: Palette,
+    pub(crate) kinds: Vec<u16>,
+    pub(crate) colors: Vec<Color>,
+}
+
+impl SavedChunk {
+    /// Returns the kind of the cell at a position inside the chunk, if it is
+    /// known, and its color, without restoring the chunk.
+    pub fn cell_at(&self, x: i32, y: i32) -> (Option<CellKind>, Color) {
+        let i = (y * CHUNK_WIDTH + x) as usize;
+        let kind = self.palette.name(self.kinds[i]).and_then(CellKind::from_name);
+        (kind, self.colors[i])
+    }
+}
+
+pub type SerialChunk = AttachedChunk<SavedChunk>;
+
+impl ManagedChunk for SavedChunk {
+    const SECTOR_SIZE: usize = 4096;
+
+    const REGION_WIDTH: i32 = 32;
+
+    fn occupancy(&self) -> Option<Occupancy> {
+        let walkable: Vec<bool> = self.palette.names().iter()
+            .map(|name| CellKind::from_name(name).map_or(false, |k| k.can_walk()))
+            .collect();
+        let width = CHUNK_WIDTH as u32;
+        Some(Occupancy::from_fn(width, width, |x, y| {
+            walkable[self.kinds[(y * width + x) as usize] as usize]
+        }))
+    }
+}
+
+impl DiffableChunk for Chunk {
+    type Cell = Cell;
+
+    fn cells(&self) -> &[Cell] {
+        &self.cells
+    }
+
+    fn cells_mut(&mut self) -> &mut [Cell] {
+        &mut self.cells
+    }
+}
@@ -0,0 +1,88 @@
+use std::env;
+
+use infinigen::SplitMix64;
+use rand;
+
+/// The color of a cell or dude, in red, green and blue. Frontends whose
+/// terminal or renderer can't show every color map it to the nearest one in
+/// `Color::NAMED`.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Color {
+    pub r: u8,
+    pub g: u8,
+    pub b: u8,
+}
+
+impl Color {
+    pub const RED: Color     = Color { r: 220, g: 60,  b: 60 };
+    pub const BLUE: Color    = Color { r: 70,  g: 90,  b: 230 };
+    pub const GREEN: Color   = Color { r: 60,  g: 190, b: 80 };
+    pub const CYAN: Color    = Color { r: 60,  g: 200, b: 210 };
+    pub const MAGENTA: Color = Color { r: 200, g: 70,  b: 200 };
+    pub const YELLOW: Color  = Color { r: 230, g: 210, b: 70 };
+    pub const WHITE: Color   = Color { r: 255, g: 255, b: 255 };
+
+    /// The colors every frontend can show, which were the only colors before
+    /// the demo supported true color.
+    pub const NAMED: [Color; 7] = [Color::RED, Color::BLUE, Color::GREEN, Color::CYAN,
+                                   Color::MAGENTA, Color::YELLOW, Color::WHITE];
+
+    pub fn new(r: u8, g: u8, b: u8) -> Color {
+        Color { r: r, g: g, b: b }
+    }
+
+    pub fn rand() -> Color {
+        Color::from_bits(rand::random::<u32>())
+    }
+
+    /// Picks a color from a generator, so the same seed always gives the
+    /// same color.
+    pub fn from_rng(rng: &mut SplitMix64) -> Color {
+        Color::from_bits(rng.next_u32())
+    }
+
+    /// Returns the color as red, green and blue bytes, for frontends and
+    /// tools that draw pixels.
+    pub fn rgb(&self) -> [u8; 3] {
+        [self.r, self.g, self.b]
+    }
+
+    /// Returns the index in `Color::NAMED` of the color closest to this one.
+    pub fn nearest_named(&self) -> usize {
+        let distance = |c: &Color| {
+            let d = |a: u8, b: u8| (a as i32 - b as i32).pow(2);
+            d(self.r, c.r) + d(self.g, c.g) + d(self.b, c.b)
+        };
+        (0..Color::NAMED.len()).min_by_key(|&i| distance(&Color::NAMED[i])).unwrap()
+    }
+
+    /// Keeps every channel above 60 so cells stay readable on a black
+    /// background.
+    fn from_bits(bits: u32) -> Color {
+        let channel = |shift: u32| 60 + ((bits >> shift) & 0xff) as u8 % 196;
+        Color::new(channel(0), channel(8), channel(16))
+    }
+}
+
+/// Returns whether the terminal can show 24-bit color, which most modern
+/// terminals advertise through `COLORTERM`. Windows Terminal doesn't, but sets
+/// `WT_SESSION`. Other terminals get the nearest named color.
+pub fn terminal_has_truecolor() -> bool {
+    match env::var("COLORTERM") {
+        Ok(ref value) if value == "truecolor" || value == "24bit" => true,
+        _ => env::var_os("WT_SESSION").is_some(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_nearest_named() {
+        assert_eq!(Color::NAMED[Color::new(250, 40, 30).nearest_named()], Color::RED);
+        for (i, color) in Color::NAMED.iter().enumerate() {
+            assert_eq!(color.nearest_named(), i);
+        }
+    }
+}
@@ -0,0 +1,69 @@
+use std::error::Error;
+use std::fs::File;
+use std::io::Read;
+use std::path::Path;
+
+use infinigen::{SerialResult, WorldMetadata, WorldSeed};
+use toml;
+
+/// The file generation settings are read from when a new world is created.
+pub const CONFIG_PATH: &'static str = "generation.toml";
+
+/// The key the settings are stored under in the world metadata.
+const METADATA_KEY: &'static str = "generation";
+
+/// Parameters of the terrain generator.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[serde(default)]
+pub struct GenConfig {
+    /// Generation passes derive their own seeds from this one.
+    pub seed: WorldSeed,
+    /// How quickly the noise changes between neighboring cells. Smaller
+    /// values give larger forests.
+    pub noise_scale: f32,
+    /// Cells where the noise is above this value become forest.
+    pub threshold: f32,
+    /// The chance of a lone tree growing on a floor cell outside of forests.
+    pub tree_density: f32,
+}
+
+impl Default for GenConfig {
+    fn default() -> Self {
+        GenConfig {
+            seed: WorldSeed::new(2),
+            noise_scale: 0.05,
+            threshold: 0.30,
+            tree_density: 0.0,
+        }
+    }
+}
+
+impl GenConfig {
+    /// Reads the settings from a TOML file. Missing keys keep their default
+    /// values, and a missing file gives the defaults.
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<Self, Box<dyn Error>> {
+        if !path.as_ref().exists() {
+            return Ok(GenConfig::default());
+        }
+        let mut text = String::new();
+        File::open(path)?.read_to_string(&mut text)?;
+        Ok(toml::from_str(&text)?)
+    }
+
+    /// Returns the settings a world was created with. A new world takes its
+    /// settings from the file at `path` and stores them in its metadata, so
+    /// editing the file later doesn't change how the rest of an existing
+    /// world is generated and leave seams at the border of old chunks.
+    pub fn resolve<P: AsRef<Path>>(metadata: &mut WorldMetadata, path: P) -> Result<Self, Box<dyn Error>> {
+        if let Some(config) = metadata.get(METADATA_KEY)? {
+            return Ok(config);
+        }
+        let config = GenConfig::load(path)?;
+        config.store(metadata)?;
+        Ok(config)
+    }
+
+    pub fn store(&self, metadata: &mut WorldMetadata) -> SerialResult<()> {
+        metadata.insert(METADATA_KEY, self)
+    }
+}
@@ -1,4 +1,4 @@
-use canvas::Color;
+use color::Color;
 use world::WorldPosition;
 
 #[derive(Debug, Serialize, Deserialize)]
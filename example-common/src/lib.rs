@@ -0,0 +1,21 @@
+//! The demo world shared by the example frontends: cells, chunks, dudes and
+//! terrain generation. Frontends only need to draw a `World` and feed it
+//! input.
+
+#![feature(associated_consts)]
+extern crate infinigen;
+extern crate noise;
+extern crate rand;
+extern crate serde;
+extern crate toml;
+#[macro_use] extern crate serde_derive;
+
+pub mod cell;
+pub mod chunk;
+pub mod color;
+pub mod config;
+pub mod direction;
+pub mod dude;
+pub mod point;
+pub mod upgrade;
+pub mod world;
@@ -0,0 +1,108 @@
+//! Upgrades saves written by older versions of the demo, whose chunks can't
+//! be read by the current one.
+
+use std::collections::HashMap;
+
+use infinigen::*;
+
+use chunk::{SavedChunk, SerialChunk};
+use color::Color;
+use dude::Dude;
+use world::{Terrain, WorldPosition};
+
+/// Key the version of the saved chunks is stored under in the world metadata.
+const VERSION_KEY: &'static str = "example.save_version";
+
+/// The version of the saved chunks written by this build. Saves without a
+/// version were written before the demo supported true color.
+pub const SAVE_VERSION: u32 = 1;
+
+/// The colors saved before the demo supported true color.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy)]
+enum LegacyColor {
+    Red,
+    Blue,
+    Green,
+    Cyan,
+    Magenta,
+    Yellow,
+    White
+}
+
+impl LegacyColor {
+    fn upgrade(self) -> Color {
+        match self {
+            LegacyColor::Red     => Color::RED,
+            LegacyColor::Blue    => Color::BLUE,
+            LegacyColor::Green   => Color::GREEN,
+            LegacyColor::Cyan    => Color::CYAN,
+            LegacyColor::Magenta => Color::MAGENTA,
+            LegacyColor::Yellow  => Color::YELLOW,
+            LegacyColor::White   => Color::WHITE,
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize)]
+struct LegacySavedChunk {
+    palette: Palette,
+    kinds: Vec<u16>,
+    colors: Vec<LegacyColor>,
+}
+
+impl ManagedChunk for LegacySavedChunk {
+    const SECTOR_SIZE: usize = 4096;
+
+    const REGION_WIDTH: i32 = 32;
+}
+
+#[derive(Serialize, Deserialize)]
+struct LegacyDude {
+    pos: WorldPosition,
+    appearance: char,
+    color: LegacyColor,
+    name: String,
+}
+
+/// Brings the saved chunks of a world up to `SAVE_VERSION`, and records the
+/// version in its metadata. Archived regions are restored first so they are
+/// upgraded too. Returns the number of chunks that were converted.
+///
+/// Must run before any chunk is loaded.
+pub fn upgrade_save(terrain: &mut Terrain, metadata: &mut WorldMetadata) -> SerialResult<usize> {
+    let version: Option<u32> = metadata.get(VERSION_KEY)?;
+    if version.is_some() {
+        return Ok(0);
+    }
+    metadata.insert(VERSION_KEY, &SAVE_VERSION)?;
+
+    let layout = terrain.layout.clone();
+    for index in terrain.archive.region_indices().into_iter() {
+        terrain.archive.restore(&index, &layout.region_path(&index))?;
+    }
+    if region_files(&layout)?.is_empty() {
+        // A new world.
+        return Ok(0);
+    }
+
+    let converted = convert_layout::<AttachedChunk<LegacySavedChunk>, SerialChunk, _>(&layout, |_, old| {
+        let chunk = SavedChunk {
+            palette: old.chunk.palette,
+            kinds: old.chunk.kinds,
+            colors: old.chunk.colors.into_iter().map(LegacyColor::upgrade).collect(),
+        };
+        let mut attachments = old.attachments;
+        if let Some(dudes) = attachments.get::<CanonicalMap<WorldPosition, LegacyDude>>("dudes")? {
+            let dudes: CanonicalMap<WorldPosition, Dude> = dudes.into_inner().into_iter()
+                .map(|(pos, d)| (pos, Dude { pos: d.pos, appearance: d.appearance, color: d.color.upgrade(), name: d.name }))
+                .collect::<HashMap<_, _>>()
+                .into();
+            attachments.insert("dudes", &dudes)?;
+        }
+        Ok(AttachedChunk { chunk: chunk, attachments: attachments })
+    })?;
+
+    // Written right away, so the converted chunks are never converted twice.
+    metadata.save(layout.metadata_path())?;
+    Ok(converted)
+}
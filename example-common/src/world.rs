@@ -0,0 +1,741 @@
+use std::collections::{hash_map, HashMap};
+use std::time::Duration;
+
+use noise::{Perlin, Seedable};
+use infinigen::*;
+
+use color::Color;
+use config::{self, GenConfig};
+use cell::{Cell, CellKind};
+use chunk::*;
+use direction::Direction;
+use dude::Dude;
+use point::Point;
+use upgrade;
+
+// TODO: Is there some way of using AsRef here instead, because we don't care
+// about the underlying 2D point struct?
+impl Index for ChunkIndex {
+    fn x(&self) -> i32 { self.0.x }
+    fn y(&self) -> i32 { self.0.y }
+    fn from_xy(x: i32, y: i32) -> Self { ChunkIndex::new(x, y) }
+}
+
+/// Implementation of a region manager.
+pub struct Terrain {
+    pub regions: HashMap<RegionIndex, Region<ChunkIndex>>,
+    pub layout: RegionLayout,
+    pub archive: ColdArchive,
+    pub io: IoTracker,
+}
+
+impl Terrain {
+    pub fn new() -> Self {
+        let layout = RegionLayout::new("save").with_subdirectory("region");
+        let archive = ColdArchive::open(layout.archive_path()).unwrap();
+        Terrain {
+            regions: HashMap::new(),
+            layout: layout,
+            archive: archive,
+            io: IoTracker::new(),
+        }
+    }
+}
+
+impl<'a> RegionManager<'a, ChunkIndex, SerialChunk> for Terrain
+    where Region<ChunkIndex>: ManagedRegion<'a, ChunkIndex, SerialChunk>{
+    fn load(&mut self, index: RegionIndex) -> SerialResult<()> {
+        let path = self.layout.region_path(&index);
+
+        let handle = Region::get_region_file(path)?;
+
+        let region = Region::new(handle);
+
+        self.regions.insert(index.clone(), region);
+        Ok(())
+    }
+
+    fn region_indices(&self) -> Vec<RegionIndex> {
+        self.regions.iter().map(|(i, _)| i).cloned().collect()
+    }
+
+    fn get(&mut self, index: &RegionIndex) -> Option<&Region<ChunkIndex>> {
+        self.regions.get(index)
+    }
+
+    fn get_mut(&mut self, index: &RegionIndex) -> Option<&mut Region<ChunkIndex>> {
+        self.regions.get_mut(index)
+    }
+
+    fn remove(&mut self, index: &RegionIndex) {
+        self.regions.remove(index);
+    }
+
+    fn region_loaded(&self, index: &RegionIndex) -> bool {
+        self.regions.contains_key(index)
+    }
+
+    fn layout(&self) -> &RegionLayout {
+        &self.layout
+    }
+
+    fn cold_archive(&mut self) -> Option<&mut ColdArchive> {
+        Some(&mut self.archive)
+    }
+
+    fn io_tracker(&self) -> Option<&IoTracker> {
+        Some(&self.io)
+    }
+}
+
+
+pub type WorldPosition = Point;
+
+impl WorldPosition {
+    pub fn from_chunk_index(index: ChunkIndex) -> Point {
+        Point::new(index.0.x * CHUNK_WIDTH, index.0.y * CHUNK_WIDTH)
+    }
+}
+
+pub struct World {
+    regions: Terrain,
+    chunks: FxHashMap<ChunkIndex, Chunk>,
+    dudes: FxHashMap<WorldPosition, Dude>,
+    dude_cap: EntityCap,
+    pub observer: WorldPosition,
+
+    gen: Perlin,
+    config: GenConfig,
+    remap: CellRemap,
+    transient: bool,
+    metadata: WorldMetadata,
+    relevance: RelevancePolicy<ChunkIndex>,
+    /// Chunks kept loaded for gameplay reasons, on top of the ones around
+    /// the player.
+    pub relevance_providers: RelevanceProviders<ChunkIndex>,
+    leases: ChunkLeases<ChunkIndex>,
+    attachments: AttachmentRegistry<World, ChunkIndex>,
+    load_registry: LoadRegistry<ChunkIndex>,
+    residency: Option<ResidencyLog>,
+    pub subscriptions: CellSubscriptions,
+    metrics: Option<Box<dyn MetricsSink>>,
+    compression: Option<AdaptiveCompression>,
+    occupancy: OccupancyStore,
+    save_times: SaveTimes,
+    visits: ChunkVisits,
+    tombstones: ChunkTombstones,
+    inbox: ChunkInbox,
+    versions: ChunkVersions,
+    coalescing: WriteCoalescing<ChunkIndex>,
+    timings: LoadTimings<ChunkIndex>,
+    protection: ChunkProtection,
+    /// The cell buffers of unloaded chunks, reused by the next chunks loaded
+    /// or generated.
+    pool: BufferPool<Cell>,
+    /// Generates chunks from a template instead of noise if set.
+    flat: Option<FlatGenerator<Cell>>,
+    /// Keeps other processes from opening the save while this one plays it.
+    lock: Option<WorldLock>,
+}
+
+/// How a world locks its save when it is opened.
+enum Locking {
+    Acquire,
+    TakeOver,
+    None,
+}
+
+/// The dudes standing inside a chunk, saved along with it.
+struct Dudes;
+
+impl Attachment<World, ChunkIndex> for Dudes {
+    /// Canonical, so an unchanged chunk always saves the same bytes and isn't
+    /// rewritten by `save_in_place`.
+    type Data = CanonicalMap<WorldPosition, Dude>;
+    const KEY: &'static str = "dudes";
+
+    fn detach(world: &mut World, index: &ChunkIndex) -> Option<Self::Data> {
+        Some(CanonicalMap(world.remove_dudes_in_chunk(index)))
+    }
+
+    fn attach(world: &mut World, _index: &ChunkIndex, dudes: Self::Data) {
+        for (pos, dude) in dudes.into_inner() {
+            world.dudes.insert(pos, dude);
+        }
+    }
+}
+
+impl World {
+    /// Opens the world in the save directory. Panics if another game that may
+    /// still be running has it open.
+    pub fn new_empty() -> Self {
+        World::open(Locking::Acquire)
+    }
+
+    /// Like `new_empty`, but opens the world even if another game has it
+    /// open, such as one that crashed on another machine sharing the save.
+    pub fn new_taking_over() -> Self {
+        World::open(Locking::TakeOver)
+    }
+
+    fn open(locking: Locking) -> Self {
+        let mut attachments = AttachmentRegistry::new();
+        attachments.register::<Dudes>();
+
+        let mut regions = Terrain::new();
+        let layout = regions.layout.clone();
+        let lock = match locking {
+            Locking::Acquire  => Some(WorldLock::acquire(&layout)),
+            Locking::TakeOver => Some(WorldLock::take_over(&layout)),
+            Locking::None     => None,
+        };
+        let lock = lock.map(|l| l.unwrap_or_else(|e| panic!("can't open the save: {}", e)));
+
+        // Keep the area around the spawn point loaded, saving it every so
+        // often in case the game crashes. Frontends that draw the world set
+        // their viewport, and load the chunks it covers instead of a
+        // diamond.
+        let mut relevance = RelevancePolicy::new(InterestShape::Diamond(UPDATE_RADIUS))
+            .with_viewport(VIEWPORT_MARGIN, CHUNK_WIDTH)
+            .with_active_shape(InterestShape::Diamond(ACTIVE_RADIUS));
+        relevance.pin("spawn", InterestShape::Square(1).indices(&ChunkIndex::new(0, 0)), Some(SPAWN_SAVE_INTERVAL));
+        let mut metadata = WorldMetadata::load(regions.layout.metadata_path()).unwrap();
+        upgrade::upgrade_save(&mut regions, &mut metadata).unwrap();
+        let config = GenConfig::resolve(&mut metadata, config::CONFIG_PATH).unwrap();
+        let protection = ChunkProtection::load(&metadata).unwrap();
+        let flat = metadata.get(FLAT_METADATA_KEY).unwrap();
+
+        World {
+            regions: regions,
+            chunks: FxHashMap::default(),
+            dudes: FxHashMap::default(),
+            dude_cap: EntityCap::new(MAX_DUDES_PER_CHUNK, OverflowPolicy::Reject),
+            observer: WorldPosition::new(0, 0),
+
+            // The raw seed, which older worlds were generated with.
+            gen: Perlin::new().set_seed(config.seed.value() as usize),
+            config: config,
+            remap: CellRemap::new(),
+            transient: false,
+            metadata: metadata,
+            relevance: relevance,
+            relevance_providers: RelevanceProviders::new(),
+            leases: ChunkLeases::new(),
+            attachments: attachments,
+            load_registry: LoadRegistry::new(),
+            residency: None,
+            subscriptions: CellSubscriptions::new(CHUNK_WIDTH),
+            metrics: None,
+            compression: None,
+            occupancy: OccupancyStore::new(layout.clone()),
+            save_times: SaveTimes::new(layout.clone()),
+            visits: ChunkVisits::new(layout.clone()),
+            tombstones: ChunkTombstones::new(layout.clone()),
+            inbox: ChunkInbox::new(layout.clone()),
+            versions: ChunkVersions::new(layout),
+            coalescing: WriteCoalescing::new(Duration::from_secs(WRITE_WINDOW_SECS)),
+            timings: LoadTimings::new(),
+            protection: protection,
+            pool: BufferPool::new().with_max_per_class(POOLED_CHUNKS),
+            flat: flat,
+            lock: lock,
+        }
+    }
+
+    /// Creates a world that is generated as usual but never saved to disk.
+    pub fn new_transient() -> Self {
+        World {
+            transient: true,
+            metadata: WorldMetadata::new(),
+            protection: ChunkProtection::new(),
+            flat: None,
+            ..World::open(Locking::None)
+        }
+    }
+
+    /// Records every chunk that is loaded, generated or unloaded to a file,
+    /// for replaying with `ChunkedWorld::replay`.
+    pub fn record_residency_to(&mut self, path: &str) -> SerialResult<()> {
+        self.residency = Some(ResidencyLog::create(path)?);
+        Ok(())
+    }
+
+    /// Sets the sink the world reports its streaming counters to.
+    pub fn set_metrics(&mut self, sink: Box<dyn MetricsSink>) {
+        self.metrics = Some(sink);
+    }
+
+    /// Picks the compression level of saved chunks from measurements instead
+    /// of always using the default level.
+    pub fn set_adaptive_compression(&mut self, adaptive: AdaptiveCompression) {
+        self.compression = Some(adaptive);
+    }
+
+    /// Sets the renames applied to the kinds of cells in chunks loaded from
+    /// disk, for saves made before a kind was renamed or split.
+    pub fn set_cell_remap(&mut self, remap: CellRemap) {
+        self.remap = remap;
+    }
+
+    /// Generates the chunks that weren't saved yet from a template instead
+    /// of noise. The template is kept in the world metadata, so it only has
+    /// to be set when the world is created.
+    pub fn set_flat_generator(&mut self, generator: FlatGenerator<Cell>) -> SerialResult<()> {
+        self.metadata.insert(FLAT_METADATA_KEY, &generator)?;
+        self.flat = Some(generator);
+        Ok(())
+    }
+
+    /// Returns the pool the cell buffers of chunks are recycled through.
+    pub fn buffer_pool(&self) -> &BufferPool<Cell> {
+        &self.pool
+    }
+
+    pub fn chunk_from_world_pos(&self, pos: WorldPosition) -> Option<&Chunk> {
+        let index = ChunkIndex::from_world_pos(pos);
+        self.chunk(index)
+    }
+
+    pub fn chunk_mut_from_world_pos(&mut self, pos: WorldPosition) -> Option<&mut Chunk> {
+        let index = ChunkIndex::from_world_pos(pos);
+        self.chunk_mut(index)
+    }
+
+    pub fn chunk(&self, index: ChunkIndex) -> Option<&Chunk> {
+        self.chunks.get(&index)
+    }
+
+    pub fn chunk_mut(&mut self, index: ChunkIndex) -> Option<&mut Chunk> {
+        self.chunks.get_mut(&index)
+    }
+
+    pub fn cell(&self, world_pos: &WorldPosition) -> Option<&Cell> {
+        let chunk_pos = ChunkPosition::from_world(world_pos);
+        let chunk_opt = self.chunk_from_world_pos(*world_pos);
+        match chunk_opt {
+            Some(chunk) => {
+                Some(chunk.cell(chunk_pos))
+            },
+            None => None,
+        }
+    }
+
+    /// Gets a cell for changing it, unless its chunk is protected.
+    /// Subscriptions watching the cell are told it changed and the version of
+    /// its chunk goes up, whether or not it really does.
+    pub fn cell_mut(&mut self, world_pos: &WorldPosition) -> Option<&mut Cell> {
+        let index = ChunkIndex::from_world_pos(*world_pos);
+        if self.is_protected(&index) {
+            return None;
+        }
+        if self.chunks.contains_key(&index) {
+            self.subscriptions.cell_changed(world_pos.x, world_pos.y);
+            // Refuse changes whose version can't be recorded, so editors
+            // never miss one.
+            self.bump_chunk_version(&index).ok()?;
+        }
+        let chunk_pos = ChunkPosition::from_world(world_pos);
+        let chunk_opt = self.chunk_mut_from_world_pos(*world_pos);
+        match chunk_opt {
+            Some(chunk) => {
+                Some(chunk.cell_mut(chunk_pos))
+            }
+            None => None,
+        }
+    }
+
+    pub fn can_walk(&self, pos: &WorldPosition) -> bool {
+        let cell_walkable = self.cell(pos).map_or(false, |c| c.can_walk());
+        let no_dude = self.dudes.get(pos).is_none();
+        let no_player = self.observer != *pos;
+        cell_walkable && no_dude && no_player
+    }
+
+    /// Copies the cells of a rectangle of the given size centered on the
+    /// observer. Cells in chunks that aren't loaded are left empty.
+    pub fn view(&self, size: Point) -> CellView<Cell> {
+        self.view_at(self.observer, size)
+    }
+
+    fn view_at(&self, center: WorldPosition, size: Point) -> CellView<Cell> {
+        let empty = Cell::new(CellKind::Nothing, Color::WHITE);
+        CellView::extract((center.x, center.y), size.x, size.y, CHUNK_WIDTH, &empty,
+                          |index: &ChunkIndex| self.chunks.get(index).map(|c| c.cells()))
+    }
+
+    /// Copies the cells of a single chunk, for frontends that draw the world
+    /// one chunk at a time.
+    pub fn chunk_view(&self, index: ChunkIndex) -> CellView<Cell> {
+        let origin = WorldPosition::from_chunk_index(index);
+        let center = origin + (CHUNK_WIDTH / 2, CHUNK_WIDTH / 2);
+        self.view_at(center, Point::new(CHUNK_WIDTH, CHUNK_WIDTH))
+    }
+}
+
+impl World {
+    /// Places a dude, unless the chunk it would stand in is already full.
+    pub fn place_dude(&mut self, pos: WorldPosition) -> bool {
+        if !self.has_room_for_dude(&pos) {
+            return false;
+        }
+        self.dudes.insert(pos, Dude::new(pos.clone()));
+        true
+    }
+
+    fn dudes_in_chunk(&self, index: &ChunkIndex) -> Option<usize> {
+        if !self.chunks.contains_key(index) {
+            return None;
+        }
+        Some(self.dudes.keys().filter(|&&pos| ChunkIndex::from_world_pos(pos) == *index).count())
+    }
+
+    fn has_room_for_dude(&self, pos: &WorldPosition) -> bool {
+        let index = ChunkIndex::from_world_pos(*pos);
+        self.dude_cap.place(&index, |i| self.dudes_in_chunk(i)) == Placement::Accepted
+    }
+
+    pub fn dudes(&mut self) -> hash_map::Values<'_, WorldPosition, Dude> {
+        self.dudes.values()
+    }
+
+    fn remove_dudes_in_chunk(&mut self, chunk_index: &ChunkIndex) -> HashMap<WorldPosition, Dude>
+    {
+        let mut dudes = HashMap::new();
+        for j in 0..CHUNK_WIDTH {
+            for i in 0..CHUNK_WIDTH {
+                let chunk_pos = ChunkPosition::from(Point::new(i, j));
+                let cell_world_pos = Chunk::world_position_at(chunk_index, &chunk_pos);
+                if let Some(dude) = self.dudes.remove(&cell_world_pos) {
+                    dudes.insert(cell_world_pos, dude);
+                }
+            }
+        }
+        dudes
+    }
+
+    /// Turns the walls and trees within `radius` cells of `center` into
+    /// floor. Cells in chunks that aren't loaded are cleared once they load.
+    /// Returns the number of cells cleared right away.
+    pub fn blast(&mut self, center: WorldPosition, radius: i32) -> SerialResult<usize> {
+        let mut cleared = 0;
+        let mut deferred: HashMap<ChunkIndex, Vec<WorldPosition>> = HashMap::new();
+        for dy in -radius..radius + 1 {
+            for dx in -radius..radius + 1 {
+                if dx * dx + dy * dy > radius * radius {
+                    continue;
+                }
+                let pos = center + (dx, dy);
+                let index = ChunkIndex::from_world_pos(pos);
+                if self.chunks.contains_key(&index) {
+                    if self.clear_cell(&pos) {
+                        cleared += 1;
+                    }
+                } else if !self.is_protected(&index) {
+                    deferred.entry(index).or_insert_with(Vec::new).push(pos);
+                }
+            }
+        }
+        for (index, cells) in deferred {
+            self.post_message(&index, BLAST_MESSAGE, &cells)?;
+        }
+        Ok(cleared)
+    }
+
+    fn clear_cell(&mut self, pos: &WorldPosition) -> bool {
+        let cell = match self.cell_mut(pos) {
+            Some(cell) => cell,
+            None       => return false,
+        };
+        match cell.kind {
+            CellKind::Wall | CellKind::Tree => {
+                cell.kind = CellKind::Floor;
+                true
+            },
+            _ => false,
+        }
+    }
+
+    pub fn step_dudes(&mut self) {
+        // Not using id-based entities is painful.
+        let mut actions: Vec<(WorldPosition, WorldPosition)> = Vec::new();
+        for pos in self.dudes.keys() {
+            if !self.relevance.is_active(&ChunkIndex::from_world_pos(*pos)) {
+                continue;
+            }
+            let dir = Direction::choose8();
+            let new_pos = *pos + dir;
+            actions.push((pos.clone(), new_pos));
+        }
+
+        for (pos, new_pos) in actions {
+            let crosses = ChunkIndex::from_world_pos(pos) != ChunkIndex::from_world_pos(new_pos);
+            if self.can_walk(&new_pos) && (!crosses || self.has_room_for_dude(&new_pos)) {
+                let mut dude = self.dudes.remove(&pos).unwrap();
+                dude.pos = new_pos.clone();
+                self.dudes.insert(new_pos, dude);
+            }
+        }
+    }
+}
+
+const UPDATE_RADIUS: i32 = 2;
+const ACTIVE_RADIUS: i32 = 1;
+/// Cells loaded past each edge of the viewport, so chunks are ready before
+/// they scroll into view.
+const VIEWPORT_MARGIN: i32 = 16;
+/// Chunks outside the active area wait to load while this many reads and
+/// writes are in progress.
+const MAX_IO_IN_FLIGHT: usize = 4;
+const SPAWN_SAVE_INTERVAL: u64 = 100;
+/// Seconds the periodic saves of the spawn area are held back, so they hit
+/// each region file once. Changes are lost for at most this long on a crash.
+const WRITE_WINDOW_SECS: u64 = 10;
+const MAX_DUDES_PER_CHUNK: usize = 64;
+/// Cell buffers of unloaded chunks kept for reuse, about as many as are
+/// unloaded at once when the player crosses a chunk border.
+const POOLED_CHUNKS: usize = 16;
+/// The key the template of flat worlds is stored under in the world
+/// metadata.
+const FLAT_METADATA_KEY: &'static str = "generation.flat";
+/// Kind of the messages holding the cells of a chunk a blast cleared while
+/// it wasn't loaded.
+const BLAST_MESSAGE: &'static str = "blast";
+
+impl<'a> ChunkedTerrain<'a, ChunkIndex, SerialChunk, Terrain> for World {
+    type Chunk = Chunk;
+
+    fn regions_mut(&mut self) -> &mut Terrain {
+        &mut self.regions
+    }
+
+    fn chunk_loaded(&self, index: &ChunkIndex) -> bool {
+        self.chunks.contains_key(index)
+    }
+
+    fn chunk_indices(&self) -> Vec<ChunkIndex> {
+        self.chunks.iter().map(|(&i, _)| i).collect()
+    }
+
+    fn chunk_count(&self) -> usize {
+        self.chunks.len()
+    }
+
+    fn chunk(&self, index: &ChunkIndex) -> Option<&Chunk> {
+        self.chunks.get(index)
+    }
+
+    fn chunk_mut(&mut self, index: &ChunkIndex) -> Option<&mut Chunk> {
+        self.chunks.get_mut(index)
+    }
+}
+
+impl HostedWorld for World {
+    fn update_within(&mut self, budget: Duration) -> SerialResult<bool> {
+        self.update_chunks_with_deadline(budget)
+    }
+
+    fn autosave(&mut self) -> SerialResult<()> {
+        self.save_in_place()
+    }
+
+    fn shut_down(&mut self) -> SerialResult<()> {
+        self.save()
+    }
+}
+
+// Dudes are keyed by where they stand, so their position is their handle.
+impl<'a> EntityQuery<'a, ChunkIndex, SerialChunk, Terrain, World> for World {
+    type Entity = WorldPosition;
+
+    fn chunk_width(&self) -> i32 { CHUNK_WIDTH }
+
+    fn entities_in_chunk(&self, index: &ChunkIndex) -> Vec<((i32, i32), WorldPosition)> {
+        self.dudes.keys()
+            .filter(|&&pos| ChunkIndex::from_world_pos(pos) == *index)
+            .map(|&pos| ((pos.x, pos.y), pos))
+            .collect()
+    }
+}
+
+impl<'a> ChunkedWorld<'a, ChunkIndex, SerialChunk, Terrain, World> for World
+    where Terrain: RegionManager<'a, ChunkIndex, SerialChunk> {
+    fn terrain(&self) -> &World { self }
+    fn terrain_mut(&mut self) -> &mut World { self }
+
+    fn load_registry(&self) -> &LoadRegistry<ChunkIndex> { &self.load_registry }
+    fn load_registry_mut(&mut self) -> &mut LoadRegistry<ChunkIndex> { &mut self.load_registry }
+
+    fn residency_log(&mut self) -> Option<&mut ResidencyLog> { self.residency.as_mut() }
+
+    fn metrics(&self) -> Option<&dyn MetricsSink> { self.metrics.as_ref().map(|m| &**m) }
+
+    fn adaptive_compression(&self) -> Option<&AdaptiveCompression> { self.compression.as_ref() }
+    fn adaptive_compression_mut(&mut self) -> Option<&mut AdaptiveCompression> { self.compression.as_mut() }
+
+    fn cell_subscriptions_mut(&mut self) -> Option<&mut CellSubscriptions> { Some(&mut self.subscriptions) }
+
+    fn occupancy_store_mut(&mut self) -> Option<&mut OccupancyStore> { Some(&mut self.occupancy) }
+
+    fn save_times_mut(&mut self) -> Option<&mut SaveTimes> { Some(&mut self.save_times) }
+
+    fn visits_mut(&mut self) -> Option<&mut ChunkVisits> { Some(&mut self.visits) }
+
+    fn tombstones_mut(&mut self) -> Option<&mut ChunkTombstones> { Some(&mut self.tombstones) }
+
+    fn chunk_inbox_mut(&mut self) -> Option<&mut ChunkInbox> { Some(&mut self.inbox) }
+
+    fn chunk_versions_mut(&mut self) -> Option<&mut ChunkVersions> { Some(&mut self.versions) }
+
+    fn write_coalescing_mut(&mut self) -> Option<&mut WriteCoalescing<ChunkIndex>> { Some(&mut self.coalescing) }
+
+    fn load_timings(&self) -> Option<&LoadTimings<ChunkIndex>> { Some(&self.timings) }
+    fn load_timings_mut(&mut self) -> Option<&mut LoadTimings<ChunkIndex>> { Some(&mut self.timings) }
+
+    fn world_lock(&self) -> Option<&WorldLock> { self.lock.as_ref() }
+
+    fn chunk_protection(&self) -> Option<&ChunkProtection> { Some(&self.protection) }
+    fn chunk_protection_mut(&mut self) -> Option<&mut ChunkProtection> { Some(&mut self.protection) }
+
+    fn load_chunk_internal(&mut self, chunk: SerialChunk, index: &ChunkIndex) -> Result<(), SerialError> {
+        let cells = Chunk::from_saved(chunk.chunk, index, &self.remap, self.config.seed, &mut self.pool)?;
+        let registry = self.attachments.clone();
+        registry.attach_all(self, index, chunk.attachments)?;
+
+        if let Some(placeholder) = self.chunks.insert(index.clone(), cells) {
+            self.pool.give(placeholder.into_cells());
+        }
+
+        Ok(())
+    }
+
+    fn unload_chunk_internal(&mut self, index: &ChunkIndex) -> Result<SerialChunk, SerialError> {
+        let chunk = match self.chunks.remove(&index) {
+            Some(c) => c,
+            None => return Err(NoChunkInWorld(index.0.x, index.0.y).into()),
+        };
+        let registry = self.attachments.clone();
+        let attachments = registry.detach_all(self, index)?;
+        // println!("Unloading chunk at {}", index);
+        let serial = SerialChunk {
+            chunk: chunk.to_saved(),
+            attachments: attachments,
+        };
+        self.pool.give(chunk.into_cells());
+        Ok(serial)
+    }
+
+    fn snapshot_chunk(&mut self, index: &ChunkIndex) -> Result<Option<SerialChunk>, SerialError> {
+        let saved = match self.chunks.get(index) {
+            Some(c) => c.to_saved(),
+            None => return Err(NoChunkInWorld(index.0.x, index.0.y).into()),
+        };
+        let registry = self.attachments.clone();
+        let attachments = registry.detach_all(self, index)?;
+        registry.attach_all(self, index, attachments.clone())?;
+        Ok(Some(SerialChunk {
+            chunk: saved,
+            attachments: attachments,
+        }))
+    }
+
+    fn generate_at(&mut self, index: &ChunkIndex, _level: DetailLevel) -> SerialResult<()> {
+        let chunk = match self.flat {
+            Some(ref flat) => {
+                let mut cells = self.pool.take(CHUNK_CELLS);
+                flat.fill_chunk(index, CHUNK_WIDTH, &mut cells);
+                Chunk::from_cells(cells)
+            },
+            None => Chunk::new(index, &self.gen, &self.config, &mut self.pool),
+        };
+        if let Some(placeholder) = self.chunks.insert(index.clone(), chunk) {
+            self.pool.give(placeholder.into_cells());
+        }
+
+        for i in 4..8 {
+            for j in 4..8 {
+                let chunk_pos = ChunkPosition::from(Point::new(i, j));
+                let cell_pos = Chunk::world_position_at(&index, &chunk_pos);
+                if self.can_walk(&cell_pos) {
+                    self.place_dude(cell_pos);
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    fn insert_placeholder(&mut self, index: &ChunkIndex) -> SerialResult<bool> {
+        self.chunks.insert(index.clone(), Chunk::placeholder());
+        Ok(true)
+    }
+
+    fn relevant_chunks(&self) -> FxHashSet<ChunkIndex> {
+        let center = ChunkIndex::from_world_pos(self.observer);
+        self.relevance.relevant(&[center])
+    }
+
+    fn active_chunks(&self) -> FxHashSet<ChunkIndex> {
+        let center = ChunkIndex::from_world_pos(self.observer);
+        self.relevance.active(&[center])
+    }
+
+    fn relevance_policy_mut(&mut self) -> Option<&mut RelevancePolicy<ChunkIndex>> {
+        Some(&mut self.relevance)
+    }
+
+    fn relevance_providers(&self) -> Option<&RelevanceProviders<ChunkIndex>> {
+        Some(&self.relevance_providers)
+    }
+
+    fn chunk_leases(&self) -> Option<&ChunkLeases<ChunkIndex>> {
+        Some(&self.leases)
+    }
+
+    fn observed_chunks(&self) -> Vec<ChunkIndex> {
+        vec![ChunkIndex::from_world_pos(self.observer)]
+    }
+
+    fn on_first_visit(&mut self, index: &ChunkIndex) -> SerialResult<()> {
+        // Every chunk greets the player with a dude the first time they
+        // enter it.
+        let origin = WorldPosition::from_chunk_index(*index);
+        let spot = (0..CHUNK_WIDTH * CHUNK_WIDTH)
+            .map(|i| origin + (i % CHUNK_WIDTH, i / CHUNK_WIDTH))
+            .find(|pos| self.can_walk(pos));
+        if let Some(pos) = spot {
+            self.place_dude(pos);
+        }
+        Ok(())
+    }
+
+    fn on_chunk_messages(&mut self, _index: &ChunkIndex, messages: &[ChunkMessage]) -> SerialResult<()> {
+        for message in messages.iter().filter(|m| m.kind == BLAST_MESSAGE) {
+            for pos in message.decode::<Vec<WorldPosition>>()?.iter() {
+                self.clear_cell(pos);
+            }
+        }
+        Ok(())
+    }
+
+    fn admission_policy(&self) -> Option<AdmissionPolicy> {
+        // The chunks the player can see are never held back.
+        Some(AdmissionPolicy::new(MAX_IO_IN_FLIGHT, ACTIVE_RADIUS))
+    }
+
+    fn observer_distance(&self, index: &ChunkIndex) -> i32 {
+        let center = ChunkIndex::from_world_pos(self.observer);
+        (index.0.x - center.0.x).abs().max((index.0.y - center.0.y).abs())
+    }
+
+    fn is_transient(&self) -> bool {
+        self.transient
+    }
+
+    fn metadata(&self) -> &WorldMetadata {
+        &self.metadata
+    }
+
+    fn metadata_mut(&mut self) -> &mut WorldMetadata {
+        &mut self.metadata
+    }
+}
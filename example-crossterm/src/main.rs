@@ -0,0 +1,233 @@
+//! Draws the demo world in a terminal with crossterm, which works in the
+//! Windows console as well as on Unix terminals. The controls are the same as
+//! the curses example: hjkl, tybn or the arrow keys move, and q saves and
+//! quits. o toggles a debug overlay of chunk and region borders, and x
+//! blasts the walls and trees around the player, even in chunks that aren't
+//! loaded yet.
+
+use std::io::{self, Stdout, Write};
+use std::time::Duration;
+
+use crossterm::cursor::{Hide, MoveTo, Show};
+use crossterm::event::{self, Event, KeyCode, KeyEventKind};
+use crossterm::style::{self, Print, SetForegroundColor};
+use crossterm::terminal::{self, Clear, ClearType, EnterAlternateScreen, LeaveAlternateScreen};
+use crossterm::{execute, queue};
+
+use infinigen::{CellRect, ChunkOrigin, ChunkedWorld, DebugOverlay};
+use infinigen_example_common::cell::CellKind;
+use infinigen_example_common::chunk::{ChunkIndex, CHUNK_WIDTH};
+use infinigen_example_common::color::{self, Color};
+use infinigen_example_common::direction::Direction;
+use infinigen_example_common::point::Point;
+use infinigen_example_common::world::World;
+
+/// How far the blast of the x key reaches, in cells. Farther than the rows
+/// of most terminals, so it reaches chunks that aren't loaded.
+const BLAST_RADIUS: i32 = 48;
+
+/// Uses the exact color on terminals with true color, and the nearest of the
+/// basic terminal colors elsewhere.
+fn fg(color: Color, truecolor: bool) -> style::Color {
+    if truecolor {
+        return style::Color::Rgb { r: color.r, g: color.g, b: color.b };
+    }
+    match color.nearest_named() {
+        0 => style::Color::Red,
+        1 => style::Color::Blue,
+        2 => style::Color::Green,
+        3 => style::Color::Cyan,
+        4 => style::Color::Magenta,
+        5 => style::Color::Yellow,
+        _ => style::Color::White,
+    }
+}
+
+/// Puts the terminal in raw mode on an alternate screen, and restores it
+/// when dropped, even if the demo panics.
+struct Screen {
+    out: Stdout,
+    truecolor: bool,
+    overlay: bool,
+}
+
+impl Screen {
+    fn new() -> io::Result<Screen> {
+        let mut out = io::stdout();
+        terminal::enable_raw_mode()?;
+        execute!(out, EnterAlternateScreen, Hide)?;
+        Ok(Screen { out, truecolor: color::terminal_has_truecolor(), overlay: false })
+    }
+
+    fn show_splash(&mut self) -> io::Result<()> {
+        queue!(self.out, Clear(ClearType::All), SetForegroundColor(style::Color::White))?;
+        let lines = ["move: hjkltybn or arrows", "quit: q", "chunk borders: o", "blast: x", "run into walls to destroy them.", "autosave on quit."];
+        for (y, line) in lines.iter().enumerate() {
+            queue!(self.out, MoveTo(0, y as u16), Print(line))?;
+        }
+        self.out.flush()?;
+        read_key().map(|_| ())
+    }
+
+    fn print(&mut self, world: &mut World) -> io::Result<()> {
+        let (cols, rows) = terminal::size()?;
+        let size = Point::new(cols as i32, rows as i32);
+        let center = world.observer - size / 2;
+        world.set_viewport(size.x, size.y);
+
+        queue!(self.out, Clear(ClearType::All))?;
+        let view = world.view(size);
+        for (y, row) in view.rows().enumerate() {
+            queue!(self.out, MoveTo(0, y as u16))?;
+            // Only change the color when it differs from the last cell's, since
+            // the Windows console is slow to switch colors.
+            let mut current = None;
+            for c in row.iter() {
+                if current != Some(c.color) {
+                    queue!(self.out, SetForegroundColor(fg(c.color, self.truecolor)))?;
+                    current = Some(c.color);
+                }
+                queue!(self.out, Print(c.to_char()))?;
+            }
+        }
+
+        for dude in world.dudes() {
+            let pos = dude.pos() - center;
+            if pos.x >= 0 && pos.y >= 0 && pos.x < size.x && pos.y < size.y {
+                queue!(self.out, MoveTo(pos.x as u16, pos.y as u16),
+                       SetForegroundColor(fg(dude.color, self.truecolor)), Print(dude.appearance))?;
+            }
+        }
+        if self.overlay {
+            let overlay = world.debug_overlay(CellRect::new(center.x, center.y, size.x, size.y), CHUNK_WIDTH);
+            self.print_overlay(world, &overlay, center)?;
+        }
+        queue!(self.out, MoveTo(cols / 2, rows / 2), SetForegroundColor(style::Color::White), Print('@'))?;
+        self.out.flush()
+    }
+
+    /// Draws chunk borders in grey and region borders in yellow over the
+    /// world, and the state of the chunk under the player in the top line.
+    fn print_overlay(&mut self, world: &World, overlay: &DebugOverlay<ChunkIndex>, center: Point) -> io::Result<()> {
+        let view = overlay.viewport;
+        for &x in overlay.chunk_lines_x.iter() {
+            let color = if overlay.region_lines_x.contains(&x) { style::Color::Yellow } else { style::Color::DarkGrey };
+            queue!(self.out, SetForegroundColor(color))?;
+            for y in 0..view.height {
+                queue!(self.out, MoveTo((x - center.x) as u16, y as u16), Print('|'))?;
+            }
+        }
+        for &y in overlay.chunk_lines_y.iter() {
+            let color = if overlay.region_lines_y.contains(&y) { style::Color::Yellow } else { style::Color::DarkGrey };
+            queue!(self.out, SetForegroundColor(color), MoveTo(0, (y - center.y) as u16),
+                   Print("-".repeat(view.width as usize)))?;
+        }
+
+        let here = (view.x + view.width / 2, view.y + view.height / 2);
+        if let Some(chunk) = overlay.chunk_at(here.0, here.1) {
+            let mut flags = Vec::new();
+            for &(set, name) in [(chunk.unsaved, "unsaved"), (chunk.pinned, "pinned"), (chunk.active, "active"),
+                                 (chunk.leased, "leased"), (chunk.protected, "protected")].iter() {
+                if set {
+                    flags.push(name);
+                }
+            }
+            let mut line = format!("chunk {} in region {}: {:?} {}", chunk.index, chunk.region, chunk.state, flags.join(" "));
+            if let Some(provenance) = world.chunk_provenance(&chunk.index) {
+                let origin = match provenance.origin {
+                    ChunkOrigin::Loaded    => "loaded",
+                    ChunkOrigin::Generated => "generated",
+                };
+                line.push_str(&format!(" ({} in {:.2} ms)", origin, millis(provenance.elapsed)));
+            }
+            queue!(self.out, MoveTo(0, 0), SetForegroundColor(style::Color::White), Print(line))?;
+        }
+        if let Some(timings) = world.load_timings() {
+            let line = format!("{} loaded, mean {:.2} ms; {} generated, mean {:.2} ms",
+                               timings.loaded().count, millis(timings.loaded().mean()),
+                               timings.generated().count, millis(timings.generated().mean()));
+            queue!(self.out, MoveTo(0, 1), SetForegroundColor(style::Color::White), Print(line))?;
+        }
+        Ok(())
+    }
+}
+
+impl Drop for Screen {
+    fn drop(&mut self) {
+        let _ = execute!(self.out, style::ResetColor, Show, LeaveAlternateScreen);
+        let _ = terminal::disable_raw_mode();
+    }
+}
+
+fn millis(duration: Duration) -> f64 {
+    duration.as_secs() as f64 * 1000.0 + duration.subsec_nanos() as f64 * 1e-6
+}
+
+/// Blocks until a key is pressed. Windows reports releases as well as
+/// presses, so only presses are returned.
+fn read_key() -> io::Result<KeyCode> {
+    loop {
+        if let Event::Key(key) = event::read()? {
+            if key.kind == KeyEventKind::Press {
+                return Ok(key.code);
+            }
+        }
+    }
+}
+
+fn main() -> io::Result<()> {
+    let transient = std::env::args().any(|arg| arg == "--transient");
+    let mut world = if transient {
+        World::new_transient()
+    } else {
+        World::new_empty()
+    };
+    world.replay_journal().expect("Failed to replay save journal!");
+
+    let mut screen = Screen::new()?;
+    screen.show_splash()?;
+
+    loop {
+        world.update_chunks().expect("Failed to update chunks!");
+        screen.print(&mut world)?;
+
+        let dir = match read_key()? {
+            KeyCode::Char('q') => {
+                world.save().expect("Failed to save world!");
+                return Ok(());
+            },
+            KeyCode::Up | KeyCode::Char('k')    => Direction::N,
+            KeyCode::Down | KeyCode::Char('j')  => Direction::S,
+            KeyCode::Left | KeyCode::Char('h')  => Direction::W,
+            KeyCode::Right | KeyCode::Char('l') => Direction::E,
+            KeyCode::Char('t') => Direction::NW,
+            KeyCode::Char('y') => Direction::NE,
+            KeyCode::Char('b') => Direction::SW,
+            KeyCode::Char('n') => Direction::SE,
+            KeyCode::Char('x') => {
+                world.blast(world.observer, BLAST_RADIUS).expect("Failed to blast!");
+                continue;
+            },
+            KeyCode::Char('o') => {
+                screen.overlay = !screen.overlay;
+                continue;
+            },
+            _ => continue,
+        };
+        try_step(&mut world, dir);
+
+        world.step_dudes();
+        world.advance_tick();
+    }
+}
+
+fn try_step(world: &mut World, dir: Direction) {
+    let new_pos = world.observer + dir;
+    if world.can_walk(&new_pos) {
+        world.observer = new_pos;
+    } else if !world.cell(&new_pos).map_or(false, |c| c.can_walk()) {
+        if let Some(c) = world.cell_mut(&new_pos) {
+            c.kind = CellKind::Floor;
+        }
+    }
+}
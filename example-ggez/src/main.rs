@@ -0,0 +1,286 @@
+//! Draws the demo world as a tile map with ggez. The arrow keys or hjkl move
+//! the observer and the camera follows it. Escape saves and quits.
+//!
+//! Every chunk is drawn from its own batch of tiles, which is only rebuilt
+//! when the chunk is loaded or one of its cells changes.
+
+use std::collections::{HashMap, HashSet};
+
+use ggez::event::{self, EventHandler};
+use ggez::glam::Vec2;
+use ggez::graphics::{self, Canvas, DrawParam, Image, ImageFormat, InstanceArray, Rect, Sampler};
+use ggez::input::keyboard::{KeyCode, KeyInput};
+use ggez::{Context, GameResult};
+
+use infinigen::{CellEvent, CellRect, ChunkedTerrain, ChunkedWorld, SubscriptionId};
+use infinigen_example_common::cell::{Cell, CellKind};
+use infinigen_example_common::chunk::{ChunkIndex, CHUNK_WIDTH};
+use infinigen_example_common::color::Color;
+use infinigen_example_common::direction::Direction;
+use infinigen_example_common::world::World;
+
+const SCREEN_WIDTH: f32 = 960.0;
+const SCREEN_HEIGHT: f32 = 640.0;
+
+/// The size of a tile on screen, in pixels.
+const TILE_SIZE: f32 = 16.0;
+
+/// The size of a tile in the atlas, in pixels.
+const ATLAS_TILE: u32 = 8;
+
+/// The tiles of the atlas, from left to right.
+#[derive(Clone, Copy)]
+enum Tile {
+    Wall,
+    Floor,
+    Tree,
+    Dude,
+    Player,
+}
+
+const TILE_COUNT: u32 = 5;
+
+impl Tile {
+    fn of_cell(cell: &Cell) -> Option<Tile> {
+        match cell.kind {
+            CellKind::Wall    => Some(Tile::Wall),
+            CellKind::Floor   => Some(Tile::Floor),
+            CellKind::Tree    => Some(Tile::Tree),
+            CellKind::Nothing => None,
+        }
+    }
+
+    /// Returns whether a pixel of this tile is drawn. Tiles are white, so
+    /// they can be tinted with the color of what they show.
+    fn covers(self, x: i32, y: i32) -> bool {
+        let (dx, dy) = (2 * x - 7, 2 * y - 7);
+        match self {
+            Tile::Wall   => true,
+            Tile::Floor  => x == 3 && y == 4,
+            Tile::Tree   => y < 6 && dx.abs() <= 2 * y,
+            Tile::Dude   => dx * dx + dy * dy <= 36,
+            Tile::Player => {
+                let d = dx * dx + dy * dy;
+                d <= 49 && d >= 16
+            },
+        }
+    }
+
+    fn src(self) -> Rect {
+        let width = 1.0 / TILE_COUNT as f32;
+        Rect::new(self as u32 as f32 * width, 0.0, width, 1.0)
+    }
+}
+
+fn build_atlas(ctx: &Context) -> Image {
+    let width = ATLAS_TILE * TILE_COUNT;
+    let mut pixels = vec![0u8; (width * ATLAS_TILE * 4) as usize];
+    let tiles = [Tile::Wall, Tile::Floor, Tile::Tree, Tile::Dude, Tile::Player];
+    for (i, tile) in tiles.iter().enumerate() {
+        for y in 0..ATLAS_TILE {
+            for x in 0..ATLAS_TILE {
+                if tile.covers(x as i32, y as i32) {
+                    let offset = ((y * width + i as u32 * ATLAS_TILE + x) * 4) as usize;
+                    pixels[offset..offset + 4].copy_from_slice(&[255, 255, 255, 255]);
+                }
+            }
+        }
+    }
+    Image::from_pixels(ctx, &pixels, ImageFormat::Rgba8UnormSrgb, width, ATLAS_TILE)
+}
+
+fn tint(color: Color) -> graphics::Color {
+    let [r, g, b] = color.rgb();
+    graphics::Color::from_rgb(r, g, b)
+}
+
+fn tile_param(tile: Tile, color: Color, x: i32, y: i32) -> DrawParam {
+    let scale = TILE_SIZE / ATLAS_TILE as f32;
+    DrawParam::new()
+        .src(tile.src())
+        .dest(Vec2::new(x as f32 * TILE_SIZE, y as f32 * TILE_SIZE))
+        .scale(Vec2::new(scale, scale))
+        .color(tint(color))
+}
+
+struct TileMap {
+    world: World,
+    atlas: Image,
+    /// The tiles of every drawn chunk, positioned in world pixels.
+    batches: HashMap<ChunkIndex, InstanceArray>,
+    dirty: HashSet<ChunkIndex>,
+    /// The subscription to the cells on screen and the rectangle it covers.
+    watch: Option<(SubscriptionId, CellRect)>,
+    rebuilt: usize,
+}
+
+impl TileMap {
+    fn new(ctx: &mut Context) -> GameResult<TileMap> {
+        let mut world = World::new_empty();
+        world.set_viewport((SCREEN_WIDTH / TILE_SIZE) as i32, (SCREEN_HEIGHT / TILE_SIZE) as i32);
+        world.replay_journal().expect("Failed to replay save journal!");
+        Ok(TileMap {
+            world: world,
+            atlas: build_atlas(ctx),
+            batches: HashMap::new(),
+            dirty: HashSet::new(),
+            watch: None,
+            rebuilt: 0,
+        })
+    }
+
+    /// Returns the rectangle of cells on screen, plus a chunk on every side.
+    fn screen_rect(&self) -> CellRect {
+        let cols = (SCREEN_WIDTH / TILE_SIZE) as i32;
+        let rows = (SCREEN_HEIGHT / TILE_SIZE) as i32;
+        let observer = self.world.observer;
+        CellRect::new(observer.x - cols / 2 - CHUNK_WIDTH, observer.y - rows / 2 - CHUNK_WIDTH,
+                      cols + 2 * CHUNK_WIDTH, rows + 2 * CHUNK_WIDTH)
+    }
+
+    /// Moves the subscription along with the camera once the camera gets
+    /// close to its edge.
+    fn follow_camera(&mut self) {
+        let screen = self.screen_rect();
+        let inside = self.watch.map_or(false, |(_, rect)| {
+            rect.intersection(&screen) == Some(screen)
+        });
+        if inside {
+            return;
+        }
+        if let Some((id, _)) = self.watch.take() {
+            self.world.subscriptions.unsubscribe(id);
+        }
+        let rect = CellRect::new(screen.x - CHUNK_WIDTH, screen.y - CHUNK_WIDTH,
+                                 screen.width + 2 * CHUNK_WIDTH, screen.height + 2 * CHUNK_WIDTH);
+        let id = self.world.subscriptions.subscribe(rect);
+        self.watch = Some((id, rect));
+    }
+
+    fn mark_dirty(&mut self) {
+        for (_, event) in self.world.subscriptions.drain() {
+            match event {
+                CellEvent::Changed(x, y) => {
+                    self.dirty.insert(ChunkIndex::from_world_pos((x, y).into()));
+                },
+                CellEvent::Loaded(rect) => {
+                    self.dirty.insert(ChunkIndex::from_world_pos((rect.x, rect.y).into()));
+                },
+                CellEvent::Unloaded(rect) => {
+                    let index = ChunkIndex::from_world_pos((rect.x, rect.y).into());
+                    self.batches.remove(&index);
+                    self.dirty.remove(&index);
+                },
+            }
+        }
+
+        // Chunks that were loaded before they came into view have no batch.
+        for index in self.world.chunk_indices() {
+            if !self.batches.contains_key(&index) {
+                self.dirty.insert(index);
+            }
+        }
+    }
+
+    fn rebuild_dirty(&mut self, ctx: &Context) {
+        for index in self.dirty.drain() {
+            if !self.world.chunk_loaded(&index) {
+                continue;
+            }
+            let view = self.world.chunk_view(index);
+            let (ox, oy) = view.origin();
+            let mut batch = InstanceArray::new(ctx, self.atlas.clone());
+            for (y, row) in view.rows().enumerate() {
+                for (x, cell) in row.iter().enumerate() {
+                    if let Some(tile) = Tile::of_cell(cell) {
+                        batch.push(tile_param(tile, cell.color, ox + x as i32, oy + y as i32));
+                    }
+                }
+            }
+            self.batches.insert(index, batch);
+            self.rebuilt += 1;
+        }
+    }
+
+    fn try_step(&mut self, dir: Direction) {
+        let new_pos = self.world.observer + dir;
+        if self.world.can_walk(&new_pos) {
+            self.world.observer = new_pos;
+        } else if !self.world.cell(&new_pos).map_or(false, |c| c.can_walk()) {
+            if let Some(c) = self.world.cell_mut(&new_pos) {
+                c.kind = CellKind::Floor;
+            }
+        }
+    }
+}
+
+impl EventHandler for TileMap {
+    fn update(&mut self, ctx: &mut Context) -> GameResult {
+        self.world.update_chunks().expect("Failed to update chunks!");
+        self.follow_camera();
+        self.mark_dirty();
+        self.rebuild_dirty(ctx);
+
+        if ctx.time.ticks() % 10 == 0 {
+            self.world.step_dudes();
+            self.world.advance_tick();
+        }
+        Ok(())
+    }
+
+    fn draw(&mut self, ctx: &mut Context) -> GameResult {
+        let mut canvas = Canvas::from_frame(ctx, graphics::Color::BLACK);
+        canvas.set_sampler(Sampler::nearest_clamp());
+
+        let observer = self.world.observer;
+        let camera = Vec2::new(SCREEN_WIDTH / 2.0 - observer.x as f32 * TILE_SIZE,
+                               SCREEN_HEIGHT / 2.0 - observer.y as f32 * TILE_SIZE);
+        for batch in self.batches.values() {
+            canvas.draw(batch, DrawParam::new().dest(camera));
+        }
+
+        let mut sprites = InstanceArray::new(ctx, self.atlas.clone());
+        for dude in self.world.dudes() {
+            let pos = dude.pos();
+            sprites.push(tile_param(Tile::Dude, dude.color, pos.x, pos.y));
+        }
+        sprites.push(tile_param(Tile::Player, Color::WHITE, observer.x, observer.y));
+        canvas.draw(&sprites, DrawParam::new().dest(camera));
+
+        let status = format!("{} chunks loaded, {} batches rebuilt, {:.0} fps",
+                             self.world.chunk_count(), self.rebuilt, ctx.time.fps());
+        canvas.draw(&graphics::Text::new(status), DrawParam::new().dest(Vec2::new(8.0, 8.0)));
+
+        canvas.finish(ctx)
+    }
+
+    fn key_down_event(&mut self, ctx: &mut Context, input: KeyInput, _repeated: bool) -> GameResult {
+        let dir = match input.keycode {
+            Some(KeyCode::Up) | Some(KeyCode::K)    => Direction::N,
+            Some(KeyCode::Down) | Some(KeyCode::J)  => Direction::S,
+            Some(KeyCode::Left) | Some(KeyCode::H)  => Direction::W,
+            Some(KeyCode::Right) | Some(KeyCode::L) => Direction::E,
+            Some(KeyCode::Escape) => {
+                ctx.request_quit();
+                return Ok(());
+            },
+            _ => return Ok(()),
+        };
+        self.try_step(dir);
+        Ok(())
+    }
+
+    fn quit_event(&mut self, _ctx: &mut Context) -> GameResult<bool> {
+        self.world.save().expect("Failed to save world!");
+        Ok(false)
+    }
+}
+
+fn main() -> GameResult {
+    let (mut ctx, event_loop) = ggez::ContextBuilder::new("infinigen-tiles", "infinigen")
+        .window_setup(ggez::conf::WindowSetup::default().title("infinigen"))
+        .window_mode(ggez::conf::WindowMode::default().dimensions(SCREEN_WIDTH, SCREEN_HEIGHT))
+        .build()?;
+    let state = TileMap::new(&mut ctx)?;
+    event::run(ctx, event_loop, state)
+}
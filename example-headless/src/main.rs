@@ -0,0 +1,384 @@
+//! Walks the observer of the demo world along a scripted path without
+//! drawing anything, then reports how many chunks were streamed and how long
+//! it took. Useful for benchmarking and on systems without curses.
+//!
+//! Usage: infinigen-example-headless [--ticks N] [--walk SCRIPT] [--transient] [--reset N] [--map DIR]
+//!                                   [--serve ADDR] [--adaptive MS] [--dump-tables DIR] [--restore-table FILE]
+//!                                   [--anonymize DIR] [--flat] [--take-over]
+//!
+//! A script is a comma separated list of steps like `e*200,n*50`, each
+//! moving the observer one cell per tick in a direction for a number of
+//! ticks. The script repeats until the given number of ticks has passed.
+//!
+//! With `--reset N`, the chunks within N chunks of the origin are generated
+//! again the next time they are loaded.
+//!
+//! With `--map`, a PNG map of every saved region is written to the given
+//! directory afterwards.
+//!
+//! With `--serve`, chunks are served to `ChunkClient`s at the given address
+//! while walking, and the chunks they subscribe to are kept loaded.
+//!
+//! With `--adaptive MS`, chunks unloaded while walking are compressed at the
+//! level that packs them tightest while taking at most MS milliseconds per
+//! MiB, and the measurements of every level are reported.
+//!
+//! With `--dump-tables`, the lookup table and sidecar tables of every saved
+//! region are written to the given directory as JSON afterwards. With
+//! `--restore-table`, the tables in such a file are written back to their
+//! region before the world is opened.
+//!
+//! With `--flat`, chunks that weren't saved yet are generated from a fixed
+//! template of walls around plots instead of noise, which takes the cost of
+//! generation out of the timings.
+//!
+//! The save is locked while walking. With `--take-over`, it is opened even
+//! if another process may still hold the lock.
+//!
+//! With `--anonymize DIR`, a copy of the world with the names of every dude
+//! replaced is written to the given directory afterwards, as a game would
+//! for attaching a save to a bug report.
+
+extern crate infinigen;
+extern crate infinigen_example_common as common;
+
+use std::cell::Cell;
+use std::env;
+use std::fs;
+use std::path::Path;
+use std::process;
+use std::rc::Rc;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use infinigen::{AdaptiveCompression, CanonicalMap, ChunkMessage, ChunkServer, ChunkedTerrain, ChunkedWorld, FlatGenerator, FlatLayer,
+                FxHashSet, InterestShape, MapExporter, MetricsSink, Occupancy, RegionConfig, RegionLayout, RegionManager, RegionSidecar,
+                RegionTableSnapshot, Rgb, SerialResult, CHUNKS_GENERATED, CHUNKS_LOADED, CHUNKS_SAVED, INBOX_EXTENSION, OCCUPANCY_EXTENSION,
+                SAVE_TIMES_EXTENSION, TOMBSTONES_EXTENSION, VERSIONS_EXTENSION, VISITS_EXTENSION, anonymize_world, region_files};
+
+use common::cell::{self, CellKind};
+use common::color::Color;
+use common::chunk::{Chunk, ChunkIndex, SavedChunk, SerialChunk, CHUNK_WIDTH};
+use common::direction::Direction;
+use common::dude::Dude;
+use common::world::{World, WorldPosition};
+
+const DEFAULT_WALK: &'static str = "e*300,s*100,w*300,n*100";
+
+/// Counts the chunks the world streams.
+#[derive(Default)]
+struct Counters {
+    loaded: Cell<u64>,
+    generated: Cell<u64>,
+    saved: Cell<u64>,
+}
+
+struct CountingSink(Rc<Counters>);
+
+impl MetricsSink for CountingSink {
+    fn counter(&self, name: &'static str, value: u64) {
+        let counter = match name {
+            CHUNKS_LOADED    => &self.0.loaded,
+            CHUNKS_GENERATED => &self.0.generated,
+            CHUNKS_SAVED     => &self.0.saved,
+            _                => return,
+        };
+        counter.set(counter.get() + value);
+    }
+}
+
+fn parse_direction(name: &str) -> Option<Direction> {
+    match name {
+        "n"  => Some(Direction::N),
+        "ne" => Some(Direction::NE),
+        "e"  => Some(Direction::E),
+        "se" => Some(Direction::SE),
+        "s"  => Some(Direction::S),
+        "sw" => Some(Direction::SW),
+        "w"  => Some(Direction::W),
+        "nw" => Some(Direction::NW),
+        _    => None,
+    }
+}
+
+/// Parses a walk script into the direction taken on each tick of one pass.
+fn parse_walk(script: &str) -> Result<Vec<Direction>, String> {
+    let mut steps = Vec::new();
+    for step in script.split(',') {
+        let mut parts = step.trim().splitn(2, '*');
+        let name = parts.next().unwrap_or("");
+        let dir = parse_direction(name).ok_or_else(|| format!("unknown direction {:?}", name))?;
+        let count = match parts.next() {
+            Some(n) => n.parse::<usize>().map_err(|_| format!("bad step count in {:?}", step))?,
+            None    => 1,
+        };
+        for _ in 0..count {
+            steps.push(dir);
+        }
+    }
+    if steps.is_empty() {
+        return Err("the walk has no steps".to_string());
+    }
+    Ok(steps)
+}
+
+/// The color a saved cell is drawn with on the map.
+fn map_color(chunk: &SerialChunk, (x, y): (i32, i32)) -> Rgb {
+    match chunk.chunk.cell_at(x, y) {
+        (Some(CellKind::Wall), color) => color.rgb(),
+        (Some(CellKind::Floor), _)    => [50, 50, 50],
+        (Some(CellKind::Tree), _)     => [30, 110, 40],
+        _                             => [0, 0, 0],
+    }
+}
+
+/// Writes a snapshot of the tables of every saved region to `dir`. Returns
+/// the number of regions.
+fn dump_tables(layout: &RegionLayout, dir: &str) -> SerialResult<usize> {
+    fs::create_dir_all(dir)?;
+    let regions = region_files(layout)?;
+    for &(index, ref path) in regions.iter() {
+        let mut snapshot = RegionTableSnapshot::read(index, path, &RegionConfig::of::<SerialChunk>())?;
+        snapshot.add_sidecar(&mut RegionSidecar::<u64>::new(layout.clone(), SAVE_TIMES_EXTENSION))?;
+        snapshot.add_sidecar(&mut RegionSidecar::<u64>::new(layout.clone(), VISITS_EXTENSION))?;
+        snapshot.add_sidecar(&mut RegionSidecar::<u64>::new(layout.clone(), TOMBSTONES_EXTENSION))?;
+        snapshot.add_sidecar(&mut RegionSidecar::<u64>::new(layout.clone(), VERSIONS_EXTENSION))?;
+        snapshot.add_sidecar(&mut RegionSidecar::<Occupancy>::new(layout.clone(), OCCUPANCY_EXTENSION))?;
+        snapshot.add_sidecar(&mut RegionSidecar::<Vec<ChunkMessage>>::new(layout.clone(), INBOX_EXTENSION))?;
+        let name = path.file_name().unwrap().to_string_lossy();
+        snapshot.save(Path::new(dir).join(format!("{}.json", name)))?;
+    }
+    Ok(regions.len())
+}
+
+/// Writes the tables of a snapshot back to its region file and sidecars.
+fn restore_tables(layout: &RegionLayout, file: &str) -> SerialResult<RegionTableSnapshot> {
+    let snapshot = RegionTableSnapshot::load(file)?;
+    snapshot.restore(layout.region_path(&snapshot.region_index()))?;
+
+    let mut times = RegionSidecar::<u64>::new(layout.clone(), SAVE_TIMES_EXTENSION);
+    let mut visits = RegionSidecar::<u64>::new(layout.clone(), VISITS_EXTENSION);
+    let mut tombstones = RegionSidecar::<u64>::new(layout.clone(), TOMBSTONES_EXTENSION);
+    let mut versions = RegionSidecar::<u64>::new(layout.clone(), VERSIONS_EXTENSION);
+    let mut occupancy = RegionSidecar::<Occupancy>::new(layout.clone(), OCCUPANCY_EXTENSION);
+    let mut inbox = RegionSidecar::<Vec<ChunkMessage>>::new(layout.clone(), INBOX_EXTENSION);
+    for sidecar in [&mut times, &mut visits, &mut tombstones, &mut versions].iter_mut() {
+        snapshot.restore_sidecar(&mut **sidecar)?;
+        sidecar.flush()?;
+    }
+    snapshot.restore_sidecar(&mut occupancy)?;
+    occupancy.flush()?;
+    snapshot.restore_sidecar(&mut inbox)?;
+    inbox.flush()?;
+    Ok(snapshot)
+}
+
+/// Copies the world to `dir` with the names of the dudes replaced. Returns
+/// the number of chunks copied.
+fn anonymize(layout: &RegionLayout, dir: &str) -> SerialResult<usize> {
+    let (_, count) = anonymize_world::<SavedChunk, _, _>(layout, dir, |_, attachments| {
+        if let Some(mut dudes) = attachments.get::<CanonicalMap<WorldPosition, Dude>>("dudes")? {
+            for dude in dudes.values_mut() {
+                dude.name = "Dude".to_string();
+            }
+            attachments.insert("dudes", &dudes)?;
+        }
+        Ok(())
+    })?;
+    Ok(count)
+}
+
+/// A world of plots walled off every 64 cells, with a platform around the
+/// origin.
+fn flat_template() -> FlatGenerator<cell::Cell> {
+    let floor = cell::Cell::new(CellKind::Floor, Color::new(90, 110, 70));
+    FlatGenerator::new(floor)
+        .with_layer(FlatLayer::Grid { spacing: 64, cell: cell::Cell::new(CellKind::Wall, Color::WHITE) })
+        .with_layer(FlatLayer::Rect { x: -4, y: -4, width: 9, height: 9, cell: cell::Cell::new(CellKind::Floor, Color::YELLOW) })
+}
+
+struct Options {
+    ticks: usize,
+    walk: Vec<Direction>,
+    transient: bool,
+    map: Option<String>,
+    reset: Option<i32>,
+    serve: Option<String>,
+    adaptive: Option<u64>,
+    dump_tables: Option<String>,
+    restore_table: Option<String>,
+    anonymize: Option<String>,
+    flat: bool,
+    take_over: bool,
+}
+
+fn parse_args() -> Result<Options, String> {
+    let mut options = Options {
+        ticks: 1000,
+        walk: parse_walk(DEFAULT_WALK)?,
+        transient: false,
+        map: None,
+        reset: None,
+        serve: None,
+        adaptive: None,
+        dump_tables: None,
+        restore_table: None,
+        anonymize: None,
+        flat: false,
+        take_over: false,
+    };
+
+    let mut args = env::args().skip(1);
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--ticks"     => {
+                let n = args.next().ok_or("--ticks needs a value")?;
+                options.ticks = n.parse().map_err(|_| format!("bad tick count {:?}", n))?;
+            },
+            "--walk"      => {
+                let script = args.next().ok_or("--walk needs a value")?;
+                options.walk = parse_walk(&script)?;
+            },
+            "--transient" => options.transient = true,
+            "--map"       => options.map = Some(args.next().ok_or("--map needs a directory")?),
+            "--serve"     => options.serve = Some(args.next().ok_or("--serve needs an address")?),
+            "--reset"     => {
+                let n = args.next().ok_or("--reset needs a radius")?;
+                options.reset = Some(n.parse().map_err(|_| format!("bad reset radius {:?}", n))?);
+            },
+            "--adaptive"  => {
+                let n = args.next().ok_or("--adaptive needs a budget")?;
+                options.adaptive = Some(n.parse().map_err(|_| format!("bad compression budget {:?}", n))?);
+            },
+            "--dump-tables"   => options.dump_tables = Some(args.next().ok_or("--dump-tables needs a directory")?),
+            "--restore-table" => options.restore_table = Some(args.next().ok_or("--restore-table needs a file")?),
+            "--flat"          => options.flat = true,
+            "--take-over"     => options.take_over = true,
+            "--anonymize"     => options.anonymize = Some(args.next().ok_or("--anonymize needs a directory")?),
+            _             => return Err(format!("unknown argument {:?}", arg)),
+        }
+    }
+    Ok(options)
+}
+
+fn millis(duration: Duration) -> f64 {
+    duration.as_secs() as f64 * 1000.0 + duration.subsec_nanos() as f64 * 1e-6
+}
+
+fn main() {
+    let options = match parse_args() {
+        Ok(o) => o,
+        Err(e) => {
+            eprintln!("{}", e);
+            process::exit(2);
+        },
+    };
+
+    let counters = Rc::new(Counters::default());
+    let mut world = if options.transient {
+        World::new_transient()
+    } else if options.take_over {
+        World::new_taking_over()
+    } else {
+        World::new_empty()
+    };
+    if let Some(ref file) = options.restore_table {
+        let layout = world.regions_mut().layout().clone();
+        let snapshot = restore_tables(&layout, file).unwrap();
+        println!("restored table:   {} chunks of region {}", snapshot.entries.len(), snapshot.region_index());
+    }
+    if options.flat {
+        world.set_flat_generator(flat_template()).unwrap();
+    }
+    world.set_metrics(Box::new(CountingSink(counters.clone())));
+    if let Some(budget) = options.adaptive {
+        world.set_adaptive_compression(AdaptiveCompression::new(Duration::from_millis(budget)));
+    }
+    world.replay_journal().unwrap();
+    if let Some(recovery) = world.recover_crash_save().unwrap() {
+        println!("recovered {} chunks saved while crashing", recovery.chunks);
+    }
+
+    if let Some(radius) = options.reset {
+        let area = InterestShape::Square(radius).indices(&ChunkIndex::new(0, 0));
+        world.schedule_reset(&area).unwrap();
+        println!("reset scheduled:  {} chunks", area.len());
+    }
+
+    let mut server: Option<ChunkServer<ChunkIndex, Chunk>> = None;
+    let watched = Arc::new(Mutex::new(FxHashSet::default()));
+    if let Some(ref addr) = options.serve {
+        let s = ChunkServer::bind(addr.as_str()).unwrap();
+        println!("serving chunks:   {}", s.local_addr().unwrap());
+        server = Some(s);
+        let provider = watched.clone();
+        world.relevance_providers.register("clients", move || provider.lock().unwrap().clone());
+    }
+
+    let start = Instant::now();
+    let mut slowest = Duration::from_secs(0);
+    for tick in 0..options.ticks {
+        let dir = options.walk[tick % options.walk.len()];
+        world.observer = world.observer + dir;
+
+        let update = Instant::now();
+        world.update_chunks().unwrap();
+        slowest = slowest.max(update.elapsed());
+
+        world.step_dudes();
+        world.advance_tick();
+
+        if let Some(ref mut server) = server {
+            server.poll(|index: &ChunkIndex| world.chunk(*index)).unwrap();
+            *watched.lock().unwrap() = server.subscribed_chunks();
+        }
+    }
+    let (load_mean, generate_mean) = match world.load_timings() {
+        Some(t) => (t.loaded().mean(), t.generated().mean()),
+        None    => (Duration::from_secs(0), Duration::from_secs(0)),
+    };
+    let save = Instant::now();
+    world.save_parallel().unwrap();
+    let save = save.elapsed();
+    let elapsed = start.elapsed();
+
+    println!("ticks:            {}", options.ticks);
+    println!("final position:   {}", world.observer);
+    println!("chunks loaded:    {}", counters.loaded.get());
+    println!("chunks generated: {}", counters.generated.get());
+    println!("chunks saved:     {}", counters.saved.get());
+    println!("total time:       {:.1} ms", millis(elapsed));
+    println!("mean tick:        {:.3} ms", millis(elapsed) / options.ticks.max(1) as f64);
+    println!("slowest update:   {:.3} ms", millis(slowest));
+    println!("mean chunk load:  {:.3} ms", millis(load_mean));
+    println!("mean generation:  {:.3} ms", millis(generate_mean));
+    println!("final save:       {:.1} ms", millis(save));
+    println!("buffer reuse:     {:.1}%", world.buffer_pool().stats().reuse_rate() * 100.0);
+
+    if let Some(adaptive) = world.adaptive_compression() {
+        for (level, stats) in adaptive.stats().into_iter().filter(|&(_, s)| s.samples > 0) {
+            println!("{:<18}{} chunks, ratio {:.3}, {:.2} ms/MiB",
+                     format!("{:?}:", level).to_lowercase(), stats.samples, stats.ratio(), stats.seconds_per_mib() * 1000.0);
+        }
+    }
+
+    if let Some(dir) = options.map {
+        let layout = world.regions_mut().layout().clone();
+        let tiles = MapExporter::new(CHUNK_WIDTH)
+            .export_tiles::<SerialChunk, _, _>(&layout, &dir, map_color)
+            .unwrap();
+        println!("map tiles:        {} in {}", tiles.len(), dir);
+    }
+
+    if let Some(dir) = options.dump_tables {
+        let layout = world.regions_mut().layout().clone();
+        let regions = dump_tables(&layout, &dir).unwrap();
+        println!("region tables:    {} in {}", regions, dir);
+    }
+
+    if let Some(dir) = options.anonymize {
+        let layout = world.regions_mut().layout().clone();
+        let chunks = anonymize(&layout, &dir).unwrap();
+        println!("anonymized copy:  {} chunks in {}", chunks, dir);
+    }
+}
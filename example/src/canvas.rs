@@ -1,39 +1,14 @@
+use infinigen::{ChunkedWorld, SaveProgress};
 use pancurses;
 use pancurses::*;
-use rand;
 
-use cell::Cell;
-use world::World;
-use point::Point;
+use common::color::Color;
+use common::point::Point;
+use common::world::World;
 
-#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
-pub enum Color {
-    Red,
-    Blue,
-    Green,
-    Cyan,
-    Magenta,
-    Yellow,
-    White
-}
-
-impl Color {
-    pub fn rand() -> Color {
-        let len = 7;    //ROYGBIV
-        //use Color::*;
-        match rand::random::<u8>() % len {
-            0 => Color::Red,
-            1 => Color::Blue,
-            2 => Color::Green,
-            3 => Color::Cyan,
-            4 => Color::Magenta,
-            5 => Color::Yellow,
-            _ => Color::White,
-        }
-    }
-    pub fn to_pancurses(&self) -> ColorPair {
-        pancurses::ColorPair((*self as u8))
-    }
+/// Curses only gets the basic terminal colors, so cells use the nearest one.
+fn color_pair(color: Color) -> ColorPair {
+    pancurses::ColorPair(color.nearest_named() as u8)
 }
 
 const COLOR_TABLE: [i16; 8] = [COLOR_RED,
@@ -65,9 +40,10 @@ pub fn show_splash() {
         init();
         w.erase();
         w.mvaddstr(0, 0, "move: hjkltybn");
-        w.mvaddstr(1, 0, "quit: q");
-        w.mvaddstr(2, 0, "run into walls to destroy them.");
-        w.mvaddstr(3, 0, "autosave on quit.");
+        w.mvaddstr(1, 0, "save: s");
+        w.mvaddstr(2, 0, "quit: q");
+        w.mvaddstr(3, 0, "run into walls to destroy them.");
+        w.mvaddstr(4, 0, "autosave on quit.");
         w.refresh();
         w.getch();
     })
@@ -79,24 +55,43 @@ pub fn print(world: &mut World) {
 
         let size = Point::new(w.get_max_x(), w.get_max_y());
         let center = world.observer - size/2;
+        world.set_viewport(size.x, size.y);
 
-        world.with_cells(center, size, |p: Point, c: &Cell| {
-            w.attrset(c.color.to_pancurses());
-            w.mvaddch(p.y - center.y, p.x - center.x, c.to_char());
-        } );
+        let view = world.view(size);
+        for (y, row) in view.rows().enumerate() {
+            for (x, c) in row.iter().enumerate() {
+                w.attrset(color_pair(c.color));
+                w.mvaddch(y as i32, x as i32, c.to_char());
+            }
+        }
 
         for dude in world.dudes() {
             let pos = dude.pos() - center;
-            w.attrset(dude.color.to_pancurses());
+            w.attrset(color_pair(dude.color));
             w.mvaddch(pos.y, pos.x, dude.appearance);
         }
-        w.attrset(Color::White.to_pancurses());
+        w.attrset(color_pair(Color::WHITE));
         w.mvaddch(size.y/2, size.x/2, '@');
 
         w.refresh()
     });
 }
 
+/// Shows how far saving on quit has come in the bottom line.
+pub fn print_save_progress(progress: &SaveProgress) {
+    instance::with_mut(|w| {
+        let line = format!("saving... {}%, {} chunks left, {} KiB written",
+                           (progress.fraction() * 100.0) as u32,
+                           progress.remaining,
+                           progress.bytes_written / 1024);
+        w.attrset(color_pair(Color::WHITE));
+        w.mv(w.get_max_y() - 1, 0);
+        w.clrtoeol();
+        w.mvaddstr(w.get_max_y() - 1, 0, &line);
+        w.refresh();
+    })
+}
+
 pub fn endwin() {
     pancurses::endwin();
 }
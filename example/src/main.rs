@@ -1,26 +1,21 @@
 #![feature(associated_consts)]
 extern crate infinigen;
-extern crate noise;
+extern crate infinigen_example_common as common;
 extern crate pancurses;
-extern crate rand;
-extern crate serde;
-#[macro_use] extern crate serde_derive;
 
 #[macro_use] mod macros;
 mod canvas;
-mod cell;
-mod chunk;
-mod direction;
-mod dude;
-mod point;
-mod world;
 
-use infinigen::ChunkedWorld;
+use std::time::Duration;
+
+use infinigen::{ArchivePolicy, ChunkedTerrain, ChunkedWorld, RegionManager};
 use pancurses::Input;
 
-use cell::CellKind;
-use direction::Direction;
-use world::World;
+use common::cell::CellKind;
+use common::direction::Direction;
+use common::world::World;
+
+const SHUTDOWN_SAVE_LIMIT: Duration = Duration::from_secs(3);
 
 fn main() {
     go();
@@ -28,34 +23,64 @@ fn main() {
 }
 
 fn go() {
-    let mut world = World::new_empty();
+    let transient = ::std::env::args().any(|arg| arg == "--transient");
+    let mut world = if transient {
+        World::new_transient()
+    } else {
+        World::new_empty()
+    };
+    world.replay_journal().unwrap();
+    // Chunks saved while the last session was crashing.
+    world.recover_crash_save().unwrap();
+    if ::std::env::args().any(|arg| arg == "--record") {
+        world.record_residency_to("residency.log").unwrap();
+    }
+    if !transient {
+        // Regions nobody visited for a month are moved into the cold archive.
+        let policy = ArchivePolicy::idle_for(Duration::from_secs(30 * 24 * 60 * 60));
+        world.terrain_mut().regions_mut().archive_idle_regions(&policy).unwrap();
+    }
 
     canvas::show_splash();
 
-    loop {
-        world.update_chunks().unwrap();
-        canvas::print(&mut world);
+    // If anything panics, the changed chunks are saved to a crash journal
+    // first, so the session isn't lost.
+    world.run_with_crash_save(|world| {
+        loop {
+            world.update_chunks().unwrap();
+            canvas::print(world);
 
-        let event = canvas::get_event().unwrap();
-        match event {
-            Input::Character('q') => { world.save().unwrap(); return; },
-            Input::KeyUp |
-            Input::Character('k') => { try_step(&mut world, Direction::N) },
-            Input::KeyDown |
-            Input::Character('j') => { try_step(&mut world, Direction::S) },
-            Input::KeyLeft |
-            Input::Character('h') => { try_step(&mut world, Direction::W) },
-            Input::KeyRight |
-            Input::Character('l') => { try_step(&mut world, Direction::E) },
-            Input::Character('t') => { try_step(&mut world, Direction::NW) },
-            Input::Character('y') => { try_step(&mut world, Direction::NE) },
-            Input::Character('b') => { try_step(&mut world, Direction::SW) },
-            Input::Character('n') => { try_step(&mut world, Direction::SE) },
-            _                     => (),
-        }
+            let event = canvas::get_event().unwrap();
+            match event {
+                Input::Character('q') => {
+                    // Past the limit the rest of the chunks are written
+                    // uncompressed, so quitting never hangs for long.
+                    world.save_for_shutdown(SHUTDOWN_SAVE_LIMIT, |p| canvas::print_save_progress(p)).unwrap();
+                    if let Some(log) = world.residency_log() {
+                        log.flush().unwrap();
+                    }
+                    return;
+                },
+                Input::Character('s') => world.save_in_place().unwrap(),
+                Input::KeyUp |
+                Input::Character('k') => { try_step(world, Direction::N) },
+                Input::KeyDown |
+                Input::Character('j') => { try_step(world, Direction::S) },
+                Input::KeyLeft |
+                Input::Character('h') => { try_step(world, Direction::W) },
+                Input::KeyRight |
+                Input::Character('l') => { try_step(world, Direction::E) },
+                Input::Character('t') => { try_step(world, Direction::NW) },
+                Input::Character('y') => { try_step(world, Direction::NE) },
+                Input::Character('b') => { try_step(world, Direction::SW) },
+                Input::Character('n') => { try_step(world, Direction::SE) },
+                _                     => (),
+            }
 
-        world.step_dudes();
-    }
+            world.step_dudes();
+            world.advance_tick();
+        }
+    })
 }
 
 fn try_step(world: &mut World, dir: Direction) {
@@ -0,0 +1,61 @@
+//! Compares the standard library's SipHash with `FxHasher` on the work
+//! `update_chunks` does every tick: building the relevant set around a
+//! moving observer and looking up every loaded chunk in it.
+//!
+//! Run with `cargo bench -p infinigen --bench hashing`.
+
+extern crate infinigen;
+
+use std::collections::HashSet;
+use std::hash::BuildHasher;
+use std::time::{Duration, Instant};
+
+use infinigen::{FxBuildHasher, Index, InterestShape};
+
+#[derive(Copy, Clone, Debug, Hash, Eq, PartialEq)]
+struct ChunkIndex(i32, i32);
+
+impl Index for ChunkIndex {
+    fn x(&self) -> i32 { self.0 }
+    fn y(&self) -> i32 { self.1 }
+    fn from_xy(x: i32, y: i32) -> Self { ChunkIndex(x, y) }
+}
+
+const TICKS: i32 = 2000;
+
+/// Walks the observer east one chunk per tick, keeping a set of loaded
+/// chunks up to date like `update_chunks` does.
+fn walk<S: BuildHasher + Default>(shape: InterestShape) -> (Duration, usize) {
+    let start = Instant::now();
+    let mut loaded: HashSet<ChunkIndex, S> = HashSet::default();
+    let mut unloads = 0;
+    for tick in 0..TICKS {
+        let mut relevant: HashSet<ChunkIndex, S> = HashSet::default();
+        relevant.extend(shape.indices(&ChunkIndex(tick, 0)));
+
+        for index in relevant.iter() {
+            if !loaded.contains(index) {
+                loaded.insert(*index);
+            }
+        }
+        let stale: Vec<ChunkIndex> = loaded.iter().filter(|i| !relevant.contains(i)).cloned().collect();
+        unloads += stale.len();
+        for index in stale.iter() {
+            loaded.remove(index);
+        }
+    }
+    (start.elapsed(), unloads)
+}
+
+fn main() {
+    for &radius in [8, 16, 32].iter() {
+        let shape = InterestShape::Diamond(radius);
+        let (sip, sip_unloads) = walk::<std::collections::hash_map::RandomState>(shape);
+        let (fx, fx_unloads) = walk::<FxBuildHasher>(shape);
+        assert_eq!(sip_unloads, fx_unloads);
+
+        let per_tick = |d: Duration| d.as_secs_f64() * 1e6 / TICKS as f64;
+        println!("radius {:>2}: siphash {:>8.1} us/tick, fxhash {:>8.1} us/tick, {:.2}x",
+                 radius, per_tick(sip), per_tick(fx), sip.as_secs_f64() / fx.as_secs_f64());
+    }
+}
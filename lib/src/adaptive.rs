@@ -0,0 +1,224 @@
+use std::collections::HashMap;
+use std::time::Duration;
+
+use format::CompressionLevel;
+use metrics;
+use region::RegionIndex;
+
+/// The levels `AdaptiveCompression` picks from, fastest first.
+const LEVELS: [CompressionLevel; 4] = [CompressionLevel::Stored, CompressionLevel::Fast,
+                                       CompressionLevel::Default, CompressionLevel::Best];
+
+/// Older samples are halved once a level has this many, so the measurements
+/// follow chunks that change over time.
+const MAX_SAMPLES: u32 = 64;
+
+/// What compressing one chunk cost.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct CompressionSample {
+    pub level: CompressionLevel,
+    /// Size of the serialized chunk.
+    pub raw_bytes: usize,
+    /// Size of the compressed chunk, without sector padding.
+    pub compressed_bytes: usize,
+    /// Time spent compressing.
+    pub elapsed: Duration,
+}
+
+/// The measurements of one compression level.
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub struct LevelStats {
+    pub samples: u32,
+    pub raw_bytes: u64,
+    pub compressed_bytes: u64,
+    pub seconds: f64,
+}
+
+impl LevelStats {
+    /// Compressed size over raw size. Lower is better.
+    pub fn ratio(&self) -> f64 {
+        if self.raw_bytes == 0 {
+            return 1.0;
+        }
+        self.compressed_bytes as f64 / self.raw_bytes as f64
+    }
+
+    /// Seconds spent compressing a MiB of serialized chunks.
+    pub fn seconds_per_mib(&self) -> f64 {
+        if self.raw_bytes == 0 {
+            return 0.0;
+        }
+        self.seconds * (1 << 20) as f64 / self.raw_bytes as f64
+    }
+
+    fn add(&mut self, sample: &CompressionSample) {
+        if self.samples >= MAX_SAMPLES {
+            self.samples /= 2;
+            self.raw_bytes /= 2;
+            self.compressed_bytes /= 2;
+            self.seconds /= 2.0;
+        }
+        self.samples += 1;
+        self.raw_bytes += sample.raw_bytes as u64;
+        self.compressed_bytes += sample.compressed_bytes as u64;
+        self.seconds += metrics::seconds(sample.elapsed);
+    }
+
+    fn merge(&mut self, other: &LevelStats) {
+        self.samples += other.samples;
+        self.raw_bytes += other.raw_bytes;
+        self.compressed_bytes += other.compressed_bytes;
+        self.seconds += other.seconds;
+    }
+}
+
+#[derive(Debug, Clone, Default)]
+struct RegionStats {
+    levels: [LevelStats; 4],
+    saves: u32,
+}
+
+fn slot(level: CompressionLevel) -> usize {
+    LEVELS.iter().position(|&l| l == level).unwrap()
+}
+
+/// Picks the compression level of every chunk a world saves from how well
+/// and how fast each level compressed the chunks of the same region so far,
+/// so regions that barely compress, like noise, are saved quickly while
+/// regions of open water are packed tightly.
+///
+/// Each region uses the level with the smallest output among those that
+/// compress a MiB within the time budget, or the fastest one if none does.
+/// Levels a region hasn't tried yet are tried first, and every
+/// `explore_every` saves a region tries the next level in turn, so the
+/// choice keeps up with changing chunks. The level a chunk was compressed
+/// with is recorded in front of its data as usual, so reading needs nothing
+/// from here and the measurements don't have to be saved.
+///
+/// Used by `ChunkedWorld::unload_chunk` and `save_chunk_in_place` when the
+/// world returns one from `ChunkedWorld::adaptive_compression_mut`. Chunks
+/// smaller than `ManagedChunk::COMPRESSION_THRESHOLD` are still stored
+/// uncompressed.
+#[derive(Debug, Clone)]
+pub struct AdaptiveCompression {
+    budget: f64,
+    explore_every: u32,
+    regions: HashMap<RegionIndex, RegionStats>,
+}
+
+impl AdaptiveCompression {
+    /// Creates a chooser that lets compressing a MiB of serialized chunks
+    /// take up to `budget`.
+    pub fn new(budget: Duration) -> Self {
+        AdaptiveCompression {
+            budget: metrics::seconds(budget),
+            explore_every: 32,
+            regions: HashMap::new(),
+        }
+    }
+
+    /// Sets how many saves of a region pass between trying other levels.
+    pub fn with_explore_every(mut self, saves: u32) -> Self {
+        assert!(saves > 0, "Exploration interval can't be zero!");
+        self.explore_every = saves;
+        self
+    }
+
+    /// Returns the level the next chunk saved in a region should use.
+    pub fn level_for(&mut self, region: &RegionIndex) -> CompressionLevel {
+        let budget = self.budget;
+        let explore_every = self.explore_every;
+        let stats = self.regions.entry(region.clone()).or_insert_with(RegionStats::default);
+        stats.saves = stats.saves.wrapping_add(1);
+
+        if let Some(&untried) = LEVELS[1..].iter().find(|&&l| stats.levels[slot(l)].samples == 0) {
+            return untried;
+        }
+        if stats.saves % explore_every == 0 {
+            return LEVELS[1 + (stats.saves / explore_every) as usize % (LEVELS.len() - 1)];
+        }
+        best_level(&stats.levels, budget)
+    }
+
+    /// Records what compressing a chunk of a region cost.
+    pub fn record(&mut self, region: &RegionIndex, sample: &CompressionSample) {
+        let stats = self.regions.entry(region.clone()).or_insert_with(RegionStats::default);
+        stats.levels[slot(sample.level)].add(sample);
+    }
+
+    /// Returns the level a region currently prefers, if it saved anything.
+    pub fn region_level(&self, region: &RegionIndex) -> Option<CompressionLevel> {
+        self.regions.get(region).map(|stats| best_level(&stats.levels, self.budget))
+    }
+
+    /// Returns the measurements of every level in a region.
+    pub fn region_stats(&self, region: &RegionIndex) -> Vec<(CompressionLevel, LevelStats)> {
+        match self.regions.get(region) {
+            Some(stats) => LEVELS.iter().cloned().zip(stats.levels.iter().cloned()).collect(),
+            None        => Vec::new(),
+        }
+    }
+
+    /// Returns the measurements of every level, summed over all regions.
+    pub fn stats(&self) -> Vec<(CompressionLevel, LevelStats)> {
+        let mut totals = [LevelStats::default(); 4];
+        for stats in self.regions.values() {
+            for (total, level) in totals.iter_mut().zip(stats.levels.iter()) {
+                total.merge(level);
+            }
+        }
+        LEVELS.iter().cloned().zip(totals.iter().cloned()).collect()
+    }
+}
+
+fn best_level(levels: &[LevelStats; 4], budget: f64) -> CompressionLevel {
+    let mut best = CompressionLevel::Stored;
+    let mut best_ratio = 1.0;
+    for &level in LEVELS[1..].iter() {
+        let stats = &levels[slot(level)];
+        if stats.samples > 0 && stats.seconds_per_mib() <= budget && stats.ratio() < best_ratio {
+            best = level;
+            best_ratio = stats.ratio();
+        }
+    }
+    best
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sample(level: CompressionLevel, compressed: usize, millis: u64) -> CompressionSample {
+        CompressionSample {
+            level: level,
+            raw_bytes: 1 << 20,
+            compressed_bytes: compressed,
+            elapsed: Duration::from_millis(millis),
+        }
+    }
+
+    #[test]
+    fn test_adaptive_choice() {
+        let mut adaptive = AdaptiveCompression::new(Duration::from_millis(20)).with_explore_every(1000);
+        let region = RegionIndex(0, 0);
+
+        // Every level is tried once before choosing.
+        for &(level, compressed, millis) in [(CompressionLevel::Fast, 500_000, 5),
+                                             (CompressionLevel::Default, 300_000, 15),
+                                             (CompressionLevel::Best, 250_000, 50)].iter() {
+            assert_eq!(adaptive.level_for(&region), level);
+            adaptive.record(&region, &sample(level, compressed, millis));
+        }
+
+        // Best is too slow for the budget, so the smaller output of the
+        // rest wins.
+        assert_eq!(adaptive.level_for(&region), CompressionLevel::Default);
+        assert_eq!(adaptive.region_level(&region), Some(CompressionLevel::Default));
+        assert_eq!(adaptive.region_level(&RegionIndex(1, 0)), None);
+
+        let stats = adaptive.stats();
+        assert_eq!(stats[1].0, CompressionLevel::Fast);
+        assert_eq!(stats[1].1.samples, 1);
+        assert!((stats[2].1.ratio() - 300_000.0 / (1 << 20) as f64).abs() < 1e-9);
+    }
+}
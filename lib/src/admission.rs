@@ -0,0 +1,90 @@
+use std::sync::Arc;
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+/// Counts the reads and writes of a region backend that are in progress,
+/// including those running on other threads like background loads. Clones
+/// share the same count.
+#[derive(Debug, Clone, Default)]
+pub struct IoTracker {
+    in_flight: Arc<AtomicUsize>,
+}
+
+impl IoTracker {
+    pub fn new() -> Self {
+        IoTracker::default()
+    }
+
+    /// Returns the number of operations in progress.
+    pub fn in_flight(&self) -> usize {
+        self.in_flight.load(Ordering::SeqCst)
+    }
+
+    /// Counts an operation as in progress until the returned guard is
+    /// dropped.
+    pub fn begin(&self) -> IoGuard {
+        self.in_flight.fetch_add(1, Ordering::SeqCst);
+        IoGuard {
+            in_flight: self.in_flight.clone(),
+        }
+    }
+}
+
+/// An operation counted by an `IoTracker`.
+#[derive(Debug)]
+pub struct IoGuard {
+    in_flight: Arc<AtomicUsize>,
+}
+
+impl Drop for IoGuard {
+    fn drop(&mut self) {
+        self.in_flight.fetch_sub(1, Ordering::SeqCst);
+    }
+}
+
+/// Decides which chunk loads go ahead while the disk is busy, such as during
+/// an autosave. Loads of chunks close to an observer are urgent and always
+/// go ahead; the rest are deferred to a later update while too many
+/// operations are in flight.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct AdmissionPolicy {
+    /// Non-urgent loads are deferred while at least this many operations are
+    /// in flight.
+    pub max_in_flight: usize,
+    /// Loads of chunks at most this many chunks away from an observer are
+    /// urgent.
+    pub urgent_distance: i32,
+}
+
+impl AdmissionPolicy {
+    pub fn new(max_in_flight: usize, urgent_distance: i32) -> Self {
+        AdmissionPolicy {
+            max_in_flight: max_in_flight,
+            urgent_distance: urgent_distance,
+        }
+    }
+
+    /// Returns whether a load of a chunk `distance` chunks away from the
+    /// nearest observer may start.
+    pub fn admits(&self, in_flight: usize, distance: i32) -> bool {
+        distance <= self.urgent_distance || in_flight < self.max_in_flight
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_admission() {
+        let tracker = IoTracker::new();
+        let policy = AdmissionPolicy::new(2, 1);
+        let guards: Vec<_> = (0..2).map(|_| tracker.clone().begin()).collect();
+        assert_eq!(tracker.in_flight(), 2);
+        assert!(policy.admits(tracker.in_flight(), 1));
+        assert!(!policy.admits(tracker.in_flight(), 4));
+
+        drop(guards);
+        assert_eq!(tracker.in_flight(), 0);
+        assert!(policy.admits(tracker.in_flight(), 4));
+    }
+}
@@ -0,0 +1,191 @@
+use std::fs::{self, OpenOptions};
+use std::io;
+use std::path::Path;
+
+use bincode;
+
+use archive::ColdArchive;
+use attachment::{AttachedChunk, Attachments};
+use error::{ErrorContext, SerialResult, WithContext};
+use format::{self, RegionConfig};
+use layout::RegionLayout;
+use managed_region::ManagedRegion;
+use inbox::INBOX_EXTENSION;
+use migrate::region_files;
+use region::{Region, RegionIndex, RegionLocalIndex};
+use sidecar::{sidecar_files, SIDECAR_EXTENSIONS};
+use traits::ManagedChunk;
+
+/// Copies a world that isn't running into a new root directory, passing the
+/// attachments of every chunk to `strip` on the way, so a player can share
+/// the copy in a bug report without the names, chat logs or other private
+/// data the game saves with its chunks. `strip` is given the position of the
+/// chunk and removes or replaces whatever it shouldn't contain. Returns the
+/// layout of the copy and the number of chunks copied.
+///
+/// The chunks are written to new region files, so nothing removed from them
+/// is left behind in unused sectors. Archived regions are copied as plain
+/// region files. The world metadata and the sidecar files of the regions are
+/// copied as they are, while the game store, the spill file and the message
+/// inboxes of chunks are left out. Games keeping private data in the world
+/// metadata have to remove it from the copy themselves.
+///
+/// Fails if a world already exists at the destination.
+pub fn anonymize_world<C, P, F>(layout: &RegionLayout, dest: P, mut strip: F) -> SerialResult<(RegionLayout, usize)>
+    where C: ManagedChunk,
+          P: AsRef<Path>,
+          F: FnMut((i32, i32), &mut Attachments) -> SerialResult<()>
+{
+    let copy = layout.with_root(dest);
+    if copy.metadata_path().exists() || !region_files(&copy)?.is_empty() {
+        let e = io::Error::new(io::ErrorKind::AlreadyExists, "a world already exists at the destination");
+        return Err(e).with_context(|| ErrorContext::new("anonymize world").with_path(copy.root()));
+    }
+
+    fs::create_dir_all(copy.directory())?;
+    let mut count = 0;
+    for (index, path) in region_files(layout)?.into_iter() {
+        count += strip_region::<C, _, _, _>(&path, &copy.region_path(&index), &index, &mut strip)?;
+    }
+    let extensions: Vec<_> = SIDECAR_EXTENSIONS.iter().cloned().filter(|&e| e != INBOX_EXTENSION).collect();
+    for (index, extension, path) in sidecar_files(layout, &extensions)?.into_iter() {
+        fs::copy(&path, copy.sidecar_path(&index, extension))
+            .with_context(|| ErrorContext::new("anonymize world").with_region(index.clone()).with_path(&path))?;
+    }
+
+    // Archived regions are restored from a scratch copy of the archive, which
+    // leaves the original untouched.
+    if layout.archive_path().exists() {
+        let scratch = copy.archive_path().with_extension("scratch");
+        fs::copy(layout.archive_path(), &scratch)?;
+        let mut archive = ColdArchive::open(&scratch)?;
+        for index in archive.region_indices() {
+            let dest = copy.region_path(&index);
+            if dest.exists() {
+                continue;
+            }
+            let restored = dest.with_extension("restored");
+            archive.restore(&index, &restored)?;
+            count += strip_region::<C, _, _, _>(&restored, &dest, &index, &mut strip)?;
+            fs::remove_file(&restored)?;
+        }
+        fs::remove_file(&scratch)?;
+    }
+
+    if layout.metadata_path().exists() {
+        fs::copy(layout.metadata_path(), copy.metadata_path())
+            .with_context(|| ErrorContext::new("anonymize world").with_path(layout.metadata_path()))?;
+    }
+    Ok((copy, count))
+}
+
+/// Writes every chunk of the region file at `from` into a new region file at
+/// `to`, passing its attachments to `strip` first.
+fn strip_region<C, P, Q, F>(from: P, to: Q, index: &RegionIndex, strip: &mut F) -> SerialResult<usize>
+    where C: ManagedChunk,
+          P: AsRef<Path>,
+          Q: AsRef<Path>,
+          F: FnMut((i32, i32), &mut Attachments) -> SerialResult<()>
+{
+    let from = from.as_ref();
+    let context = || ErrorContext::new("anonymize region").with_region(index.clone()).with_path(from);
+    let config = RegionConfig::of::<AttachedChunk<C>>();
+
+    let mut file = OpenOptions::new().read(true).open(from).with_context(&context)?;
+    format::check_header(&mut file, &config).with_context(&context)?;
+    let mut region: Region<RegionLocalIndex> = Region::new(file);
+
+    let mut out = OpenOptions::new().read(true).write(true).create_new(true).open(to.as_ref())
+        .with_context(|| ErrorContext::new("anonymize region").with_region(index.clone()).with_path(to.as_ref()))?;
+    format::write_header(&mut out, &config)?;
+    let mut stripped: Region<RegionLocalIndex> = Region::new(out);
+
+    let mut count = 0;
+    for ly in 0..C::REGION_WIDTH {
+        for lx in 0..C::REGION_WIDTH {
+            let local = RegionLocalIndex(lx, ly);
+            let chunk_context = || context().with_chunk(local.clone());
+            let read = <Region<RegionLocalIndex> as ManagedRegion<RegionLocalIndex, AttachedChunk<C>>>::read_chunk_data(&mut region, &local);
+            let (meta, data) = match read {
+                Ok(read) => read,
+                Err(ref e) if e.is_missing_chunk() => continue,
+                Err(e) => return Err(e.context(chunk_context())),
+            };
+
+            let mut chunk: AttachedChunk<C> = bincode::deserialize(&data).with_context(&chunk_context)?;
+            let pos = config.chunk_position(index, &local).with_context(&chunk_context)?;
+            strip(pos, &mut chunk.attachments).with_context(&chunk_context)?;
+
+            let (compressed, _) = <Region<RegionLocalIndex> as ManagedRegion<RegionLocalIndex, AttachedChunk<C>>>::encode_chunk(&chunk, &meta)
+                .with_context(&chunk_context)?;
+            <Region<RegionLocalIndex> as ManagedRegion<RegionLocalIndex, AttachedChunk<C>>>::write_encoded(&mut stripped, compressed, &local)
+                .with_context(&chunk_context)?;
+            count += 1;
+        }
+    }
+    <Region<RegionLocalIndex> as ManagedRegion<RegionLocalIndex, AttachedChunk<C>>>::handle(&mut stripped).sync_all()?;
+    Ok(count)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::env;
+    use std::fs::File;
+    use std::io::Read;
+    use metadata::ChunkMeta;
+    use prune::visit_layout;
+
+    #[derive(Debug, PartialEq, Serialize, Deserialize)]
+    struct TestChunk(u8);
+
+    impl ManagedChunk for TestChunk {
+        const SECTOR_SIZE: usize = 64;
+        const REGION_WIDTH: i32 = 4;
+    }
+
+    type Saved = AttachedChunk<TestChunk>;
+
+    #[test]
+    fn test_anonymize_world() {
+        let root = env::temp_dir().join("infinigen-test-anonymize");
+        let _ = fs::remove_dir_all(&root);
+        let layout = RegionLayout::new(root.join("a"));
+        let path = layout.region_path(&RegionIndex(0, 0));
+
+        {
+            let file = <Region<RegionLocalIndex> as ManagedRegion<RegionLocalIndex, Saved>>::get_region_file(&path).unwrap();
+            let mut region: Region<RegionLocalIndex> = Region::new(file);
+            let mut attachments = Attachments::new();
+            attachments.insert("player", &"alice_the_secret".to_string()).unwrap();
+            attachments.insert("signs", &vec!["welcome".to_string()]).unwrap();
+            let chunk = AttachedChunk { chunk: TestChunk(3), attachments: attachments };
+            let (data, _) = <Region<RegionLocalIndex> as ManagedRegion<RegionLocalIndex, Saved>>::encode_chunk(&chunk, &ChunkMeta::default()).unwrap();
+            <Region<RegionLocalIndex> as ManagedRegion<RegionLocalIndex, Saved>>::write_encoded(&mut region, data, &RegionLocalIndex(1, 2)).unwrap();
+        }
+
+        let (copy, count) = anonymize_world::<TestChunk, _, _>(&layout, root.join("b"), |_, attachments| {
+            attachments.remove("player");
+            Ok(())
+        }).unwrap();
+        assert_eq!(count, 1);
+
+        let mut seen = Vec::new();
+        visit_layout::<Saved, _>(&copy, |pos, chunk| {
+            seen.push((pos, chunk.chunk.0, chunk.attachments.keys().join(",")));
+            Ok(())
+        }).unwrap();
+        assert_eq!(seen, vec![((1, 2), 3, "signs".to_string())]);
+
+        let contains_name = |path: &Path| {
+            let mut bytes = Vec::new();
+            File::open(path).unwrap().read_to_end(&mut bytes).unwrap();
+            bytes.windows(16).any(|w| w == b"alice_the_secret")
+        };
+        assert!(contains_name(&path));
+        assert!(!contains_name(&copy.region_path(&RegionIndex(0, 0))));
+        assert!(anonymize_world::<TestChunk, _, _>(&layout, root.join("b"), |_, _| Ok(())).is_err());
+
+        fs::remove_dir_all(&root).unwrap();
+    }
+}
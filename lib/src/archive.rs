@@ -0,0 +1,267 @@
+use std::collections::HashMap;
+use std::fs::{self, File, OpenOptions};
+use std::io::prelude::*;
+use std::io::{BufReader, SeekFrom};
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime};
+
+use bincode::{self, Infinite};
+use error::SerialResult;
+use format::CompressionLevel;
+use managed_region::{zlib_compress, zlib_reader};
+use platform::replace_file;
+use region::RegionIndex;
+
+/// Precedes every region stored in the archive file. A record with a length
+/// of zero means the region was restored and is no longer archived.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct RecordHeader {
+    region: (i32, i32),
+    len: u64,
+}
+
+const RECORD_HEADER_SIZE: u64 = 16;
+
+/// Decides which region files are moved to the cold archive.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ArchivePolicy {
+    /// How long a region file must have gone without being written to.
+    pub idle: Duration,
+    /// Region files smaller than this many bytes are left alone, since
+    /// archiving them saves little.
+    pub min_size: u64,
+}
+
+impl ArchivePolicy {
+    /// Archives regions that weren't written to for the given duration.
+    pub fn idle_for(idle: Duration) -> Self {
+        ArchivePolicy {
+            idle: idle,
+            min_size: 0,
+        }
+    }
+
+    pub fn with_min_size(mut self, min_size: u64) -> Self {
+        self.min_size = min_size;
+        self
+    }
+
+    /// Returns whether a region file should be archived, given its metadata.
+    pub fn should_archive(&self, meta: &fs::Metadata, now: SystemTime) -> bool {
+        if meta.len() < self.min_size {
+            return false;
+        }
+        match meta.modified().ok().and_then(|m| now.duration_since(m).ok()) {
+            Some(idle) => idle >= self.idle,
+            None       => false,
+        }
+    }
+}
+
+/// A single file holding the compressed contents of region files that are
+/// rarely visited, so huge worlds don't keep thousands of region files
+/// around.
+///
+/// Regions are appended to the file as they are archived, and only an index
+/// of where each region starts is kept in memory. Restoring a region writes
+/// its region file back and appends a record marking it as restored; the
+/// space it used is reclaimed by `compact`.
+///
+/// Archiving requires the `compression` feature.
+pub struct ColdArchive {
+    path: PathBuf,
+    entries: HashMap<RegionIndex, (u64, u64)>,
+    dead_bytes: u64,
+}
+
+impl ColdArchive {
+    /// Opens the archive at the given path, or starts an empty one if it
+    /// doesn't exist yet.
+    pub fn open<P: AsRef<Path>>(path: P) -> SerialResult<Self> {
+        let mut archive = ColdArchive {
+            path: path.as_ref().to_path_buf(),
+            entries: HashMap::new(),
+            dead_bytes: 0,
+        };
+        if !archive.path.exists() {
+            return Ok(archive);
+        }
+
+        let mut reader = BufReader::new(File::open(&archive.path)?);
+        let end = reader.get_ref().metadata()?.len();
+        let mut pos = 0;
+        while pos + RECORD_HEADER_SIZE <= end {
+            let header: RecordHeader = bincode::deserialize_from(&mut reader, Infinite)?;
+            let index = RegionIndex(header.region.0, header.region.1);
+            pos += RECORD_HEADER_SIZE;
+            if let Some((_, len)) = archive.entries.remove(&index) {
+                archive.dead_bytes += RECORD_HEADER_SIZE + len;
+            }
+            if header.len > 0 {
+                archive.entries.insert(index, (pos, header.len));
+            } else {
+                archive.dead_bytes += RECORD_HEADER_SIZE;
+            }
+            pos += header.len;
+            reader.seek(SeekFrom::Start(pos))?;
+        }
+        Ok(archive)
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    pub fn contains(&self, index: &RegionIndex) -> bool {
+        self.entries.contains_key(index)
+    }
+
+    /// Returns the indices of every archived region.
+    pub fn region_indices(&self) -> Vec<RegionIndex> {
+        self.entries.keys().cloned().collect()
+    }
+
+    /// Compresses the region file at `path` into the archive and removes the
+    /// file.
+    pub fn archive(&mut self, index: &RegionIndex, path: &Path) -> SerialResult<()> {
+        let mut data = Vec::new();
+        File::open(path)?.read_to_end(&mut data)?;
+
+        let compressed = zlib_compress(&data, CompressionLevel::Best)?;
+
+        let offset = self.append(index, &compressed)?;
+        if let Some((_, len)) = self.entries.insert(index.clone(), (offset, compressed.len() as u64)) {
+            self.dead_bytes += RECORD_HEADER_SIZE + len;
+        }
+        fs::remove_file(path)?;
+        Ok(())
+    }
+
+    /// Writes an archived region back to `path` and removes it from the
+    /// archive. Returns false if the region isn't archived.
+    ///
+    /// If a region file already exists at `path`, it is newer than the
+    /// archived copy, which is discarded.
+    pub fn restore(&mut self, index: &RegionIndex, path: &Path) -> SerialResult<bool> {
+        let (offset, len) = match self.entries.get(index) {
+            Some(&entry) => entry,
+            None         => return Ok(false),
+        };
+
+        if !path.exists() {
+            let mut file = File::open(&self.path)?;
+            file.seek(SeekFrom::Start(offset))?;
+            let mut data = Vec::new();
+            zlib_reader(file.take(len))?.read_to_end(&mut data)?;
+
+            if let Some(parent) = path.parent() {
+                if !parent.as_os_str().is_empty() {
+                    fs::create_dir_all(parent)?;
+                }
+            }
+            let temp = path.with_extension("restore");
+            {
+                let mut out = File::create(&temp)?;
+                out.write_all(&data)?;
+                out.sync_all()?;
+            }
+            replace_file(&temp, path)?;
+        }
+
+        self.append(index, &[])?;
+        self.entries.remove(index);
+        self.dead_bytes += 2 * RECORD_HEADER_SIZE + len;
+        Ok(true)
+    }
+
+    /// Returns the number of bytes in the archive file taken up by restored
+    /// or replaced regions.
+    pub fn dead_bytes(&self) -> u64 {
+        self.dead_bytes
+    }
+
+    /// Rewrites the archive file without the space left behind by restored
+    /// regions.
+    pub fn compact(&mut self) -> SerialResult<()> {
+        if self.dead_bytes == 0 {
+            return Ok(());
+        }
+
+        let temp = self.path.with_extension("compact");
+        let mut entries = HashMap::new();
+        {
+            let mut source = File::open(&self.path)?;
+            let mut out = File::create(&temp)?;
+            let mut pos = 0;
+            for (index, &(offset, len)) in self.entries.iter() {
+                let mut data = vec![0u8; len as usize];
+                source.seek(SeekFrom::Start(offset))?;
+                source.read_exact(&mut data)?;
+                write_record(&mut out, index, &data)?;
+                entries.insert(index.clone(), (pos + RECORD_HEADER_SIZE, len));
+                pos += RECORD_HEADER_SIZE + len;
+            }
+            out.sync_all()?;
+        }
+        replace_file(&temp, &self.path)?;
+        self.entries = entries;
+        self.dead_bytes = 0;
+        Ok(())
+    }
+
+    /// Appends a record to the archive file and returns the offset of its
+    /// data.
+    fn append(&mut self, index: &RegionIndex, data: &[u8]) -> SerialResult<u64> {
+        if let Some(parent) = self.path.parent() {
+            if !parent.as_os_str().is_empty() {
+                fs::create_dir_all(parent)?;
+            }
+        }
+        let mut file = OpenOptions::new().create(true).append(true).open(&self.path)?;
+        let start = file.seek(SeekFrom::End(0))?;
+        write_record(&mut file, index, data)?;
+        file.sync_all()?;
+        Ok(start + RECORD_HEADER_SIZE)
+    }
+}
+
+fn write_record<W: Write>(writer: &mut W, index: &RegionIndex, data: &[u8]) -> SerialResult<()> {
+    let header = RecordHeader {
+        region: (index.0, index.1),
+        len: data.len() as u64,
+    };
+    writer.write_all(&bincode::serialize(&header, Infinite)?)?;
+    writer.write_all(data)?;
+    Ok(())
+}
+
+#[cfg(all(test, feature = "compression"))]
+mod tests {
+    use super::*;
+    use std::env;
+
+    #[test]
+    fn test_archive_and_restore() {
+        let dir = env::temp_dir().join("infinigen-test-archive");
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        let region = dir.join("r.1.2.sr");
+        File::create(&region).unwrap().write_all(&[3; 300]).unwrap();
+
+        let mut archive = ColdArchive::open(dir.join("cold.archive")).unwrap();
+        archive.archive(&RegionIndex(1, 2), &region).unwrap();
+        assert!(!region.exists());
+
+        let mut archive = ColdArchive::open(dir.join("cold.archive")).unwrap();
+        assert!(archive.contains(&RegionIndex(1, 2)));
+        assert!(archive.restore(&RegionIndex(1, 2), &region).unwrap());
+        assert!(!archive.restore(&RegionIndex(1, 2), &region).unwrap());
+        archive.compact().unwrap();
+
+        let mut data = Vec::new();
+        File::open(&region).unwrap().read_to_end(&mut data).unwrap();
+        assert_eq!(data, vec![3; 300]);
+        assert!(!ColdArchive::open(dir.join("cold.archive")).unwrap().contains(&RegionIndex(1, 2)));
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,290 @@
+use std::collections::{BTreeMap, HashMap};
+use std::hash::Hash;
+use std::ops::{Deref, DerefMut};
+
+use bincode::{self, Infinite};
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+use serde::de::DeserializeOwned;
+use serde::ser::{self, SerializeMap};
+
+use error::SerialResult;
+use format::{CompressionLevel, Padding, Preallocation};
+use managed_region::UnsavedReadPolicy;
+use occupancy::Occupancy;
+use traits::ManagedChunk;
+
+/// Serialized per-chunk data of the application, keyed by name, that is saved
+/// alongside a chunk.
+#[derive(Debug, Default, Clone, Serialize, Deserialize)]
+pub struct Attachments {
+    data: BTreeMap<String, Vec<u8>>,
+}
+
+impl Attachments {
+    pub fn new() -> Self {
+        Attachments {
+            data: BTreeMap::new(),
+        }
+    }
+
+    /// Serializes a value and stores it under the given key, replacing any
+    /// previous value.
+    pub fn insert<T: Serialize>(&mut self, key: &str, value: &T) -> SerialResult<()> {
+        let encoded = bincode::serialize(value, Infinite)?;
+        self.data.insert(key.to_string(), encoded);
+        Ok(())
+    }
+
+    /// Deserializes the value stored under the given key without removing it.
+    pub fn get<T: DeserializeOwned>(&self, key: &str) -> SerialResult<Option<T>> {
+        match self.data.get(key) {
+            Some(bytes) => Ok(Some(bincode::deserialize(bytes)?)),
+            None        => Ok(None),
+        }
+    }
+
+    /// Removes the value stored under the given key and deserializes it.
+    pub fn take<T: DeserializeOwned>(&mut self, key: &str) -> SerialResult<Option<T>> {
+        match self.data.remove(key) {
+            Some(bytes) => Ok(Some(bincode::deserialize(&bytes)?)),
+            None        => Ok(None),
+        }
+    }
+
+    /// Removes the value stored under the given key without reading it.
+    /// Returns whether there was one.
+    pub fn remove(&mut self, key: &str) -> bool {
+        self.data.remove(key).is_some()
+    }
+
+    pub fn contains(&self, key: &str) -> bool {
+        self.data.contains_key(key)
+    }
+
+    pub fn keys(&self) -> Vec<&str> {
+        self.data.keys().map(|k| k.as_str()).collect()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.data.is_empty()
+    }
+}
+
+/// A `HashMap` that always serializes its entries in the same order, so equal
+/// maps encode to the same bytes. Plain hash maps encode their entries in
+/// an order that changes between runs, which makes unchanged chunks look
+/// changed to `save_in_place` and to anything comparing chunk hashes.
+///
+/// Entries are ordered by the encoding of their keys, so keys don't need to
+/// be `Ord`. The map is encoded the same as a `HashMap`, so it can replace
+/// one in saved data without a migration.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CanonicalMap<K: Hash + Eq, V>(pub HashMap<K, V>);
+
+impl<K: Hash + Eq, V> CanonicalMap<K, V> {
+    pub fn new() -> Self {
+        CanonicalMap(HashMap::new())
+    }
+
+    pub fn into_inner(self) -> HashMap<K, V> {
+        self.0
+    }
+}
+
+impl<K: Hash + Eq, V> Default for CanonicalMap<K, V> {
+    fn default() -> Self {
+        CanonicalMap::new()
+    }
+}
+
+impl<K: Hash + Eq, V> From<HashMap<K, V>> for CanonicalMap<K, V> {
+    fn from(map: HashMap<K, V>) -> Self {
+        CanonicalMap(map)
+    }
+}
+
+impl<K: Hash + Eq, V> Deref for CanonicalMap<K, V> {
+    type Target = HashMap<K, V>;
+
+    fn deref(&self) -> &HashMap<K, V> {
+        &self.0
+    }
+}
+
+impl<K: Hash + Eq, V> DerefMut for CanonicalMap<K, V> {
+    fn deref_mut(&mut self) -> &mut HashMap<K, V> {
+        &mut self.0
+    }
+}
+
+impl<K: Hash + Eq + Serialize, V: Serialize> Serialize for CanonicalMap<K, V> {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serialize_canonical(&self.0, serializer)
+    }
+}
+
+impl<'de, K, V> Deserialize<'de> for CanonicalMap<K, V>
+    where K: Hash + Eq + Deserialize<'de>,
+          V: Deserialize<'de>
+{
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        HashMap::deserialize(deserializer).map(CanonicalMap)
+    }
+}
+
+/// Serializes a `HashMap` the way `CanonicalMap` does, for map fields of
+/// chunks marked `#[serde(serialize_with = "serialize_canonical")]`.
+pub fn serialize_canonical<K, V, S>(map: &HashMap<K, V>, serializer: S) -> Result<S::Ok, S::Error>
+    where K: Hash + Eq + Serialize,
+          V: Serialize,
+          S: Serializer
+{
+    let mut entries = Vec::with_capacity(map.len());
+    for (key, value) in map.iter() {
+        let encoded = bincode::serialize(key, Infinite).map_err(ser::Error::custom)?;
+        entries.push((encoded, key, value));
+    }
+    entries.sort_by(|a, b| a.0.cmp(&b.0));
+
+    let mut out = serializer.serialize_map(Some(entries.len()))?;
+    for (_, key, value) in entries.into_iter() {
+        out.serialize_entry(key, value)?;
+    }
+    out.end()
+}
+
+/// A chunk bundled with the attachments that were detached from the world
+/// when it was unloaded.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct AttachedChunk<T> {
+    pub chunk: T,
+    pub attachments: Attachments,
+}
+
+impl<T: ManagedChunk> ManagedChunk for AttachedChunk<T> {
+    const SECTOR_SIZE: usize = T::SECTOR_SIZE;
+    const REGION_WIDTH: i32 = T::REGION_WIDTH;
+    const COMPRESSION_THRESHOLD: usize = T::COMPRESSION_THRESHOLD;
+    const UNSAVED_READ_POLICY: UnsavedReadPolicy = T::UNSAVED_READ_POLICY;
+    const PREALLOCATION: Preallocation = T::PREALLOCATION;
+    const PADDING: Padding = T::PADDING;
+    const VERIFY_WRITES: bool = T::VERIFY_WRITES;
+
+    fn compression_level(&self) -> CompressionLevel {
+        self.chunk.compression_level()
+    }
+
+    fn occupancy(&self) -> Option<Occupancy> {
+        self.chunk.occupancy()
+    }
+}
+
+/// A kind of per-chunk data, such as entities or scheduled events, that is
+/// kept by the world `W` while a chunk is loaded and saved with the chunk
+/// while it is unloaded.
+pub trait Attachment<W, I> {
+    type Data: Serialize + DeserializeOwned;
+
+    /// Key identifying this attachment inside saved chunks. Must be unique
+    /// among the attachments registered on a world.
+    const KEY: &'static str;
+
+    /// Removes the data belonging to the chunk at `index` from the world, as
+    /// the chunk is being unloaded. Returning `None` stores nothing.
+    fn detach(world: &mut W, index: &I) -> Option<Self::Data>;
+
+    /// Gives data that was saved with the chunk at `index` back to the world,
+    /// as the chunk is being loaded.
+    fn attach(world: &mut W, index: &I, data: Self::Data);
+}
+
+type DetachFn<W, I> = fn(&mut W, &I, &mut Attachments) -> SerialResult<()>;
+type AttachFn<W, I> = fn(&mut W, &I, &mut Attachments) -> SerialResult<()>;
+
+fn detach_erased<W, I, A: Attachment<W, I>>(world: &mut W, index: &I, out: &mut Attachments) -> SerialResult<()> {
+    match A::detach(world, index) {
+        Some(data) => out.insert(A::KEY, &data),
+        None       => Ok(()),
+    }
+}
+
+fn attach_erased<W, I, A: Attachment<W, I>>(world: &mut W, index: &I, from: &mut Attachments) -> SerialResult<()> {
+    if let Some(data) = from.take::<A::Data>(A::KEY)? {
+        A::attach(world, index, data);
+    }
+    Ok(())
+}
+
+/// The set of attachments registered on a world.
+///
+/// The registry only holds function pointers, so it is cheap to clone out of
+/// the world before handing the world to `detach_all` or `attach_all`.
+pub struct AttachmentRegistry<W, I> {
+    hooks: Vec<(&'static str, DetachFn<W, I>, AttachFn<W, I>)>,
+}
+
+impl<W, I> Clone for AttachmentRegistry<W, I> {
+    fn clone(&self) -> Self {
+        AttachmentRegistry {
+            hooks: self.hooks.clone(),
+        }
+    }
+}
+
+impl<W, I> AttachmentRegistry<W, I> {
+    pub fn new() -> Self {
+        AttachmentRegistry {
+            hooks: Vec::new(),
+        }
+    }
+
+    /// Registers an attachment. Registering the same key twice panics.
+    pub fn register<A: Attachment<W, I>>(&mut self) {
+        assert!(self.hooks.iter().all(|&(key, _, _)| key != A::KEY),
+                "Attachment key {} registered twice!", A::KEY);
+        self.hooks.push((A::KEY, detach_erased::<W, I, A>, attach_erased::<W, I, A>));
+    }
+
+    /// Detaches the data of every registered attachment for the chunk at
+    /// `index` from the world.
+    pub fn detach_all(&self, world: &mut W, index: &I) -> SerialResult<Attachments> {
+        let mut attachments = Attachments::new();
+        for &(_, detach, _) in self.hooks.iter() {
+            detach(world, index, &mut attachments)?;
+        }
+        Ok(attachments)
+    }
+
+    /// Hands every attachment in `attachments` with a registered key back to
+    /// the world. Attachments with unknown keys are returned.
+    pub fn attach_all(&self, world: &mut W, index: &I, mut attachments: Attachments) -> SerialResult<Attachments> {
+        for &(_, _, attach) in self.hooks.iter() {
+            attach(world, index, &mut attachments)?;
+        }
+        Ok(attachments)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_canonical_map() {
+        let mut a = HashMap::new();
+        let mut b = HashMap::with_capacity(1024);
+        for i in 0..100 {
+            a.insert((i, -i), i as u8);
+            b.insert((99 - i, i - 99), (99 - i) as u8);
+        }
+        let a = bincode::serialize(&CanonicalMap(a), Infinite).unwrap();
+        let b = bincode::serialize(&CanonicalMap(b), Infinite).unwrap();
+        assert_eq!(a, b);
+
+        // Encoded the same as a plain map.
+        let decoded: HashMap<(i32, i32), u8> = bincode::deserialize(&a).unwrap();
+        assert_eq!(decoded[&(42, -42)], 42);
+        let decoded: CanonicalMap<(i32, i32), u8> = bincode::deserialize(&a).unwrap();
+        assert_eq!(decoded.len(), 100);
+    }
+}
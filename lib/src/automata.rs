@@ -0,0 +1,205 @@
+use grid::{chunk_at, world_pos};
+use hash::{FxHashMap, FxHashSet};
+use traits::Index;
+
+const MOORE: [(i32, i32); 8] = [(-1, -1), (0, -1), (1, -1),
+                                (-1,  0),          (1,  0),
+                                (-1,  1), (0,  1), (1,  1)];
+
+/// A cellular automaton, such as flowing water, falling sand or spreading
+/// fire.
+pub trait Automaton {
+    type State: Clone + PartialEq;
+
+    /// Computes the next state of a cell from its current state and the
+    /// states of its eight neighbors, in row-major order starting from the
+    /// top left.
+    fn step(&self, cell: &Self::State, neighbors: &[Self::State; 8]) -> Self::State;
+}
+
+/// How an automaton treats cells in chunks that aren't loaded.
+#[derive(Debug, Clone, PartialEq)]
+pub enum EdgePolicy<S> {
+    /// Cells in unloaded chunks have the given state.
+    Constant(S),
+    /// Chunks bordering an unloaded chunk aren't stepped until all of their
+    /// neighbors are loaded.
+    Freeze,
+}
+
+/// Double-buffered driver for a cellular automaton over loaded chunks.
+///
+/// The driver keeps the automaton's state for every loaded chunk, which
+/// should be inserted when a chunk loads and removed when it unloads. Only
+/// active chunks are stepped: a chunk becomes active when it is inserted, when
+/// one of its cells is set, or when it or a neighboring chunk changed during
+/// the last step, and becomes inactive once a step leaves it unchanged.
+pub struct AutomataDriver<I: Index, S> {
+    chunk_width: i32,
+    cells: FxHashMap<I, Vec<S>>,
+    active: FxHashSet<I>,
+    edge: EdgePolicy<S>,
+}
+
+impl<I: Index, S: Clone + PartialEq> AutomataDriver<I, S> {
+    pub fn new(chunk_width: i32, edge: EdgePolicy<S>) -> Self {
+        AutomataDriver {
+            chunk_width: chunk_width,
+            cells: FxHashMap::default(),
+            active: FxHashSet::default(),
+            edge: edge,
+        }
+    }
+
+    /// Inserts the state of a chunk that was loaded. The cells are in
+    /// row-major order.
+    pub fn insert(&mut self, index: I, cells: Vec<S>) {
+        assert_eq!(cells.len(), (self.chunk_width * self.chunk_width) as usize);
+        self.activate_around(&index);
+        self.cells.insert(index, cells);
+    }
+
+    /// Removes the state of a chunk that is being unloaded.
+    pub fn remove(&mut self, index: &I) -> Option<Vec<S>> {
+        self.active.remove(index);
+        let cells = self.cells.remove(index);
+        if cells.is_some() {
+            self.activate_around(index);
+        }
+        cells
+    }
+
+    /// Returns the state at a world position, or `None` if its chunk isn't
+    /// loaded.
+    pub fn get(&self, x: i32, y: i32) -> Option<&S> {
+        let (index, (lx, ly)) = chunk_at::<I>(x, y, self.chunk_width);
+        self.cells.get(&index).map(|c| &c[(ly * self.chunk_width + lx) as usize])
+    }
+
+    /// Sets the state at a world position and activates the surrounding
+    /// chunks. Returns false if the chunk isn't loaded.
+    pub fn set(&mut self, x: i32, y: i32, state: S) -> bool {
+        let (index, (lx, ly)) = chunk_at::<I>(x, y, self.chunk_width);
+        let width = self.chunk_width;
+        match self.cells.get_mut(&index) {
+            Some(cells) => cells[(ly * width + lx) as usize] = state,
+            None        => return false,
+        }
+        self.activate_around(&index);
+        true
+    }
+
+    pub fn is_active(&self, index: &I) -> bool {
+        self.active.contains(index)
+    }
+
+    pub fn active_count(&self) -> usize {
+        self.active.len()
+    }
+
+    /// Marks a chunk and its eight neighbors as active.
+    pub fn activate_around(&mut self, index: &I) {
+        self.active.insert(index.clone());
+        for &(dx, dy) in MOORE.iter() {
+            self.active.insert(I::from_xy(index.x() + dx, index.y() + dy));
+        }
+    }
+
+    fn neighbors_loaded(&self, index: &I) -> bool {
+        MOORE.iter().all(|&(dx, dy)| {
+            self.cells.contains_key(&I::from_xy(index.x() + dx, index.y() + dy))
+        })
+    }
+
+    fn read(&self, x: i32, y: i32) -> S {
+        match self.get(x, y) {
+            Some(s) => s.clone(),
+            None    => match self.edge {
+                EdgePolicy::Constant(ref s) => s.clone(),
+                EdgePolicy::Freeze          => unreachable!("Stepped a chunk with unloaded neighbors"),
+            },
+        }
+    }
+
+    /// Steps every active chunk once. All chunks read the states from before
+    /// the step. Returns the number of chunks that were stepped.
+    pub fn step<A>(&mut self, automaton: &A) -> usize
+        where A: Automaton<State = S>
+    {
+        let width = self.chunk_width;
+        let cells = &self.cells;
+        let active: Vec<I> = self.active.drain()
+            .filter(|i| cells.contains_key(i))
+            .collect();
+
+        let mut next = Vec::new();
+        for index in active.into_iter() {
+            if self.edge == EdgePolicy::Freeze && !self.neighbors_loaded(&index) {
+                self.active.insert(index);
+                continue;
+            }
+
+            let mut changed = false;
+            let mut buffer = Vec::with_capacity((width * width) as usize);
+            for ly in 0..width {
+                for lx in 0..width {
+                    let (x, y) = world_pos(&index, (lx, ly), width);
+                    let cell = self.read(x, y);
+                    let neighbors = [
+                        self.read(x - 1, y - 1), self.read(x, y - 1), self.read(x + 1, y - 1),
+                        self.read(x - 1, y),                          self.read(x + 1, y),
+                        self.read(x - 1, y + 1), self.read(x, y + 1), self.read(x + 1, y + 1),
+                    ];
+                    let new = automaton.step(&cell, &neighbors);
+                    changed |= new != cell;
+                    buffer.push(new);
+                }
+            }
+            next.push((index, buffer, changed));
+        }
+
+        let stepped = next.len();
+        for (index, buffer, changed) in next.into_iter() {
+            if changed {
+                self.activate_around(&index);
+                self.cells.insert(index, buffer);
+            }
+        }
+        stepped
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use traits::TestIndex;
+
+    /// Cells become true if any neighbor is true.
+    struct Spread;
+
+    impl Automaton for Spread {
+        type State = bool;
+        fn step(&self, cell: &bool, neighbors: &[bool; 8]) -> bool {
+            *cell || neighbors.iter().any(|n| *n)
+        }
+    }
+
+    #[test]
+    fn test_spread_across_seam() {
+        let mut driver = AutomataDriver::new(2, EdgePolicy::Constant(false));
+        driver.insert(TestIndex(0, 0), vec![false; 4]);
+        driver.insert(TestIndex(1, 0), vec![false; 4]);
+        driver.set(1, 0, true);
+
+        driver.step(&Spread);
+        assert_eq!(driver.get(2, 1), Some(&true));
+        assert_eq!(driver.get(3, 0), Some(&false));
+
+        driver.step(&Spread);
+        assert_eq!(driver.get(3, 1), Some(&true));
+
+        driver.step(&Spread);
+        assert_eq!(driver.step(&Spread), 0);
+        assert_eq!(driver.active_count(), 0);
+    }
+}
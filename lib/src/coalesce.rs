@@ -0,0 +1,142 @@
+use std::time::{Duration, Instant};
+
+use hash::{FxHashMap, FxHashSet};
+use region::RegionIndex;
+use traits::Index;
+
+/// The chunks of one region waiting to be written.
+#[derive(Debug, Clone)]
+struct PendingWrites<I: Index> {
+    since: Instant,
+    chunks: FxHashSet<I>,
+}
+
+/// Holds back in-place saves of changed chunks, like the periodic saves of
+/// pinned chunks, and writes all the chunks of a region waiting to be saved
+/// together once the first of them has waited for a time window. A chunk
+/// that changes again within the window is only written once, which cuts
+/// down on small writes wearing out SSDs. See
+/// `ChunkedWorld::save_chunk_coalesced`.
+///
+/// The window bounds how much is lost if the game crashes: changes saved
+/// through the coalescer reach the disk at most `window` later, or once a
+/// region has `max_pending` chunks waiting, whichever comes first. Saving
+/// the world with `save` or `save_in_place` writes every waiting chunk
+/// immediately, and unloading a chunk writes it as usual. Chunks waiting to
+/// be written stay loaded, so their region isn't closed before they are.
+#[derive(Debug, Clone)]
+pub struct WriteCoalescing<I: Index> {
+    window: Duration,
+    max_pending: usize,
+    pending: FxHashMap<RegionIndex, PendingWrites<I>>,
+}
+
+impl<I: Index> WriteCoalescing<I> {
+    pub fn new(window: Duration) -> Self {
+        WriteCoalescing {
+            window: window,
+            max_pending: usize::max_value(),
+            pending: FxHashMap::default(),
+        }
+    }
+
+    /// Writes a region as soon as this many of its chunks are waiting.
+    pub fn with_max_pending(mut self, chunks: usize) -> Self {
+        self.max_pending = chunks.max(1);
+        self
+    }
+
+    pub fn window(&self) -> Duration {
+        self.window
+    }
+
+    /// Changes the time window, such as to save more often while the game is
+    /// running on battery. A zero window writes every save immediately.
+    pub fn set_window(&mut self, window: Duration) {
+        self.window = window;
+    }
+
+    /// Holds back the write of a chunk. Returns whether its region should
+    /// be written now because the window is zero or too many of its chunks
+    /// are waiting.
+    pub fn defer(&mut self, region: RegionIndex, index: I, now: Instant) -> bool {
+        let pending = self.pending.entry(region).or_insert_with(|| PendingWrites {
+            since: now,
+            chunks: FxHashSet::default(),
+        });
+        pending.chunks.insert(index);
+        self.window == Duration::from_secs(0) || pending.chunks.len() >= self.max_pending
+    }
+
+    /// Forgets a chunk waiting to be written, such as because it was saved
+    /// by other means. Returns whether it was waiting.
+    pub fn cancel(&mut self, region: &RegionIndex, index: &I) -> bool {
+        let (removed, empty) = match self.pending.get_mut(region) {
+            Some(pending) => (pending.chunks.remove(index), pending.chunks.is_empty()),
+            None          => return false,
+        };
+        if empty {
+            self.pending.remove(region);
+        }
+        removed
+    }
+
+    pub fn is_pending(&self, region: &RegionIndex, index: &I) -> bool {
+        self.pending.get(region).map_or(false, |p| p.chunks.contains(index))
+    }
+
+    /// Returns the number of chunks waiting to be written.
+    pub fn pending_count(&self) -> usize {
+        self.pending.values().map(|p| p.chunks.len()).sum()
+    }
+
+    /// Returns the regions whose window has passed at `now`.
+    pub fn due(&self, now: Instant) -> Vec<RegionIndex> {
+        self.pending.iter()
+            .filter(|&(_, p)| now.duration_since(p.since) >= self.window)
+            .map(|(region, _)| region.clone())
+            .collect()
+    }
+
+    /// Removes and returns the chunks of a region waiting to be written.
+    pub fn take(&mut self, region: &RegionIndex) -> Vec<I> {
+        self.pending.remove(region).map_or(Vec::new(), |p| p.chunks.into_iter().collect())
+    }
+
+    /// Removes and returns every chunk waiting to be written.
+    pub fn take_all(&mut self) -> Vec<I> {
+        self.pending.drain().flat_map(|(_, p)| p.chunks.into_iter()).collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use traits::TestIndex;
+
+    #[test]
+    fn test_coalescing() {
+        let start = Instant::now();
+        let mut coalescing = WriteCoalescing::new(Duration::from_secs(5)).with_max_pending(3);
+        let (a, b) = (RegionIndex(0, 0), RegionIndex(1, 0));
+
+        assert!(!coalescing.defer(a.clone(), TestIndex(0, 0), start));
+        assert!(!coalescing.defer(a.clone(), TestIndex(0, 0), start + Duration::from_secs(1)));
+        assert!(!coalescing.defer(b.clone(), TestIndex(20, 0), start + Duration::from_secs(2)));
+        assert_eq!(coalescing.pending_count(), 2);
+
+        // The window runs from the first write held back in a region.
+        assert!(coalescing.due(start + Duration::from_secs(4)).is_empty());
+        assert_eq!(coalescing.due(start + Duration::from_secs(5)), vec![a.clone()]);
+        assert_eq!(coalescing.take(&a), vec![TestIndex(0, 0)]);
+
+        assert!(coalescing.cancel(&b, &TestIndex(20, 0)));
+        assert!(!coalescing.is_pending(&b, &TestIndex(20, 0)));
+        assert_eq!(coalescing.pending_count(), 0);
+
+        assert!(!coalescing.defer(a.clone(), TestIndex(0, 0), start));
+        assert!(!coalescing.defer(a.clone(), TestIndex(1, 0), start));
+        assert!(coalescing.defer(a.clone(), TestIndex(2, 0), start));
+        assert_eq!(coalescing.take_all().len(), 3);
+    }
+}
@@ -0,0 +1,98 @@
+use std::any::Any;
+use std::fs::{self, File};
+use std::io::{BufReader, Write};
+use std::path::Path;
+
+use bincode::{self, Infinite};
+
+use error::{self, SerialResult};
+use platform::replace_file;
+
+/// Written next to the crash journal by `ChunkedWorld::crash_save`, saying
+/// the world wasn't shut down cleanly and why.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct CrashMarker {
+    /// The world tick the crash happened at.
+    pub tick: u64,
+    /// What went wrong, like the message of the panic.
+    pub reason: String,
+    /// Number of chunks written to the crash journal.
+    pub chunks: usize,
+}
+
+impl CrashMarker {
+    /// Writes the marker to a temporary file and renames it into place, so a
+    /// marker that exists is always complete.
+    pub fn save<P: AsRef<Path>>(&self, path: P) -> SerialResult<()> {
+        let path = path.as_ref();
+        let temp = path.with_extension("tmp");
+        {
+            let mut file = File::create(&temp)?;
+            let written = file.write_all(&bincode::serialize(self, Infinite)?)
+                .and_then(|_| file.sync_all());
+            if let Err(e) = written {
+                let _ = fs::remove_file(&temp);
+                return Err(error::write_error(e));
+            }
+        }
+        replace_file(&temp, path)?;
+        Ok(())
+    }
+
+    /// Loads the marker at the given path, if the world crashed.
+    pub fn load<P: AsRef<Path>>(path: P) -> SerialResult<Option<Self>> {
+        if !path.as_ref().exists() {
+            return Ok(None);
+        }
+        let mut reader = BufReader::new(File::open(path)?);
+        Ok(Some(bincode::deserialize_from(&mut reader, Infinite)?))
+    }
+}
+
+/// What `ChunkedWorld::recover_crash_save` found after a crash.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CrashRecovery {
+    /// The marker left by the crash, or `None` if the process died before
+    /// writing it.
+    pub marker: Option<CrashMarker>,
+    /// Number of chunks moved from the crash journal into the region files.
+    pub chunks: usize,
+}
+
+/// Returns the message a panic was raised with, for a crash marker.
+pub fn panic_message(payload: &Box<dyn Any + Send>) -> String {
+    if let Some(s) = payload.downcast_ref::<&str>() {
+        s.to_string()
+    } else if let Some(s) = payload.downcast_ref::<String>() {
+        s.clone()
+    } else {
+        "panic".to_string()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::env;
+    use std::panic;
+
+    #[test]
+    fn test_crash_marker() {
+        let dir = env::temp_dir().join("infinigen-test-crash");
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("crash.marker");
+        assert_eq!(CrashMarker::load(&path).unwrap(), None);
+
+        let payload = panic::catch_unwind(|| panic!("out of {}", "dudes")).unwrap_err();
+        let marker = CrashMarker {
+            tick: 12,
+            reason: panic_message(&payload),
+            chunks: 3,
+        };
+        marker.save(&path).unwrap();
+        assert_eq!(CrashMarker::load(&path).unwrap(), Some(marker));
+        assert_eq!(CrashMarker::load(&path).unwrap().unwrap().reason, "out of dudes");
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,67 @@
+/// How much of a chunk is generated. Chunks far from observers only need
+/// enough to be shown or planned around from afar, and are promoted to
+/// higher levels as observers come closer. Levels are ordered from least to
+/// most detailed.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum DetailLevel {
+    /// Only what is needed to show the chunk from afar, like heights or
+    /// biomes. See `Staged`.
+    Stub,
+    /// The terrain of the chunk, without the features that are costly to
+    /// generate or only matter up close, like structures or entities.
+    Coarse,
+    Full,
+}
+
+/// Decides the detail level chunks are generated at from their distance to
+/// the nearest observer, as given by `ChunkedWorld::observer_distance`. See
+/// `ChunkedWorld::detail_rules`.
+///
+/// Chunks farther away than every distance given to `with_level` are stubs.
+#[derive(Debug, Clone, Default)]
+pub struct DetailRules {
+    levels: Vec<(DetailLevel, i32)>,
+}
+
+impl DetailRules {
+    pub fn new() -> Self {
+        DetailRules {
+            levels: Vec::new(),
+        }
+    }
+
+    /// Generates chunks up to `distance` chunks from the nearest observer at
+    /// `level` or higher.
+    pub fn with_level(mut self, level: DetailLevel, distance: i32) -> Self {
+        self.levels.push((level, distance));
+        self
+    }
+
+    /// Returns the level of a chunk `distance` chunks from the nearest
+    /// observer.
+    pub fn level_at(&self, distance: i32) -> DetailLevel {
+        self.levels.iter()
+            .filter(|&&(_, d)| distance <= d)
+            .map(|&(level, _)| level)
+            .max()
+            .unwrap_or(DetailLevel::Stub)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_level_at() {
+        let rules = DetailRules::new()
+            .with_level(DetailLevel::Full, 1)
+            .with_level(DetailLevel::Coarse, 3);
+        assert_eq!(rules.level_at(0), DetailLevel::Full);
+        assert_eq!(rules.level_at(1), DetailLevel::Full);
+        assert_eq!(rules.level_at(2), DetailLevel::Coarse);
+        assert_eq!(rules.level_at(3), DetailLevel::Coarse);
+        assert_eq!(rules.level_at(4), DetailLevel::Stub);
+        assert_eq!(DetailRules::new().level_at(0), DetailLevel::Stub);
+    }
+}
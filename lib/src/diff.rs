@@ -0,0 +1,197 @@
+use std::collections::HashMap;
+use std::hash::{BuildHasher, Hash};
+
+use bincode::{self, Infinite};
+use serde::Serialize;
+use serde::de::DeserializeOwned;
+
+use error::SerialResult;
+
+/// A chunk whose contents can be viewed as a flat list of cells, so it can be
+/// diffed.
+pub trait DiffableChunk {
+    type Cell: Serialize + DeserializeOwned;
+
+    fn cells(&self) -> &[Self::Cell];
+    fn cells_mut(&mut self) -> &mut [Self::Cell];
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+struct CellChange {
+    index: u32,
+    old: Vec<u8>,
+    new: Vec<u8>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+struct EntityChange {
+    key: Vec<u8>,
+    old: Option<Vec<u8>>,
+    new: Option<Vec<u8>>,
+}
+
+/// The changes between two states of a chunk, such as for sending
+/// incremental updates over the network or keeping an undo stack in an
+/// editor.
+///
+/// Cells and entities are compared by their serialized form, so they don't
+/// need to implement `PartialEq`. Both the old and the new value of every
+/// change are kept, so a diff can also be reverted.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct ChunkDiff {
+    cells: Vec<CellChange>,
+    entities: Vec<EntityChange>,
+}
+
+fn encode<T: Serialize>(value: &T) -> SerialResult<Vec<u8>> {
+    Ok(bincode::serialize(value, Infinite)?)
+}
+
+impl ChunkDiff {
+    /// Computes the cells that differ between two states of a chunk.
+    pub fn between<C: DiffableChunk>(old: &C, new: &C) -> SerialResult<Self> {
+        let (old, new) = (old.cells(), new.cells());
+        assert_eq!(old.len(), new.len(), "Chunks have different numbers of cells!");
+
+        let mut cells = Vec::new();
+        for (i, (a, b)) in old.iter().zip(new.iter()).enumerate() {
+            let (a, b) = (encode(a)?, encode(b)?);
+            if a != b {
+                cells.push(CellChange {
+                    index: i as u32,
+                    old: a,
+                    new: b,
+                });
+            }
+        }
+
+        Ok(ChunkDiff {
+            cells: cells,
+            entities: Vec::new(),
+        })
+    }
+
+    /// Adds the entities that were added, removed or changed between two
+    /// states of the entities in a chunk.
+    pub fn with_entities<K, V, S>(mut self, old: &HashMap<K, V, S>, new: &HashMap<K, V, S>) -> SerialResult<Self>
+        where K: Hash + Eq + Serialize,
+              V: Serialize,
+              S: BuildHasher
+    {
+        for (key, value) in old.iter() {
+            let a = encode(value)?;
+            let b = match new.get(key) {
+                Some(v) => Some(encode(v)?),
+                None    => None,
+            };
+            if b.as_ref() != Some(&a) {
+                self.entities.push(EntityChange {
+                    key: encode(key)?,
+                    old: Some(a),
+                    new: b,
+                });
+            }
+        }
+        for (key, value) in new.iter() {
+            if !old.contains_key(key) {
+                self.entities.push(EntityChange {
+                    key: encode(key)?,
+                    old: None,
+                    new: Some(encode(value)?),
+                });
+            }
+        }
+        Ok(self)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.cells.is_empty() && self.entities.is_empty()
+    }
+
+    /// Returns the number of cells and entities that changed.
+    pub fn len(&self) -> usize {
+        self.cells.len() + self.entities.len()
+    }
+
+    /// Returns a diff that undoes this one.
+    pub fn inverse(&self) -> ChunkDiff {
+        ChunkDiff {
+            cells: self.cells.iter().map(|c| CellChange {
+                index: c.index,
+                old: c.new.clone(),
+                new: c.old.clone(),
+            }).collect(),
+            entities: self.entities.iter().map(|e| EntityChange {
+                key: e.key.clone(),
+                old: e.new.clone(),
+                new: e.old.clone(),
+            }).collect(),
+        }
+    }
+
+    /// Applies the cell changes of this diff to a chunk.
+    pub fn apply<C: DiffableChunk>(&self, chunk: &mut C) -> SerialResult<()> {
+        let cells = chunk.cells_mut();
+        for change in self.cells.iter() {
+            cells[change.index as usize] = bincode::deserialize(&change.new)?;
+        }
+        Ok(())
+    }
+
+    /// Applies the entity changes of this diff to the entities of a chunk.
+    pub fn apply_entities<K, V, S>(&self, entities: &mut HashMap<K, V, S>) -> SerialResult<()>
+        where K: Hash + Eq + DeserializeOwned,
+              V: DeserializeOwned,
+              S: BuildHasher
+    {
+        for change in self.entities.iter() {
+            let key: K = bincode::deserialize(&change.key)?;
+            match change.new {
+                Some(ref value) => { entities.insert(key, bincode::deserialize(value)?); },
+                None            => { entities.remove(&key); },
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    struct Cells(Vec<u8>);
+
+    impl DiffableChunk for Cells {
+        type Cell = u8;
+        fn cells(&self) -> &[u8] { &self.0 }
+        fn cells_mut(&mut self) -> &mut [u8] { &mut self.0 }
+    }
+
+    #[test]
+    fn test_diff_and_revert() {
+        let old = Cells(vec![1, 2, 3, 4]);
+        let new = Cells(vec![1, 5, 3, 6]);
+        let mut old_entities = HashMap::new();
+        old_entities.insert(1, "a".to_string());
+        old_entities.insert(2, "b".to_string());
+        let mut new_entities = old_entities.clone();
+        new_entities.remove(&1);
+        new_entities.insert(3, "c".to_string());
+
+        let diff = ChunkDiff::between(&old, &new).unwrap()
+            .with_entities(&old_entities, &new_entities).unwrap();
+        assert_eq!(diff.len(), 4);
+
+        let mut chunk = Cells(old.0.clone());
+        let mut entities = old_entities.clone();
+        diff.apply(&mut chunk).unwrap();
+        diff.apply_entities(&mut entities).unwrap();
+        assert_eq!(chunk.0, new.0);
+        assert_eq!(entities, new_entities);
+
+        diff.inverse().apply(&mut chunk).unwrap();
+        diff.inverse().apply_entities(&mut entities).unwrap();
+        assert_eq!(chunk.0, old.0);
+        assert_eq!(entities, old_entities);
+    }
+}
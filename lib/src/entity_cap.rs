@@ -0,0 +1,232 @@
+use std::collections::BTreeMap;
+use std::fs::{self, File};
+use std::io::{BufReader, Write};
+use std::path::{Path, PathBuf};
+
+use bincode::{self, Infinite};
+use serde::Serialize;
+use serde::de::DeserializeOwned;
+
+use error::{self, SerialResult};
+use platform::replace_file;
+use traits::Index;
+
+/// What to do with an entity spawned in a chunk that already holds as many
+/// entities as it may.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum OverflowPolicy {
+    /// Don't spawn the entity.
+    Reject,
+    /// Place the entity in the first loaded neighboring chunk with room, or
+    /// reject it if there is none.
+    SpillToNeighbor,
+    /// Set the entity aside in a spill file until its chunk has room again.
+    SpillToFile,
+}
+
+/// Where an entity spawned in a chunk should go.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Placement<I: Index> {
+    /// The chunk has room for the entity.
+    Accepted,
+    /// The chunk is full and the entity should be moved to this neighbor.
+    Neighbor(I),
+    /// The chunk is full and the entity should be written to the spill file.
+    Spill,
+    /// The entity should not be spawned.
+    Rejected,
+}
+
+/// Limits the number of entities in a single chunk, so a crowded chunk
+/// can't grow past what its region's lookup table can address once
+/// serialized.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct EntityCap {
+    pub max_per_chunk: usize,
+    pub policy: OverflowPolicy,
+}
+
+impl EntityCap {
+    pub fn new(max_per_chunk: usize, policy: OverflowPolicy) -> Self {
+        EntityCap {
+            max_per_chunk: max_per_chunk,
+            policy: policy,
+        }
+    }
+
+    pub fn has_room(&self, count: usize) -> bool {
+        count < self.max_per_chunk
+    }
+
+    /// Decides where an entity spawned in the chunk at `index` goes.
+    /// `count` returns the number of entities in a chunk, or `None` if the
+    /// chunk isn't loaded.
+    pub fn place<I, F>(&self, index: &I, count: F) -> Placement<I>
+        where I: Index,
+              F: Fn(&I) -> Option<usize> {
+        if count(index).map_or(false, |n| self.has_room(n)) {
+            return Placement::Accepted;
+        }
+
+        match self.policy {
+            OverflowPolicy::Reject => Placement::Rejected,
+            OverflowPolicy::SpillToFile => Placement::Spill,
+            OverflowPolicy::SpillToNeighbor => {
+                for &(dx, dy) in NEIGHBORS.iter() {
+                    let neighbor = I::from_xy(index.x() + dx, index.y() + dy);
+                    if count(&neighbor).map_or(false, |n| self.has_room(n)) {
+                        return Placement::Neighbor(neighbor);
+                    }
+                }
+                Placement::Rejected
+            },
+        }
+    }
+}
+
+const NEIGHBORS: [(i32, i32); 8] = [(0, -1), (1, 0), (0, 1), (-1, 0),
+                                    (1, -1), (1, 1), (-1, 1), (-1, -1)];
+
+/// Entities that overflowed their chunk, kept in a single file beside the
+/// region files until their chunk has room for them again.
+#[derive(Debug)]
+pub struct SpillFile<E> {
+    path: PathBuf,
+    entities: BTreeMap<(i32, i32), Vec<E>>,
+}
+
+impl<E: Serialize + DeserializeOwned> SpillFile<E> {
+    /// Opens the spill file at the given path, which is empty if the file
+    /// doesn't exist yet.
+    pub fn open<P: AsRef<Path>>(path: P) -> SerialResult<Self> {
+        let path = path.as_ref().to_path_buf();
+        let entities = if path.exists() {
+            let mut reader = BufReader::new(File::open(&path)?);
+            bincode::deserialize_from(&mut reader, Infinite)?
+        } else {
+            BTreeMap::new()
+        };
+        Ok(SpillFile {
+            path: path,
+            entities: entities,
+        })
+    }
+
+    pub fn push<I: Index>(&mut self, index: &I, entity: E) {
+        self.entities.entry((index.x(), index.y())).or_insert_with(Vec::new).push(entity);
+    }
+
+    /// Removes up to `max` entities spilled from the chunk at `index`.
+    pub fn take<I: Index>(&mut self, index: &I, max: usize) -> Vec<E> {
+        let key = (index.x(), index.y());
+        let (taken, empty) = match self.entities.get_mut(&key) {
+            Some(spilled) => {
+                let n = max.min(spilled.len());
+                let taken = spilled.drain(..n).collect();
+                (taken, spilled.is_empty())
+            },
+            None => return Vec::new(),
+        };
+        if empty {
+            self.entities.remove(&key);
+        }
+        taken
+    }
+
+    /// Returns the number of entities spilled from the chunk at `index`.
+    pub fn count<I: Index>(&self, index: &I) -> usize {
+        self.entities.get(&(index.x(), index.y())).map_or(0, |e| e.len())
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entities.is_empty()
+    }
+
+    /// Writes the spilled entities to disk, or removes the file if there are
+    /// none.
+    pub fn save(&self) -> SerialResult<()> {
+        if self.entities.is_empty() {
+            if self.path.exists() {
+                fs::remove_file(&self.path)?;
+            }
+            return Ok(());
+        }
+        if let Some(parent) = self.path.parent() {
+            if !parent.as_os_str().is_empty() {
+                fs::create_dir_all(parent)?;
+            }
+        }
+
+        // Written to a temporary file first and renamed into place, so a
+        // crash can't lose the entities already spilled.
+        let temp = self.path.with_extension("spill.tmp");
+        {
+            let mut file = File::create(&temp)?;
+            let written = file.write_all(&bincode::serialize(&self.entities, Infinite)?)
+                .and_then(|_| file.sync_all());
+            if let Err(e) = written {
+                let _ = fs::remove_file(&temp);
+                return Err(error::write_error(e));
+            }
+        }
+        replace_file(&temp, &self.path)?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use testing::TempDir;
+    use traits::TestIndex;
+
+    #[test]
+    fn test_place() {
+        let count = |i: &TestIndex| match *i {
+            TestIndex(0, 0) => Some(2),
+            TestIndex(1, 0) => Some(1),
+            _               => None,
+        };
+        let origin = TestIndex(0, 0);
+
+        assert_eq!(EntityCap::new(3, OverflowPolicy::Reject).place(&origin, &count), Placement::Accepted);
+        assert_eq!(EntityCap::new(2, OverflowPolicy::Reject).place(&origin, &count), Placement::Rejected);
+        assert_eq!(EntityCap::new(2, OverflowPolicy::SpillToFile).place(&origin, &count), Placement::Spill);
+        assert_eq!(EntityCap::new(2, OverflowPolicy::SpillToNeighbor).place(&origin, &count),
+                   Placement::Neighbor(TestIndex(1, 0)));
+        assert_eq!(EntityCap::new(1, OverflowPolicy::SpillToNeighbor).place(&origin, &count),
+                   Placement::Rejected);
+    }
+
+    #[test]
+    fn test_spill_file() {
+        let dir = TempDir::new("spill");
+        let path = dir.path().join("entities.spill");
+        let index = TestIndex(-1, 2);
+
+        let mut spill = SpillFile::open(&path).unwrap();
+        for n in 0..3u32 {
+            spill.push(&index, n);
+        }
+        spill.save().unwrap();
+
+        let mut spill: SpillFile<u32> = SpillFile::open(&path).unwrap();
+        assert_eq!(spill.count(&index), 3);
+
+        // A save that fails keeps the entities spilled before.
+        fs::create_dir(path.with_extension("spill.tmp")).unwrap();
+        spill.push(&index, 3);
+        assert!(spill.save().is_err());
+        assert_eq!(SpillFile::<u32>::open(&path).unwrap().count(&index), 3);
+        fs::remove_dir(path.with_extension("spill.tmp")).unwrap();
+        assert_eq!(spill.take(&index, 1), vec![0]);
+        assert_eq!(spill.take(&index, 2), vec![1, 2]);
+        spill.save().unwrap();
+
+        let mut spill: SpillFile<u32> = SpillFile::open(&path).unwrap();
+        assert_eq!(spill.take(&index, 2), vec![3]);
+        assert!(spill.is_empty());
+        spill.save().unwrap();
+        assert!(!path.exists());
+    }
+}
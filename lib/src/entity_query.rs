@@ -0,0 +1,149 @@
+use error::SerialResult;
+use grid::{chunk_at, world_pos, ChunkArrangement};
+use traits::{ChunkedTerrain, ChunkedWorld, Index, ManagedChunk, RegionManager};
+
+/// Returns the chunks holding any cell within `radius` cells of the world
+/// position `center`, nearest first.
+pub fn chunks_within<I: Index>(center: (i32, i32), radius: i32, chunk_width: i32) -> Vec<I> {
+    let (min, _) = chunk_at::<I>(center.0 - radius, center.1 - radius, chunk_width);
+    let (max, _) = chunk_at::<I>(center.0 + radius, center.1 + radius, chunk_width);
+    let radius_sq = radius as i64 * radius as i64;
+
+    let mut chunks = Vec::new();
+    for y in min.y()..max.y() + 1 {
+        for x in min.x()..max.x() + 1 {
+            let index = I::from_xy(x, y);
+            let distance_sq = distance_sq_to_chunk(&index, center, chunk_width);
+            if distance_sq <= radius_sq {
+                chunks.push((distance_sq, index));
+            }
+        }
+    }
+    chunks.sort_by_key(|&(distance_sq, _)| distance_sq);
+    chunks.into_iter().map(|(_, index)| index).collect()
+}
+
+/// Returns whether the world position `pos` is within `radius` cells of
+/// `center`.
+pub fn within_radius(center: (i32, i32), pos: (i32, i32), radius: i32) -> bool {
+    let dx = (pos.0 - center.0) as i64;
+    let dy = (pos.1 - center.1) as i64;
+    dx * dx + dy * dy <= radius as i64 * radius as i64
+}
+
+/// Squared distance from `center` to the nearest cell of a chunk. Strips are
+/// as tall as the world, so only the x axis counts for them.
+fn distance_sq_to_chunk<I: Index>(index: &I, center: (i32, i32), chunk_width: i32) -> i64 {
+    let (ox, oy) = world_pos(index, (0, 0), chunk_width);
+    let dx = axis_distance(center.0, ox, chunk_width);
+    let dy = match I::ARRANGEMENT {
+        ChunkArrangement::Grid    => axis_distance(center.1, oy, chunk_width),
+        ChunkArrangement::Columns => 0,
+    };
+    dx * dx + dy * dy
+}
+
+fn axis_distance(pos: i32, start: i32, width: i32) -> i64 {
+    if pos < start {
+        (start - pos) as i64
+    } else if pos >= start + width {
+        (pos - (start + width - 1)) as i64
+    } else {
+        0
+    }
+}
+
+/// The entities found by `EntityQuery::entities_within`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct EntitiesWithin<I, E> {
+    pub entities: Vec<E>,
+    /// Chunks in the radius that weren't loaded, because the load budget ran
+    /// out, the admission policy deferred them or they couldn't be read. Any
+    /// entities in them are missing from `entities`.
+    pub unloaded: Vec<I>,
+}
+
+impl<I, E> EntitiesWithin<I, E> {
+    /// Returns whether every chunk in the radius was searched.
+    pub fn is_complete(&self) -> bool {
+        self.unloaded.is_empty()
+    }
+}
+
+/// A world that keeps entities in its chunks and can look them up by area,
+/// for things like monsters noticing the player, area effects and spawners.
+pub trait EntityQuery<'a, I, C, M, T>: ChunkedWorld<'a, I, C, M, T>
+    where I: Index,
+          C: ManagedChunk,
+          M: RegionManager<'a, I, C>,
+          T: ChunkedTerrain<'a, I, C, M> {
+    /// A handle to an entity, like its id.
+    type Entity;
+
+    /// Width of a chunk in cells.
+    fn chunk_width(&self) -> i32;
+
+    /// Returns the entities in a loaded chunk with their world positions.
+    fn entities_in_chunk(&self, index: &I) -> Vec<((i32, i32), Self::Entity)>;
+
+    /// Returns the entities within `radius` cells of the world position
+    /// `center`, loading at most `max_loads` of the chunks in the radius that
+    /// aren't loaded, nearest first. Loads also have to be admitted by the
+    /// admission policy.
+    ///
+    /// Chunks loaded here that aren't relevant are unloaded again by the
+    /// next `update_chunks`.
+    fn entities_within(&mut self, center: (i32, i32), radius: i32, max_loads: usize) -> SerialResult<EntitiesWithin<I, Self::Entity>> {
+        let mut found = EntitiesWithin {
+            entities: Vec::new(),
+            unloaded: Vec::new(),
+        };
+        let mut loads = 0;
+
+        for index in chunks_within::<I>(center, radius, self.chunk_width()) {
+            if !self.terrain().chunk_loaded(&index) {
+                if loads >= max_loads || !self.admit_load(&index) {
+                    found.unloaded.push(index);
+                    continue;
+                }
+                self.load_chunk(&index)?;
+                loads += 1;
+                // The chunk may have been skipped by the recovery policy.
+                if !self.terrain().chunk_loaded(&index) {
+                    found.unloaded.push(index);
+                    continue;
+                }
+            }
+
+            for (pos, entity) in self.entities_in_chunk(&index) {
+                if within_radius(center, pos, radius) {
+                    found.entities.push(entity);
+                }
+            }
+        }
+        Ok(found)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use grid::StripIndex;
+    use traits::TestIndex;
+
+    #[test]
+    fn test_chunks_within() {
+        let chunks = chunks_within::<TestIndex>((8, 8), 4, 16);
+        assert_eq!(chunks, vec![TestIndex(0, 0)]);
+
+        // The corner chunk is only touched by a larger radius.
+        let chunks = chunks_within::<TestIndex>((1, 1), 2, 16);
+        assert_eq!(chunks[0], TestIndex(0, 0));
+        assert_eq!(chunks.len(), 3);
+        assert!(!chunks.contains(&TestIndex(-1, -1)));
+        assert_eq!(chunks_within::<TestIndex>((1, 1), 3, 16).len(), 4);
+
+        let strips = chunks_within::<StripIndex>((-1, 500), 16, 16);
+        assert_eq!(strips, vec![StripIndex(-1), StripIndex(0), StripIndex(-2)]);
+    }
+}
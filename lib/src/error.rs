@@ -0,0 +1,362 @@
+use std::error::Error;
+use std::fmt;
+use std::io;
+use std::path::{Path, PathBuf};
+
+use bincode;
+
+use format::RegionConfig;
+use region::{RegionIndex, RegionLocalIndex};
+use world_lock::LockOwner;
+
+pub use self::LogicError::*;
+
+/// Any error that can occur while loading, saving or tracking chunks.
+///
+/// Errors are split into logical errors, which describe the state of a chunk
+/// and are usually recoverable, and hard failures of the underlying storage or
+/// encoding.
+#[derive(Debug)]
+pub enum SerialError {
+    /// The requested operation doesn't make sense for the current state of a
+    /// chunk.
+    Logic(LogicError),
+    /// Reading from or writing to a region file failed.
+    IoError(io::Error),
+    /// Chunk data couldn't be serialized or deserialized.
+    EncodingError(bincode::ErrorKind),
+    /// A region file isn't in the expected format.
+    Format(FormatError),
+    /// The disk ran out of space while saving. Nothing written so far is
+    /// referenced by the region file and the chunk stays in memory, so the
+    /// save can be retried once space was freed.
+    DiskFull,
+    /// Another error along with the operation, chunk and region file it
+    /// occurred in.
+    Context(ErrorContext, Box<SerialError>),
+}
+
+/// Describes what was being done, and to which chunk and region file, when
+/// an error occurred.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ErrorContext {
+    pub operation: &'static str,
+    pub region: Option<RegionIndex>,
+    pub chunk: Option<RegionLocalIndex>,
+    pub path: Option<PathBuf>,
+}
+
+impl ErrorContext {
+    pub fn new(operation: &'static str) -> Self {
+        ErrorContext {
+            operation: operation,
+            region: None,
+            chunk: None,
+            path: None,
+        }
+    }
+
+    pub fn with_region(mut self, region: RegionIndex) -> Self {
+        self.region = Some(region);
+        self
+    }
+
+    pub fn with_chunk(mut self, chunk: RegionLocalIndex) -> Self {
+        self.chunk = Some(chunk);
+        self
+    }
+
+    pub fn with_path<P: AsRef<Path>>(mut self, path: P) -> Self {
+        self.path = Some(path.as_ref().to_path_buf());
+        self
+    }
+
+    /// Fills in the fields this context is missing from an outer one. The
+    /// operation is kept, since the innermost one is the most specific.
+    fn merge(&mut self, outer: ErrorContext) {
+        if self.region.is_none() { self.region = outer.region; }
+        if self.chunk.is_none()  { self.chunk = outer.chunk; }
+        if self.path.is_none()   { self.path = outer.path; }
+    }
+}
+
+impl fmt::Display for ErrorContext {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "failed to {}", self.operation)?;
+        if let Some(ref chunk) = self.chunk {
+            write!(f, " chunk {}", chunk)?;
+        }
+        if let Some(ref region) = self.region {
+            write!(f, " in region {}", region)?;
+        }
+        if let Some(ref path) = self.path {
+            write!(f, " ({})", path.display())?;
+        }
+        Ok(())
+    }
+}
+
+/// Adds context to the error of a failed operation.
+pub trait WithContext<T> {
+    fn with_context<F>(self, context: F) -> SerialResult<T>
+        where F: FnOnce() -> ErrorContext;
+}
+
+impl<T, E: Into<SerialError>> WithContext<T> for Result<T, E> {
+    fn with_context<F>(self, context: F) -> SerialResult<T>
+        where F: FnOnce() -> ErrorContext
+    {
+        self.map_err(|e| e.into().context(context()))
+    }
+}
+
+/// An error describing the state of a chunk, such as it not existing in the
+/// savefile yet.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum LogicError {
+    NoChunkInWorld(i32, i32),
+    NoChunkInSavefile(RegionLocalIndex),
+    ChunkAlreadyLoaded(i32, i32),
+    /// The chunk was read from disk or created this session and hasn't been
+    /// saved since, so the copy on disk is out of date.
+    ChunkUnsaved(RegionLocalIndex),
+    /// The chunk was saved without having been created or loaded.
+    ChunkNotTracked(RegionLocalIndex),
+    /// The chunk is protected from changes by normal gameplay. See
+    /// `ChunkProtection`.
+    ChunkProtected(i32, i32),
+    /// An edit was made against an older version of the chunk than the
+    /// current one, given last. See `ChunkedWorld::edit_chunk`.
+    StaleVersion(i32, i32, u64),
+    /// Another process holds the lock of the world. See `WorldLock`.
+    WorldLocked(LockOwner),
+    /// The lock file of the world can't be read, so it isn't known whether
+    /// another process holds it. See `WorldLock::take_over`.
+    WorldLockDamaged,
+}
+
+/// An error describing why a region file can't be read.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum FormatError {
+    /// The file has no header, so it was written by an older version of this
+    /// library.
+    MissingHeader,
+    UnsupportedVersion(u32),
+    /// The file was written with different region parameters than the ones
+    /// in use, so its chunks would be mis-addressed. Use `migrate_layout` to
+    /// convert it.
+    LayoutMismatch {
+        expected: RegionConfig,
+        found: RegionConfig,
+    },
+    /// The data is compressed, but this build of the library has the
+    /// `compression` feature disabled.
+    CompressionDisabled,
+    /// A chunk read back right after it was written differs from what was
+    /// written. Only checked if `ManagedChunk::VERIFY_WRITES` is set.
+    WriteMismatch(RegionLocalIndex),
+    /// A chunk needs more sectors than a lookup table entry can hold. See
+    /// `format::MAX_SECTORS`.
+    ChunkTooLarge {
+        index: RegionLocalIndex,
+        sectors: usize,
+    },
+    /// A chunk would have to be stored past the last sector a lookup table
+    /// entry can point to.
+    RegionFull(RegionLocalIndex),
+    /// The chunks of a region lie outside the range of `i32` coordinates.
+    IndexOverflow(RegionIndex),
+    /// A region file header holds parameters no region can have, such as a
+    /// region width of zero.
+    InvalidConfig(RegionConfig),
+    /// A lookup table entry restored from a `RegionTableSnapshot` doesn't
+    /// point at the chunk data it pointed at when the snapshot was taken.
+    TableMismatch(RegionLocalIndex),
+    /// The world was first saved with different region parameters than the
+    /// chunk type in use has, so its chunks would be mis-addressed. Use
+    /// `migrate_layout` to convert it.
+    WorldLayoutMismatch {
+        world: RegionConfig,
+        in_use: RegionConfig,
+    },
+    /// The length prefix of a chunk's data claims more bytes than were
+    /// stored, such as after the file was cut short.
+    TruncatedData {
+        expected: usize,
+        found: usize,
+    },
+}
+
+pub type SerialResult<T> = Result<T, SerialError>;
+
+impl SerialError {
+    /// Wraps this error with context, merging it into any context the error
+    /// already has.
+    pub fn context(self, context: ErrorContext) -> SerialError {
+        match self {
+            SerialError::Context(mut inner, error) => {
+                inner.merge(context);
+                SerialError::Context(inner, error)
+            },
+            error => SerialError::Context(context, Box::new(error)),
+        }
+    }
+
+    /// Returns the context of this error, if any was added.
+    pub fn error_context(&self) -> Option<&ErrorContext> {
+        match *self {
+            SerialError::Context(ref context, _) => Some(context),
+            _                                    => None,
+        }
+    }
+
+    /// Returns the underlying error without any context.
+    pub fn root(&self) -> &SerialError {
+        match *self {
+            SerialError::Context(_, ref error) => error.root(),
+            ref error                          => error,
+        }
+    }
+
+    /// Returns the logical error, if this is one.
+    pub fn logic(&self) -> Option<&LogicError> {
+        match *self.root() {
+            SerialError::Logic(ref e) => Some(e),
+            _                         => None,
+        }
+    }
+
+    /// Returns true if this error means a chunk was never saved.
+    pub fn is_missing_chunk(&self) -> bool {
+        match self.logic() {
+            Some(&NoChunkInSavefile(_)) => true,
+            _                           => false,
+        }
+    }
+
+    /// Returns true if another process holds the lock of the world, or may
+    /// hold it since its lock file is damaged.
+    pub fn is_world_locked(&self) -> bool {
+        match self.logic() {
+            Some(&WorldLocked(_)) |
+            Some(&WorldLockDamaged) => true,
+            _                       => false,
+        }
+    }
+
+    /// Returns true if the disk ran out of space.
+    pub fn is_disk_full(&self) -> bool {
+        match *self.root() {
+            SerialError::DiskFull => true,
+            _                     => false,
+        }
+    }
+
+    /// Returns true if this error was caused by a failure of the underlying
+    /// storage or encoding rather than the state of a chunk.
+    pub fn is_fatal(&self) -> bool {
+        self.logic().is_none()
+    }
+}
+
+impl fmt::Display for SerialError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            SerialError::Logic(ref e)     => write!(f, "{}", e),
+            SerialError::IoError(_)       => write!(f, "region file I/O failed"),
+            SerialError::EncodingError(_) => write!(f, "chunk data could not be encoded or decoded"),
+            SerialError::Format(ref e)    => write!(f, "{}", e),
+            SerialError::DiskFull         => write!(f, "no space left on disk"),
+            SerialError::Context(ref context, ref e) => write!(f, "{}: {}", context, e),
+        }
+    }
+}
+
+impl Error for SerialError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match *self {
+            SerialError::Logic(ref e)         => Some(e),
+            SerialError::IoError(ref e)       => Some(e),
+            SerialError::EncodingError(ref e) => Some(e),
+            SerialError::Format(ref e)        => Some(e),
+            SerialError::DiskFull             => None,
+            SerialError::Context(_, ref e)    => e.source(),
+        }
+    }
+}
+
+impl fmt::Display for LogicError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            NoChunkInWorld(x, y)         => write!(f, "chunk ({}, {}) is not loaded", x, y),
+            NoChunkInSavefile(ref index) => write!(f, "chunk at region-local index {} is not in the savefile", index),
+            ChunkAlreadyLoaded(x, y)     => write!(f, "chunk ({}, {}) is already loaded", x, y),
+            ChunkUnsaved(ref index)      => write!(f, "chunk at region-local index {} has unsaved changes", index),
+            ChunkNotTracked(ref index)   => write!(f, "chunk at region-local index {} was saved without being loaded", index),
+            ChunkProtected(x, y)         => write!(f, "chunk ({}, {}) is protected", x, y),
+            StaleVersion(x, y, current)  => write!(f, "chunk ({}, {}) was changed since the edit was made, and is at version {}", x, y, current),
+            WorldLocked(ref owner)       => write!(f, "world is locked by process {} on {:?}", owner.pid, owner.host),
+            WorldLockDamaged             => write!(f, "world lock file is damaged"),
+        }
+    }
+}
+
+impl Error for LogicError {}
+
+impl fmt::Display for FormatError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            FormatError::MissingHeader          => write!(f, "region file has no header"),
+            FormatError::UnsupportedVersion(v)  => write!(f, "unsupported region file version {}", v),
+            FormatError::LayoutMismatch { ref expected, ref found } =>
+                write!(f, "region file was written with {}, but {} is in use", found, expected),
+            FormatError::CompressionDisabled    => write!(f, "data is compressed, but the compression feature is disabled"),
+            FormatError::WriteMismatch(ref index) => write!(f, "chunk at region-local index {} reads back differently than it was written", index),
+            FormatError::ChunkTooLarge { ref index, sectors } =>
+                write!(f, "chunk at region-local index {} needs {} sectors, more than a region can address", index, sectors),
+            FormatError::RegionFull(ref index) => write!(f, "no sector a region can address is free for the chunk at region-local index {}", index),
+            FormatError::IndexOverflow(ref index) => write!(f, "chunks of region {} are outside the range of chunk coordinates", index),
+            FormatError::InvalidConfig(ref config) => write!(f, "region file header has invalid parameters: {}", config),
+            FormatError::TableMismatch(ref index) => write!(f, "snapshot entry of the chunk at region-local index {} doesn't match its data", index),
+            FormatError::WorldLayoutMismatch { ref world, ref in_use } =>
+                write!(f, "world was saved with {}, but {} is in use", world, in_use),
+            FormatError::TruncatedData { expected, found } =>
+                write!(f, "chunk data should be {} bytes long, but only {} were stored", expected, found),
+        }
+    }
+}
+
+impl Error for FormatError {}
+
+impl From<FormatError> for SerialError {
+    fn from(e: FormatError) -> SerialError {
+        SerialError::Format(e)
+    }
+}
+
+impl From<LogicError> for SerialError {
+    fn from(e: LogicError) -> SerialError {
+        SerialError::Logic(e)
+    }
+}
+
+/// Converts an error from a write, reporting a full disk as `DiskFull`.
+pub(crate) fn write_error(e: io::Error) -> SerialError {
+    match e.kind() {
+        io::ErrorKind::StorageFull |
+        io::ErrorKind::WriteZero   => SerialError::DiskFull,
+        _                          => SerialError::IoError(e),
+    }
+}
+
+impl From<io::Error> for SerialError {
+    fn from(e: io::Error) -> SerialError {
+        SerialError::IoError(e)
+    }
+}
+
+impl From<Box<bincode::ErrorKind>> for SerialError {
+    fn from(e: Box<bincode::ErrorKind>) -> SerialError {
+        SerialError::EncodingError(*e)
+    }
+}
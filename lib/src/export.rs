@@ -0,0 +1,195 @@
+use std::fs::{self, File};
+use std::io::{self, BufWriter};
+use std::path::{Path, PathBuf};
+
+use png;
+
+use error::{self, ErrorContext, SerialResult, WithContext};
+use layout::RegionLayout;
+use migrate::region_files;
+use prune::visit_region;
+use region::RegionIndex;
+use traits::ManagedChunk;
+
+/// A color as red, green and blue bytes.
+pub type Rgb = [u8; 3];
+
+/// Renders the saved chunks of a world into PNG maps, reading the region
+/// files directly so the world doesn't have to be loaded or running. The
+/// color of every cell comes from a callback given the chunk and the cell's
+/// position inside it.
+///
+/// Every pixel shows one cell, or the top left cell of a square of `scale`
+/// cells. Cells of chunks that were never saved are left in the background
+/// color. Only worlds arranged in a grid are supported.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct MapExporter {
+    chunk_width: i32,
+    scale: i32,
+    background: Rgb,
+}
+
+impl MapExporter {
+    pub fn new(chunk_width: i32) -> Self {
+        MapExporter {
+            chunk_width: chunk_width,
+            scale: 1,
+            background: [0, 0, 0],
+        }
+    }
+
+    /// Shows only every `scale`th cell along each axis, to keep maps of large
+    /// worlds to a manageable size.
+    pub fn with_scale(mut self, scale: i32) -> Self {
+        assert!(scale > 0, "Map scale must be positive!");
+        self.scale = scale;
+        self
+    }
+
+    pub fn with_background(mut self, color: Rgb) -> Self {
+        self.background = color;
+        self
+    }
+
+    /// Writes one image per region file into `dir`, named after the region
+    /// like `-1.2.png`, so worlds of any size can be exported without holding
+    /// more than a region's image in memory. Returns the paths written.
+    pub fn export_tiles<C, P, F>(&self, layout: &RegionLayout, dir: P, mut color: F) -> SerialResult<Vec<PathBuf>>
+        where C: ManagedChunk,
+              P: AsRef<Path>,
+              F: FnMut(&C, (i32, i32)) -> Rgb
+    {
+        let dir = dir.as_ref();
+        fs::create_dir_all(dir).with_context(|| ErrorContext::new("export map").with_path(dir))?;
+
+        let mut written = Vec::new();
+        for (index, path) in region_files(layout)?.into_iter() {
+            let mut canvas = self.canvas::<C>(&index, &index);
+            visit_region::<C, _, _>(&path, &index, |pos, chunk| {
+                canvas.paint(pos, &chunk, &mut color);
+                Ok(())
+            })?;
+
+            let dest = dir.join(format!("{}.{}.png", index.0, index.1));
+            canvas.save(&dest).with_context(|| ErrorContext::new("export map").with_region(index.clone()).with_path(&dest))?;
+            written.push(dest);
+        }
+        Ok(written)
+    }
+
+    /// Writes a single image of every saved chunk to `path`. The image spans
+    /// every region file of the world, so set a scale for large worlds.
+    /// Writes nothing and returns `false` if the world has no regions.
+    pub fn export_overview<C, P, F>(&self, layout: &RegionLayout, path: P, mut color: F) -> SerialResult<bool>
+        where C: ManagedChunk,
+              P: AsRef<Path>,
+              F: FnMut(&C, (i32, i32)) -> Rgb
+    {
+        let regions = region_files(layout)?;
+        let (min, max) = match regions.first() {
+            Some(&(ref first, _)) => regions.iter().fold((*first, *first), |(min, max), &(ref r, _)| {
+                (RegionIndex(min.0.min(r.0), min.1.min(r.1)), RegionIndex(max.0.max(r.0), max.1.max(r.1)))
+            }),
+            None => return Ok(false),
+        };
+
+        let mut canvas = self.canvas::<C>(&min, &max);
+        for &(ref index, ref region_path) in regions.iter() {
+            visit_region::<C, _, _>(region_path, index, |pos, chunk| {
+                canvas.paint(pos, &chunk, &mut color);
+                Ok(())
+            })?;
+        }
+
+        let path = path.as_ref();
+        canvas.save(path).with_context(|| ErrorContext::new("export map").with_path(path))?;
+        Ok(true)
+    }
+
+    /// Returns an empty canvas covering the regions from `min` to `max`.
+    fn canvas<C: ManagedChunk>(&self, min: &RegionIndex, max: &RegionIndex) -> Canvas {
+        let region_cells = C::REGION_WIDTH as i64 * self.chunk_width as i64;
+        let pixels = |from: i32, to: i32| {
+            let cells = (to as i64 - from as i64 + 1) * region_cells;
+            ((cells + self.scale as i64 - 1) / self.scale as i64) as u32
+        };
+        let (width, height) = (pixels(min.0, max.0), pixels(min.1, max.1));
+
+        let mut pixels = Vec::with_capacity(width as usize * height as usize * 3);
+        for _ in 0..width as usize * height as usize {
+            pixels.extend_from_slice(&self.background);
+        }
+        Canvas {
+            origin: (min.0 as i64 * region_cells, min.1 as i64 * region_cells),
+            chunk_width: self.chunk_width,
+            scale: self.scale as i64,
+            width: width,
+            height: height,
+            pixels: pixels,
+        }
+    }
+}
+
+/// The pixels of a map being rendered. `origin` is the world position of
+/// the cell in the top left corner.
+struct Canvas {
+    origin: (i64, i64),
+    chunk_width: i32,
+    scale: i64,
+    width: u32,
+    height: u32,
+    pixels: Vec<u8>,
+}
+
+impl Canvas {
+    /// Paints the cells of the chunk at `pos` that fall on a pixel.
+    fn paint<C, F>(&mut self, pos: (i32, i32), chunk: &C, color: &mut F)
+        where F: FnMut(&C, (i32, i32)) -> Rgb
+    {
+        for ly in 0..self.chunk_width {
+            let dy = pos.1 as i64 * self.chunk_width as i64 + ly as i64 - self.origin.1;
+            if dy < 0 || dy % self.scale != 0 || dy / self.scale >= self.height as i64 {
+                continue;
+            }
+            for lx in 0..self.chunk_width {
+                let dx = pos.0 as i64 * self.chunk_width as i64 + lx as i64 - self.origin.0;
+                if dx < 0 || dx % self.scale != 0 || dx / self.scale >= self.width as i64 {
+                    continue;
+                }
+                let i = 3 * ((dy / self.scale) as usize * self.width as usize + (dx / self.scale) as usize);
+                self.pixels[i..i + 3].copy_from_slice(&color(chunk, (lx, ly)));
+            }
+        }
+    }
+
+    fn save(&self, path: &Path) -> SerialResult<()> {
+        let file = File::create(path)?;
+        let mut encoder = png::Encoder::new(BufWriter::new(file), self.width, self.height);
+        encoder.set_color(png::ColorType::Rgb);
+        encoder.set_depth(png::BitDepth::Eight);
+        encoder.write_header()
+            .and_then(|mut writer| writer.write_image_data(&self.pixels))
+            .map_err(|e| error::write_error(io::Error::from(e)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_paint() {
+        let mut canvas = Canvas {
+            origin: (-4, 0),
+            chunk_width: 2,
+            scale: 2,
+            width: 4,
+            height: 1,
+            pixels: vec![9; 12],
+        };
+
+        // Only the top left cell of every 2x2 square is drawn.
+        canvas.paint((-1, 0), &(), &mut |_: &(), (x, y)| [x as u8, y as u8, 1]);
+        assert_eq!(&canvas.pixels[..], &[9, 9, 9, 0, 0, 1, 9, 9, 9, 9, 9, 9]);
+    }
+}
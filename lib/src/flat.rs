@@ -0,0 +1,134 @@
+use grid::split_coord;
+use traits::Index;
+
+/// One layer of a `FlatGenerator`. Positions and sizes are in cells of the
+/// world, so layers line up across chunk borders.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub enum FlatLayer<T> {
+    /// Covers the whole world.
+    Fill(T),
+    /// Repeats a pattern, given row by row with `width` cells per row,
+    /// across the whole world starting at the origin.
+    Tile { width: i32, cells: Vec<T> },
+    /// Lines every `spacing` cells along both axes, like roads or walls
+    /// between plots.
+    Grid { spacing: i32, cell: T },
+    /// A rectangle, like a building or a spawn platform.
+    Rect { x: i32, y: i32, width: i32, height: i32, cell: T },
+}
+
+impl<T> FlatLayer<T> {
+    /// Returns the cell this layer puts at a world position, if it covers
+    /// it.
+    pub fn cell_at(&self, x: i32, y: i32) -> Option<&T> {
+        match *self {
+            FlatLayer::Fill(ref cell) => Some(cell),
+            FlatLayer::Tile { width, ref cells } => {
+                if width <= 0 || cells.len() < width as usize {
+                    return None;
+                }
+                let height = cells.len() as i32 / width;
+                let (_, tx) = split_coord(x, width);
+                let (_, ty) = split_coord(y, height);
+                cells.get((ty * width + tx) as usize)
+            },
+            FlatLayer::Grid { spacing, ref cell } => {
+                if spacing > 0 && (split_coord(x, spacing).1 == 0 || split_coord(y, spacing).1 == 0) {
+                    Some(cell)
+                } else {
+                    None
+                }
+            },
+            FlatLayer::Rect { x: rx, y: ry, width, height, ref cell } => {
+                if x >= rx && x < rx + width && y >= ry && y < ry + height {
+                    Some(cell)
+                } else {
+                    None
+                }
+            },
+        }
+    }
+}
+
+/// Generates chunks from a stack of `FlatLayer`s instead of noise, for
+/// worlds that don't need terrain, like lobbies and test worlds, or for
+/// benchmarking streaming without the cost of generation. The same template
+/// always gives the same chunks.
+///
+/// Layers are applied in the order they were added over a base cell, and
+/// later layers cover earlier ones. The generator is serializable, so it can be kept in the
+/// world metadata to keep generating the same world after a restart.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct FlatGenerator<T> {
+    base: T,
+    layers: Vec<FlatLayer<T>>,
+}
+
+impl<T: Clone> FlatGenerator<T> {
+    /// Creates a generator filling the world with `base`.
+    pub fn new(base: T) -> Self {
+        FlatGenerator {
+            base: base,
+            layers: Vec::new(),
+        }
+    }
+
+    pub fn with_layer(mut self, layer: FlatLayer<T>) -> Self {
+        self.layers.push(layer);
+        self
+    }
+
+    pub fn layers(&self) -> &[FlatLayer<T>] {
+        &self.layers
+    }
+
+    /// Returns the cell at a world position.
+    pub fn cell_at(&self, x: i32, y: i32) -> &T {
+        self.layers.iter().rev()
+            .filter_map(|layer| layer.cell_at(x, y))
+            .next()
+            .unwrap_or(&self.base)
+    }
+
+    /// Replaces the contents of `cells` with the cells of a chunk
+    /// `chunk_width` cells wide, row by row. Taking the buffer lets it come
+    /// from a `BufferPool`.
+    pub fn fill_chunk<I: Index>(&self, index: &I, chunk_width: i32, cells: &mut Vec<T>) {
+        cells.clear();
+        let (x0, y0) = (index.x() * chunk_width, index.y() * chunk_width);
+        for y in y0..y0 + chunk_width {
+            for x in x0..x0 + chunk_width {
+                cells.push(self.cell_at(x, y).clone());
+            }
+        }
+    }
+
+    /// Returns the cells of a chunk `chunk_width` cells wide, row by row.
+    pub fn chunk_cells<I: Index>(&self, index: &I, chunk_width: i32) -> Vec<T> {
+        let mut cells = Vec::with_capacity((chunk_width * chunk_width) as usize);
+        self.fill_chunk(index, chunk_width, &mut cells);
+        cells
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use traits::TestIndex;
+
+    #[test]
+    fn test_flat_generator() {
+        let generator = FlatGenerator::new('.')
+            .with_layer(FlatLayer::Tile { width: 2, cells: vec!['a', 'b', 'c', 'd'] })
+            .with_layer(FlatLayer::Grid { spacing: 4, cell: '#' })
+            .with_layer(FlatLayer::Rect { x: -2, y: 1, width: 1, height: 1, cell: '@' });
+
+        let chunk: String = generator.chunk_cells(&TestIndex(-1, 0), 4).into_iter().collect();
+        assert_eq!(chunk, "####\
+                           #d@d\
+                           #bab\
+                           #dcd");
+        assert_eq!(*generator.cell_at(-1, -1), 'd');
+        assert_eq!(FlatGenerator::new(0u8).chunk_cells(&TestIndex(3, 3), 2), vec![0; 4]);
+    }
+}
@@ -0,0 +1,72 @@
+use std::fs;
+use std::io;
+use std::path::Path;
+
+use error::{ErrorContext, SerialResult, WithContext};
+use layout::RegionLayout;
+use migrate::region_files;
+use sidecar::{sidecar_files, SIDECAR_EXTENSIONS};
+
+/// Copies the saved files of a world into a new root directory and returns
+/// the layout of the copy. The copy uses the same naming scheme and
+/// subdirectory as the original. Every sidecar file is copied, including
+/// those of regions without a region file.
+///
+/// Fails if a world already exists at the destination.
+pub fn copy_world<P: AsRef<Path>>(layout: &RegionLayout, dest: P) -> SerialResult<RegionLayout> {
+    let copy = layout.with_root(dest);
+    if copy.metadata_path().exists() || !region_files(&copy)?.is_empty() {
+        let e = io::Error::new(io::ErrorKind::AlreadyExists, "a world already exists at the destination");
+        return Err(e).with_context(|| ErrorContext::new("fork world").with_path(copy.root()));
+    }
+
+    fs::create_dir_all(copy.directory())?;
+    for (index, path) in region_files(layout)?.into_iter() {
+        let dest = copy.region_path(&index);
+        fs::copy(&path, &dest)
+            .with_context(|| ErrorContext::new("fork world").with_region(index.clone()).with_path(&path))?;
+    }
+    for (index, extension, path) in sidecar_files(layout, &SIDECAR_EXTENSIONS)?.into_iter() {
+        fs::copy(&path, copy.sidecar_path(&index, extension))
+            .with_context(|| ErrorContext::new("fork world").with_region(index.clone()).with_path(&path))?;
+    }
+
+    for &(ref from, ref to) in [(layout.metadata_path(), copy.metadata_path()),
+                                (layout.archive_path(), copy.archive_path()),
+                                (layout.spill_path(), copy.spill_path()),
+                                (layout.store_path(), copy.store_path())].iter() {
+        if from.exists() {
+            fs::copy(from, to).with_context(|| ErrorContext::new("fork world").with_path(from))?;
+        }
+    }
+    Ok(copy)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs::File;
+    use inbox::INBOX_EXTENSION;
+    use region::RegionIndex;
+    use testing::TempDir;
+    use versions::VERSIONS_EXTENSION;
+
+    #[test]
+    fn test_copy_world() {
+        let dir = TempDir::new("fork");
+        let layout = RegionLayout::new(dir.path().join("a")).with_subdirectory("region");
+        fs::create_dir_all(layout.directory()).unwrap();
+        File::create(layout.region_path(&RegionIndex(0, 1))).unwrap();
+        File::create(layout.metadata_path()).unwrap();
+        fs::write(layout.sidecar_path(&RegionIndex(0, 1), VERSIONS_EXTENSION), b"ver").unwrap();
+        // Messages to a region that was never saved.
+        fs::write(layout.sidecar_path(&RegionIndex(2, 2), INBOX_EXTENSION), b"inbox").unwrap();
+
+        let copy = copy_world(&layout, dir.path().join("b")).unwrap();
+        assert!(copy.region_path(&RegionIndex(0, 1)).exists());
+        assert!(copy.metadata_path().exists());
+        assert_eq!(fs::read(copy.sidecar_path(&RegionIndex(0, 1), VERSIONS_EXTENSION)).unwrap(), b"ver");
+        assert_eq!(fs::read(copy.sidecar_path(&RegionIndex(2, 2), INBOX_EXTENSION)).unwrap(), b"inbox");
+        assert!(copy_world(&layout, dir.path().join("b")).is_err());
+    }
+}
@@ -0,0 +1,338 @@
+//! The layout of region files on disk.
+//!
+//! Every multi-byte integer has a fixed byte order, so saves can be moved
+//! between platforms:
+//!
+//! - Header fields are big-endian.
+//! - The length prefix in front of each chunk's data is little-endian.
+//! - Lookup table entries are single bytes: the sector a chunk's data starts
+//!   at, then the number of sectors it takes up. This limits how large a
+//!   chunk and a region file can get; see `MAX_SECTORS`.
+//! - Chunk data and metadata are encoded with bincode's top-level functions,
+//!   which always use little-endian, fixed-size integers.
+
+use std::fmt;
+use std::fs::File;
+use std::io::{self, SeekFrom};
+use std::io::prelude::*;
+
+use error::{self, FormatError, SerialResult};
+use metadata::WorldMetadata;
+use region::{RegionIndex, RegionLocalIndex};
+use traits::ManagedChunk;
+
+/// Bytes every region file starts with.
+pub const REGION_MAGIC: [u8; 4] = *b"IGRN";
+
+/// The current version of the region file format.
+pub const REGION_VERSION: u32 = 1;
+
+/// Size of the header preceding the lookup table of a region file. It holds
+/// the magic bytes, the format version, the region width and the sector size,
+/// each stored as a big-endian u32 after the magic.
+pub const HEADER_SIZE: u64 = 16;
+
+/// The highest sector a chunk's data can start at and the most sectors it can
+/// take up, as both are stored in a byte of its lookup table entry. Writing a
+/// chunk fails with `FormatError::ChunkTooLarge` or `FormatError::RegionFull`
+/// past these limits.
+pub const MAX_SECTORS: usize = 255;
+
+/// How the data of a chunk is compressed. The level is recorded in front of
+/// each chunk's data, so chunks in the same region file can use different
+/// levels.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CompressionLevel {
+    /// Stored without compression.
+    Stored,
+    Fast,
+    Default,
+    Best,
+}
+
+/// Set in the length prefix of a chunk's data if it is stored without
+/// compression.
+const STORED_FLAG: u32 = 1 << 31;
+
+/// The compression level is kept in the three bits below the stored flag.
+/// Files written before levels were recorded have these bits unset, which
+/// reads as the default level.
+const LEVEL_SHIFT: u32 = 28;
+const LENGTH_MASK: u32 = (1 << LEVEL_SHIFT) - 1;
+
+impl CompressionLevel {
+    /// Encodes the level and the length of a chunk's data into the prefix
+    /// stored in front of it.
+    pub fn encode_prefix(&self, len: usize) -> u32 {
+        assert!(len as u32 & !LENGTH_MASK == 0, "Chunk data too large!");
+        let code = match *self {
+            CompressionLevel::Stored  => return STORED_FLAG | len as u32,
+            CompressionLevel::Default => 0,
+            CompressionLevel::Fast    => 1,
+            CompressionLevel::Best    => 2,
+        };
+        (code << LEVEL_SHIFT) | len as u32
+    }
+
+    /// Decodes the level and length of a chunk's data from its prefix.
+    pub fn decode_prefix(prefix: u32) -> (CompressionLevel, usize) {
+        let len = (prefix & LENGTH_MASK) as usize;
+        if prefix & STORED_FLAG != 0 {
+            return (CompressionLevel::Stored, len);
+        }
+        let level = match (prefix >> LEVEL_SHIFT) & 0x7 {
+            1 => CompressionLevel::Fast,
+            2 => CompressionLevel::Best,
+            _ => CompressionLevel::Default,
+        };
+        (level, len)
+    }
+}
+
+/// The storage parameters a region file was written with.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct RegionConfig {
+    pub region_width: i32,
+    pub sector_size: usize,
+}
+
+impl fmt::Display for RegionConfig {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "region width {}, sector size {}", self.region_width, self.sector_size)
+    }
+}
+
+impl RegionConfig {
+    /// Returns the parameters used for regions of the chunk type `C`.
+    pub fn of<C: ManagedChunk>() -> Self {
+        RegionConfig {
+            region_width: C::REGION_WIDTH,
+            sector_size: C::SECTOR_SIZE,
+        }
+    }
+
+    pub fn lookup_table_size(&self) -> u64 {
+        let width = self.region_width as u64;
+        width * width * 2
+    }
+
+    /// Returns the byte offset chunk data starts at.
+    pub fn data_start(&self) -> u64 {
+        HEADER_SIZE + self.lookup_table_size()
+    }
+
+    /// Returns the byte offset of the lookup table entry of a chunk.
+    pub fn entry_offset(&self, index: &RegionLocalIndex) -> u64 {
+        HEADER_SIZE + 2 * ((index.0 % self.region_width) +
+                           ((index.1 % self.region_width) * self.region_width)) as u64
+    }
+
+    /// Checks that a world's regions are written with these parameters, so
+    /// two chunk types with different parameters can't share a save
+    /// directory. Records them in the world's metadata if none were
+    /// recorded yet, and fails with `FormatError::WorldLayoutMismatch` if
+    /// different ones were.
+    pub fn check_world(&self, metadata: &mut WorldMetadata) -> SerialResult<()> {
+        match metadata.region_config()? {
+            Some(world) if world != *self => Err(FormatError::WorldLayoutMismatch {
+                world: world,
+                in_use: *self,
+            }.into()),
+            Some(_) => Ok(()),
+            None    => metadata.set_region_config(self),
+        }
+    }
+
+    /// Checks that regions can be laid out with these parameters.
+    pub fn validate(&self) -> SerialResult<()> {
+        let width_fits = self.region_width > 0 &&
+            self.region_width.checked_mul(self.region_width).is_some();
+        if !width_fits || self.sector_size == 0 {
+            return Err(FormatError::InvalidConfig(*self).into());
+        }
+        Ok(())
+    }
+
+    /// Returns the grid position of the chunk at `local` inside the region
+    /// at `region`.
+    pub fn chunk_position(&self, region: &RegionIndex, local: &RegionLocalIndex) -> SerialResult<(i32, i32)> {
+        let coord = |r: i32, l: i32| r.checked_mul(self.region_width).and_then(|c| c.checked_add(l));
+        match (coord(region.0, local.0), coord(region.1, local.1)) {
+            (Some(x), Some(y)) => Ok((x, y)),
+            _                  => Err(FormatError::IndexOverflow(*region).into()),
+        }
+    }
+
+    /// Returns the number of sectors needed to hold `len` bytes.
+    pub fn sectors_for(&self, len: usize) -> usize {
+        (len + self.sector_size - 1) / self.sector_size
+    }
+}
+
+/// What is written after a chunk's data to fill up its last sector.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Padding {
+    /// The rest of the sector is filled with zeroes, so nothing that was
+    /// stored there before is left behind the chunk.
+    ZeroFill,
+    /// Only the chunk's data is written, which saves writing up to a sector
+    /// of zeroes per chunk. Whatever was stored in the rest of the sector
+    /// before stays there, and the last chunk of a file may end before its
+    /// last sector does.
+    None,
+}
+
+/// How region files are sized when they are created.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Preallocation {
+    /// Files grow as chunks are written.
+    None,
+    /// Every chunk gets a fixed slot of this many sectors, and the file is
+    /// extended to hold all of them without writing anything. On file
+    /// systems with sparse files, disk space is only used once a slot is
+    /// written to.
+    Sparse(u8),
+    /// Like `Sparse`, but the slots are filled with zeroes so the disk space
+    /// is allocated up front and running out of space can't happen later.
+    Full(u8),
+}
+
+/// Points the lookup table entry of every chunk at its own slot of
+/// `sectors` sectors and extends the file to hold all of them. Chunks that
+/// outgrow their slot are moved to the end of the file like usual.
+pub fn preallocate(file: &mut File, config: &RegionConfig, preallocation: Preallocation) -> SerialResult<()> {
+    let (sectors, fill) = match preallocation {
+        Preallocation::None          => return Ok(()),
+        Preallocation::Sparse(n)     => (n, false),
+        Preallocation::Full(n)       => (n, true),
+    };
+    let chunks = (config.region_width * config.region_width) as usize;
+    if sectors == 0 || (chunks - 1) * sectors as usize > MAX_SECTORS {
+        return Err(io::Error::new(io::ErrorKind::InvalidInput,
+                                  "preallocated slots don't fit in the lookup table").into());
+    }
+
+    let mut table = Vec::with_capacity(2 * chunks);
+    for i in 0..chunks {
+        table.push((i * sectors as usize) as u8);
+        table.push(sectors);
+    }
+
+    let slot_size = sectors as usize * config.sector_size;
+    let end = config.data_start() + (chunks * slot_size) as u64;
+    if fill {
+        let zeroes = vec![0u8; slot_size];
+        file.seek(SeekFrom::Start(config.data_start()))?;
+        for _ in 0..chunks {
+            file.write_all(&zeroes).map_err(error::write_error)?;
+        }
+    } else {
+        file.set_len(end).map_err(error::write_error)?;
+    }
+
+    file.seek(SeekFrom::Start(HEADER_SIZE))?;
+    file.write_all(&table).map_err(error::write_error)?;
+    Ok(())
+}
+
+/// Writes the header and an empty lookup table to the start of a new region
+/// file.
+pub fn write_header(file: &mut File, config: &RegionConfig) -> SerialResult<()> {
+    let mut header = Vec::with_capacity(config.data_start() as usize);
+    header.extend_from_slice(&REGION_MAGIC);
+    header.extend_from_slice(&REGION_VERSION.to_be_bytes());
+    header.extend_from_slice(&(config.region_width as u32).to_be_bytes());
+    header.extend_from_slice(&(config.sector_size as u32).to_be_bytes());
+    header.resize(config.data_start() as usize, 0);
+
+    file.seek(SeekFrom::Start(0))?;
+    file.write_all(&header)?;
+    Ok(())
+}
+
+/// Reads the parameters a region file was written with. Returns `None` for
+/// files written before region files had a header.
+pub fn read_header(file: &mut File) -> SerialResult<Option<RegionConfig>> {
+    let mut header = [0u8; HEADER_SIZE as usize];
+    file.seek(SeekFrom::Start(0))?;
+    file.read_exact(&mut header)?;
+
+    if header[0..4] != REGION_MAGIC {
+        return Ok(None);
+    }
+
+    let field = |i: usize| {
+        let mut bytes = [0u8; 4];
+        bytes.copy_from_slice(&header[i..i + 4]);
+        u32::from_be_bytes(bytes)
+    };
+
+    let version = field(4);
+    if version != REGION_VERSION {
+        return Err(FormatError::UnsupportedVersion(version).into());
+    }
+
+    let config = RegionConfig {
+        region_width: field(8) as i32,
+        sector_size: field(12) as usize,
+    };
+    config.validate()?;
+    Ok(Some(config))
+}
+
+/// Checks that a region file was written with the expected parameters.
+pub fn check_header(file: &mut File, expected: &RegionConfig) -> SerialResult<()> {
+    match read_header(file)? {
+        Some(ref found) if found == expected => Ok(()),
+        Some(found) => Err(FormatError::LayoutMismatch {
+            expected: *expected,
+            found: found,
+        }.into()),
+        None => Err(FormatError::MissingHeader.into()),
+    }
+}
+
+/// Reads the byte offset and byte size of a chunk's data from the lookup
+/// table, or `None` if the chunk isn't stored in the file. `table_start` is
+/// the offset of the lookup table, which is zero for files without a header.
+pub fn read_entry(file: &mut File, config: &RegionConfig, table_start: u64,
+                  index: &RegionLocalIndex) -> SerialResult<Option<(u64, usize)>> {
+    let mut entry = [0u8; 2];
+    file.seek(SeekFrom::Start(config.entry_offset(index) - HEADER_SIZE + table_start))?;
+    file.read_exact(&mut entry)?;
+
+    if entry[1] == 0 {
+        return Ok(None);
+    }
+    let data_start = table_start + config.lookup_table_size();
+    let offset = data_start + entry[0] as u64 * config.sector_size as u64;
+    Ok(Some((offset, entry[1] as usize * config.sector_size)))
+}
+
+/// Returns the lookup table entry of a chunk whose data starts at the byte
+/// `offset` and takes up `sector_count` sectors, or an error if an entry
+/// can't address it.
+pub fn pack_entry(config: &RegionConfig, index: &RegionLocalIndex,
+                  offset: u64, sector_count: usize) -> SerialResult<[u8; 2]> {
+    if sector_count > MAX_SECTORS {
+        return Err(FormatError::ChunkTooLarge {
+            index: index.clone(),
+            sectors: sector_count,
+        }.into());
+    }
+    let sector_offset = offset.checked_sub(config.data_start())
+        .map(|bytes| bytes / config.sector_size as u64);
+    match sector_offset {
+        Some(sector) if sector <= MAX_SECTORS as u64 => Ok([sector as u8, sector_count as u8]),
+        _ => Err(FormatError::RegionFull(index.clone()).into()),
+    }
+}
+
+/// Writes the lookup table entry of a chunk in a file with a header.
+pub fn write_entry(file: &mut File, config: &RegionConfig, index: &RegionLocalIndex,
+                   offset: u64, sector_count: usize) -> SerialResult<()> {
+    let entry = pack_entry(config, index, offset, sector_count)?;
+    file.seek(SeekFrom::Start(config.entry_offset(index)))?;
+    file.write_all(&entry)?;
+    Ok(())
+}
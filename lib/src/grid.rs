@@ -0,0 +1,333 @@
+use subscribe::CellRect;
+use traits::Index;
+
+/// How the chunks of a world are arranged.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ChunkArrangement {
+    /// Square chunks on a two-dimensional grid, packed into square regions.
+    Grid,
+    /// Vertical strips side by side along the x axis, each as tall as the
+    /// world, like the terrain of a side-scrolling game. Only the x
+    /// coordinate of an index is used, and `region_width * region_width`
+    /// consecutive strips are packed into each region.
+    ///
+    /// Light and automata grids assume square chunks and don't support
+    /// strips.
+    Columns,
+}
+
+/// The index of a vertical strip of a world arranged in
+/// `ChunkArrangement::Columns`.
+#[derive(Copy, Clone, Debug, Hash, Eq, PartialEq, Ord, PartialOrd, Serialize, Deserialize)]
+pub struct StripIndex(pub i32);
+
+impl Index for StripIndex {
+    const ARRANGEMENT: ChunkArrangement = ChunkArrangement::Columns;
+
+    fn x(&self) -> i32 { self.0 }
+    fn y(&self) -> i32 { 0 }
+    fn from_xy(x: i32, _y: i32) -> Self { StripIndex(x) }
+}
+
+/// Splits a world coordinate into the coordinate of the chunk containing it
+/// and the coordinate relative to that chunk.
+pub fn split_coord(world: i32, chunk_width: i32) -> (i32, i32) {
+    let local = world % chunk_width;
+    if local < 0 {
+        (world / chunk_width - 1, local + chunk_width)
+    } else {
+        (world / chunk_width, local)
+    }
+}
+
+/// Returns the index of the chunk containing the world position `(x, y)` and
+/// the position relative to that chunk. Positions in strips keep their
+/// world y coordinate.
+pub fn chunk_at<I: Index>(x: i32, y: i32, chunk_width: i32) -> (I, (i32, i32)) {
+    let (cx, lx) = split_coord(x, chunk_width);
+    match I::ARRANGEMENT {
+        ChunkArrangement::Grid    => {
+            let (cy, ly) = split_coord(y, chunk_width);
+            (I::from_xy(cx, cy), (lx, ly))
+        },
+        ChunkArrangement::Columns => (I::from_xy(cx, 0), (lx, y)),
+    }
+}
+
+/// Returns the world position of a position relative to the chunk at `index`.
+pub fn world_pos<I: Index>(index: &I, local: (i32, i32), chunk_width: i32) -> (i32, i32) {
+    match I::ARRANGEMENT {
+        ChunkArrangement::Grid    => (index.x() * chunk_width + local.0, index.y() * chunk_width + local.1),
+        ChunkArrangement::Columns => (index.x() * chunk_width + local.0, local.1),
+    }
+}
+
+/// Iterates over the cells of a rectangle of the world in rows from the top
+/// left, yielding the world position of every cell along with the cell, or
+/// `None` if its chunk isn't loaded. `chunk_cells` returns the cells of a
+/// loaded chunk in row-major order, and is called once for every run of
+/// cells in the same chunk.
+///
+/// The cells are borrowed from the chunks for `'w`, so they can be kept
+/// while iterating, and iteration can stop early, like with `find`.
+pub struct CellsInRect<'w, I, T: 'w, F> {
+    rect: CellRect,
+    chunk_width: i32,
+    chunk_cells: F,
+    next: (i32, i32),
+    current: Option<(I, Option<&'w [T]>)>,
+}
+
+impl<'w, I, T, F> CellsInRect<'w, I, T, F>
+    where I: Index,
+          F: FnMut(&I) -> Option<&'w [T]>
+{
+    pub fn new(rect: CellRect, chunk_width: i32, chunk_cells: F) -> Self {
+        CellsInRect {
+            rect: rect,
+            chunk_width: chunk_width,
+            chunk_cells: chunk_cells,
+            next: (rect.x, rect.y),
+            current: None,
+        }
+    }
+
+    fn remaining(&self) -> usize {
+        let (x, y) = self.next;
+        let bottom = self.rect.y + self.rect.height;
+        if self.rect.width <= 0 || y >= bottom {
+            return 0;
+        }
+        ((bottom - y) * self.rect.width - (x - self.rect.x)) as usize
+    }
+}
+
+impl<'w, I, T, F> Iterator for CellsInRect<'w, I, T, F>
+    where I: Index,
+          F: FnMut(&I) -> Option<&'w [T]>
+{
+    type Item = ((i32, i32), Option<&'w T>);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.remaining() == 0 {
+            return None;
+        }
+        let (x, y) = self.next;
+        self.next = if x + 1 < self.rect.x + self.rect.width {
+            (x + 1, y)
+        } else {
+            (self.rect.x, y + 1)
+        };
+
+        let (index, (lx, ly)) = chunk_at::<I>(x, y, self.chunk_width);
+        let stale = match self.current {
+            Some((ref i, _)) => *i != index,
+            None             => true,
+        };
+        if stale {
+            let found = (self.chunk_cells)(&index);
+            self.current = Some((index, found));
+        }
+
+        let cell = match self.current {
+            Some((_, Some(chunk))) => chunk.get((ly * self.chunk_width + lx) as usize),
+            _                      => None,
+        };
+        Some(((x, y), cell))
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        let remaining = self.remaining();
+        (remaining, Some(remaining))
+    }
+}
+
+/// Returns the number of chunks within `radius` chunks of a center along
+/// either axis, or before the ring at `radius + 1` in spiral order.
+fn spiral_count(arrangement: ChunkArrangement, radius: i32) -> u64 {
+    if radius < 0 {
+        return 0;
+    }
+    let side = 2 * radius as u64 + 1;
+    match arrangement {
+        ChunkArrangement::Grid    => side * side,
+        ChunkArrangement::Columns => side,
+    }
+}
+
+/// Returns the offset from the center of the `step`th chunk of the ring
+/// `radius` chunks away from it.
+fn ring_offset(arrangement: ChunkArrangement, radius: i32, step: i32) -> (i32, i32) {
+    if radius == 0 {
+        return (0, 0);
+    }
+    match arrangement {
+        ChunkArrangement::Grid    => {
+            let (side, t) = (step / (2 * radius), step % (2 * radius));
+            match side {
+                0 => (-radius + t, -radius),
+                1 => (radius, -radius + t),
+                2 => (radius - t, radius),
+                _ => (-radius, radius - t),
+            }
+        },
+        ChunkArrangement::Columns => if step == 0 { (-radius, 0) } else { (radius, 0) },
+    }
+}
+
+/// Returns the position of `index` in the order `indices_spiral` visits the
+/// chunks around `center` in, for sorting chunks that weren't produced by
+/// it.
+pub fn spiral_rank<I: Index>(center: &I, index: &I) -> u64 {
+    let (dx, dy) = (index.x() - center.x(), index.y() - center.y());
+    let radius = match I::ARRANGEMENT {
+        ChunkArrangement::Grid    => dx.abs().max(dy.abs()),
+        ChunkArrangement::Columns => dx.abs(),
+    };
+    if radius == 0 {
+        return 0;
+    }
+    let r = radius as i64;
+    let (dx, dy) = (dx as i64, dy as i64);
+    let step = match I::ARRANGEMENT {
+        ChunkArrangement::Grid    => {
+            if dy == -r && dx < r {
+                dx + r
+            } else if dx == r && dy < r {
+                3 * r + dy
+            } else if dy == r && dx > -r {
+                5 * r - dx
+            } else {
+                7 * r - dy
+            }
+        },
+        ChunkArrangement::Columns => if dx < 0 { 0 } else { 1 },
+    };
+    spiral_count(I::ARRANGEMENT, radius - 1) + step as u64
+}
+
+/// Iterates over the chunks within `max_radius` chunks of `center` along
+/// either axis, starting at the center and going around it in rings of
+/// growing distance, each clockwise from its top left corner. Strips are
+/// visited alternating left and right. Loading chunks in this order makes
+/// terrain fill in outward from the player.
+pub fn indices_spiral<I: Index>(center: &I, max_radius: i32) -> SpiralIndices<I> {
+    SpiralIndices {
+        center: center.clone(),
+        max_radius: max_radius,
+        radius: 0,
+        step: 0,
+    }
+}
+
+/// An iterator over the chunks around a center in spiral order. See
+/// `indices_spiral`.
+#[derive(Debug, Clone)]
+pub struct SpiralIndices<I> {
+    center: I,
+    max_radius: i32,
+    radius: i32,
+    step: i32,
+}
+
+impl<I: Index> Iterator for SpiralIndices<I> {
+    type Item = I;
+
+    fn next(&mut self) -> Option<I> {
+        if self.radius > self.max_radius {
+            return None;
+        }
+        let (dx, dy) = ring_offset(I::ARRANGEMENT, self.radius, self.step);
+        self.step += 1;
+        let ring = spiral_count(I::ARRANGEMENT, self.radius) - spiral_count(I::ARRANGEMENT, self.radius - 1);
+        if self.step as u64 >= ring {
+            self.radius += 1;
+            self.step = 0;
+        }
+        Some(I::from_xy(self.center.x() + dx, self.center.y() + dy))
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        let visited = spiral_count(I::ARRANGEMENT, self.radius - 1) + self.step as u64;
+        let remaining = spiral_count(I::ARRANGEMENT, self.max_radius).saturating_sub(visited) as usize;
+        (remaining, Some(remaining))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_split_coord() {
+        assert_eq!(split_coord(0, 16), (0, 0));
+        assert_eq!(split_coord(15, 16), (0, 15));
+        assert_eq!(split_coord(16, 16), (1, 0));
+        assert_eq!(split_coord(-1, 16), (-1, 15));
+        assert_eq!(split_coord(-16, 16), (-1, 0));
+        assert_eq!(split_coord(-17, 16), (-2, 15));
+    }
+
+    #[test]
+    fn test_cells_in_rect() {
+        use std::collections::HashMap;
+        use traits::TestIndex;
+
+        let mut chunks = HashMap::new();
+        chunks.insert(TestIndex(0, 0), vec![1, 2, 3, 4]);
+        chunks.insert(TestIndex(-1, 0), vec![5, 6, 7, 8]);
+        let lookups = ::std::cell::Cell::new(0);
+        let lookup = |i: &TestIndex| {
+            lookups.set(lookups.get() + 1);
+            chunks.get(i).map(|c| c.as_slice())
+        };
+
+        let cells: Vec<_> = CellsInRect::new(CellRect::new(-1, -1, 3, 2), 2, &lookup).collect();
+        assert_eq!(cells, vec![((-1, -1), None), ((0, -1), None), ((1, -1), None),
+                               ((-1, 0), Some(&6)), ((0, 0), Some(&1)), ((1, 0), Some(&2))]);
+        assert_eq!(lookups.get(), 4);
+
+        let mut iter = CellsInRect::new(CellRect::new(-2, 0, 4, 2), 2, &lookup);
+        assert_eq!(iter.size_hint(), (8, Some(8)));
+        let found = iter.find(|&(_, cell)| cell == Some(&3));
+        assert_eq!(found, Some(((0, 1), Some(&3))));
+        assert_eq!(iter.size_hint(), (1, Some(1)));
+    }
+
+    #[test]
+    fn test_indices_spiral() {
+        use traits::TestIndex;
+
+        let center = TestIndex(5, -2);
+        let ring: Vec<_> = indices_spiral(&center, 1).map(|i| (i.0 - 5, i.1 + 2)).collect();
+        assert_eq!(ring, vec![(0, 0), (-1, -1), (0, -1), (1, -1), (1, 0), (1, 1), (0, 1), (-1, 1), (-1, 0)]);
+
+        let mut spiral = indices_spiral(&center, 3);
+        assert_eq!(spiral.size_hint(), (49, Some(49)));
+        spiral.next();
+        assert_eq!(spiral.size_hint(), (48, Some(48)));
+        for (rank, index) in indices_spiral(&center, 3).enumerate() {
+            assert_eq!(spiral_rank(&center, &index), rank as u64);
+        }
+
+        let strips: Vec<_> = indices_spiral(&StripIndex(0), 2).collect();
+        assert_eq!(strips, vec![StripIndex(0), StripIndex(-1), StripIndex(1), StripIndex(-2), StripIndex(2)]);
+        assert_eq!(spiral_rank(&StripIndex(0), &StripIndex(2)), 4);
+        assert_eq!(indices_spiral(&StripIndex(0), -1).count(), 0);
+    }
+
+    #[test]
+    fn test_strips() {
+        use region::{RegionIndex, RegionLocalIndex};
+        use relevance::InterestShape;
+        use traits::ChunkKey;
+
+        assert_eq!(chunk_at::<StripIndex>(-3, 500, 16), (StripIndex(-1), (13, 500)));
+        assert_eq!(world_pos(&StripIndex(-1), (13, 500), 16), (-3, 500));
+        assert_eq!(StripIndex(37).partition(4), (RegionIndex(2, 0), RegionLocalIndex(1, 1)));
+        assert_eq!(StripIndex(-1).partition(4), (RegionIndex(-1, 0), RegionLocalIndex(3, 3)));
+
+        let strips = InterestShape::Square(2).indices(&StripIndex(5));
+        assert_eq!(strips, (3..8).map(StripIndex).collect::<Vec<_>>());
+    }
+}
@@ -0,0 +1,105 @@
+use std::collections::{HashMap, HashSet};
+use std::hash::{BuildHasherDefault, Hasher};
+
+const SEED: u64 = 0x51_7c_c1_b7_27_22_0a_95;
+
+/// The hasher rustc uses for its own tables, which mixes in a word at a time
+/// with a rotate, xor and multiply. Much faster than the standard library's
+/// SipHash on small keys like chunk indices and cell positions, which are
+/// hashed many times every update.
+///
+/// It is not randomly seeded, so hashes are the same on every run, and it
+/// doesn't resist hash flooding. Keys chosen by an attacker, like indices
+/// sent by a remote client, could make lookups slow.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct FxHasher {
+    hash: u64,
+}
+
+impl FxHasher {
+    #[inline]
+    fn add(&mut self, word: u64) {
+        self.hash = (self.hash.rotate_left(5) ^ word).wrapping_mul(SEED);
+    }
+}
+
+impl Hasher for FxHasher {
+    #[inline]
+    fn write(&mut self, bytes: &[u8]) {
+        let mut chunks = bytes.chunks_exact(8);
+        for chunk in &mut chunks {
+            let mut word = [0u8; 8];
+            word.copy_from_slice(chunk);
+            self.add(u64::from_le_bytes(word));
+        }
+        for &byte in chunks.remainder() {
+            self.add(byte as u64);
+        }
+    }
+
+    #[inline]
+    fn write_u8(&mut self, i: u8) {
+        self.add(i as u64);
+    }
+
+    #[inline]
+    fn write_u16(&mut self, i: u16) {
+        self.add(i as u64);
+    }
+
+    #[inline]
+    fn write_u32(&mut self, i: u32) {
+        self.add(i as u64);
+    }
+
+    #[inline]
+    fn write_u64(&mut self, i: u64) {
+        self.add(i);
+    }
+
+    #[inline]
+    fn write_usize(&mut self, i: usize) {
+        self.add(i as u64);
+    }
+
+    #[inline]
+    fn write_i32(&mut self, i: i32) {
+        self.add(i as u32 as u64);
+    }
+
+    #[inline]
+    fn finish(&self) -> u64 {
+        self.hash
+    }
+}
+
+pub type FxBuildHasher = BuildHasherDefault<FxHasher>;
+
+/// A `HashMap` using `FxHasher`. Create one with `FxHashMap::default()`.
+pub type FxHashMap<K, V> = HashMap<K, V, FxBuildHasher>;
+
+/// A `HashSet` using `FxHasher`. Create one with `FxHashSet::default()`.
+pub type FxHashSet<T> = HashSet<T, FxBuildHasher>;
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::hash::{BuildHasher, Hash};
+    use traits::TestIndex;
+
+    fn hash<T: Hash>(value: &T) -> u64 {
+        let mut hasher = FxBuildHasher::default().build_hasher();
+        value.hash(&mut hasher);
+        hasher.finish()
+    }
+
+    #[test]
+    fn test_fx_hash() {
+        assert_eq!(hash(&TestIndex(3, -4)), hash(&TestIndex(3, -4)));
+        assert!(hash(&TestIndex(3, -4)) != hash(&TestIndex(-4, 3)));
+
+        let set: FxHashSet<TestIndex> = (-8..8).map(|i| TestIndex(i, -i)).collect();
+        assert_eq!(set.len(), 16);
+        assert!(set.contains(&TestIndex(-8, 8)));
+    }
+}
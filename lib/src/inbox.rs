@@ -0,0 +1,116 @@
+use bincode::{self, Infinite};
+use serde::Serialize;
+use serde::de::DeserializeOwned;
+
+use error::SerialResult;
+use layout::RegionLayout;
+use region::{RegionIndex, RegionLocalIndex};
+use sidecar::RegionSidecar;
+
+/// Extension appended to region file names for the files holding the
+/// messages posted to their chunks.
+pub const INBOX_EXTENSION: &'static str = "inbox";
+
+/// A message posted to a chunk, such as the cells an explosion next door
+/// destroyed in it.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct ChunkMessage {
+    /// Names what the message is about, so the receiver knows what to decode
+    /// it as.
+    pub kind: String,
+    /// The world tick the message was posted at.
+    pub posted_at: u64,
+    data: Vec<u8>,
+}
+
+impl ChunkMessage {
+    pub fn new<T: Serialize>(kind: &str, posted_at: u64, value: &T) -> SerialResult<Self> {
+        Ok(ChunkMessage {
+            kind: kind.to_string(),
+            posted_at: posted_at,
+            data: bincode::serialize(value, Infinite)?,
+        })
+    }
+
+    pub fn decode<T: DeserializeOwned>(&self) -> SerialResult<T> {
+        Ok(bincode::deserialize(&self.data)?)
+    }
+}
+
+/// Messages posted to chunks by other systems, kept until the chunk is
+/// loaded, so effects that cross a chunk border, like explosions or
+/// machines, can reach chunks that aren't loaded. See
+/// `ChunkedWorld::post_message`.
+///
+/// The messages of each region are kept in a small file next to its region
+/// file. The files are read the first time a region is looked up and written
+/// by `flush`.
+pub struct ChunkInbox {
+    chunks: RegionSidecar<Vec<ChunkMessage>>,
+}
+
+impl ChunkInbox {
+    pub fn new(layout: RegionLayout) -> Self {
+        ChunkInbox {
+            chunks: RegionSidecar::new(layout, INBOX_EXTENSION),
+        }
+    }
+
+    /// Adds a message to the inbox of a chunk, after the ones already there.
+    pub fn post(&mut self, region: &RegionIndex, local: &RegionLocalIndex, message: ChunkMessage) -> SerialResult<()> {
+        let mut messages = self.messages(region, local)?.to_vec();
+        messages.push(message);
+        self.chunks.set(region, local, Some(messages))
+    }
+
+    /// Returns the messages waiting for a chunk, oldest first.
+    pub fn messages(&mut self, region: &RegionIndex, local: &RegionLocalIndex) -> SerialResult<&[ChunkMessage]> {
+        Ok(self.chunks.get(region, local)?.map_or(&[], |m| m.as_slice()))
+    }
+
+    /// Removes the first `count` messages of a chunk, once they were handled.
+    pub fn remove(&mut self, region: &RegionIndex, local: &RegionLocalIndex, count: usize) -> SerialResult<()> {
+        let rest: Vec<ChunkMessage> = self.messages(region, local)?.iter().skip(count).cloned().collect();
+        self.chunks.set(region, local, if rest.is_empty() { None } else { Some(rest) })
+    }
+
+    /// Writes the inboxes of every region that changed since the last flush.
+    pub fn flush(&mut self) -> SerialResult<()> {
+        self.chunks.flush()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::env;
+    use std::fs;
+
+    #[test]
+    fn test_inbox() {
+        let root = env::temp_dir().join("infinigen-test-inbox");
+        let _ = fs::remove_dir_all(&root);
+        let layout = RegionLayout::new(&root);
+        let (region, local) = (RegionIndex(-1, 0), RegionLocalIndex(3, 4));
+
+        let mut inbox = ChunkInbox::new(layout.clone());
+        inbox.post(&region, &local, ChunkMessage::new("blast", 3, &(5i32, 6i32)).unwrap()).unwrap();
+        inbox.post(&region, &local, ChunkMessage::new("blast", 4, &(7i32, 8i32)).unwrap()).unwrap();
+        inbox.flush().unwrap();
+
+        let mut inbox = ChunkInbox::new(layout.clone());
+        {
+            let messages = inbox.messages(&region, &local).unwrap();
+            assert_eq!(messages.len(), 2);
+            assert_eq!(messages[0].kind, "blast");
+            assert_eq!(messages[1].decode::<(i32, i32)>().unwrap(), (7, 8));
+        }
+        inbox.remove(&region, &local, 1).unwrap();
+        assert_eq!(inbox.messages(&region, &local).unwrap()[0].posted_at, 4);
+        inbox.remove(&region, &local, 1).unwrap();
+        assert!(inbox.messages(&region, &RegionLocalIndex(0, 0)).unwrap().is_empty());
+        assert!(inbox.messages(&region, &local).unwrap().is_empty());
+
+        fs::remove_dir_all(&root).unwrap();
+    }
+}
@@ -0,0 +1,134 @@
+use std::fs::{self, File};
+use std::io::{BufReader, Write};
+use std::path::Path;
+
+use bincode::{self, Infinite};
+
+use error::{self, SerialResult};
+use platform::replace_file;
+
+/// The encoded data of one chunk in a save group.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct JournalEntry {
+    pub chunk: (i32, i32),
+    /// The compressed chunk data, exactly as it is written to the region
+    /// file.
+    pub data: Vec<u8>,
+    /// The hash of the chunk's serialized data.
+    pub hash: u64,
+}
+
+/// A group of chunk writes that must either all reach the region files or
+/// none of them.
+///
+/// The journal is written in full before any region file is touched and
+/// removed once every write has been synced. If the process dies in between,
+/// replaying the journal on the next start finishes the group.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct SaveJournal {
+    pub entries: Vec<JournalEntry>,
+}
+
+impl SaveJournal {
+    pub fn new() -> Self {
+        SaveJournal::default()
+    }
+
+    pub fn push(&mut self, chunk: (i32, i32), data: Vec<u8>, hash: u64) {
+        self.entries.push(JournalEntry {
+            chunk: chunk,
+            data: data,
+            hash: hash,
+        });
+    }
+
+    /// Durably writes the journal to the given path. The journal is written
+    /// to a temporary file first and renamed into place, so a journal that
+    /// exists is always complete.
+    pub fn commit<P: AsRef<Path>>(&self, path: P) -> SerialResult<()> {
+        let path = path.as_ref();
+        if let Some(parent) = path.parent() {
+            if !parent.as_os_str().is_empty() {
+                fs::create_dir_all(parent)?;
+            }
+        }
+
+        let temp = path.with_extension("tmp");
+        {
+            let mut file = File::create(&temp)?;
+            let written = file.write_all(&bincode::serialize(self, Infinite)?)
+                .and_then(|_| file.sync_all());
+            if let Err(e) = written {
+                let _ = fs::remove_file(&temp);
+                return Err(error::write_error(e));
+            }
+        }
+        replace_file(&temp, path)?;
+        Ok(())
+    }
+
+    /// Loads the journal at the given path, if a save group was interrupted.
+    pub fn load<P: AsRef<Path>>(path: P) -> SerialResult<Option<Self>> {
+        if !path.as_ref().exists() {
+            return Ok(None);
+        }
+        let mut reader = BufReader::new(File::open(path)?);
+        let journal = bincode::deserialize_from(&mut reader, Infinite)?;
+        Ok(Some(journal))
+    }
+
+    /// Removes the journal after all of its writes were synced.
+    pub fn clear<P: AsRef<Path>>(path: P) -> SerialResult<()> {
+        if path.as_ref().exists() {
+            fs::remove_file(path)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use testing::TempDir;
+
+    #[test]
+    fn test_journal() {
+        let dir = TempDir::new("journal");
+        let path = dir.path().join("journal").join("save.journal");
+        assert!(SaveJournal::load(&path).unwrap().is_none());
+
+        let mut journal = SaveJournal::new();
+        journal.push((1, -2), vec![1, 2, 3], 7);
+        journal.push((0, 0), vec![], 8);
+        journal.commit(&path).unwrap();
+        assert!(!path.with_extension("tmp").exists());
+
+        let loaded = SaveJournal::load(&path).unwrap().unwrap();
+        assert_eq!(loaded.entries.len(), 2);
+        assert_eq!(loaded.entries[0].chunk, (1, -2));
+        assert_eq!(loaded.entries[0].data, vec![1, 2, 3]);
+        assert_eq!(loaded.entries[1].hash, 8);
+
+        SaveJournal::clear(&path).unwrap();
+        assert!(!path.exists());
+        SaveJournal::clear(&path).unwrap();
+    }
+
+    #[test]
+    fn test_journal_failures() {
+        let dir = TempDir::new("journal-failures");
+        let path = dir.path().join("save.journal");
+
+        // A journal that can't be written leaves the previous one in place.
+        let mut journal = SaveJournal::new();
+        journal.push((3, 3), vec![9], 1);
+        journal.commit(&path).unwrap();
+        fs::create_dir(path.with_extension("tmp")).unwrap();
+        assert!(SaveJournal::new().commit(&path).is_err());
+        assert_eq!(SaveJournal::load(&path).unwrap().unwrap().entries.len(), 1);
+        fs::remove_dir(path.with_extension("tmp")).unwrap();
+
+        fs::write(&path, [0xff; 3]).unwrap();
+        assert!(SaveJournal::load(&path).is_err());
+    }
+}
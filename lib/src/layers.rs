@@ -0,0 +1,216 @@
+use std::collections::BTreeMap;
+use std::fmt;
+use std::time::{Duration, Instant};
+
+use error::SerialResult;
+use registry::HostedWorld;
+
+/// The key of a chunk in a world of discrete z-levels, like the floors of a
+/// dungeon above and below the surface: the layer and the chunk's index
+/// inside that layer.
+#[derive(Copy, Clone, Debug, Hash, Eq, PartialEq)]
+pub struct LayeredIndex<I> {
+    pub layer: i32,
+    pub index: I,
+}
+
+impl<I> LayeredIndex<I> {
+    pub fn new(layer: i32, index: I) -> Self {
+        LayeredIndex {
+            layer: layer,
+            index: index,
+        }
+    }
+}
+
+impl<I: fmt::Display> fmt::Display for LayeredIndex<I> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{} on layer {}", self.index, self.layer)
+    }
+}
+
+type OpenLayer<W> = Box<dyn FnMut(i32) -> SerialResult<W>>;
+
+/// The layers of a world of discrete z-levels. Every layer is a world of its
+/// own with its own region files, usually placed with
+/// `RegionLayout::for_layer`, so layers never share a region file.
+///
+/// Only the current layer and the layers within `reach` of it are kept open:
+/// the rest are saved and closed when the current layer changes, and opened
+/// again with the given callback when they come back in reach.
+pub struct LayerStack<W: HostedWorld> {
+    layers: BTreeMap<i32, W>,
+    open: OpenLayer<W>,
+    current: i32,
+    reach: i32,
+}
+
+impl<W: HostedWorld> LayerStack<W> {
+    /// Creates a stack whose layers are opened by `open`. Nothing is opened
+    /// until the first `set_current`. The reach is 1, so the layers directly
+    /// above and below the current one are kept open.
+    pub fn new<F>(open: F) -> Self
+        where F: FnMut(i32) -> SerialResult<W> + 'static
+    {
+        LayerStack {
+            layers: BTreeMap::new(),
+            open: Box::new(open),
+            current: 0,
+            reach: 1,
+        }
+    }
+
+    /// Sets how many layers above and below the current one are kept open.
+    pub fn with_reach(mut self, reach: i32) -> Self {
+        assert!(reach >= 0, "Layer reach can't be negative!");
+        self.reach = reach;
+        self
+    }
+
+    pub fn current(&self) -> i32 {
+        self.current
+    }
+
+    /// Returns whether a layer is close enough to the current one to be kept
+    /// open.
+    pub fn in_reach(&self, layer: i32) -> bool {
+        (layer as i64 - self.current as i64).abs() <= self.reach as i64
+    }
+
+    /// Makes a layer the current one, like when the player takes the stairs.
+    /// Layers that fall out of reach are saved and closed, and layers that
+    /// come in reach are opened.
+    pub fn set_current(&mut self, layer: i32) -> SerialResult<()> {
+        self.current = layer;
+
+        let stale: Vec<i32> = self.layers.keys().cloned().filter(|&l| !self.in_reach(l)).collect();
+        for l in stale.into_iter() {
+            // A layer that fails to save stays open, so nothing is lost.
+            self.layers.get_mut(&l).unwrap().shut_down()?;
+            self.layers.remove(&l);
+        }
+
+        let low = layer.saturating_sub(self.reach);
+        let high = layer.saturating_add(self.reach);
+        for l in low..=high {
+            if !self.layers.contains_key(&l) {
+                let world = (self.open)(l)?;
+                self.layers.insert(l, world);
+            }
+        }
+        Ok(())
+    }
+
+    /// Returns the open layers, lowest first.
+    pub fn open_layers(&self) -> Vec<i32> {
+        self.layers.keys().cloned().collect()
+    }
+
+    pub fn layer(&self, layer: i32) -> Option<&W> {
+        self.layers.get(&layer)
+    }
+
+    pub fn layer_mut(&mut self, layer: i32) -> Option<&mut W> {
+        self.layers.get_mut(&layer)
+    }
+
+    pub fn current_layer_mut(&mut self) -> Option<&mut W> {
+        let current = self.current;
+        self.layers.get_mut(&current)
+    }
+
+    /// Returns the world holding a chunk, if its layer is open.
+    pub fn world_of<I>(&mut self, index: &LayeredIndex<I>) -> Option<&mut W> {
+        self.layers.get_mut(&index.layer)
+    }
+
+    /// Updates every open layer, taking about `budget` in total. The current
+    /// layer goes first and may use half of the budget; the other layers
+    /// share the rest. Returns `true` if any layer has work left.
+    pub fn update(&mut self, budget: Duration) -> SerialResult<bool> {
+        let deadline = Instant::now() + budget;
+        let mut behind = false;
+        if let Some(world) = self.layers.get_mut(&self.current) {
+            behind |= world.update_within(budget / 2)?;
+        }
+
+        let current = self.current;
+        let mut others: Vec<&mut W> = self.layers.iter_mut()
+            .filter(|&(&l, _)| l != current)
+            .map(|(_, w)| w)
+            .collect();
+        let count = others.len();
+        for (turn, world) in others.iter_mut().enumerate() {
+            let left = deadline.saturating_duration_since(Instant::now());
+            behind |= world.update_within(left / (count - turn) as u32)?;
+        }
+        Ok(behind)
+    }
+
+    /// Saves every open layer while they keep running.
+    pub fn autosave(&mut self) -> SerialResult<()> {
+        for world in self.layers.values_mut() {
+            world.autosave()?;
+        }
+        Ok(())
+    }
+
+    /// Saves and closes every open layer.
+    pub fn shut_down(&mut self) -> SerialResult<()> {
+        let layers: Vec<i32> = self.open_layers();
+        for l in layers.into_iter() {
+            self.layers.get_mut(&l).unwrap().shut_down()?;
+            self.layers.remove(&l);
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::cell::RefCell;
+    use std::rc::Rc;
+
+    struct FakeLayer {
+        layer: i32,
+        log: Rc<RefCell<Vec<String>>>,
+    }
+
+    impl HostedWorld for FakeLayer {
+        fn update_within(&mut self, _budget: Duration) -> SerialResult<bool> {
+            self.log.borrow_mut().push(format!("update {}", self.layer));
+            Ok(false)
+        }
+
+        fn autosave(&mut self) -> SerialResult<()> { Ok(()) }
+
+        fn shut_down(&mut self) -> SerialResult<()> {
+            self.log.borrow_mut().push(format!("close {}", self.layer));
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn test_layer_reach() {
+        let log = Rc::new(RefCell::new(Vec::new()));
+        let opened = log.clone();
+        let mut stack = LayerStack::new(move |layer| {
+            opened.borrow_mut().push(format!("open {}", layer));
+            Ok(FakeLayer { layer: layer, log: opened.clone() })
+        });
+
+        stack.set_current(0).unwrap();
+        assert_eq!(stack.open_layers(), vec![-1, 0, 1]);
+        stack.update(Duration::from_millis(10)).unwrap();
+        assert_eq!(&log.borrow()[3..], &["update 0", "update -1", "update 1"]);
+
+        // Going down a floor closes the one above and opens the next below.
+        log.borrow_mut().clear();
+        stack.set_current(-1).unwrap();
+        assert_eq!(stack.open_layers(), vec![-2, -1, 0]);
+        assert_eq!(*log.borrow(), vec!["close 1", "open -2"]);
+        assert!(stack.world_of(&LayeredIndex::new(-2, (4, 4))).is_some());
+        assert!(stack.world_of(&LayeredIndex::new(1, (4, 4))).is_none());
+    }
+}
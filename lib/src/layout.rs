@@ -0,0 +1,206 @@
+use std::path::{Path, PathBuf};
+
+use region::RegionIndex;
+
+/// Describes where region files are placed on disk and how they are named.
+///
+/// Region files are named `<prefix>.<x>.<y>.<extension>` and placed inside
+/// the root directory, optionally nested in a subdirectory (for example
+/// `region/`). All paths are built with `Path::join` so that the platform's
+/// path separator is always used.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct RegionLayout {
+    root: PathBuf,
+    subdirectory: Option<PathBuf>,
+    prefix: String,
+    extension: String,
+}
+
+impl Default for RegionLayout {
+    /// Places region files named `r.<x>.<y>.sr` in the current directory.
+    fn default() -> Self {
+        RegionLayout::new(PathBuf::new())
+    }
+}
+
+impl RegionLayout {
+    /// Creates a layout that places region files directly inside `root`.
+    pub fn new<P: AsRef<Path>>(root: P) -> Self {
+        RegionLayout {
+            root: root.as_ref().to_path_buf(),
+            subdirectory: None,
+            prefix: "r".to_string(),
+            extension: "sr".to_string(),
+        }
+    }
+
+    /// Places region files inside the given directory relative to the root.
+    pub fn with_subdirectory<P: AsRef<Path>>(mut self, subdirectory: P) -> Self {
+        self.subdirectory = Some(subdirectory.as_ref().to_path_buf());
+        self
+    }
+
+    /// Sets the extension of region files. A leading dot is ignored.
+    pub fn with_extension(mut self, extension: &str) -> Self {
+        self.extension = extension.trim_start_matches('.').to_string();
+        self
+    }
+
+    /// Sets the prefix of region file names.
+    pub fn with_prefix(mut self, prefix: &str) -> Self {
+        self.prefix = prefix.to_string();
+        self
+    }
+
+    /// Returns a layout with the same naming scheme placed in another root
+    /// directory.
+    pub fn with_root<P: AsRef<Path>>(&self, root: P) -> Self {
+        RegionLayout {
+            root: root.as_ref().to_path_buf(),
+            ..self.clone()
+        }
+    }
+
+    /// Returns the layout of a layer of a world of z-levels. Layer 0 uses this
+    /// layout itself, so a flat world can gain layers later, and every other
+    /// layer uses the same naming scheme rooted in a `layer.<z>` directory
+    /// inside this one. See `LayerStack`.
+    pub fn for_layer(&self, layer: i32) -> Self {
+        if layer == 0 {
+            self.clone()
+        } else {
+            self.with_root(self.root.join(format!("layer.{}", layer)))
+        }
+    }
+
+    pub fn root(&self) -> &Path {
+        &self.root
+    }
+
+    pub fn extension(&self) -> &str {
+        &self.extension
+    }
+
+    /// Returns the directory region files are stored in.
+    pub fn directory(&self) -> PathBuf {
+        match self.subdirectory {
+            Some(ref sub) => self.root.join(sub),
+            None          => self.root.clone(),
+        }
+    }
+
+    /// Returns the path of the world metadata file, which is always placed in
+    /// the root directory.
+    pub fn metadata_path(&self) -> PathBuf {
+        self.root.join("world.meta")
+    }
+
+    /// Returns the path of the journal of interrupted save groups, which is
+    /// always placed in the root directory.
+    pub fn journal_path(&self) -> PathBuf {
+        self.root.join("save.journal")
+    }
+
+    /// Returns the paths of the journal of chunks saved while the game was
+    /// crashing and of the marker saying it crashed, which are always placed
+    /// in the root directory. See `ChunkedWorld::crash_save`.
+    pub fn crash_journal_path(&self) -> PathBuf {
+        self.root.join("crash.journal")
+    }
+
+    pub fn crash_marker_path(&self) -> PathBuf {
+        self.root.join("crash.marker")
+    }
+
+    /// Returns the path of the lock file of the process playing the world,
+    /// which is always placed in the root directory. See `WorldLock`.
+    pub fn lock_path(&self) -> PathBuf {
+        self.root.join("world.lock")
+    }
+
+    /// Returns the path of the cold archive of rarely visited regions, which
+    /// is always placed in the root directory.
+    pub fn archive_path(&self) -> PathBuf {
+        self.root.join("cold.archive")
+    }
+
+    /// Returns the path of the file holding entities that overflowed their
+    /// chunk, which is always placed in the root directory.
+    pub fn spill_path(&self) -> PathBuf {
+        self.root.join("entities.spill")
+    }
+
+    /// Returns the path of the game store, which is always placed in the
+    /// root directory. See `GameStore`.
+    pub fn store_path(&self) -> PathBuf {
+        self.root.join("game.kv")
+    }
+
+    /// Returns the file name of the region at an index, without any directory
+    /// components.
+    pub fn file_name(&self, index: &RegionIndex) -> String {
+        if self.extension.is_empty() {
+            format!("{}.{}.{}", self.prefix, index.0, index.1)
+        } else {
+            format!("{}.{}.{}.{}", self.prefix, index.0, index.1, self.extension)
+        }
+    }
+
+    /// Returns the full path of the region file at an index.
+    pub fn region_path(&self, index: &RegionIndex) -> PathBuf {
+        self.directory().join(self.file_name(index))
+    }
+
+    /// Returns the path of a file holding extra information about the chunks
+    /// of a region, which is placed next to the region file and named after
+    /// it with `extension` appended.
+    pub fn sidecar_path(&self, index: &RegionIndex, extension: &str) -> PathBuf {
+        self.directory().join(format!("{}.{}", self.file_name(index), extension))
+    }
+
+    /// Parses the region index out of a path following this layout's naming
+    /// scheme. Only the file name is inspected.
+    pub fn parse_path<P: AsRef<Path>>(&self, path: P) -> Option<RegionIndex> {
+        let name = match path.as_ref().file_name().and_then(|n| n.to_str()) {
+            Some(n) => n,
+            None    => return None,
+        };
+
+        let mut parts = name.split('.');
+        if parts.next() != Some(self.prefix.as_str()) {
+            return None;
+        }
+        let x = parts.next().and_then(|p| p.parse::<i32>().ok());
+        let y = parts.next().and_then(|p| p.parse::<i32>().ok());
+        let ext = parts.next().unwrap_or("");
+        if ext != self.extension || parts.next().is_some() {
+            return None;
+        }
+
+        match (x, y) {
+            (Some(x), Some(y)) => Some(RegionIndex(x, y)),
+            _                  => None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_region_path() {
+        let layout = RegionLayout::new("save").with_subdirectory("region");
+        let path = layout.region_path(&RegionIndex(3, -2));
+        assert_eq!(path, Path::new("save").join("region").join("r.3.-2.sr"));
+        assert_eq!(layout.parse_path(&path), Some(RegionIndex(3, -2)));
+        assert_eq!(layout.for_layer(0), layout);
+        assert_eq!(layout.for_layer(-1).region_path(&RegionIndex(3, -2)),
+                   Path::new("save").join("layer.-1").join("region").join("r.3.-2.sr"));
+
+        let layout = RegionLayout::default().with_extension(".mca");
+        assert_eq!(layout.region_path(&RegionIndex(0, 1)), PathBuf::from("r.0.1.mca"));
+        assert_eq!(layout.parse_path("r.0.1.sr"), None);
+        assert_eq!(layout.parse_path("r.0.mca"), None);
+    }
+}
@@ -0,0 +1,118 @@
+use bincode::{self, Infinite};
+
+use error::SerialResult;
+use managed_region::decompress_data;
+use metadata::ChunkMeta;
+use traits::ManagedChunk;
+
+enum LazyState<C> {
+    /// The compressed payload as stored on disk, including the chunk's
+    /// metadata.
+    Packed(Vec<u8>),
+    Unpacked(C),
+}
+
+/// A chunk loaded from disk whose data is kept compressed until it is first
+/// accessed.
+///
+/// Chunks that are only loaded because they are near an observer are often
+/// never read, so deferring decompression and deserialization to the first
+/// access avoids most of the cost of loading them.
+pub struct LazyChunk<C: ManagedChunk> {
+    state: LazyState<C>,
+}
+
+impl<C: ManagedChunk> LazyChunk<C> {
+    /// Wraps a chunk that is already deserialized.
+    pub fn new(chunk: C) -> Self {
+        LazyChunk {
+            state: LazyState::Unpacked(chunk),
+        }
+    }
+
+    /// Wraps the compressed payload of a chunk as read from a region file.
+    pub fn packed(payload: Vec<u8>) -> Self {
+        LazyChunk {
+            state: LazyState::Packed(payload),
+        }
+    }
+
+    /// Returns whether the chunk's data has been deserialized.
+    pub fn is_unpacked(&self) -> bool {
+        match self.state {
+            LazyState::Unpacked(_) => true,
+            LazyState::Packed(_)   => false,
+        }
+    }
+
+    fn unpack(&mut self) -> SerialResult<()> {
+        let chunk = match self.state {
+            LazyState::Packed(ref payload) => {
+                let decompressed = decompress_data(payload)?;
+                let mut reader = decompressed.as_slice();
+                let _: ChunkMeta = bincode::deserialize_from(&mut reader, Infinite)?;
+                bincode::deserialize(reader)?
+            },
+            LazyState::Unpacked(_) => return Ok(()),
+        };
+        self.state = LazyState::Unpacked(chunk);
+        Ok(())
+    }
+
+    /// Returns the chunk, deserializing it if this is the first access.
+    pub fn get(&mut self) -> SerialResult<&C> {
+        self.unpack()?;
+        match self.state {
+            LazyState::Unpacked(ref chunk) => Ok(chunk),
+            LazyState::Packed(_)           => unreachable!(),
+        }
+    }
+
+    /// Returns the chunk mutably, deserializing it if this is the first
+    /// access.
+    pub fn get_mut(&mut self) -> SerialResult<&mut C> {
+        self.unpack()?;
+        match self.state {
+            LazyState::Unpacked(ref mut chunk) => Ok(chunk),
+            LazyState::Packed(_)               => unreachable!(),
+        }
+    }
+
+    /// Consumes the wrapper and returns the chunk, deserializing it if it was
+    /// never accessed.
+    pub fn into_inner(mut self) -> SerialResult<C> {
+        self.unpack()?;
+        match self.state {
+            LazyState::Unpacked(chunk) => Ok(chunk),
+            LazyState::Packed(_)       => unreachable!(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use format::CompressionLevel;
+    use managed_region::compress_data;
+
+    #[derive(Debug, PartialEq, Serialize, Deserialize)]
+    struct TestChunk(Vec<u32>);
+
+    impl ManagedChunk for TestChunk {
+        const SECTOR_SIZE: usize = 4096;
+        const REGION_WIDTH: i32 = 16;
+    }
+
+    #[test]
+    fn test_unpack_on_access() {
+        let mut encoded = bincode::serialize(&ChunkMeta { saved_at: 7 }, Infinite).unwrap();
+        encoded.extend(bincode::serialize(&TestChunk(vec![1, 2, 3]), Infinite).unwrap());
+
+        let payload = compress_data(&encoded, CompressionLevel::Fast).unwrap();
+        let mut lazy: LazyChunk<TestChunk> = LazyChunk::packed(payload);
+        assert!(!lazy.is_unpacked());
+        lazy.get_mut().unwrap().0.push(4);
+        assert!(lazy.is_unpacked());
+        assert_eq!(lazy.into_inner().unwrap(), TestChunk(vec![1, 2, 3, 4]));
+    }
+}
@@ -0,0 +1,105 @@
+use std::sync::{Arc, Mutex};
+
+use hash::FxHashMap;
+use traits::Index;
+
+type Counts<I> = Arc<Mutex<FxHashMap<I, usize>>>;
+
+/// Keeps a chunk from being unloaded by `ChunkedWorld::update_chunks` while
+/// it is held, for things that keep pointing into a chunk across updates,
+/// like an iterator or an AI plan. Unloading resumes once every lease of the
+/// chunk is dropped. Taken from `ChunkLeases::lease`.
+pub struct ChunkLease<I: Index> {
+    index: I,
+    counts: Counts<I>,
+}
+
+impl<I: Index> ChunkLease<I> {
+    pub fn index(&self) -> &I {
+        &self.index
+    }
+}
+
+impl<I: Index> Clone for ChunkLease<I> {
+    fn clone(&self) -> Self {
+        *self.counts.lock().unwrap().entry(self.index.clone()).or_insert(0) += 1;
+        ChunkLease {
+            index: self.index.clone(),
+            counts: self.counts.clone(),
+        }
+    }
+}
+
+impl<I: Index> Drop for ChunkLease<I> {
+    fn drop(&mut self) {
+        let mut counts = match self.counts.lock() {
+            Ok(counts) => counts,
+            Err(poisoned) => poisoned.into_inner(),
+        };
+        let last = match counts.get_mut(&self.index) {
+            Some(count) => {
+                *count -= 1;
+                *count == 0
+            },
+            None => false,
+        };
+        if last {
+            counts.remove(&self.index);
+        }
+    }
+}
+
+/// Counts the leases held on the chunks of a world. Leases only keep chunks
+/// from being unloaded because they left the relevant area; a full `save`
+/// still unloads every chunk.
+#[derive(Clone)]
+pub struct ChunkLeases<I: Index> {
+    counts: Counts<I>,
+}
+
+impl<I: Index> ChunkLeases<I> {
+    pub fn new() -> Self {
+        ChunkLeases {
+            counts: Arc::new(Mutex::new(FxHashMap::default())),
+        }
+    }
+
+    /// Takes a lease on a chunk.
+    pub fn lease(&self, index: &I) -> ChunkLease<I> {
+        *self.counts.lock().unwrap().entry(index.clone()).or_insert(0) += 1;
+        ChunkLease {
+            index: index.clone(),
+            counts: self.counts.clone(),
+        }
+    }
+
+    pub fn is_leased(&self, index: &I) -> bool {
+        self.counts.lock().unwrap().contains_key(index)
+    }
+
+    /// Returns the number of leases held on a chunk.
+    pub fn lease_count(&self, index: &I) -> usize {
+        self.counts.lock().unwrap().get(index).cloned().unwrap_or(0)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use traits::TestIndex;
+
+    #[test]
+    fn test_leases() {
+        let leases = ChunkLeases::new();
+        let index = TestIndex(1, -1);
+        let lease = leases.lease(&index);
+        let copy = lease.clone();
+        assert_eq!(leases.lease_count(&index), 2);
+
+        drop(lease);
+        assert!(leases.is_leased(&index));
+        drop(copy);
+        assert!(!leases.is_leased(&index));
+        assert_eq!(leases.lease_count(&index), 0);
+    }
+}
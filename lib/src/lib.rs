@@ -1,13 +1,164 @@
 #![feature(associated_consts)]
 extern crate bincode;
+#[cfg(feature = "compression")]
 extern crate flate2;
+#[cfg(feature = "image-export")]
+extern crate png;
+#[cfg(feature = "parallel")]
+extern crate rayon;
 extern crate serde;
+#[macro_use] extern crate serde_derive;
+#[cfg(feature = "table-snapshots")]
+extern crate serde_json;
+#[cfg(feature = "tracing")]
+#[macro_use] extern crate tracing;
+
+#[macro_use] mod profile;
 
 mod region;
 
+mod adaptive;
+mod admission;
+mod anonymize;
+mod archive;
+mod attachment;
+mod automata;
+mod coalesce;
+mod crash;
+mod detail;
+mod diff;
+mod entity_cap;
+mod entity_query;
+mod error;
+#[cfg(feature = "image-export")]
+mod export;
+mod flat;
+mod fork;
+mod format;
+mod grid;
+mod hash;
+mod inbox;
+mod journal;
+mod layers;
+mod layout;
+mod lazy;
+mod lease;
+#[cfg(feature = "async")]
+mod load_handle;
+mod load_state;
+mod lifetime;
+mod light;
+mod links;
+mod metadata;
+mod metrics;
+mod migrate;
+mod occupancy;
+mod overlay;
+#[cfg(feature = "parallel")]
+mod parallel;
+mod platform;
+mod pool;
+mod protection;
+mod provenance;
+mod prune;
+mod recovery;
+mod registry;
+mod relevance;
+mod remap;
+#[cfg(feature = "server")]
+mod remote;
+mod replay;
+mod schedule;
+mod seed;
+#[cfg(feature = "server")]
+mod server;
+mod shard;
+mod shutdown;
+mod sidecar;
+mod space;
+mod store;
+mod stub;
+mod subscribe;
+#[cfg(feature = "table-snapshots")]
+mod table_snapshot;
+#[cfg(test)]
+mod testing;
+mod timestamps;
+mod tombstone;
 mod traits;
+mod versions;
+mod view;
+mod visits;
+mod world_lock;
 mod managed_region;
 
+pub use self::adaptive::*;
+pub use self::admission::*;
+pub use self::anonymize::*;
+pub use self::archive::*;
+pub use self::attachment::*;
+pub use self::automata::*;
+pub use self::coalesce::*;
+pub use self::crash::*;
+pub use self::detail::*;
+pub use self::diff::*;
+pub use self::entity_cap::*;
+pub use self::entity_query::*;
+pub use self::error::*;
+#[cfg(feature = "image-export")]
+pub use self::export::*;
+pub use self::flat::*;
+pub use self::fork::*;
+pub use self::format::*;
+pub use self::grid::*;
+pub use self::hash::*;
+pub use self::inbox::*;
+pub use self::journal::*;
+pub use self::layers::*;
+pub use self::layout::*;
+pub use self::lazy::*;
+pub use self::lease::*;
+#[cfg(feature = "async")]
+pub use self::load_handle::*;
+pub use self::load_state::*;
+pub use self::lifetime::*;
+pub use self::light::*;
+pub use self::links::*;
+pub use self::metadata::*;
+pub use self::metrics::*;
+pub use self::migrate::*;
+pub use self::occupancy::*;
+pub use self::overlay::*;
+pub use self::pool::*;
+pub use self::protection::*;
+pub use self::provenance::*;
+pub use self::prune::*;
+pub use self::recovery::*;
+pub use self::registry::*;
+pub use self::relevance::*;
+pub use self::remap::*;
+#[cfg(feature = "server")]
+pub use self::remote::*;
+pub use self::replay::*;
+pub use self::schedule::*;
+pub use self::seed::*;
+#[cfg(feature = "server")]
+pub use self::server::*;
+pub use self::shard::*;
+pub use self::shutdown::*;
+pub use self::sidecar::*;
+pub use self::space::*;
+pub use self::store::*;
+pub use self::stub::*;
+pub use self::subscribe::*;
+#[cfg(feature = "table-snapshots")]
+pub use self::table_snapshot::*;
+pub use self::timestamps::*;
+pub use self::tombstone::*;
 pub use self::traits::*;
+pub use self::versions::*;
+pub use self::view::*;
+pub use self::visits::*;
+pub use self::world_lock::*;
 pub use self::managed_region::*;
 pub use self::region::*;
@@ -0,0 +1,107 @@
+use hash::FxHashMap;
+use traits::ChunkKey;
+
+/// Why a region is keeping track of a chunk. A tracked chunk is held in
+/// memory and its copy in the region file, if any, may be out of date.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ChunkLifetime {
+    /// The chunk was created this session and hasn't been saved yet.
+    Created,
+    /// The chunk was read from the region file.
+    Loaded,
+}
+
+/// A chunk lifetime transition that isn't allowed from the chunk's current
+/// state.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LifetimeError {
+    /// The chunk is already being tracked.
+    AlreadyTracked(ChunkLifetime),
+    /// The chunk isn't being tracked, so it has nothing to save.
+    NotTracked,
+}
+
+/// Keeps track of the chunks of a region that are held in memory and not
+/// saved, enforcing that each is created or loaded once and saved once.
+///
+/// A chunk goes from untracked to `Created` or `Loaded`, and back to
+/// untracked when it is saved. Any other transition is an error.
+#[derive(Debug, Clone)]
+pub struct ChunkLifetimeTracker<I: ChunkKey> {
+    chunks: FxHashMap<I, ChunkLifetime>,
+}
+
+impl<I: ChunkKey> ChunkLifetimeTracker<I> {
+    pub fn new() -> Self {
+        ChunkLifetimeTracker {
+            chunks: FxHashMap::default(),
+        }
+    }
+
+    pub fn state(&self, index: &I) -> Option<ChunkLifetime> {
+        self.chunks.get(index).cloned()
+    }
+
+    pub fn is_unsaved(&self, index: &I) -> bool {
+        self.chunks.contains_key(index)
+    }
+
+    pub fn len(&self) -> usize {
+        self.chunks.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.chunks.is_empty()
+    }
+
+    /// Returns the indices of every tracked chunk.
+    pub fn indices(&self) -> Vec<I> {
+        self.chunks.keys().cloned().collect()
+    }
+
+    pub fn created(&mut self, index: &I) -> Result<(), LifetimeError> {
+        self.begin(index, ChunkLifetime::Created)
+    }
+
+    pub fn loaded(&mut self, index: &I) -> Result<(), LifetimeError> {
+        self.begin(index, ChunkLifetime::Loaded)
+    }
+
+    /// Stops tracking a chunk after it was saved.
+    pub fn saved(&mut self, index: &I) -> Result<ChunkLifetime, LifetimeError> {
+        self.chunks.remove(index).ok_or(LifetimeError::NotTracked)
+    }
+
+    /// Stops tracking a chunk whether or not it was tracked, such as when
+    /// its copy in memory was abandoned.
+    pub fn forget(&mut self, index: &I) -> Option<ChunkLifetime> {
+        self.chunks.remove(index)
+    }
+
+    fn begin(&mut self, index: &I, lifetime: ChunkLifetime) -> Result<(), LifetimeError> {
+        if let Some(&existing) = self.chunks.get(index) {
+            return Err(LifetimeError::AlreadyTracked(existing));
+        }
+        self.chunks.insert(index.clone(), lifetime);
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use traits::TestIndex;
+
+    #[test]
+    fn test_transitions() {
+        let mut tracker = ChunkLifetimeTracker::new();
+        let index = TestIndex(0, 0);
+
+        assert_eq!(tracker.saved(&index), Err(LifetimeError::NotTracked));
+        tracker.created(&index).unwrap();
+        assert_eq!(tracker.loaded(&index), Err(LifetimeError::AlreadyTracked(ChunkLifetime::Created)));
+        assert_eq!(tracker.saved(&index), Ok(ChunkLifetime::Created));
+        tracker.loaded(&index).unwrap();
+        assert!(tracker.is_unsaved(&index));
+    }
+}
@@ -0,0 +1,233 @@
+use std::collections::{HashMap, VecDeque};
+
+use grid::{chunk_at, world_pos};
+use hash::FxHashMap;
+use traits::Index;
+
+/// The highest light level a cell can have.
+pub const MAX_LIGHT: u8 = 15;
+
+const NEIGHBORS: [(i32, i32); 4] = [(0, -1), (1, 0), (0, 1), (-1, 0)];
+
+/// The light levels of the cells of a single chunk, saved alongside it.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct LightGrid {
+    width: i32,
+    levels: Vec<u8>,
+}
+
+impl LightGrid {
+    pub fn new(width: i32) -> Self {
+        LightGrid {
+            width: width,
+            levels: vec![0; (width * width) as usize],
+        }
+    }
+
+    pub fn get(&self, x: i32, y: i32) -> u8 {
+        self.levels[(y * self.width + x) as usize]
+    }
+
+    pub fn set(&mut self, x: i32, y: i32, level: u8) {
+        self.levels[(y * self.width + x) as usize] = level;
+    }
+}
+
+/// Light levels of every loaded chunk, propagated by breadth-first search
+/// across chunk borders.
+///
+/// Light that would spread into a chunk that isn't loaded is queued and
+/// applied once the chunk's grid is attached. The grid of each chunk should be
+/// detached and saved with the chunk when it unloads, usually through an
+/// `Attachment`. Queued border updates only live in memory.
+///
+/// All positions are world positions. `opaque` callbacks return whether the
+/// cell at a world position blocks light.
+pub struct LightMap<I: Index> {
+    chunk_width: i32,
+    grids: FxHashMap<I, LightGrid>,
+    pending: FxHashMap<I, HashMap<(i32, i32), u8>>,
+}
+
+impl<I: Index> LightMap<I> {
+    pub fn new(chunk_width: i32) -> Self {
+        LightMap {
+            chunk_width: chunk_width,
+            grids: FxHashMap::default(),
+            pending: FxHashMap::default(),
+        }
+    }
+
+    /// Returns the light level at a position, or `None` if its chunk isn't
+    /// loaded.
+    pub fn light(&self, x: i32, y: i32) -> Option<u8> {
+        let (index, (lx, ly)) = chunk_at::<I>(x, y, self.chunk_width);
+        self.grids.get(&index).map(|g| g.get(lx, ly))
+    }
+
+    fn set_light(&mut self, x: i32, y: i32, level: u8) {
+        let (index, (lx, ly)) = chunk_at::<I>(x, y, self.chunk_width);
+        if let Some(grid) = self.grids.get_mut(&index) {
+            grid.set(lx, ly, level);
+        }
+    }
+
+    /// Returns the number of border updates waiting for the chunk at `index`
+    /// to be attached.
+    pub fn pending_updates(&self, index: &I) -> usize {
+        self.pending.get(index).map_or(0, |p| p.len())
+    }
+
+    /// Places a light source of the given level and spreads its light.
+    pub fn add_light<F>(&mut self, x: i32, y: i32, level: u8, opaque: F)
+        where F: Fn(i32, i32) -> bool
+    {
+        match self.light(x, y) {
+            Some(current) if current < level => self.set_light(x, y, level),
+            _ => return,
+        }
+        let mut queue = VecDeque::new();
+        queue.push_back((x, y));
+        self.propagate(queue, &opaque);
+    }
+
+    /// Removes the light at a position along with all light that spread from
+    /// it, then refills the darkened area from the remaining sources.
+    ///
+    /// Light that already spread into unloaded chunks is not removed.
+    pub fn remove_light<F>(&mut self, x: i32, y: i32, opaque: F)
+        where F: Fn(i32, i32) -> bool
+    {
+        let level = match self.light(x, y) {
+            Some(l) if l > 0 => l,
+            _                => return,
+        };
+        self.set_light(x, y, 0);
+
+        let mut removal = VecDeque::new();
+        let mut refill = VecDeque::new();
+        removal.push_back((x, y, level));
+
+        while let Some((cx, cy, old)) = removal.pop_front() {
+            for &(dx, dy) in NEIGHBORS.iter() {
+                let (nx, ny) = (cx + dx, cy + dy);
+                match self.light(nx, ny) {
+                    Some(l) if l != 0 && l < old => {
+                        self.set_light(nx, ny, 0);
+                        removal.push_back((nx, ny, l));
+                    },
+                    Some(l) if l >= old => refill.push_back((nx, ny)),
+                    _ => (),
+                }
+            }
+        }
+
+        self.propagate(refill, &opaque);
+    }
+
+    /// Attaches the saved light grid of a chunk that was loaded, then applies
+    /// light queued for it and light spreading in from loaded neighbors.
+    pub fn attach<F>(&mut self, index: &I, grid: LightGrid, opaque: F)
+        where F: Fn(i32, i32) -> bool
+    {
+        self.grids.insert(index.clone(), grid);
+
+        let mut queue = VecDeque::new();
+
+        if let Some(pending) = self.pending.remove(index) {
+            for ((lx, ly), level) in pending.into_iter() {
+                let (x, y) = world_pos(index, (lx, ly), self.chunk_width);
+                if !opaque(x, y) && self.light(x, y).map_or(false, |l| l < level) {
+                    self.set_light(x, y, level);
+                    queue.push_back((x, y));
+                }
+            }
+        }
+
+        // Neighboring chunks may have changed while this one was unloaded, so
+        // let the light on the other side of each border spread in again.
+        let w = self.chunk_width;
+        for i in 0..w {
+            for &(lx, ly) in [(i, -1), (i, w), (-1, i), (w, i)].iter() {
+                let (x, y) = world_pos(index, (lx, ly), w);
+                if self.light(x, y).map_or(false, |l| l > 1) {
+                    queue.push_back((x, y));
+                }
+            }
+        }
+
+        self.propagate(queue, &opaque);
+    }
+
+    /// Attaches an unlit grid for a chunk that was just generated.
+    pub fn attach_new<F>(&mut self, index: &I, opaque: F)
+        where F: Fn(i32, i32) -> bool
+    {
+        let grid = LightGrid::new(self.chunk_width);
+        self.attach(index, grid, opaque);
+    }
+
+    /// Detaches the light grid of a chunk that is being unloaded.
+    pub fn detach(&mut self, index: &I) -> Option<LightGrid> {
+        self.grids.remove(index)
+    }
+
+    fn propagate<F>(&mut self, mut queue: VecDeque<(i32, i32)>, opaque: &F)
+        where F: Fn(i32, i32) -> bool
+    {
+        while let Some((x, y)) = queue.pop_front() {
+            let level = match self.light(x, y) {
+                Some(l) if l > 1 => l - 1,
+                _                => continue,
+            };
+
+            for &(dx, dy) in NEIGHBORS.iter() {
+                let (nx, ny) = (x + dx, y + dy);
+                if opaque(nx, ny) {
+                    continue;
+                }
+                match self.light(nx, ny) {
+                    Some(current) => {
+                        if current < level {
+                            self.set_light(nx, ny, level);
+                            queue.push_back((nx, ny));
+                        }
+                    },
+                    None => {
+                        let (index, local) = chunk_at::<I>(nx, ny, self.chunk_width);
+                        let queued = self.pending.entry(index).or_insert_with(HashMap::new)
+                            .entry(local).or_insert(0);
+                        if *queued < level {
+                            *queued = level;
+                        }
+                    },
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use traits::TestIndex;
+
+    #[test]
+    fn test_light_crosses_borders() {
+        let mut light: LightMap<TestIndex> = LightMap::new(4);
+        light.attach_new(&TestIndex(0, 0), |_, _| false);
+        light.add_light(3, 1, 4, |_, _| false);
+        assert_eq!(light.light(2, 1), Some(3));
+        assert_eq!(light.light(4, 1), None);
+        assert_eq!(light.pending_updates(&TestIndex(1, 0)), 4);
+
+        light.attach_new(&TestIndex(1, 0), |_, _| false);
+        assert_eq!(light.light(4, 1), Some(3));
+        assert_eq!(light.light(5, 1), Some(2));
+        assert_eq!(light.pending_updates(&TestIndex(1, 0)), 0);
+
+        light.remove_light(3, 1, |_, _| false);
+        assert_eq!(light.light(3, 1), Some(0));
+        assert_eq!(light.light(5, 1), Some(0));
+    }
+}
@@ -0,0 +1,120 @@
+use std::collections::BTreeMap;
+
+use error::SerialResult;
+use metadata::WorldMetadata;
+
+/// Identifies a link between two positions in the world.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord, Serialize, Deserialize)]
+pub struct LinkId(pub u64);
+
+/// One end of a link: a position inside the chunk at the given chunk
+/// coordinates.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub struct LinkEnd {
+    pub chunk: (i32, i32),
+    pub position: (i32, i32),
+}
+
+impl LinkEnd {
+    pub fn new(chunk: (i32, i32), position: (i32, i32)) -> Self {
+        LinkEnd {
+            chunk: chunk,
+            position: position,
+        }
+    }
+}
+
+/// Durable links between positions that may lie in different chunks, like
+/// the two sides of a portal or the parts of a multi-tile machine.
+///
+/// The table is stored in the world metadata, so links survive both chunk
+/// unloads and restarts.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct LinkTable {
+    next_id: u64,
+    links: BTreeMap<LinkId, (LinkEnd, LinkEnd)>,
+}
+
+impl LinkTable {
+    const METADATA_KEY: &'static str = "infinigen.links";
+
+    /// Reads the link table out of world metadata.
+    pub fn load(metadata: &WorldMetadata) -> SerialResult<Self> {
+        let table = metadata.get(Self::METADATA_KEY)?;
+        Ok(table.unwrap_or_default())
+    }
+
+    /// Writes the link table into world metadata.
+    pub fn store(&self, metadata: &mut WorldMetadata) -> SerialResult<()> {
+        metadata.insert(Self::METADATA_KEY, self)
+    }
+
+    pub fn register(&mut self, a: LinkEnd, b: LinkEnd) -> LinkId {
+        let id = LinkId(self.next_id);
+        self.next_id += 1;
+        self.links.insert(id, (a, b));
+        id
+    }
+
+    pub fn remove(&mut self, id: LinkId) -> Option<(LinkEnd, LinkEnd)> {
+        self.links.remove(&id)
+    }
+
+    pub fn get(&self, id: LinkId) -> Option<(LinkEnd, LinkEnd)> {
+        self.links.get(&id).cloned()
+    }
+
+    /// Returns the end of a link opposite to `from`, or `None` if the link
+    /// doesn't exist or doesn't touch `from`.
+    pub fn other_end(&self, id: LinkId, from: &LinkEnd) -> Option<LinkEnd> {
+        match self.links.get(&id) {
+            Some(&(a, b)) if a == *from => Some(b),
+            Some(&(a, b)) if b == *from => Some(a),
+            _                           => None,
+        }
+    }
+
+    /// Returns the links that have an end at the given position.
+    pub fn links_at(&self, end: &LinkEnd) -> Vec<LinkId> {
+        self.links.iter()
+            .filter(|&(_, &(a, b))| a == *end || b == *end)
+            .map(|(id, _)| *id)
+            .collect()
+    }
+
+    pub fn len(&self) -> usize {
+        self.links.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.links.is_empty()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_link_table() {
+        let (a, b, c) = (LinkEnd::new((0, 0), (1, 2)), LinkEnd::new((3, -1), (0, 0)), LinkEnd::new((3, -1), (5, 5)));
+        let mut links = LinkTable::default();
+        let ab = links.register(a, b);
+        let bc = links.register(b, c);
+        assert_ne!(ab, bc);
+        assert_eq!(links.other_end(ab, &a), Some(b));
+        assert_eq!(links.other_end(ab, &b), Some(a));
+        assert_eq!(links.other_end(ab, &c), None);
+        assert_eq!(links.links_at(&b), vec![ab, bc]);
+
+        let mut metadata = WorldMetadata::new();
+        links.store(&mut metadata).unwrap();
+        let mut links = LinkTable::load(&metadata).unwrap();
+        assert_eq!(links.remove(ab), Some((a, b)));
+        assert_eq!(links.len(), 1);
+
+        // Ids aren't reused after a link is removed.
+        assert!(links.register(a, c) > bc);
+        assert!(LinkTable::load(&WorldMetadata::new()).unwrap().is_empty());
+    }
+}
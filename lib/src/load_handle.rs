@@ -0,0 +1,197 @@
+use std::fs::File;
+use std::io::{self, Read, Seek, SeekFrom};
+use std::path::Path;
+use std::sync::mpsc::{Receiver, TryRecvError};
+use std::time::Instant;
+
+use bincode::{self, Infinite};
+
+use error::{ErrorContext, SerialError, SerialResult, WithContext};
+use format::{self, RegionConfig, HEADER_SIZE};
+use managed_region::{decompress_data, read_data_prefix};
+use metadata::ChunkMeta;
+use region::RegionLocalIndex;
+use traits::ManagedChunk;
+
+/// The outcome of reading a chunk in the background: the chunk and its
+/// metadata, or `None` if it was never saved and must be generated.
+pub type ChunkLoadResult<C> = SerialResult<Option<(C, ChunkMeta)>>;
+
+/// A pending load of a chunk started by `ChunkedWorld::request_chunks`.
+///
+/// The chunk is read and deserialized on a background thread. Once the handle
+/// is ready, pass it to `ChunkedWorld::finish_chunk_load` to insert the chunk
+/// into the world.
+pub struct ChunkLoadHandle<I, C> {
+    index: I,
+    /// The load ticket from the world's `LoadRegistry`, or `None` if the
+    /// chunk was already loaded when it was requested.
+    ticket: Option<u64>,
+    receiver: Option<Receiver<ChunkLoadResult<C>>>,
+    result: Option<ChunkLoadResult<C>>,
+    /// When the chunk was requested, for timing the load.
+    requested_at: Instant,
+}
+
+impl<I, C> ChunkLoadHandle<I, C> {
+    pub(crate) fn pending(index: I, ticket: u64, receiver: Receiver<ChunkLoadResult<C>>) -> Self {
+        ChunkLoadHandle {
+            index: index,
+            ticket: Some(ticket),
+            receiver: Some(receiver),
+            result: None,
+            requested_at: Instant::now(),
+        }
+    }
+
+    pub(crate) fn ready(index: I, ticket: Option<u64>, result: ChunkLoadResult<C>) -> Self {
+        ChunkLoadHandle {
+            index: index,
+            ticket: ticket,
+            receiver: None,
+            result: Some(result),
+            requested_at: Instant::now(),
+        }
+    }
+
+    pub fn index(&self) -> &I {
+        &self.index
+    }
+
+    pub(crate) fn ticket(&self) -> Option<u64> {
+        self.ticket
+    }
+
+    pub(crate) fn requested_at(&self) -> Instant {
+        self.requested_at
+    }
+
+    /// Returns whether the chunk has been read, without blocking.
+    pub fn is_ready(&mut self) -> bool {
+        if self.result.is_some() {
+            return true;
+        }
+
+        let received = match self.receiver {
+            Some(ref rx) => match rx.try_recv() {
+                Ok(result)                      => result,
+                Err(TryRecvError::Empty)        => return false,
+                Err(TryRecvError::Disconnected) => Err(thread_exited()),
+            },
+            None => return true,
+        };
+        self.result = Some(received);
+        true
+    }
+
+    /// Blocks until the chunk has been read.
+    pub fn wait(&mut self) {
+        if self.result.is_some() {
+            return;
+        }
+
+        let received = match self.receiver {
+            Some(ref rx) => rx.recv().unwrap_or_else(|_| Err(thread_exited())),
+            None         => return,
+        };
+        self.result = Some(received);
+    }
+
+    /// Blocks until the chunk has been read and returns the result.
+    pub(crate) fn into_result(mut self) -> ChunkLoadResult<C> {
+        self.wait();
+        self.result.unwrap_or(Ok(None))
+    }
+}
+
+fn thread_exited() -> SerialError {
+    io::Error::new(io::ErrorKind::Other, "chunk load thread exited").into()
+}
+
+/// Reads a chunk from a region file opened separately from the region's own
+/// handle, so it can be done on another thread.
+pub(crate) fn read_chunk_at<C, P>(path: P, index: &RegionLocalIndex) -> ChunkLoadResult<C>
+    where C: ManagedChunk,
+          P: AsRef<Path>
+{
+    read_chunk_file(path.as_ref(), index).with_context(|| {
+        ErrorContext::new("load chunk")
+            .with_chunk(index.clone())
+            .with_path(&path)
+    })
+}
+
+fn read_chunk_file<C: ManagedChunk>(path: &Path, index: &RegionLocalIndex) -> ChunkLoadResult<C> {
+    if !path.exists() {
+        return Ok(None);
+    }
+
+    let config = RegionConfig::of::<C>();
+    let mut file = File::open(path)?;
+    format::check_header(&mut file, &config)?;
+
+    let (offset, size) = match format::read_entry(&mut file, &config, HEADER_SIZE, index)? {
+        Some(entry) => entry,
+        None        => return Ok(None),
+    };
+
+    let mut buf = vec![0u8; size];
+    file.seek(SeekFrom::Start(offset))?;
+    file.read_exact(&mut buf)?;
+    if read_data_prefix(&buf).1 == 0 {
+        return Ok(None);
+    }
+
+    let decompressed = decompress_data(&buf)?;
+    let mut reader = decompressed.as_slice();
+    let meta: ChunkMeta = bincode::deserialize_from(&mut reader, Infinite)?;
+    let chunk: C = bincode::deserialize(reader)?;
+    Ok(Some((chunk, meta)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+    use std::sync::mpsc;
+    use testing::{TempDir, TestChunk, TestWorld};
+    use traits::{ChunkedWorld, TestIndex};
+    use region::RegionIndex;
+
+    #[test]
+    fn test_read_chunk_at() {
+        let dir = TempDir::new("read-chunk-at");
+        let path = dir.layout().region_path(&RegionIndex(0, 0));
+        assert!(read_chunk_at::<TestChunk, _>(&path, &RegionLocalIndex(1, 1)).unwrap().is_none());
+
+        let mut world = TestWorld::new(dir.layout());
+        world.edit(TestIndex(1, 1), 3);
+        world.advance_tick();
+        world.save().unwrap();
+
+        let (chunk, meta) = read_chunk_at::<TestChunk, _>(&path, &RegionLocalIndex(1, 1)).unwrap().unwrap();
+        assert_eq!(chunk, TestChunk { pos: (1, 1), edits: 3 });
+        assert_eq!(meta.saved_at, 1);
+        assert!(read_chunk_at::<TestChunk, _>(&path, &RegionLocalIndex(2, 1)).unwrap().is_none());
+
+        // Errors name the file they came from.
+        fs::write(&path, b"garbage").unwrap();
+        let e = read_chunk_at::<TestChunk, _>(&path, &RegionLocalIndex(1, 1)).unwrap_err();
+        assert_eq!(e.error_context().and_then(|c| c.path.clone()), Some(path));
+    }
+
+    #[test]
+    fn test_load_handle() {
+        let (tx, rx) = mpsc::channel();
+        let mut handle: ChunkLoadHandle<TestIndex, TestChunk> = ChunkLoadHandle::pending(TestIndex(0, 0), 1, rx);
+        assert!(!handle.is_ready());
+        tx.send(Ok(None)).unwrap();
+        assert!(handle.is_ready());
+        assert!(handle.into_result().unwrap().is_none());
+
+        let (tx, rx) = mpsc::channel();
+        let handle: ChunkLoadHandle<TestIndex, TestChunk> = ChunkLoadHandle::pending(TestIndex(0, 0), 2, rx);
+        drop(tx);
+        assert!(handle.into_result().is_err());
+    }
+}
@@ -0,0 +1,176 @@
+use hash::{FxHashMap, FxHashSet};
+use traits::ChunkKey;
+
+/// Where a chunk is in its lifecycle.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LoadState {
+    NotLoaded,
+    /// A load was requested with `ChunkedWorld::request_chunks` and hasn't
+    /// been finished yet.
+    Loading,
+    Loaded,
+    /// The chunk was removed from the world and is being written to disk.
+    Unloading,
+    /// The chunk couldn't be read and was skipped, so it isn't loaded again
+    /// until `LoadRegistry::retry_failed` is called.
+    Failed,
+}
+
+/// Tracks the load state of every chunk that isn't `NotLoaded`, so that
+/// loading a chunk twice is harmless.
+///
+/// Every requested load gets a ticket. Only the load holding the latest
+/// ticket of a chunk may insert it, so the result of a request is dropped if
+/// the chunk was loaded some other way, or requested again, in the meantime.
+///
+/// Also remembers which chunks in the world are only placeholders, shown
+/// until the real chunk is loaded or generated.
+#[derive(Debug, Clone)]
+pub struct LoadRegistry<I: ChunkKey> {
+    states: FxHashMap<I, LoadState>,
+    tickets: FxHashMap<I, u64>,
+    next_ticket: u64,
+    placeholders: FxHashSet<I>,
+}
+
+impl<I: ChunkKey> LoadRegistry<I> {
+    pub fn new() -> Self {
+        LoadRegistry {
+            states: FxHashMap::default(),
+            tickets: FxHashMap::default(),
+            next_ticket: 0,
+            placeholders: FxHashSet::default(),
+        }
+    }
+
+    pub fn state(&self, index: &I) -> LoadState {
+        self.states.get(index).cloned().unwrap_or(LoadState::NotLoaded)
+    }
+
+    /// Starts a load of a chunk that isn't loaded and returns its ticket,
+    /// superseding any load already in flight. Returns `None` if the chunk
+    /// is loaded or being unloaded.
+    pub fn begin_load(&mut self, index: &I) -> Option<u64> {
+        match self.state(index) {
+            LoadState::NotLoaded | LoadState::Loading | LoadState::Failed => {
+                let ticket = self.next_ticket;
+                self.next_ticket += 1;
+                self.states.insert(index.clone(), LoadState::Loading);
+                self.tickets.insert(index.clone(), ticket);
+                Some(ticket)
+            },
+            LoadState::Loaded | LoadState::Unloading => None,
+        }
+    }
+
+    /// Returns whether a ticket belongs to the latest load of a chunk that is
+    /// still in flight.
+    pub fn is_current(&self, index: &I, ticket: u64) -> bool {
+        self.state(index) == LoadState::Loading && self.tickets.get(index) == Some(&ticket)
+    }
+
+    /// Abandons a load that failed, if it is the latest one.
+    pub fn cancel_load(&mut self, index: &I, ticket: u64) {
+        if self.is_current(index, ticket) {
+            self.states.remove(index);
+            self.tickets.remove(index);
+        }
+    }
+
+    pub fn mark_loaded(&mut self, index: &I) {
+        self.tickets.remove(index);
+        self.states.insert(index.clone(), LoadState::Loaded);
+    }
+
+    pub fn mark_unloading(&mut self, index: &I) {
+        self.states.insert(index.clone(), LoadState::Unloading);
+    }
+
+    pub fn mark_unloaded(&mut self, index: &I) {
+        self.tickets.remove(index);
+        self.states.remove(index);
+    }
+
+    pub fn mark_failed(&mut self, index: &I) {
+        self.tickets.remove(index);
+        self.states.insert(index.clone(), LoadState::Failed);
+    }
+
+    pub fn mark_placeholder(&mut self, index: &I) {
+        self.placeholders.insert(index.clone());
+    }
+
+    /// Forgets that a chunk is a placeholder. Returns whether it was one.
+    pub fn clear_placeholder(&mut self, index: &I) -> bool {
+        self.placeholders.remove(index)
+    }
+
+    pub fn is_placeholder(&self, index: &I) -> bool {
+        self.placeholders.contains(index)
+    }
+
+    /// Returns the indices of every placeholder in the world.
+    pub fn placeholders(&self) -> Vec<I> {
+        self.placeholders.iter().cloned().collect()
+    }
+
+    /// Lets every chunk that failed to load be loaded again, such as after
+    /// the player repaired the save. Returns their indices.
+    pub fn retry_failed(&mut self) -> Vec<I> {
+        let failed: Vec<I> = self.states.iter()
+            .filter(|&(_, &state)| state == LoadState::Failed)
+            .map(|(index, _)| index.clone())
+            .collect();
+        for index in failed.iter() {
+            self.states.remove(index);
+        }
+        failed
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use traits::TestIndex;
+
+    #[test]
+    fn test_superseded_load() {
+        let mut registry = LoadRegistry::new();
+        let index = TestIndex(0, 0);
+
+        let first = registry.begin_load(&index).unwrap();
+        let second = registry.begin_load(&index).unwrap();
+        assert!(!registry.is_current(&index, first));
+        assert!(registry.is_current(&index, second));
+
+        registry.mark_loaded(&index);
+        assert!(!registry.is_current(&index, second));
+        assert_eq!(registry.begin_load(&index), None);
+    }
+
+    #[test]
+    fn test_retry_failed() {
+        let mut registry = LoadRegistry::new();
+        registry.mark_failed(&TestIndex(1, 2));
+        registry.mark_loaded(&TestIndex(0, 0));
+        assert_eq!(registry.state(&TestIndex(1, 2)), LoadState::Failed);
+
+        assert_eq!(registry.retry_failed(), vec![TestIndex(1, 2)]);
+        assert_eq!(registry.state(&TestIndex(1, 2)), LoadState::NotLoaded);
+        assert_eq!(registry.state(&TestIndex(0, 0)), LoadState::Loaded);
+    }
+
+    #[test]
+    fn test_placeholders() {
+        let mut registry = LoadRegistry::new();
+        let index = TestIndex(3, -3);
+        registry.begin_load(&index).unwrap();
+        registry.mark_placeholder(&index);
+        assert!(registry.is_placeholder(&index));
+        assert_eq!(registry.placeholders(), vec![index]);
+
+        assert!(registry.clear_placeholder(&index));
+        assert!(!registry.clear_placeholder(&index));
+        assert_eq!(registry.state(&index), LoadState::Loading);
+    }
+}
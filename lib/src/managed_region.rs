@@ -1,55 +1,159 @@
 use std::io::prelude::*;
 use std::io::SeekFrom;
-use std::fs::{File, OpenOptions};
+use std::fs::{self, File, OpenOptions};
 use std::path::Path;
+use std::time::Instant;
 
 use bincode::{self, Infinite};
+#[cfg(feature = "compression")]
 use flate2::write::ZlibEncoder;
+#[cfg(feature = "compression")]
 use flate2::read::ZlibDecoder;
+#[cfg(feature = "compression")]
 use flate2::Compression;
 
+use adaptive::CompressionSample;
+use error::*;
+use format::{self, CompressionLevel, Padding, RegionConfig};
+use lazy::LazyChunk;
+use lifetime::{ChunkLifetimeTracker, LifetimeError};
+use metadata::ChunkMeta;
 use region::*;
-use traits::{ManagedChunk, Index};
+use traits::{ChunkKey, ManagedChunk};
 
 /// Pads the given byte vec with zeroes to the next multiple of the given sector
-/// size.
-fn pad_byte_vec(bytes: &mut Vec<u8>, size: usize) {
-    for _ in 0..(size - (bytes.len() % size)) {
-        bytes.push(0);
+/// size, unless it is a multiple already or `padding` is `Padding::None`.
+pub(crate) fn pad_byte_vec(bytes: &mut Vec<u8>, size: usize, padding: Padding) {
+    if padding == Padding::ZeroFill && bytes.len() % size != 0 {
+        let padded = (bytes.len() / size + 1) * size;
+        bytes.resize(padded, 0);
     }
 }
 
+/// What reading a chunk that has unsaved changes does, such as reloading a
+/// chunk created this session before it was first saved.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum UnsavedReadPolicy {
+    /// Return `LogicError::ChunkUnsaved`.
+    Fail,
+    /// Return `LogicError::NoChunkInSavefile`, so the chunk is generated
+    /// again.
+    TreatAsMissing,
+}
+
+/// Encodes the length prefix of a chunk's data, which is little-endian on
+/// every platform.
 fn serialize_u32(val: u32) -> [u8; 4] {
-    let bits = u32::from_be(val);
-    [(bits >> 24) as u8, (bits >> 16) as u8, (bits >> 8) as u8, bits as u8]
+    val.to_le_bytes()
 }
 
 fn deserialize_u32(buf: &[u8]) -> u32 {
-    (((buf[0] as u32) << 24) |
-     ((buf[1] as u32) << 16) |
-     ((buf[2] as u32) <<  8) |
-     ((buf[3] as u32) <<  0)).to_be()
+    u32::from_le_bytes([buf[0], buf[1], buf[2], buf[3]])
+}
+
+/// Computes a 64-bit FNV-1a hash of the given bytes. Unlike the hasher in the
+/// standard library, the result is stable across platforms and Rust versions.
+pub fn hash_bytes(bytes: &[u8]) -> u64 {
+    let mut hash: u64 = 0xcbf29ce484222325;
+    for byte in bytes {
+        hash ^= *byte as u64;
+        hash = hash.wrapping_mul(0x100000001b3);
+    }
+    hash
+}
+
+/// Compresses bytes with zlib at the given level.
+#[cfg(feature = "compression")]
+pub(crate) fn zlib_compress(bytes: &[u8], level: CompressionLevel) -> SerialResult<Vec<u8>> {
+    let compression = match level {
+        CompressionLevel::Stored  => return Ok(bytes.to_vec()),
+        CompressionLevel::Fast    => Compression::Fast,
+        CompressionLevel::Default => Compression::Default,
+        CompressionLevel::Best    => Compression::Best,
+    };
+
+    let mut e = ZlibEncoder::new(Vec::new(), compression);
+    e.write_all(bytes)?;
+    Ok(e.finish()?)
+}
+
+#[cfg(not(feature = "compression"))]
+pub(crate) fn zlib_compress(_bytes: &[u8], _level: CompressionLevel) -> SerialResult<Vec<u8>> {
+    Err(FormatError::CompressionDisabled.into())
 }
 
-fn compress_data(bytes: &Vec<u8>) -> SerialResult<Vec<u8>> {
-    let mut e = ZlibEncoder::new(Vec::new(), Compression::Default);
-    e.write(bytes.as_slice())?;
-    let buf = e.finish().map_err(SerialError::from)?;
+/// Returns a reader that decompresses zlib data.
+#[cfg(feature = "compression")]
+pub(crate) fn zlib_reader<'r, R: Read + 'r>(reader: R) -> SerialResult<Box<dyn Read + 'r>> {
+    Ok(Box::new(ZlibDecoder::new(reader)))
+}
+
+#[cfg(not(feature = "compression"))]
+pub(crate) fn zlib_reader<'r, R: Read + 'r>(_reader: R) -> SerialResult<Box<dyn Read + 'r>> {
+    Err(FormatError::CompressionDisabled.into())
+}
 
-    let size: u32 = buf.len() as u32;
-    let mut header = serialize_u32(size).to_vec();
+/// Compresses the serialized data of a chunk at the given level and prefixes
+/// it with its length and level. Without the `compression` feature, the data
+/// is always stored uncompressed.
+pub(crate) fn compress_data(bytes: &Vec<u8>, level: CompressionLevel) -> SerialResult<Vec<u8>> {
+    #[cfg(not(feature = "compression"))]
+    let level = { let _ = level; CompressionLevel::Stored };
+
+    if level == CompressionLevel::Stored {
+        let mut header = serialize_u32(level.encode_prefix(bytes.len())).to_vec();
+        header.extend(bytes.as_slice());
+        return Ok(header);
+    }
+
+    let buf = zlib_compress(bytes, level)?;
+    let mut header = serialize_u32(level.encode_prefix(buf.len())).to_vec();
     header.extend(buf.as_slice());
 
     Ok(header)
 }
 
-fn decompress_data(bytes: &Vec<u8>) -> SerialResult<Vec<u8>> {
-    let (header, _) = bytes.split_at(4);
-    let data_length = deserialize_u32(header) as usize;
+/// Serializes a chunk after its metadata. Also returns the hash of the
+/// chunk's serialized data.
+fn serialize_chunk<C: ManagedChunk>(chunk: &C, meta: &ChunkMeta) -> SerialResult<(Vec<u8>, u64)> {
+    profile_span!("serialize_chunk");
+    let chunk_data: Vec<u8> = bincode::serialize(chunk, Infinite)?;
+    let hash = hash_bytes(&chunk_data);
+
+    let mut encoded: Vec<u8> = bincode::serialize(meta, Infinite)?;
+    encoded.extend(chunk_data);
+    Ok((encoded, hash))
+}
+
+/// Compresses serialized chunk data and pads it to the sector size.
+fn pack_chunk<C: ManagedChunk>(encoded: &Vec<u8>, level: CompressionLevel) -> SerialResult<Vec<u8>> {
+    profile_span!("compress_chunk", bytes = encoded.len());
+    let mut compressed = compress_data(encoded, level)?;
+    pad_byte_vec(&mut compressed, C::SECTOR_SIZE, C::PADDING);
+    Ok(compressed)
+}
+
+/// Reads the level and length of a chunk's data from its prefix.
+pub(crate) fn read_data_prefix(bytes: &[u8]) -> (CompressionLevel, usize) {
+    CompressionLevel::decode_prefix(deserialize_u32(&bytes[0..4]))
+}
+
+pub(crate) fn decompress_data(bytes: &Vec<u8>) -> SerialResult<Vec<u8>> {
+    if bytes.len() < 4 {
+        return Err(FormatError::TruncatedData { expected: 4, found: bytes.len() }.into());
+    }
+    let (level, data_length) = read_data_prefix(bytes);
+    if bytes.len() - 4 < data_length {
+        return Err(FormatError::TruncatedData { expected: 4 + data_length, found: bytes.len() }.into());
+    }
+    let data = &bytes[4..4 + data_length];
+
+    if level == CompressionLevel::Stored {
+        return Ok(data.to_vec());
+    }
 
-    let mut d = ZlibDecoder::new(&bytes[4..4 + data_length]);
     let mut buf = Vec::new();
-    d.read_to_end(&mut buf).map_err(SerialError::from)?;
+    zlib_reader(data)?.read_to_end(&mut buf)?;
 
     Ok(buf)
 }
@@ -65,203 +169,484 @@ fn decompress_data(bytes: &Vec<u8>) -> SerialResult<Vec<u8>> {
 /// certain area. This reduces the number of open file handles and allows the
 /// file to remain open as large parts of terrain are saved to disk.
 ///
+/// Each region file starts with a header recording the format version, region
+/// width and sector size, so files written with other parameters are detected.
 /// Information about the size and offset of the chunk data is stored as a
-/// lookup table at the start of each region file. The lookup table indexes
+/// lookup table following the header. The lookup table indexes
 /// 16-bit integers, where the low 8 bits count the number of sectors the data
 /// occupies and the high 8 bits provide the offset in sectors from the end of
 /// the lookup table in the file. Both indices are currently limited to 255.
 /// Data is aligned to a specified number of bytes, the sector size, for better
-/// performance and easier encoding of offsets and sizes.
+/// performance and easier encoding of offsets and sizes. Chunks that outgrow
+/// their sectors are moved to free space, and the sectors they leave behind
+/// are reused by later writes.
 pub trait ManagedRegion<'a, I, C>
-    where I: Index,
+    where I: ChunkKey,
           C: ManagedChunk {
 
-    fn chunk_unsaved(&self, index: &I) -> bool;
-    fn mark_as_saved(&mut self, index: &I);
-    fn mark_as_unsaved(&mut self, index: &I);
+    /// Returns the chunks of this region that are in memory and not saved.
+    fn lifetimes(&self) -> &ChunkLifetimeTracker<I>;
+    fn lifetimes_mut(&mut self) -> &mut ChunkLifetimeTracker<I>;
     fn handle(&mut self) -> &mut File;
 
-    fn lookup_table_size() -> u64 { (C::REGION_WIDTH * C::REGION_WIDTH) as u64 * 2 }
+    fn chunk_unsaved(&self, index: &I) -> bool {
+        self.lifetimes().is_unsaved(index)
+    }
+
+    /// Records that a chunk was read into memory. Fails if the chunk is
+    /// already in memory.
+    fn mark_as_loaded(&mut self, index: &I) -> SerialResult<()> {
+        let result = self.lifetimes_mut().loaded(index);
+        self.lifetime_result(index, result)
+    }
+
+    /// Records that a chunk was saved. Fails if the chunk wasn't created or
+    /// loaded before.
+    fn mark_as_saved(&mut self, index: &I) -> SerialResult<()> {
+        let result = self.lifetimes_mut().saved(index).map(|_| ());
+        self.lifetime_result(index, result)
+    }
 
-    fn create_lookup_table_entry(&self, eof: u64, sector_count: u8) -> [u8; 2] {
-        let offset: u8 = ((eof - Self::lookup_table_size()) / C::SECTOR_SIZE as u64) as u8;
+    /// Notifies this Region that a chunk was created, so that its lifetime
+    /// should be tracked by the Region. Fails if the chunk is already in
+    /// memory.
+    fn receive_created_chunk(&mut self, index: &I) -> SerialResult<()> {
+        let result = self.lifetimes_mut().created(index);
+        self.lifetime_result(index, result)
+    }
 
-        [offset, sector_count]
+    /// Returns true if no chunk of this region is in memory.
+    fn is_empty(&self) -> bool {
+        self.lifetimes().is_empty()
     }
 
+    /// Converts the result of a lifetime transition into a `LogicError`.
+    fn lifetime_result(&self, index: &I, result: Result<(), LifetimeError>) -> SerialResult<()> {
+        let normalized_idx = self.normalize_chunk_index(index);
+        match result {
+            Ok(())                                => Ok(()),
+            Err(LifetimeError::AlreadyTracked(_)) => Err(ChunkUnsaved(normalized_idx).into()),
+            Err(LifetimeError::NotTracked)        => Err(ChunkNotTracked(normalized_idx).into()),
+        }
+    }
+
+    /// Returns the content hash of a chunk, if one was computed since this
+    /// region was loaded.
+    fn cached_hash(&self, index: &I) -> Option<u64>;
+    fn cache_hash(&mut self, index: &I, hash: u64);
+
+    /// Returns the parameters region files of this chunk type are written
+    /// with.
+    fn config() -> RegionConfig { RegionConfig::of::<C>() }
+
+    fn lookup_table_size() -> u64 { Self::config().lookup_table_size() }
+
     /// Returns the index of the region that manages the chunk at the given
     /// chunk index.
     fn get_region_index(chunk_index: &I) -> RegionIndex {
-        let conv = |mut q: i32, d: i32| {
-            // Divide by a larger number to make sure that negative division is
-            // handled properly. Chunk index (-1, -1) should map to region index
-            // (-1, -1), but -1 / self.width() = 0.
-            if q < 0 {
-                q -= C::REGION_WIDTH;
-            }
-
-            (q / d)
-        };
-        RegionIndex(conv(chunk_index.x(), C::REGION_WIDTH),
-                    conv(chunk_index.y(), C::REGION_WIDTH))
+        chunk_index.partition(C::REGION_WIDTH).0
     }
 
     /// Returns the handle to a region file. If it doesn't exist, it is created
-    /// and the lookup table initialized.
-    fn get_region_file<T: AsRef<Path>>(path: T) -> File {
+    /// along with any missing parent directories and the header and lookup
+    /// table initialized.
+    ///
+    /// Existing files are checked to have been written with the same region
+    /// width and sector size as `C` uses.
+    fn get_region_file<T: AsRef<Path>>(path: T) -> SerialResult<File> {
         if !path.as_ref().exists() {
+            if let Some(parent) = path.as_ref().parent() {
+                if !parent.as_os_str().is_empty() {
+                    fs::create_dir_all(parent)?;
+                }
+            }
             let mut file = OpenOptions::new()
                 .read(true)
                 .write(true)
                 .create(true)
-                .open(path.as_ref()).unwrap();
-            file.write(&vec![0u8; Self::lookup_table_size() as usize]).unwrap();
-            file
+                .open(path.as_ref())
+                .with_context(|| ErrorContext::new("create region file").with_path(&path))?;
+            format::write_header(&mut file, &Self::config())
+                .and_then(|_| format::preallocate(&mut file, &Self::config(), C::PREALLOCATION))
+                .with_context(|| ErrorContext::new("create region file").with_path(&path))?;
+            Ok(file)
         } else {
-            OpenOptions::new()
+            let mut file = OpenOptions::new()
                 .read(true)
                 .write(true)
-                .open(path.as_ref()).unwrap()
+                .open(path.as_ref())
+                .with_context(|| ErrorContext::new("open region file").with_path(&path))?;
+            format::check_header(&mut file, &Self::config())
+                .with_context(|| ErrorContext::new("open region file").with_path(&path))?;
+            Ok(file)
         }
     }
 
     /// Obtain this chunk's index relative to this region's index.
     fn normalize_chunk_index(&self, chunk_index: &I) -> RegionLocalIndex {
-        let conv = |i: i32| {
-            let i_new = i % C::REGION_WIDTH;
-            if i_new < 0 {
-                C::REGION_WIDTH + i_new
-            } else {
-                i_new
-            }
-        };
-        RegionLocalIndex(conv(chunk_index.x()), conv(chunk_index.y()))
+        chunk_index.partition(C::REGION_WIDTH).1
     }
 
     /// Writes a chunk at an index to disk as marks it as saved.
-    fn write_chunk(&mut self, chunk: C, index: &I) -> SerialResult<()>{
-        assert!(self.chunk_unsaved(index));
-
-        let mut encoded: Vec<u8> = bincode::serialize(&chunk, Infinite)?;
-
-        let mut compressed = compress_data(&mut encoded)?;
-        pad_byte_vec(&mut compressed, C::SECTOR_SIZE);
+    ///
+    /// The chunk's metadata is stored in front of the chunk data.
+    fn write_chunk(&mut self, chunk: C, index: &I, meta: &ChunkMeta) -> SerialResult<()>{
+        let (compressed, hash) = Self::encode_chunk(&chunk, meta)?;
+        self.save_encoded(compressed, hash, index)
+    }
 
+    /// Writes chunk data encoded with `encode_chunk` and marks the chunk as
+    /// saved. If the write fails, the chunk stays unsaved.
+    fn save_encoded(&mut self, compressed: Vec<u8>, hash: u64, index: &I) -> SerialResult<()> {
         let normalized_idx = self.normalize_chunk_index(index);
+        if !self.chunk_unsaved(index) {
+            return Err(ChunkNotTracked(normalized_idx).into());
+        }
 
-        let (offset, size) = self.read_chunk_offset(&normalized_idx);
+        self.write_encoded(compressed, &normalized_idx)?;
+        self.cache_hash(index, hash);
+        self.mark_as_saved(index)
+    }
 
+    /// Writes chunk data encoded with `encode_chunk`, in place if it fits in
+    /// the chunk's sectors and to free space otherwise. The data is read back
+    /// and checked afterwards if `C::VERIFY_WRITES` is set.
+    fn write_encoded(&mut self, compressed: Vec<u8>, index: &RegionLocalIndex) -> SerialResult<()> {
+        profile_span!("write_chunk", x = index.0, y = index.1, bytes = compressed.len());
+        let expected = if C::VERIFY_WRITES { Some(compressed.clone()) } else { None };
+        let (offset, size) = self.read_chunk_offset(index)?;
         match size {
-            Some(size) => {
-                assert!(size >= compressed.len(),
-                        "Chunk data grew larger than allocated sector size! \
-                         Consider using a larger sector size.");
-                self.update_chunk(compressed, offset)?;
-            },
-            None       => { self.append_chunk(compressed, &normalized_idx)?; },
+            Some(size) if size >= compressed.len() => self.update_chunk(compressed, offset),
+            _ => self.allocate_chunk(compressed, index),
+        }?;
+
+        match expected {
+            Some(expected) => self.verify_write(&expected, index),
+            None           => Ok(()),
+        }
+    }
+
+    /// Checks that the lookup table entry of a chunk that was just written
+    /// points at `expected`.
+    fn verify_write(&mut self, expected: &[u8], index: &RegionLocalIndex) -> SerialResult<()> {
+        let (offset, size) = self.read_chunk_offset(index)?;
+        if size.map_or(true, |s| s < expected.len()) {
+            return Err(FormatError::WriteMismatch(index.clone()).into());
+        }
+
+        let mut buf = vec![0u8; expected.len()];
+        self.handle().seek(SeekFrom::Start(offset))?;
+        self.handle().read_exact(&mut buf)?;
+        if buf.as_slice() != expected {
+            return Err(FormatError::WriteMismatch(index.clone()).into());
         }
-        self.mark_as_saved(index);
         Ok(())
     }
 
-    fn append_chunk(&mut self, chunk_data: Vec<u8>, index: &RegionLocalIndex) -> SerialResult<()> {
-        let sector_count = (chunk_data.len() as f32 / C::SECTOR_SIZE as f32).ceil() as u32;
-        assert!(sector_count < 256, "Sector count overflow!");
-        assert!(sector_count > 0, "Sector count zero! Len: {}", chunk_data.len());
-        let sector_count = sector_count as u8;
+    /// Serializes and compresses a chunk along with its metadata, padded to
+    /// the sector size according to `C::PADDING`. Also returns the hash of the chunk's serialized data.
+    fn encode_chunk(chunk: &C, meta: &ChunkMeta) -> SerialResult<(Vec<u8>, u64)> {
+        let (encoded, hash) = serialize_chunk(chunk, meta)?;
+        let level = if encoded.len() < C::COMPRESSION_THRESHOLD {
+            CompressionLevel::Stored
+        } else {
+            chunk.compression_level()
+        };
+        Ok((pack_chunk::<C>(&encoded, level)?, hash))
+    }
 
-        let new_offset = self.handle().seek(SeekFrom::End(0))?;
+    /// Like `encode_chunk`, but compresses at the given level instead of the
+    /// chunk's own, such as `CompressionLevel::Stored` to save quickly.
+    fn encode_chunk_with_level(chunk: &C, meta: &ChunkMeta, level: CompressionLevel) -> SerialResult<(Vec<u8>, u64)> {
+        let (encoded, hash) = serialize_chunk(chunk, meta)?;
+        Ok((pack_chunk::<C>(&encoded, level)?, hash))
+    }
+
+    /// Like `encode_chunk_with_level`, but also measures what compressing the
+    /// chunk cost, for `AdaptiveCompression`. Chunks smaller than
+    /// `C::COMPRESSION_THRESHOLD` are stored uncompressed regardless.
+    fn encode_chunk_sampled(chunk: &C, meta: &ChunkMeta, level: CompressionLevel) -> SerialResult<(Vec<u8>, u64, CompressionSample)> {
+        let (encoded, hash) = serialize_chunk(chunk, meta)?;
+        let level = if encoded.len() < C::COMPRESSION_THRESHOLD { CompressionLevel::Stored } else { level };
+
+        let start = Instant::now();
+        let mut compressed = compress_data(&encoded, level)?;
+        let sample = CompressionSample {
+            level: level,
+            raw_bytes: encoded.len(),
+            compressed_bytes: compressed.len(),
+            elapsed: start.elapsed(),
+        };
+        pad_byte_vec(&mut compressed, C::SECTOR_SIZE, C::PADDING);
+        Ok((compressed, hash, sample))
+    }
+
+    /// Returns the number of sectors a chunk would occupy if it were saved
+    /// now.
+    fn estimate_sectors(chunk: &C) -> SerialResult<usize> {
+        let (compressed, _) = Self::encode_chunk(chunk, &ChunkMeta::default())?;
+        Ok(Self::config().sectors_for(compressed.len()))
+    }
 
-        self.handle().write(chunk_data.as_slice())?;
-        self.write_chunk_offset(index, new_offset, sector_count)?;
+    /// Reserves at least `sectors` sectors for a chunk, so it can grow up to
+    /// that size later without outgrowing its space in the file. If the chunk
+    /// is already saved in fewer sectors, its data is moved to the new space.
+    fn reserve(&mut self, index: &I, sectors: usize) -> SerialResult<()> {
+        assert!(sectors > 0, "Sector count zero!");
 
-        let (o, v) = self.read_chunk_offset(index);
-        assert_eq!(new_offset, o, "index: {} new: {} old: {}", index, new_offset, o);
-        assert_eq!(sector_count as usize * C::SECTOR_SIZE, v.unwrap());
+        let normalized_idx = self.normalize_chunk_index(index);
+        if sectors > format::MAX_SECTORS {
+            return Err(FormatError::ChunkTooLarge {
+                index: normalized_idx,
+                sectors: sectors,
+            }.into());
+        }
+        let (offset, size) = self.read_chunk_offset(&normalized_idx)?;
+        let mut data = match size {
+            Some(size) if size >= sectors * C::SECTOR_SIZE => return Ok(()),
+            Some(size) => self.read_bytes(offset, size)?,
+            None       => Vec::new(),
+        };
+
+        // An all-zero length prefix marks space reserved for a chunk that
+        // hasn't been written yet.
+        data.resize(sectors * C::SECTOR_SIZE, 0);
+        self.allocate_chunk(data, &normalized_idx)
+    }
+
+    /// Returns the sector offset and sector count of every chunk stored in
+    /// this region except the given one, sorted by offset.
+    fn used_sectors(&mut self, except: &RegionLocalIndex) -> SerialResult<Vec<(usize, usize)>> {
+        let table = self.read_bytes(format::HEADER_SIZE, Self::lookup_table_size() as usize)?;
+        let mut used = Vec::new();
+        for y in 0..C::REGION_WIDTH {
+            for x in 0..C::REGION_WIDTH {
+                if RegionLocalIndex(x, y) == *except {
+                    continue;
+                }
+                let i = 2 * (x + y * C::REGION_WIDTH) as usize;
+                if table[i + 1] != 0 {
+                    used.push((table[i] as usize, table[i + 1] as usize));
+                }
+            }
+        }
+        used.sort();
+        Ok(used)
+    }
+
+    /// Finds the first run of sectors not used by any chunk other than the
+    /// given one that can hold `sectors` sectors, and returns its byte offset.
+    /// Space freed by chunks that were moved is reused before the file grows.
+    fn find_free_space(&mut self, sectors: usize, except: &RegionLocalIndex) -> SerialResult<u64> {
+        let mut cursor = 0;
+        for &(start, count) in self.used_sectors(except)?.iter() {
+            if start >= cursor + sectors {
+                break;
+            }
+            cursor = cursor.max(start + count);
+        }
+        Ok(Self::config().data_start() + (cursor * C::SECTOR_SIZE) as u64)
+    }
+
+    /// Writes a chunk's data to free space in the file and points its lookup
+    /// table entry there. The sectors it occupied before are freed.
+    ///
+    /// The lookup table entry is only changed once the data was written in
+    /// full, so a failed write leaves the previously saved data in place. Any
+    /// space the failed write added to the end of the file is given back.
+    fn allocate_chunk(&mut self, chunk_data: Vec<u8>, index: &RegionLocalIndex) -> SerialResult<()> {
+        let sector_count = Self::config().sectors_for(chunk_data.len());
+        assert!(sector_count > 0, "Sector count zero! Len: {}", chunk_data.len());
+
+        // Checked before anything is written, so a chunk that can't be
+        // addressed doesn't grow the file.
+        let new_offset = self.find_free_space(sector_count, index)?;
+        let entry = format::pack_entry(&Self::config(), index, new_offset, sector_count)?;
+        let file_len = self.handle().metadata()?.len();
+
+        let written = self.write_all_at(new_offset, &chunk_data)
+            .and_then(|_| self.write_all_at(Self::get_chunk_offset(index), &entry));
+        if let Err(e) = written {
+            if new_offset + chunk_data.len() as u64 > file_len {
+                let _ = self.handle().set_len(file_len);
+            }
+            return Err(e);
+        }
         Ok(())
     }
 
     fn update_chunk(&mut self, chunk_data: Vec<u8>, byte_offset: u64) -> SerialResult<()> {
-        self.handle().seek(SeekFrom::Start(byte_offset))?;
-        self.handle().write(chunk_data.as_slice())?;
-        Ok(())
+        self.write_all_at(byte_offset, &chunk_data)
+    }
+
+    /// Writes all of `data` at a byte offset. Running out of space is
+    /// reported as `SerialError::DiskFull`.
+    fn write_all_at(&mut self, offset: u64, data: &[u8]) -> SerialResult<()> {
+        self.handle().seek(SeekFrom::Start(offset))?;
+        self.handle().write_all(data).map_err(write_error)
     }
 
-    /// Reads a chunk from disk and marks it as unsaved.
-    fn read_chunk(&mut self, index: &I) -> SerialResult<C> {
-        assert!(!self.chunk_unsaved(index));
+    /// Reads a chunk and its metadata from disk and marks it as unsaved.
+    fn read_chunk(&mut self, index: &I) -> SerialResult<(C, ChunkMeta)> {
+        self.check_unsaved_read(index)?;
+
+        let (meta, chunk_data) = self.read_chunk_data(index)?;
+        let chunk = bincode::deserialize(chunk_data.as_slice())?;
+        self.mark_as_loaded(index)?;
+        Ok((chunk, meta))
+    }
+
+    /// Reads a chunk's metadata and its compressed data from disk and marks
+    /// it as unsaved. Only the metadata is decompressed; the chunk itself is
+    /// deserialized on first access.
+    fn read_chunk_lazy(&mut self, index: &I) -> SerialResult<(LazyChunk<C>, ChunkMeta)> {
+        self.check_unsaved_read(index)?;
+
+        let buf = self.read_payload(index)?;
+        let (level, _) = read_data_prefix(&buf);
+
+        let meta: ChunkMeta = if level == CompressionLevel::Stored {
+            let mut reader = &buf[4..];
+            bincode::deserialize_from(&mut reader, Infinite)?
+        } else {
+            let mut d = zlib_reader(&buf[4..])?;
+            bincode::deserialize_from(&mut d, Infinite)?
+        };
+
+        self.mark_as_loaded(index)?;
+        Ok((LazyChunk::packed(buf), meta))
+    }
+
+    /// Returns an error according to `C::UNSAVED_READ_POLICY` if the chunk
+    /// has unsaved changes. If the chunk is treated as missing, its copy in
+    /// memory is considered abandoned and is no longer tracked.
+    fn check_unsaved_read(&mut self, index: &I) -> SerialResult<()> {
+        if !self.chunk_unsaved(index) {
+            return Ok(());
+        }
 
         let normalized_idx = self.normalize_chunk_index(index);
-        let (offset, size_opt) = self.read_chunk_offset(&normalized_idx);
+        let error = match C::UNSAVED_READ_POLICY {
+            UnsavedReadPolicy::Fail           => ChunkUnsaved(normalized_idx),
+            UnsavedReadPolicy::TreatAsMissing => {
+                self.lifetimes_mut().forget(index);
+                NoChunkInSavefile(normalized_idx)
+            },
+        };
+        Err(error.into())
+    }
+
+    /// Reads the compressed data of a chunk, including its length prefix but
+    /// not its padding.
+    fn read_payload(&mut self, index: &I) -> SerialResult<Vec<u8>> {
+        let normalized_idx = self.normalize_chunk_index(index);
+        profile_span!("read_chunk", x = normalized_idx.0, y = normalized_idx.1);
+        let (offset, size_opt) = self.read_chunk_offset(&normalized_idx)?;
         let size = match size_opt {
             Some(s) => s,
-            None    => return Err(NoChunkInSavefile(normalized_idx.clone())),
+            None    => return Err(NoChunkInSavefile(normalized_idx.clone()).into()),
         };
 
-        let buf = self.read_bytes(offset, size);
+        let mut buf = self.read_bytes(offset, size)?;
+        let (_, data_length) = read_data_prefix(&buf);
+        if data_length == 0 {
+            // The space was reserved, but the chunk was never written.
+            return Err(NoChunkInSavefile(normalized_idx.clone()).into());
+        }
+        buf.truncate(4 + data_length);
+        Ok(buf)
+    }
 
+    /// Reads the metadata and uncompressed serialized data of a chunk from
+    /// disk and caches its content hash.
+    fn read_chunk_data(&mut self, index: &I) -> SerialResult<(ChunkMeta, Vec<u8>)> {
+        let buf = self.read_payload(index)?;
+
+        profile_span!("decompress_chunk", bytes = buf.len());
         let decompressed = decompress_data(&buf)?;
-        match bincode::deserialize(decompressed.as_slice()) {
-            Ok(dat) => {
-                self.mark_as_unsaved(index);
-                Ok(dat)
-            },
-            Err(e)  => {
-                println!("Bincode error");
-                Err (SerialError::from(e))
-            },
+        let mut reader = decompressed.as_slice();
+        let meta: ChunkMeta = bincode::deserialize_from(&mut reader, Infinite)?;
+        let chunk_data = reader.to_vec();
+
+        self.cache_hash(index, hash_bytes(&chunk_data));
+        Ok((meta, chunk_data))
+    }
+
+    /// Returns a hash of the serialized contents of a chunk as of its last
+    /// save, or `None` if the chunk has never been saved.
+    ///
+    /// The hash is cached when a chunk is read or written, so it reflects the
+    /// on-disk state and not any unsaved modifications.
+    fn chunk_hash(&mut self, index: &I) -> SerialResult<Option<u64>> {
+        if let Some(hash) = self.cached_hash(index) {
+            return Ok(Some(hash));
+        }
+
+        match self.read_chunk_data(index) {
+            Ok((_, data)) => Ok(Some(hash_bytes(&data))),
+            Err(ref e) if e.is_missing_chunk() => Ok(None),
+            Err(e) => Err(e),
         }
     }
 
     /// Reads the offset and size of the specified chunk inside this region.
-    fn read_chunk_offset(&mut self, index: &RegionLocalIndex) -> (u64, Option<usize>) {
+    fn read_chunk_offset(&mut self, index: &RegionLocalIndex) -> SerialResult<(u64, Option<usize>)> {
         let offset = Self::get_chunk_offset(index);
-        let data = self.read_bytes(offset, 2);
+        let data = self.read_bytes(offset, 2)?;
 
         // the byte offset should be u64 for Seek::seek, otherwise it will just
         // be cast every time.
-        let offset = Self::lookup_table_size() + (data[0] as usize * C::SECTOR_SIZE) as u64;
+        let offset = Self::config().data_start() + data[0] as u64 * C::SECTOR_SIZE as u64;
         let size = if data[1] == 0 {
             None
         } else {
             Some(data[1] as usize * C::SECTOR_SIZE)
         };
-        (offset, size)
-    }
-
-    fn write_chunk_offset(&mut self, index: &RegionLocalIndex, new_offset: u64, sector_count: u8) -> SerialResult<()> {
-        let val = self.create_lookup_table_entry(new_offset, sector_count);
-        let offset = Self::get_chunk_offset(index);
-        self.handle().seek(SeekFrom::Start(offset))?;
-        self.handle().write(&val)?;
-        Ok(())
+        Ok((offset, size))
     }
 
     /// Gets the offset into the lookup table for the chunk at an index.
     fn get_chunk_offset(index: &RegionLocalIndex) -> u64 {
-        2 * ((index.0 % C::REGION_WIDTH) +
-             ((index.1 % C::REGION_WIDTH) * C::REGION_WIDTH)) as u64
+        Self::config().entry_offset(index)
     }
 
-    fn read_bytes(&mut self, offset: u64, size: usize) -> Vec<u8> {
-        self.handle().seek(SeekFrom::Start(offset)).unwrap();
+    fn read_bytes(&mut self, offset: u64, size: usize) -> SerialResult<Vec<u8>> {
+        self.handle().seek(SeekFrom::Start(offset))?;
         let mut buf = vec![0u8; size];
-        self.handle().read(buf.as_mut_slice()).unwrap();
-        buf
+        self.handle().read_exact(buf.as_mut_slice())?;
+        Ok(buf)
     }
-
-    /// Notifies this Region that a chunk was created, so that its lifetime
-    /// should be tracked by the Region.
-    fn receive_created_chunk(&mut self, index: &I);
-
-    fn is_empty(&self) -> bool;
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use std::env;
+    use traits::TestIndex;
+
+    #[derive(Debug, PartialEq, Serialize, Deserialize)]
+    struct TestChunk(Vec<u8>);
+
+    impl ManagedChunk for TestChunk {
+        const SECTOR_SIZE: usize = 64;
+        const REGION_WIDTH: i32 = 4;
+    }
+
 
     #[test]
+    fn test_decompress_truncated() {
+        let data = compress_data(&vec![1, 2, 3], CompressionLevel::Stored).unwrap();
+        assert_eq!(decompress_data(&data).unwrap(), vec![1, 2, 3]);
+        for len in [0, 3, 6].iter() {
+            match decompress_data(&data[..*len].to_vec()) {
+                Err(SerialError::Format(FormatError::TruncatedData { found, .. })) => assert_eq!(found, *len),
+                other => panic!("unexpected {:?}", other),
+            }
+        }
+    }
+
+    #[test]
+    #[cfg(feature = "compression")]
     fn test_decompress() {
         let data = vec![1,2,3,4];
 
@@ -271,10 +656,199 @@ mod tests {
 
         println!("{:?}", buf);
 
-        let compress = compress_data(&data).unwrap();
+        let compress = compress_data(&data, CompressionLevel::Default).unwrap();
         println!("{:?}", compress);
 
         let decompress = decompress_data(&compress).unwrap();
         assert_eq!(decompress, data);
     }
+
+    #[test]
+    fn test_pad_byte_vec() {
+        let mut bytes = vec![1; 70];
+        pad_byte_vec(&mut bytes, 64, Padding::ZeroFill);
+        assert_eq!(bytes.len(), 128);
+
+        // Data that is already aligned doesn't get an extra sector.
+        pad_byte_vec(&mut bytes, 64, Padding::ZeroFill);
+        assert_eq!(bytes.len(), 128);
+
+        let mut bytes = vec![1; 70];
+        pad_byte_vec(&mut bytes, 64, Padding::None);
+        assert_eq!(bytes.len(), 70);
+    }
+
+    #[test]
+    fn test_golden_encoding() {
+        assert_eq!(serialize_u32(0x1234_5678), [0x78, 0x56, 0x34, 0x12]);
+        assert_eq!(deserialize_u32(&[0x78, 0x56, 0x34, 0x12]), 0x1234_5678);
+
+        let meta = ChunkMeta { saved_at: 5 };
+        let (data, _) = <Region<TestIndex> as ManagedRegion<TestIndex, TestChunk>>::encode_chunk(&TestChunk(vec![1, 2]), &meta).unwrap();
+        let mut expected = vec![
+            18, 0, 0, 0x80,          // stored, 18 bytes
+            5, 0, 0, 0, 0, 0, 0, 0,  // saved_at
+            2, 0, 0, 0, 0, 0, 0, 0,  // chunk length
+            1, 2,
+        ];
+        expected.resize(64, 0);
+        assert_eq!(data, expected);
+    }
+
+    #[test]
+    fn test_golden_header() {
+        let path = env::temp_dir().join("infinigen-test-golden-header.sr");
+        let _ = fs::remove_file(&path);
+        let mut file = <Region<TestIndex> as ManagedRegion<TestIndex, TestChunk>>::get_region_file(&path).unwrap();
+        format::write_entry(&mut file, &RegionConfig::of::<TestChunk>(), &RegionLocalIndex(1, 0),
+                            RegionConfig::of::<TestChunk>().data_start() + 3 * 64, 2).unwrap();
+
+        let mut bytes = Vec::new();
+        file.seek(SeekFrom::Start(0)).unwrap();
+        file.read_to_end(&mut bytes).unwrap();
+        let mut expected = b"IGRN".to_vec();
+        expected.extend_from_slice(&[0, 0, 0, 1, 0, 0, 0, 4, 0, 0, 0, 64]);
+        expected.extend_from_slice(&[0, 0, 3, 2]);
+        expected.resize(16 + 32, 0);
+        assert_eq!(bytes, expected);
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    #[cfg(feature = "compression")]
+    fn test_compression_levels() {
+        let data = vec![7; 64];
+        for level in [CompressionLevel::Stored, CompressionLevel::Fast,
+                      CompressionLevel::Default, CompressionLevel::Best].iter() {
+            let compress = compress_data(&data, *level).unwrap();
+            assert_eq!(read_data_prefix(&compress).0, *level);
+            assert_eq!(decompress_data(&compress).unwrap(), data);
+        }
+    }
+
+    fn check_reserve<'a, R: ManagedRegion<'a, TestIndex, TestChunk>>(region: &mut R) {
+        let index = TestIndex(1, 2);
+        let local = RegionLocalIndex(1, 2);
+
+        region.reserve(&index, 3).unwrap();
+        let (offset, size) = region.read_chunk_offset(&local).unwrap();
+        assert_eq!(size, Some(3 * 64));
+        assert!(region.read_chunk_data(&index).unwrap_err().is_missing_chunk());
+
+        region.receive_created_chunk(&index).unwrap();
+        let chunk = TestChunk((0..150).collect());
+        region.write_chunk(chunk, &index, &ChunkMeta::default()).unwrap();
+        assert_eq!(region.read_chunk_offset(&local).unwrap(), (offset, Some(3 * 64)));
+        assert_eq!(region.read_chunk(&index).unwrap().0, TestChunk((0..150).collect()));
+
+        let (other, _) = R::encode_chunk(&TestChunk(vec![1]), &ChunkMeta::default()).unwrap();
+        assert!(region.verify_write(&other, &local).is_err());
+    }
+
+    #[test]
+    fn test_reserve() {
+        let path = env::temp_dir().join("infinigen-test-reserve.sr");
+        let _ = fs::remove_file(&path);
+        let file = <Region<TestIndex> as ManagedRegion<TestIndex, TestChunk>>::get_region_file(&path);
+        check_reserve(&mut Region::new(file.unwrap()));
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn test_address_limits() {
+        type R = Region<TestIndex>;
+        let path = env::temp_dir().join("infinigen-test-address-limits.sr");
+        let _ = fs::remove_file(&path);
+        let mut region = R::new(<R as ManagedRegion<TestIndex, TestChunk>>::get_region_file(&path).unwrap());
+        let write = |region: &mut R, x: i32, sectors: usize| {
+            <R as ManagedRegion<TestIndex, TestChunk>>::write_encoded(region, vec![1; sectors * 64], &RegionLocalIndex(x, 0))
+        };
+
+        match write(&mut region, 0, 256).unwrap_err() {
+            SerialError::Format(FormatError::ChunkTooLarge { sectors: 256, .. }) => (),
+            e => panic!("unexpected error: {}", e),
+        }
+        write(&mut region, 0, 255).unwrap();
+        // The last sector an entry can point to is still usable.
+        write(&mut region, 1, 2).unwrap();
+        let len = fs::metadata(&path).unwrap().len();
+        match write(&mut region, 2, 1).unwrap_err() {
+            SerialError::Format(FormatError::RegionFull(RegionLocalIndex(2, 0))) => (),
+            e => panic!("unexpected error: {}", e),
+        }
+        assert_eq!(fs::metadata(&path).unwrap().len(), len);
+        fs::remove_file(&path).unwrap();
+    }
+
+    fn check_relocate<'a, R: ManagedRegion<'a, TestIndex, TestChunk>>(region: &mut R) {
+        let (a, b, c) = (TestIndex(0, 0), TestIndex(1, 0), TestIndex(2, 0));
+        for index in [&a, &b, &c].iter() {
+            region.receive_created_chunk(index).unwrap();
+        }
+        let meta = ChunkMeta::default();
+        let start = R::config().data_start();
+
+        region.write_chunk(TestChunk(vec![]), &a, &meta).unwrap();
+        region.write_chunk(TestChunk(vec![]), &b, &meta).unwrap();
+
+        region.mark_as_loaded(&a).unwrap();
+        region.write_chunk(TestChunk((0..150).collect()), &a, &meta).unwrap();
+        assert_eq!(region.read_chunk_offset(&RegionLocalIndex(0, 0)).unwrap().0, start + 2 * 64);
+        assert_eq!(region.read_chunk(&a).unwrap().0, TestChunk((0..150).collect()));
+        assert_eq!(region.read_chunk(&a).unwrap_err().logic(),
+                   Some(&ChunkUnsaved(RegionLocalIndex(0, 0))));
+
+        region.write_chunk(TestChunk(vec![1]), &c, &meta).unwrap();
+        assert_eq!(region.read_chunk_offset(&RegionLocalIndex(2, 0)).unwrap().0, start);
+    }
+
+    #[test]
+    fn test_relocate() {
+        let path = env::temp_dir().join("infinigen-test-relocate.sr");
+        let _ = fs::remove_file(&path);
+        let file = <Region<TestIndex> as ManagedRegion<TestIndex, TestChunk>>::get_region_file(&path);
+        check_relocate(&mut Region::new(file.unwrap()));
+        fs::remove_file(&path).unwrap();
+    }
+
+    fn check_preallocated<'a, R: ManagedRegion<'a, TestIndex, TestChunk>>(region: &mut R) {
+        let index = TestIndex(1, 0);
+        let start = R::config().data_start();
+        assert_eq!(region.handle().metadata().unwrap().len(), start + 16 * 2 * 64);
+        assert!(region.read_chunk(&index).unwrap_err().is_missing_chunk());
+
+        region.receive_created_chunk(&index).unwrap();
+        region.write_chunk(TestChunk(vec![1, 2, 3]), &index, &ChunkMeta::default()).unwrap();
+        assert_eq!(region.read_chunk_offset(&RegionLocalIndex(1, 0)).unwrap(), (start + 2 * 64, Some(2 * 64)));
+        assert_eq!(region.read_chunk(&index).unwrap().0, TestChunk(vec![1, 2, 3]));
+    }
+
+    #[test]
+    fn test_preallocate() {
+        let path = env::temp_dir().join("infinigen-test-preallocate.sr");
+        let _ = fs::remove_file(&path);
+        let mut file = <Region<TestIndex> as ManagedRegion<TestIndex, TestChunk>>::get_region_file(&path).unwrap();
+        let config = RegionConfig::of::<TestChunk>();
+        format::preallocate(&mut file, &config, format::Preallocation::Sparse(2)).unwrap();
+        assert!(format::preallocate(&mut file, &config, format::Preallocation::Sparse(100)).is_err());
+        check_preallocated(&mut Region::new(file));
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn test_read_past_end() {
+        type R = Region<TestIndex>;
+        let path = env::temp_dir().join("infinigen-test-read-past-end.sr");
+        let _ = fs::remove_file(&path);
+        let mut file = <R as ManagedRegion<TestIndex, TestChunk>>::get_region_file(&path).unwrap();
+        let config = RegionConfig::of::<TestChunk>();
+        // An entry pointing past the end of a file cut short.
+        format::write_entry(&mut file, &config, &RegionLocalIndex(0, 1), config.data_start(), 2).unwrap();
+
+        let mut region = Region::new(file);
+        let e = <R as ManagedRegion<TestIndex, TestChunk>>::read_chunk_data(&mut region, &TestIndex(0, 1)).unwrap_err();
+        assert!(!e.is_missing_chunk());
+        assert!(<R as ManagedRegion<TestIndex, TestChunk>>::reserve(&mut region, &TestIndex(0, 1), 3).is_err());
+        fs::remove_file(&path).unwrap();
+    }
 }
@@ -0,0 +1,158 @@
+use std::collections::BTreeMap;
+use std::fs::{self, File};
+use std::io::{BufReader, Write};
+use std::path::Path;
+use std::time::SystemTime;
+
+use bincode::{self, Infinite};
+use serde::Serialize;
+use serde::de::DeserializeOwned;
+
+use error::{self, SerialResult};
+use format::RegionConfig;
+use platform::replace_file;
+use timestamps::{from_unix_seconds, unix_seconds};
+
+/// Information the library stores alongside every saved chunk.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct ChunkMeta {
+    /// The world tick at which the chunk was last saved.
+    pub saved_at: u64,
+}
+
+/// Key the time a world was last saved is stored under.
+const LAST_SAVED_KEY: &'static str = "infinigen.last_saved";
+/// Key the parameters of a world's region files are stored under.
+const REGION_CONFIG_KEY: &'static str = "infinigen.region_config";
+
+/// Information about a whole world, stored in a single file next to the
+/// region files.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct WorldMetadata {
+    /// The world clock, in ticks.
+    pub tick: u64,
+    values: BTreeMap<String, Vec<u8>>,
+}
+
+impl WorldMetadata {
+    pub fn new() -> Self {
+        WorldMetadata::default()
+    }
+
+    /// Loads metadata from the given path, or returns empty metadata if the
+    /// file doesn't exist yet.
+    pub fn load<P: AsRef<Path>>(path: P) -> SerialResult<Self> {
+        if !path.as_ref().exists() {
+            return Ok(WorldMetadata::new());
+        }
+        let mut reader = BufReader::new(File::open(path)?);
+        let meta = bincode::deserialize_from(&mut reader, Infinite)?;
+        Ok(meta)
+    }
+
+    /// Saves metadata to the given path, creating parent directories as
+    /// needed. The metadata is written to a temporary file first and renamed
+    /// into place, so a crash can't leave it half written.
+    pub fn save<P: AsRef<Path>>(&self, path: P) -> SerialResult<()> {
+        let path = path.as_ref();
+        if let Some(parent) = path.parent() {
+            if !parent.as_os_str().is_empty() {
+                fs::create_dir_all(parent)?;
+            }
+        }
+
+        let temp = path.with_extension("meta.tmp");
+        {
+            let mut file = File::create(&temp)?;
+            let written = file.write_all(&bincode::serialize(self, Infinite)?)
+                .and_then(|_| file.sync_all());
+            if let Err(e) = written {
+                let _ = fs::remove_file(&temp);
+                return Err(error::write_error(e));
+            }
+        }
+        replace_file(&temp, path)?;
+        Ok(())
+    }
+
+    /// Deserializes the value stored under a key.
+    pub fn get<T: DeserializeOwned>(&self, key: &str) -> SerialResult<Option<T>> {
+        match self.values.get(key) {
+            Some(bytes) => Ok(Some(bincode::deserialize(bytes)?)),
+            None        => Ok(None),
+        }
+    }
+
+    /// Serializes a value and stores it under a key.
+    pub fn insert<T: Serialize>(&mut self, key: &str, value: &T) -> SerialResult<()> {
+        let encoded = bincode::serialize(value, Infinite)?;
+        self.values.insert(key.to_string(), encoded);
+        Ok(())
+    }
+
+    pub fn remove(&mut self, key: &str) {
+        self.values.remove(key);
+    }
+
+    /// Returns the wall-clock time the world was last saved at, such as for
+    /// showing when a save was last played.
+    pub fn last_saved(&self) -> SerialResult<Option<SystemTime>> {
+        Ok(self.get::<u64>(LAST_SAVED_KEY)?.map(from_unix_seconds))
+    }
+
+    pub fn set_last_saved(&mut self, time: SystemTime) -> SerialResult<()> {
+        self.insert(LAST_SAVED_KEY, &unix_seconds(time))
+    }
+
+    /// Returns the parameters the world's region files are written with,
+    /// recorded when it was first saved. See `RegionConfig::check_world`.
+    pub fn region_config(&self) -> SerialResult<Option<RegionConfig>> {
+        self.get(REGION_CONFIG_KEY)
+    }
+
+    pub fn set_region_config(&mut self, config: &RegionConfig) -> SerialResult<()> {
+        self.insert(REGION_CONFIG_KEY, config)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use error::{FormatError, SerialError};
+    use testing::TempDir;
+
+    #[test]
+    fn test_region_config_drift() {
+        let mut metadata = WorldMetadata::new();
+        let config = RegionConfig { region_width: 16, sector_size: 4096 };
+        config.check_world(&mut metadata).unwrap();
+        assert_eq!(metadata.region_config().unwrap(), Some(config));
+        config.check_world(&mut metadata).unwrap();
+
+        let wider = RegionConfig { region_width: 32, sector_size: 4096 };
+        match wider.check_world(&mut metadata) {
+            Err(SerialError::Format(FormatError::WorldLayoutMismatch { world, in_use })) => {
+                assert_eq!(world, config);
+                assert_eq!(in_use, wider);
+            },
+            other => panic!("unexpected result: {:?}", other.map(|_| ())),
+        }
+    }
+
+    #[test]
+    fn test_save() {
+        let dir = TempDir::new("metadata-save");
+        let path = dir.path().join("world.meta");
+        let mut metadata = WorldMetadata::new();
+        metadata.insert("seed", &7u64).unwrap();
+        metadata.save(&path).unwrap();
+        assert!(!path.with_extension("meta.tmp").exists());
+        assert_eq!(WorldMetadata::load(&path).unwrap().get::<u64>("seed").unwrap(), Some(7));
+
+        // A save that fails leaves the saved metadata as it was.
+        fs::create_dir(path.with_extension("meta.tmp")).unwrap();
+        metadata.insert("seed", &8u64).unwrap();
+        assert!(metadata.save(&path).is_err());
+        assert_eq!(WorldMetadata::load(&path).unwrap().get::<u64>("seed").unwrap(), Some(7));
+    }
+}
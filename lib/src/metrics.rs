@@ -0,0 +1,52 @@
+use std::time::Duration;
+
+/// Chunks read from region files.
+pub const CHUNKS_LOADED: &'static str = "infinigen_chunks_loaded_total";
+/// Chunks created because they weren't saved yet.
+pub const CHUNKS_GENERATED: &'static str = "infinigen_chunks_generated_total";
+/// Chunks that were promoted to a higher detail level.
+pub const CHUNKS_PROMOTED: &'static str = "infinigen_chunks_promoted_total";
+/// Chunk loads deferred because the disk was busy.
+pub const LOADS_DEFERRED: &'static str = "infinigen_loads_deferred_total";
+/// Chunks that couldn't be read and were left unloaded or regenerated.
+pub const CHUNKS_SKIPPED: &'static str = "infinigen_chunks_skipped_total";
+/// Chunks written to region files.
+pub const CHUNKS_SAVED: &'static str = "infinigen_chunks_saved_total";
+/// Loads of chunks that were already in memory.
+pub const CHUNK_CACHE_HITS: &'static str = "infinigen_chunk_cache_hits_total";
+/// Loads of chunks that had to be read or generated.
+pub const CHUNK_CACHE_MISSES: &'static str = "infinigen_chunk_cache_misses_total";
+/// Chunks currently in memory.
+pub const CHUNKS_RESIDENT: &'static str = "infinigen_chunks_resident";
+/// Region files currently open.
+pub const REGIONS_OPEN: &'static str = "infinigen_regions_open";
+/// Seconds spent reading and inserting a chunk.
+pub const CHUNK_LOAD_SECONDS: &'static str = "infinigen_chunk_load_seconds";
+/// Seconds spent generating a chunk.
+pub const CHUNK_GENERATE_SECONDS: &'static str = "infinigen_chunk_generate_seconds";
+/// Seconds spent removing and writing a chunk.
+pub const CHUNK_SAVE_SECONDS: &'static str = "infinigen_chunk_save_seconds";
+/// Compressed over serialized size of a chunk saved with `AdaptiveCompression`.
+pub const CHUNK_COMPRESSION_RATIO: &'static str = "infinigen_chunk_compression_ratio";
+/// Seconds spent compressing a chunk saved with `AdaptiveCompression`.
+pub const CHUNK_COMPRESS_SECONDS: &'static str = "infinigen_chunk_compress_seconds";
+
+/// Receives the counters a world reports while streaming chunks, so they can
+/// be forwarded to whatever monitoring system the application uses.
+///
+/// Names follow Prometheus conventions and are listed as constants in this
+/// module. Every method does nothing by default.
+pub trait MetricsSink {
+    /// Adds `value` to a monotonically increasing counter.
+    fn counter(&self, _name: &'static str, _value: u64) {}
+
+    /// Sets a value that can go up and down.
+    fn gauge(&self, _name: &'static str, _value: f64) {}
+
+    /// Records one observation of a distribution, such as a latency.
+    fn histogram(&self, _name: &'static str, _value: f64) {}
+}
+
+pub(crate) fn seconds(duration: Duration) -> f64 {
+    duration.as_secs() as f64 + duration.subsec_nanos() as f64 * 1e-9
+}
@@ -0,0 +1,509 @@
+use std::collections::HashMap;
+use std::fs::{self, File, OpenOptions};
+use std::io::prelude::*;
+use std::io::{self, SeekFrom};
+use std::mem;
+use std::path::{Path, PathBuf};
+
+use bincode::{self, Infinite};
+use serde::Serialize;
+use serde::de::DeserializeOwned;
+
+use archive::ColdArchive;
+use error::{ErrorContext, FormatError, SerialError, SerialResult, WithContext};
+use format::{self, CompressionLevel, RegionConfig, HEADER_SIZE};
+use grid::split_coord;
+use inbox::{ChunkMessage, INBOX_EXTENSION};
+use layout::RegionLayout;
+use metadata::WorldMetadata;
+use managed_region::{compress_data, decompress_data, pad_byte_vec, read_data_prefix, ManagedRegion};
+use occupancy::{Occupancy, OCCUPANCY_EXTENSION};
+use platform::replace_file;
+use region::{Region, RegionIndex, RegionLocalIndex};
+use sidecar::{sidecar_files, RegionEntries, RegionSidecar};
+use timestamps::SAVE_TIMES_EXTENSION;
+use tombstone::TOMBSTONES_EXTENSION;
+use traits::ManagedChunk;
+use versions::VERSIONS_EXTENSION;
+use visits::VISITS_EXTENSION;
+
+/// Returns the paths and indices of every region file in a layout's
+/// directory.
+pub fn region_files(layout: &RegionLayout) -> SerialResult<Vec<(RegionIndex, PathBuf)>> {
+    let dir = layout.directory();
+    let dir = if dir.as_os_str().is_empty() { PathBuf::from(".") } else { dir };
+    if !dir.exists() {
+        return Ok(Vec::new());
+    }
+
+    let mut files = Vec::new();
+    for entry in fs::read_dir(&dir)? {
+        let path = entry?.path();
+        if !path.is_file() {
+            continue;
+        }
+        if let Some(index) = layout.parse_path(&path) {
+            files.push((index, path));
+        }
+    }
+    files.sort();
+    Ok(files)
+}
+
+/// Returns the indices of the region files in a layout's directory that
+/// have a valid header written with `config`, without reading any chunks.
+/// Files that are named like region files but aren't, or were written with
+/// other parameters, are skipped.
+pub fn scan_regions(layout: &RegionLayout, config: &RegionConfig) -> SerialResult<Vec<RegionIndex>> {
+    let mut indices = Vec::new();
+    for (index, path) in region_files(layout)?.into_iter() {
+        let valid = File::open(&path).map_err(SerialError::from)
+            .and_then(|mut file| format::check_header(&mut file, config));
+        if valid.is_ok() {
+            indices.push(index);
+        }
+    }
+    Ok(indices)
+}
+
+/// Reads the raw compressed payload of a chunk, without its sector padding.
+fn read_payload(file: &mut File, offset: u64) -> SerialResult<Vec<u8>> {
+    let mut prefix = [0u8; 4];
+    file.seek(SeekFrom::Start(offset))?;
+    file.read_exact(&mut prefix)?;
+    let (_, len) = read_data_prefix(&prefix);
+
+    let mut payload = vec![0u8; 4 + len];
+    file.seek(SeekFrom::Start(offset))?;
+    file.read_exact(&mut payload)?;
+    Ok(payload)
+}
+
+/// Repacks every region file of a world written with the `old` region width
+/// and sector size into files using the `new` ones, so changing
+/// `ManagedChunk::REGION_WIDTH` or `SECTOR_SIZE` doesn't break existing saves.
+/// Files written before region files had a header are assumed to use `old`.
+/// Regions in the cold archive are restored first and migrated with the
+/// rest.
+///
+/// Chunks are copied one old region at a time into a staging directory,
+/// and the sidecar files of the regions, like the occupancy, tombstones and
+/// inboxes of their chunks, are rewritten to follow their chunks.
+/// Once every chunk was copied, a marker listing the old files to remove is
+/// written, the new files are moved into place, and only then are the old
+/// files that weren't replaced removed. A migration interrupted before the
+/// marker was written leaves the old files intact and starts over, and one
+/// interrupted after it is finished by running it again. Only worlds using
+/// the default grid partition of chunk indices can be migrated. The
+/// parameters recorded in the world metadata are updated to `new`. Returns
+/// the number of chunks that were moved.
+///
+/// None of the world's regions may be open while it runs, since open files
+/// can't be removed on Windows.
+pub fn migrate_layout(layout: &RegionLayout, old: RegionConfig, new: RegionConfig) -> SerialResult<usize> {
+    let staging = layout.directory().join(".migrate");
+    if !staging.join(MIGRATE_MARKER).exists() {
+        restore_archive(layout)?;
+        stage_migration(layout, &staging, old, &new)?;
+    }
+    let moved = finish_migration(layout, &staging)?;
+
+    // Worlds record the parameters they were first saved with, which have
+    // to follow the files.
+    let path = layout.metadata_path();
+    if path.exists() {
+        let mut metadata = WorldMetadata::load(&path)?;
+        if metadata.region_config()?.is_some() {
+            metadata.set_region_config(&new)?;
+            metadata.save(&path)?;
+        }
+    }
+
+    Ok(moved)
+}
+
+/// Moves every region out of the cold archive and removes it, since archived
+/// regions keep the layout they were written with and couldn't be restored
+/// once it changed.
+fn restore_archive(layout: &RegionLayout) -> SerialResult<()> {
+    let path = layout.archive_path();
+    if !path.exists() {
+        return Ok(());
+    }
+    let context = || ErrorContext::new("restore archive").with_path(&path);
+    let mut archive = ColdArchive::open(&path).with_context(&context)?;
+    for index in archive.region_indices() {
+        archive.restore(&index, &layout.region_path(&index))
+            .with_context(|| context().with_region(index.clone()))?;
+    }
+    fs::remove_file(&path).with_context(&context)?;
+    Ok(())
+}
+
+/// The file in the staging directory of a migration listing the number of
+/// chunks moved and the old files to remove, written once every chunk was
+/// copied.
+const MIGRATE_MARKER: &'static str = "complete";
+
+/// Copies every chunk into new files in the staging directory, then writes
+/// the marker.
+fn stage_migration(layout: &RegionLayout, staging: &Path, old: RegionConfig, new: &RegionConfig) -> SerialResult<()> {
+    let files = region_files(layout)?;
+    if staging.exists() {
+        fs::remove_dir_all(staging)?;
+    }
+    fs::create_dir_all(staging)?;
+
+    let mut outputs: HashMap<RegionIndex, File> = HashMap::new();
+    let mut moved = 0;
+
+    for &(ref region, ref path) in files.iter() {
+        let context = || ErrorContext::new("migrate region").with_region(region.clone()).with_path(path);
+        let mut file = File::open(path).with_context(&context)?;
+        let table_start = match format::read_header(&mut file).with_context(&context)? {
+            Some(ref found) if *found == old => HEADER_SIZE,
+            Some(found) => return Err(SerialError::from(FormatError::LayoutMismatch {
+                expected: old,
+                found: found,
+            }).context(context())),
+            None => 0,
+        };
+
+        for ly in 0..old.region_width {
+            for lx in 0..old.region_width {
+                let local = RegionLocalIndex(lx, ly);
+                let entry = format::read_entry(&mut file, &old, table_start, &local)
+                    .with_context(&context)?;
+                let (offset, _) = match entry {
+                    Some(entry) => entry,
+                    None        => continue,
+                };
+                let mut payload = read_payload(&mut file, offset)
+                    .with_context(|| context().with_chunk(local.clone()))?;
+                if payload.len() == 4 {
+                    // Reserved space that was never written to.
+                    continue;
+                }
+
+                let (x, y) = old.chunk_position(region, &local).with_context(&context)?;
+                let (nrx, nlx) = split_coord(x, new.region_width);
+                let (nry, nly) = split_coord(y, new.region_width);
+                let new_region = RegionIndex(nrx, nry);
+
+                if !outputs.contains_key(&new_region) {
+                    let mut out = OpenOptions::new()
+                        .read(true)
+                        .write(true)
+                        .create(true)
+                        .truncate(true)
+                        .open(staging.join(layout.file_name(&new_region)))?;
+                    format::write_header(&mut out, new)?;
+                    outputs.insert(new_region, out);
+                }
+                let out = outputs.get_mut(&new_region).unwrap();
+
+                let sectors = new.sectors_for(payload.len());
+                payload.resize(sectors * new.sector_size, 0);
+
+                let end = out.seek(SeekFrom::End(0))?;
+                out.write_all(&payload)?;
+                format::write_entry(out, new, &RegionLocalIndex(nlx, nly), end, sectors)
+                    .with_context(|| context().with_chunk(local.clone()))?;
+                moved += 1;
+            }
+        }
+    }
+
+    for (_, out) in outputs.drain() {
+        out.sync_all()?;
+    }
+    let sidecars = stage_sidecars(layout, staging, &old, new)?;
+
+    // Old files sharing a name with a new one are replaced by moving it into
+    // place, and the rest are removed afterwards.
+    let mut marker = format!("moved {}\n", moved);
+    let names = files.iter().map(|&(ref region, _)| layout.file_name(region));
+    for name in names.chain(sidecars.into_iter()) {
+        if !staging.join(&name).exists() {
+            marker.push_str(&name);
+            marker.push('\n');
+        }
+    }
+    let temp = staging.join(MIGRATE_MARKER).with_extension("tmp");
+    {
+        let mut file = File::create(&temp)?;
+        file.write_all(marker.as_bytes())?;
+        file.sync_all()?;
+    }
+    replace_file(&temp, staging.join(MIGRATE_MARKER))?;
+    Ok(())
+}
+
+/// Copies the sidecar files of every region into the staging directory,
+/// keyed by the regions and positions their chunks have in the new layout.
+/// Returns the names of the old sidecar files.
+fn stage_sidecars(layout: &RegionLayout, staging: &Path, old: &RegionConfig, new: &RegionConfig) -> SerialResult<Vec<String>> {
+    let mut names = stage_sidecar::<Occupancy>(layout, staging, old, new, OCCUPANCY_EXTENSION)?;
+    for &extension in [SAVE_TIMES_EXTENSION, VISITS_EXTENSION, TOMBSTONES_EXTENSION, VERSIONS_EXTENSION].iter() {
+        names.extend(stage_sidecar::<u64>(layout, staging, old, new, extension)?);
+    }
+    names.extend(stage_sidecar::<Vec<ChunkMessage>>(layout, staging, old, new, INBOX_EXTENSION)?);
+    Ok(names)
+}
+
+fn stage_sidecar<T>(layout: &RegionLayout, staging: &Path, old: &RegionConfig, new: &RegionConfig,
+                    extension: &'static str) -> SerialResult<Vec<String>>
+    where T: Serialize + DeserializeOwned + PartialEq
+{
+    let mut sidecar: RegionSidecar<T> = RegionSidecar::new(layout.clone(), extension);
+    let mut staged: HashMap<RegionIndex, RegionEntries<T>> = HashMap::new();
+    let mut names = Vec::new();
+
+    for (region, _, path) in sidecar_files(layout, &[extension])?.into_iter() {
+        let entries = mem::replace(sidecar.entries(&region)?, RegionEntries::new());
+        for ((lx, ly), value) in entries.into_iter() {
+            let (x, y) = old.chunk_position(&region, &RegionLocalIndex(lx, ly))
+                .with_context(|| ErrorContext::new("migrate region sidecar").with_region(region.clone()).with_path(&path))?;
+            let (nrx, nlx) = split_coord(x, new.region_width);
+            let (nry, nly) = split_coord(y, new.region_width);
+            staged.entry(RegionIndex(nrx, nry)).or_insert_with(RegionEntries::new).insert((nlx, nly), value);
+        }
+        names.push(path.file_name().unwrap().to_string_lossy().into_owned());
+    }
+
+    for (region, entries) in staged.into_iter() {
+        let mut file = File::create(staging.join(format!("{}.{}", layout.file_name(&region), extension)))?;
+        file.write_all(&bincode::serialize(&entries, Infinite)?)?;
+        file.sync_all()?;
+    }
+    Ok(names)
+}
+
+/// Moves the staged files into place and removes the old files listed in
+/// the marker. Returns the number of chunks that were moved.
+fn finish_migration(layout: &RegionLayout, staging: &Path) -> SerialResult<usize> {
+    let marker_path = staging.join(MIGRATE_MARKER);
+    let context = || ErrorContext::new("finish migration").with_path(&marker_path);
+    let mut marker = String::new();
+    File::open(&marker_path).and_then(|mut f| f.read_to_string(&mut marker)).with_context(&context)?;
+    let mut lines = marker.lines();
+    let moved = lines.next()
+        .and_then(|line| line.trim_start_matches("moved ").parse().ok())
+        .ok_or_else(|| SerialError::from(io::Error::new(io::ErrorKind::InvalidData, "the migration marker is damaged")))
+        .with_context(&context)?;
+
+    for entry in fs::read_dir(staging)? {
+        let path = entry?.path();
+        if path == marker_path {
+            continue;
+        }
+        let dest = layout.directory().join(path.file_name().unwrap());
+        replace_file(&path, dest)?;
+    }
+    for name in lines {
+        match fs::remove_file(layout.directory().join(name)) {
+            Err(ref e) if e.kind() == io::ErrorKind::NotFound => (),
+            result => result?,
+        }
+    }
+    fs::remove_file(&marker_path)?;
+    fs::remove_dir(staging)?;
+    Ok(moved)
+}
+
+/// Rewrites the chunks of a region file compressed with the level `from`,
+/// or with any level if it is `None`, so they are compressed with `to`
+/// instead. Lets existing saves adopt a different compression level without
+/// regenerating them. Returns the number of chunks that were rewritten.
+pub fn reencode_region<C, P>(path: P, from: Option<CompressionLevel>, to: CompressionLevel) -> SerialResult<usize>
+    where C: ManagedChunk,
+          P: AsRef<Path>
+{
+    let path = path.as_ref();
+    let context = || ErrorContext::new("reencode region").with_path(path);
+
+    let mut file = OpenOptions::new().read(true).write(true).open(path).with_context(&context)?;
+    format::check_header(&mut file, &RegionConfig::of::<C>()).with_context(&context)?;
+    let mut region: Region<RegionLocalIndex> = Region::new(file);
+
+    reencode_chunks::<C, _>(&mut region, from, to).with_context(&context)
+}
+
+fn reencode_chunks<'a, C, R>(region: &mut R, from: Option<CompressionLevel>, to: CompressionLevel) -> SerialResult<usize>
+    where C: ManagedChunk,
+          R: ManagedRegion<'a, RegionLocalIndex, C>
+{
+    let mut count = 0;
+    for y in 0..C::REGION_WIDTH {
+        for x in 0..C::REGION_WIDTH {
+            let index = RegionLocalIndex(x, y);
+            let payload = match region.read_payload(&index) {
+                Ok(payload) => payload,
+                Err(ref e) if e.is_missing_chunk() => continue,
+                Err(e) => {
+                    return Err(e.context(ErrorContext::new("reencode chunk").with_chunk(index)));
+                },
+            };
+
+            let (level, _) = read_data_prefix(&payload);
+            if level == to || from.map_or(false, |f| f != level) {
+                continue;
+            }
+
+            let mut compressed = compress_data(&decompress_data(&payload)?, to)?;
+            pad_byte_vec(&mut compressed, C::SECTOR_SIZE, C::PADDING);
+            region.write_encoded(compressed, &index)?;
+            count += 1;
+        }
+    }
+    region.handle().sync_all()?;
+    Ok(count)
+}
+
+/// Reencodes every region file of a world. See `reencode_region`.
+pub fn reencode_layout<C>(layout: &RegionLayout, from: Option<CompressionLevel>, to: CompressionLevel) -> SerialResult<usize>
+    where C: ManagedChunk
+{
+    let mut count = 0;
+    for (_, path) in region_files(layout)?.into_iter() {
+        count += reencode_region::<C, _>(path, from, to)?;
+    }
+    Ok(count)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::env;
+    use tombstone::ChunkTombstones;
+    use versions::ChunkVersions;
+
+    #[test]
+    fn test_migrate_width() {
+        let root = env::temp_dir().join("infinigen-test-migrate");
+        let _ = fs::remove_dir_all(&root);
+        let layout = RegionLayout::new(&root);
+        let old = RegionConfig { region_width: 4, sector_size: 64 };
+        let new = RegionConfig { region_width: 2, sector_size: 32 };
+
+        fs::create_dir_all(&root).unwrap();
+        let mut file = OpenOptions::new().read(true).write(true).create(true)
+            .open(layout.region_path(&RegionIndex(0, 0))).unwrap();
+        format::write_header(&mut file, &old).unwrap();
+        let mut payload = compress_data(&vec![7, 8, 9], CompressionLevel::Stored).unwrap();
+        let stored = payload.clone();
+        payload.resize(64, 0);
+        file.write_all(&payload).unwrap();
+        format::write_entry(&mut file, &old, &RegionLocalIndex(3, 1), old.data_start(), 1).unwrap();
+
+        // Sidecars follow their chunks, even those of regions never saved.
+        let mut tombstones = ChunkTombstones::new(layout.clone());
+        tombstones.place(&RegionIndex(0, 0), &RegionLocalIndex(3, 1), 5).unwrap();
+        tombstones.flush().unwrap();
+        let mut versions = ChunkVersions::new(layout.clone());
+        versions.bump(&RegionIndex(5, 5), &RegionLocalIndex(1, 0)).unwrap();
+        versions.flush().unwrap();
+
+        assert_eq!(migrate_layout(&layout, old, new).unwrap(), 1);
+
+        let mut tombstones = ChunkTombstones::new(layout.clone());
+        assert_eq!(tombstones.placed_at(&RegionIndex(1, 0), &RegionLocalIndex(1, 1)).unwrap(), Some(5));
+        assert!(!layout.sidecar_path(&RegionIndex(0, 0), TOMBSTONES_EXTENSION).exists());
+        let mut versions = ChunkVersions::new(layout.clone());
+        assert_eq!(versions.version(&RegionIndex(10, 10), &RegionLocalIndex(1, 0)).unwrap(), 1);
+        assert!(!layout.sidecar_path(&RegionIndex(5, 5), VERSIONS_EXTENSION).exists());
+
+        let mut file = File::open(layout.region_path(&RegionIndex(1, 0))).unwrap();
+        assert_eq!(format::read_header(&mut file).unwrap(), Some(new));
+        let (offset, _) = format::read_entry(&mut file, &new, HEADER_SIZE, &RegionLocalIndex(1, 1))
+            .unwrap().unwrap();
+        assert_eq!(read_payload(&mut file, offset).unwrap(), stored);
+        assert!(!layout.region_path(&RegionIndex(0, 0)).exists());
+
+        fs::remove_dir_all(&root).unwrap();
+    }
+
+    #[test]
+    fn test_migrate_resume() {
+        let root = env::temp_dir().join("infinigen-test-migrate-resume");
+        let _ = fs::remove_dir_all(&root);
+        let layout = RegionLayout::new(&root);
+        let old = RegionConfig { region_width: 4, sector_size: 64 };
+        let new = RegionConfig { region_width: 2, sector_size: 64 };
+
+        fs::create_dir_all(&root).unwrap();
+        for &(ref region, ref local) in [(RegionIndex(0, 0), RegionLocalIndex(0, 0)), (RegionIndex(0, 1), RegionLocalIndex(3, 3))].iter() {
+            let mut file = File::create(layout.region_path(region)).unwrap();
+            format::write_header(&mut file, &old).unwrap();
+            let mut payload = compress_data(&vec![1, 2, 3], CompressionLevel::Stored).unwrap();
+            payload.resize(64, 0);
+            file.write_all(&payload).unwrap();
+            format::write_entry(&mut file, &old, local, old.data_start(), 1).unwrap();
+        }
+
+        // Interrupted while moving the staged files into place: the new file
+        // replacing r.0.0 was moved, but r.0.1 wasn't removed yet.
+        let staging = root.join(".migrate");
+        stage_migration(&layout, &staging, old, &new).unwrap();
+        assert!(layout.region_path(&RegionIndex(0, 1)).exists());
+        let name = layout.file_name(&RegionIndex(0, 0));
+        replace_file(staging.join(&name), root.join(&name)).unwrap();
+
+        assert_eq!(migrate_layout(&layout, old, new).unwrap(), 2);
+        assert!(!staging.exists());
+        assert!(!layout.region_path(&RegionIndex(0, 1)).exists());
+        for region in [RegionIndex(0, 0), RegionIndex(1, 3)].iter() {
+            let mut file = File::open(layout.region_path(region)).unwrap();
+            assert_eq!(format::read_header(&mut file).unwrap(), Some(new));
+        }
+
+        fs::remove_dir_all(&root).unwrap();
+    }
+
+    #[test]
+    fn test_scan_regions() {
+        let root = env::temp_dir().join("infinigen-test-scan");
+        let _ = fs::remove_dir_all(&root);
+        let layout = RegionLayout::new(&root).with_subdirectory("region");
+        let config = RegionConfig { region_width: 4, sector_size: 64 };
+        let other = RegionConfig { region_width: 2, sector_size: 64 };
+        fs::create_dir_all(layout.directory()).unwrap();
+
+        for &(ref index, ref config) in [(RegionIndex(-2, 5), config), (RegionIndex(0, 0), other)].iter() {
+            let mut file = File::create(layout.region_path(index)).unwrap();
+            format::write_header(&mut file, config).unwrap();
+        }
+        File::create(layout.region_path(&RegionIndex(1, 1))).unwrap().write_all(b"junk").unwrap();
+        File::create(layout.directory().join("notes.txt")).unwrap();
+
+        assert_eq!(scan_regions(&layout, &config).unwrap(), vec![RegionIndex(-2, 5)]);
+        fs::remove_dir_all(&root).unwrap();
+    }
+
+    #[cfg(feature = "compression")]
+    #[test]
+    fn test_migrate_archived() {
+        use testing::TempDir;
+
+        let dir = TempDir::new("migrate-archived");
+        let layout = dir.layout();
+        let old = RegionConfig { region_width: 4, sector_size: 64 };
+        let new = RegionConfig { region_width: 2, sector_size: 64 };
+
+        let path = layout.region_path(&RegionIndex(1, 0));
+        let mut file = File::create(&path).unwrap();
+        format::write_header(&mut file, &old).unwrap();
+        let mut payload = compress_data(&vec![4, 5], CompressionLevel::Stored).unwrap();
+        payload.resize(64, 0);
+        file.write_all(&payload).unwrap();
+        format::write_entry(&mut file, &old, &RegionLocalIndex(0, 0), old.data_start(), 1).unwrap();
+        drop(file);
+        ColdArchive::open(layout.archive_path()).unwrap().archive(&RegionIndex(1, 0), &path).unwrap();
+
+        assert_eq!(migrate_layout(&layout, old, new).unwrap(), 1);
+        assert!(!layout.archive_path().exists());
+        let mut file = File::open(layout.region_path(&RegionIndex(2, 0))).unwrap();
+        assert_eq!(format::read_header(&mut file).unwrap(), Some(new));
+        assert!(format::read_entry(&mut file, &new, HEADER_SIZE, &RegionLocalIndex(0, 0)).unwrap().is_some());
+    }
+}
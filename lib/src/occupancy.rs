@@ -0,0 +1,152 @@
+use error::SerialResult;
+use layout::RegionLayout;
+use region::{RegionIndex, RegionLocalIndex};
+use sidecar::RegionSidecar;
+
+/// A bitmap of which cells of a chunk can be walked on, small enough to keep
+/// around for chunks that aren't loaded. Pathfinding and AI can use it to
+/// answer coarse questions about far away terrain, like whether a chunk is
+/// solid rock, without reading the chunk itself.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Occupancy {
+    width: u32,
+    height: u32,
+    bits: Vec<u8>,
+}
+
+impl Occupancy {
+    /// Creates a bitmap where no cell is walkable.
+    pub fn new(width: u32, height: u32) -> Self {
+        let len = (width as usize * height as usize + 7) / 8;
+        Occupancy {
+            width: width,
+            height: height,
+            bits: vec![0; len],
+        }
+    }
+
+    /// Creates a bitmap from a function returning whether the cell at the
+    /// given position can be walked on.
+    pub fn from_fn<F: FnMut(u32, u32) -> bool>(width: u32, height: u32, mut walkable: F) -> Self {
+        let mut occupancy = Occupancy::new(width, height);
+        for y in 0..height {
+            for x in 0..width {
+                if walkable(x, y) {
+                    occupancy.set_walkable(x, y, true);
+                }
+            }
+        }
+        occupancy
+    }
+
+    pub fn width(&self) -> u32 {
+        self.width
+    }
+
+    pub fn height(&self) -> u32 {
+        self.height
+    }
+
+    fn bit(&self, x: u32, y: u32) -> usize {
+        assert!(x < self.width && y < self.height, "Cell out of bounds!");
+        y as usize * self.width as usize + x as usize
+    }
+
+    pub fn is_walkable(&self, x: u32, y: u32) -> bool {
+        let bit = self.bit(x, y);
+        self.bits[bit / 8] & (1 << (bit % 8)) != 0
+    }
+
+    pub fn set_walkable(&mut self, x: u32, y: u32, walkable: bool) {
+        let bit = self.bit(x, y);
+        if walkable {
+            self.bits[bit / 8] |= 1 << (bit % 8);
+        } else {
+            self.bits[bit / 8] &= !(1 << (bit % 8));
+        }
+    }
+
+    /// Returns the number of walkable cells.
+    pub fn walkable_count(&self) -> usize {
+        self.bits.iter().map(|b| b.count_ones() as usize).sum()
+    }
+
+    /// Returns true if no cell can be walked on.
+    pub fn is_solid(&self) -> bool {
+        self.walkable_count() == 0
+    }
+
+    /// Returns true if every cell can be walked on.
+    pub fn is_open(&self) -> bool {
+        self.walkable_count() == self.width as usize * self.height as usize
+    }
+}
+
+/// Extension appended to region file names for the files holding their
+/// chunks' occupancy.
+pub const OCCUPANCY_EXTENSION: &'static str = "occ";
+
+/// Keeps the occupancy of saved chunks in a small file next to each region
+/// file, so it can be looked up without reading the region.
+///
+/// The files are read the first time a region is looked up and written by
+/// `flush`. They are only hints: a chunk saved after the last flush has
+/// stale or no occupancy until the next one.
+pub struct OccupancyStore {
+    chunks: RegionSidecar<Occupancy>,
+}
+
+impl OccupancyStore {
+    pub fn new(layout: RegionLayout) -> Self {
+        OccupancyStore {
+            chunks: RegionSidecar::new(layout, OCCUPANCY_EXTENSION),
+        }
+    }
+
+    /// Returns the occupancy of a chunk as of its last save, if it was
+    /// recorded.
+    pub fn get(&mut self, region: &RegionIndex, local: &RegionLocalIndex) -> SerialResult<Option<Occupancy>> {
+        Ok(self.chunks.get(region, local)?.cloned())
+    }
+
+    /// Records the occupancy of a chunk that was just saved, or forgets it if
+    /// `occupancy` is `None`.
+    pub fn record(&mut self, region: &RegionIndex, local: &RegionLocalIndex, occupancy: Option<Occupancy>) -> SerialResult<()> {
+        self.chunks.set(region, local, occupancy)
+    }
+
+    /// Writes the occupancy of every region that changed since the last
+    /// flush.
+    pub fn flush(&mut self) -> SerialResult<()> {
+        self.chunks.flush()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::env;
+    use std::fs;
+
+    #[test]
+    fn test_occupancy() {
+        let walls = Occupancy::from_fn(5, 3, |x, _| x == 2);
+        assert!(walls.is_walkable(2, 1));
+        assert!(!walls.is_walkable(1, 1));
+        assert_eq!(walls.walkable_count(), 3);
+        assert!(Occupancy::new(5, 3).is_solid());
+        assert!(Occupancy::from_fn(5, 3, |_, _| true).is_open());
+
+        let root = env::temp_dir().join("infinigen-test-occupancy");
+        let _ = fs::remove_dir_all(&root);
+        let layout = RegionLayout::new(&root);
+        let mut store = OccupancyStore::new(layout.clone());
+        store.record(&RegionIndex(0, -1), &RegionLocalIndex(3, 4), Some(walls.clone())).unwrap();
+        store.flush().unwrap();
+
+        let mut store = OccupancyStore::new(layout);
+        assert_eq!(store.get(&RegionIndex(0, -1), &RegionLocalIndex(3, 4)).unwrap(), Some(walls));
+        assert_eq!(store.get(&RegionIndex(0, -1), &RegionLocalIndex(0, 0)).unwrap(), None);
+        fs::remove_dir_all(&root).unwrap();
+    }
+}
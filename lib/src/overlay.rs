@@ -0,0 +1,149 @@
+use grid::{split_coord, ChunkArrangement};
+use load_state::LoadState;
+use region::RegionIndex;
+use subscribe::CellRect;
+use traits::{ChunkKey, Index};
+
+/// What a debug overlay shows about one chunk.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ChunkOverlay<I> {
+    pub index: I,
+    /// The cells covered by the chunk. Strips are cut off at the top and
+    /// bottom of the viewport.
+    pub bounds: CellRect,
+    /// The region file the chunk is saved in.
+    pub region: RegionIndex,
+    /// `LoadState::Loading` while a requested load or generation is pending.
+    pub state: LoadState,
+    pub placeholder: bool,
+    pub stub: bool,
+    /// The chunk was created this session and was never saved. Changes to
+    /// chunks read from disk aren't tracked.
+    pub unsaved: bool,
+    pub pinned: bool,
+    pub active: bool,
+    pub leased: bool,
+    pub protected: bool,
+}
+
+/// The chunk and region borders inside a viewport and the state of every
+/// chunk it shows, for drawing a debug overlay like chunk borders without
+/// recomputing what the library already knows. Built by
+/// `ChunkedWorld::debug_overlay`.
+///
+/// Lines are given as the world coordinate of the first cell after the
+/// border, so a border at `x` runs between cells `x - 1` and `x`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DebugOverlay<I> {
+    pub viewport: CellRect,
+    pub chunk_lines_x: Vec<i32>,
+    pub chunk_lines_y: Vec<i32>,
+    pub region_lines_x: Vec<i32>,
+    pub region_lines_y: Vec<i32>,
+    /// Every chunk overlapping the viewport, in rows from the top left.
+    pub chunks: Vec<ChunkOverlay<I>>,
+}
+
+impl<I: Index> DebugOverlay<I> {
+    /// Lays out the chunks and borders of a viewport, with every chunk
+    /// `LoadState::NotLoaded` and no flags set.
+    pub fn grid(viewport: CellRect, chunk_width: i32, region_width: i32) -> Self {
+        let (left, _) = split_coord(viewport.x, chunk_width);
+        let (right, _) = split_coord(viewport.x + viewport.width.max(1) - 1, chunk_width);
+        let (top, bottom) = match I::ARRANGEMENT {
+            ChunkArrangement::Grid    => (split_coord(viewport.y, chunk_width).0,
+                                          split_coord(viewport.y + viewport.height.max(1) - 1, chunk_width).0),
+            ChunkArrangement::Columns => (0, 0),
+        };
+        // Regions hold `region_width` squared consecutive strips.
+        let strips_per_region = match I::ARRANGEMENT {
+            ChunkArrangement::Grid    => region_width,
+            ChunkArrangement::Columns => region_width * region_width,
+        };
+
+        let mut overlay = DebugOverlay {
+            viewport: viewport,
+            chunk_lines_x: Vec::new(),
+            chunk_lines_y: Vec::new(),
+            region_lines_x: Vec::new(),
+            region_lines_y: Vec::new(),
+            chunks: Vec::new(),
+        };
+        for cx in left + 1..right + 1 {
+            overlay.chunk_lines_x.push(cx * chunk_width);
+            if split_coord(cx, strips_per_region).1 == 0 {
+                overlay.region_lines_x.push(cx * chunk_width);
+            }
+        }
+        if I::ARRANGEMENT == ChunkArrangement::Grid {
+            for cy in top + 1..bottom + 1 {
+                overlay.chunk_lines_y.push(cy * chunk_width);
+                if split_coord(cy, region_width).1 == 0 {
+                    overlay.region_lines_y.push(cy * chunk_width);
+                }
+            }
+        }
+
+        for cy in top..bottom + 1 {
+            for cx in left..right + 1 {
+                let index = I::from_xy(cx, cy);
+                let bounds = match I::ARRANGEMENT {
+                    ChunkArrangement::Grid    => CellRect::of_chunk(&index, chunk_width),
+                    ChunkArrangement::Columns => CellRect::new(cx * chunk_width, viewport.y, chunk_width, viewport.height),
+                };
+                let (region, _) = index.partition(region_width);
+                overlay.chunks.push(ChunkOverlay {
+                    index: index,
+                    bounds: bounds,
+                    region: region,
+                    state: LoadState::NotLoaded,
+                    placeholder: false,
+                    stub: false,
+                    unsaved: false,
+                    pinned: false,
+                    active: false,
+                    leased: false,
+                    protected: false,
+                });
+            }
+        }
+        overlay
+    }
+
+    /// Returns the overlay of a chunk, if it overlaps the viewport.
+    pub fn chunk(&self, index: &I) -> Option<&ChunkOverlay<I>> {
+        self.chunks.iter().find(|c| c.index == *index)
+    }
+
+    /// Returns the overlay of the chunk containing a world position, if it
+    /// is inside the viewport.
+    pub fn chunk_at(&self, x: i32, y: i32) -> Option<&ChunkOverlay<I>> {
+        if !self.viewport.contains(x, y) {
+            return None;
+        }
+        self.chunks.iter().find(|c| c.bounds.contains(x, y))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use traits::TestIndex;
+
+    #[test]
+    fn test_grid_layout() {
+        // Around the corner of a chunk and region at the origin.
+        let overlay: DebugOverlay<TestIndex> = DebugOverlay::grid(CellRect::new(-4, -4, 20, 8), 8, 2);
+        assert_eq!(overlay.chunk_lines_x, vec![0, 8]);
+        assert_eq!(overlay.chunk_lines_y, vec![0]);
+        assert_eq!(overlay.region_lines_x, vec![0]);
+        assert_eq!(overlay.region_lines_y, vec![0]);
+        assert_eq!(overlay.chunks.len(), 6);
+
+        let corner = overlay.chunk_at(-1, -1).unwrap();
+        assert_eq!(corner.index, TestIndex(-1, -1));
+        assert_eq!(corner.region, RegionIndex(-1, -1));
+        assert_eq!(overlay.chunk(&TestIndex(1, 0)).unwrap().bounds, CellRect::new(8, 0, 8, 8));
+        assert!(overlay.chunk_at(16, 0).is_none());
+    }
+}
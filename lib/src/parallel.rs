@@ -0,0 +1,78 @@
+use rayon::prelude::*;
+
+use adaptive::CompressionSample;
+use error::SerialResult;
+use format::CompressionLevel;
+use managed_region::ManagedRegion;
+use metadata::ChunkMeta;
+use region::Region;
+use traits::{ChunkKey, ManagedChunk};
+
+/// A chunk serialized and compressed by `encode_chunks`, or the error it
+/// couldn't be encoded with. The chunk is kept so it can be put back into the
+/// world if it can't be written.
+pub(crate) struct EncodedChunk<I, C> {
+    pub index: I,
+    pub chunk: C,
+    pub encoded: SerialResult<(Vec<u8>, u64)>,
+    /// How compressing the chunk went, if it was given a level.
+    pub sample: Option<CompressionSample>,
+}
+
+/// Serializes and compresses chunks on the rayon thread pool, each at the
+/// level it comes with, such as one picked by `AdaptiveCompression`, or its
+/// own. The results are ordered by region, so the chunks of one region file
+/// are written one after another by the caller.
+pub(crate) fn encode_chunks<'a, I, C>(chunks: Vec<(I, C, Option<CompressionLevel>)>, meta: &ChunkMeta) -> Vec<EncodedChunk<I, C>>
+    where I: ChunkKey + Send,
+          C: ManagedChunk + Send,
+          Region<I>: ManagedRegion<'a, I, C>
+{
+    let mut encoded: Vec<EncodedChunk<I, C>> = chunks.into_par_iter()
+        .map(|(index, chunk, level)| {
+            let (encoded, sample) = match level {
+                Some(level) => {
+                    match <Region<I> as ManagedRegion<'a, I, C>>::encode_chunk_sampled(&chunk, meta, level) {
+                        Ok((data, hash, sample)) => (Ok((data, hash)), Some(sample)),
+                        Err(e)                   => (Err(e), None),
+                    }
+                },
+                None => (<Region<I> as ManagedRegion<'a, I, C>>::encode_chunk(&chunk, meta), None),
+            };
+            EncodedChunk {
+                index: index,
+                chunk: chunk,
+                encoded: encoded,
+                sample: sample,
+            }
+        })
+        .collect();
+
+    encoded.sort_by_key(|e| {
+        let (region, local) = e.index.partition(C::REGION_WIDTH);
+        (region.0, region.1, local.1, local.0)
+    });
+    encoded
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use testing::TestChunk;
+    use traits::TestIndex;
+
+    #[test]
+    fn test_encode_chunks() {
+        let chunks: Vec<_> = [(5, 1), (0, 0), (-1, 0), (1, 0), (0, 1)].iter()
+            .map(|&(x, y)| (TestIndex(x, y), TestChunk { pos: (x, y), edits: 0 }, None))
+            .collect();
+        let encoded = encode_chunks::<TestIndex, TestChunk>(chunks, &ChunkMeta::default());
+
+        let order: Vec<_> = encoded.iter().map(|e| (e.index.0, e.index.1)).collect();
+        assert_eq!(order, vec![(-1, 0), (0, 0), (1, 0), (0, 1), (5, 1)]);
+        for entry in encoded.iter() {
+            let expected = <Region<TestIndex> as ManagedRegion<TestIndex, TestChunk>>::encode_chunk(&entry.chunk, &ChunkMeta::default()).unwrap();
+            assert_eq!(entry.encoded.as_ref().unwrap(), &expected);
+        }
+    }
+}
@@ -0,0 +1,65 @@
+use std::fs;
+use std::io;
+use std::path::Path;
+#[cfg(windows)]
+use std::thread;
+#[cfg(windows)]
+use std::time::Duration;
+
+/// How many times replacing a file is attempted before giving up.
+#[cfg(windows)]
+const REPLACE_ATTEMPTS: u32 = 10;
+
+/// Renames `from` to `to`, replacing `to` if it exists.
+///
+/// On Windows a file can't be replaced while another process has it open
+/// without sharing it for deletion, which virus scanners and search indexers
+/// routinely do for a moment after a file is written. Renames that fail with
+/// `PermissionDenied` there are retried a few times before the error is
+/// returned. Every handle this crate holds to either file must be closed
+/// before calling this.
+pub(crate) fn replace_file<P: AsRef<Path>, Q: AsRef<Path>>(from: P, to: Q) -> io::Result<()> {
+    #[cfg(windows)]
+    {
+        let mut attempt = 1;
+        loop {
+            match fs::rename(from.as_ref(), to.as_ref()) {
+                Err(ref e) if e.kind() == io::ErrorKind::PermissionDenied && attempt < REPLACE_ATTEMPTS => {
+                    thread::sleep(Duration::from_millis(10 * attempt as u64));
+                    attempt += 1;
+                },
+                result => return result,
+            }
+        }
+    }
+    #[cfg(not(windows))]
+    {
+        fs::rename(from, to)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::env;
+    use std::fs::File;
+    use std::io::{Read, Write};
+
+    #[test]
+    fn test_replace_file() {
+        let dir = env::temp_dir().join("infinigen-test-replace");
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        let (from, to) = (dir.join("new"), dir.join("old"));
+        File::create(&to).unwrap().write_all(b"old").unwrap();
+        File::create(&from).unwrap().write_all(b"new").unwrap();
+
+        replace_file(&from, &to).unwrap();
+
+        let mut contents = String::new();
+        File::open(&to).unwrap().read_to_string(&mut contents).unwrap();
+        assert_eq!(contents, "new");
+        assert!(!from.exists());
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,143 @@
+use std::mem;
+
+use hash::FxHashMap;
+
+/// How often a `BufferPool` could hand out a recycled buffer.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct PoolStats {
+    /// Buffers asked for with `take`.
+    pub requests: u64,
+    /// Requests served with a recycled buffer.
+    pub reused: u64,
+    /// Buffers given back with `give`.
+    pub returned: u64,
+    /// Buffers given back that were dropped because their size class was
+    /// full.
+    pub discarded: u64,
+}
+
+impl PoolStats {
+    /// Returns the share of requests served with a recycled buffer, between
+    /// zero and one.
+    pub fn reuse_rate(&self) -> f64 {
+        if self.requests == 0 {
+            return 0.0;
+        }
+        self.reused as f64 / self.requests as f64
+    }
+}
+
+/// Keeps the buffers of unloaded chunks, like their `Vec` of cells, to be
+/// reused by the next chunk loaded or generated instead of freeing and
+/// allocating a large buffer every time, which fragments the heap and
+/// causes latency spikes while streaming.
+///
+/// The library doesn't pool anything itself, since it never sees inside a
+/// chunk. A world keeps the pool, gives a chunk's buffer back in
+/// `ChunkedWorld::unload_chunk_internal` after converting the chunk into
+/// what is saved, and takes one when it generates a chunk or converts a
+/// loaded one in `load_chunk_internal`.
+///
+/// Buffers are sorted into size classes by their capacity in bytes, rounded
+/// to powers of two, so worlds with chunks of several sizes reuse buffers
+/// that fit. Each class holds at most `max_per_class` buffers; the rest are
+/// freed as usual.
+#[derive(Debug, Clone)]
+pub struct BufferPool<T> {
+    classes: FxHashMap<usize, Vec<Vec<T>>>,
+    max_per_class: usize,
+    stats: PoolStats,
+}
+
+impl<T> BufferPool<T> {
+    pub fn new() -> Self {
+        BufferPool {
+            classes: FxHashMap::default(),
+            max_per_class: 64,
+            stats: PoolStats::default(),
+        }
+    }
+
+    /// Keeps at most this many buffers of each size class.
+    pub fn with_max_per_class(mut self, buffers: usize) -> Self {
+        self.max_per_class = buffers;
+        self
+    }
+
+    fn bytes(len: usize) -> usize {
+        len.saturating_mul(mem::size_of::<T>().max(1))
+    }
+
+    /// Returns an empty buffer with room for at least `len` elements,
+    /// recycled if one of a fitting size is pooled.
+    pub fn take(&mut self, len: usize) -> Vec<T> {
+        self.stats.requests += 1;
+        let class = Self::bytes(len).next_power_of_two();
+        if let Some(buffer) = self.classes.get_mut(&class).and_then(|b| b.pop()) {
+            self.stats.reused += 1;
+            return buffer;
+        }
+        Vec::with_capacity(class / mem::size_of::<T>().max(1))
+    }
+
+    /// Gives back a buffer that is no longer needed. Its elements are
+    /// dropped.
+    pub fn give(&mut self, mut buffer: Vec<T>) {
+        self.stats.returned += 1;
+        let bytes = Self::bytes(buffer.capacity());
+        if bytes == 0 {
+            return;
+        }
+        // The class a buffer is kept in is at most its capacity, so every
+        // buffer taken from a class fits requests of that class.
+        let class = if bytes.is_power_of_two() { bytes } else { bytes.next_power_of_two() / 2 };
+        let max = self.max_per_class;
+        let buffers = self.classes.entry(class).or_insert_with(Vec::new);
+        if buffers.len() >= max {
+            self.stats.discarded += 1;
+            return;
+        }
+        buffer.clear();
+        buffers.push(buffer);
+    }
+
+    /// Returns the number of buffers held.
+    pub fn pooled(&self) -> usize {
+        self.classes.values().map(|b| b.len()).sum()
+    }
+
+    pub fn stats(&self) -> &PoolStats {
+        &self.stats
+    }
+
+    /// Frees every pooled buffer, such as after leaving a busy area.
+    pub fn clear(&mut self) {
+        self.classes.clear();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_buffer_pool() {
+        let mut pool: BufferPool<u32> = BufferPool::new().with_max_per_class(1);
+        let mut a = pool.take(1000);
+        assert!(a.capacity() >= 1000);
+        a.extend(0..1000);
+        let ptr = a.as_ptr();
+        pool.give(a);
+
+        let b = pool.take(900);
+        assert_eq!(b.as_ptr(), ptr);
+        assert!(b.is_empty());
+        assert!(pool.take(5000).capacity() >= 5000);
+
+        pool.give(b);
+        pool.give(Vec::with_capacity(1024));
+        assert_eq!(pool.pooled(), 1);
+        assert_eq!(pool.stats().discarded, 1);
+        assert_eq!(pool.stats().reuse_rate(), 1.0 / 3.0);
+    }
+}
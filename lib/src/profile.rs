@@ -0,0 +1,18 @@
+//! Profiling spans around the phases of loading and saving chunks.
+//!
+//! With the `tracing` feature enabled, `profile_span!` enters a trace-level
+//! span that lasts until the end of the enclosing block, so the phases show
+//! up in tools like tracy or chrome tracing. Without it the macro expands to
+//! nothing.
+
+#[cfg(feature = "tracing")]
+macro_rules! profile_span {
+    ($name:expr $(, $field:ident = $value:expr)*) => {
+        let _span = trace_span!($name $(, $field = $value)*).entered();
+    }
+}
+
+#[cfg(not(feature = "tracing"))]
+macro_rules! profile_span {
+    ($name:expr $(, $field:ident = $value:expr)*) => {}
+}
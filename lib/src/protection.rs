@@ -0,0 +1,94 @@
+use std::collections::BTreeSet;
+
+use error::{ChunkProtected, SerialResult};
+use metadata::WorldMetadata;
+use traits::Index;
+
+/// Key the protected chunks are stored under in the world metadata.
+const PROTECTED_KEY: &'static str = "infinigen.protected";
+
+/// The chunks normal gameplay may read but not change, like the area around
+/// the spawn point of a server. Kept in the world metadata.
+///
+/// Protection is only enforced by the accessors that check it, like
+/// `ChunkedWorld::chunk_for_write`. The library itself still loads, saves and
+/// regenerates protected chunks.
+#[derive(Debug, Clone, Default)]
+pub struct ChunkProtection {
+    chunks: BTreeSet<(i32, i32)>,
+}
+
+impl ChunkProtection {
+    pub fn new() -> Self {
+        ChunkProtection::default()
+    }
+
+    /// Loads the protected chunks stored in the world metadata.
+    pub fn load(metadata: &WorldMetadata) -> SerialResult<Self> {
+        let chunks: Option<Vec<(i32, i32)>> = metadata.get(PROTECTED_KEY)?;
+        Ok(ChunkProtection {
+            chunks: chunks.unwrap_or_default().into_iter().collect(),
+        })
+    }
+
+    /// Stores the protected chunks in the world metadata.
+    pub fn save(&self, metadata: &mut WorldMetadata) -> SerialResult<()> {
+        if self.chunks.is_empty() {
+            metadata.remove(PROTECTED_KEY);
+            return Ok(());
+        }
+        metadata.insert(PROTECTED_KEY, &self.protected_chunks())
+    }
+
+    /// Protects a chunk. Returns `false` if it already was.
+    pub fn protect<I: Index>(&mut self, index: &I) -> bool {
+        self.chunks.insert((index.x(), index.y()))
+    }
+
+    /// Lifts the protection of a chunk. Returns `false` if it wasn't
+    /// protected.
+    pub fn unprotect<I: Index>(&mut self, index: &I) -> bool {
+        self.chunks.remove(&(index.x(), index.y()))
+    }
+
+    pub fn is_protected<I: Index>(&self, index: &I) -> bool {
+        self.chunks.contains(&(index.x(), index.y()))
+    }
+
+    /// Returns `LogicError::ChunkProtected` if the chunk is protected.
+    pub fn check<I: Index>(&self, index: &I) -> SerialResult<()> {
+        if self.is_protected(index) {
+            return Err(ChunkProtected(index.x(), index.y()).into());
+        }
+        Ok(())
+    }
+
+    /// Returns the positions of the protected chunks, in order.
+    pub fn protected_chunks(&self) -> Vec<(i32, i32)> {
+        self.chunks.iter().cloned().collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use traits::TestIndex;
+
+    #[test]
+    fn test_protection() {
+        let mut protection = ChunkProtection::new();
+        assert!(protection.protect(&TestIndex(0, 0)));
+        assert!(protection.protect(&TestIndex(-1, 0)));
+        assert!(protection.check(&TestIndex(1, 0)).is_ok());
+        assert_eq!(protection.check(&TestIndex(0, 0)).unwrap_err().logic(), Some(&ChunkProtected(0, 0)));
+
+        let mut metadata = WorldMetadata::new();
+        protection.save(&mut metadata).unwrap();
+        let mut loaded = ChunkProtection::load(&metadata).unwrap();
+        assert_eq!(loaded.protected_chunks(), vec![(-1, 0), (0, 0)]);
+
+        assert!(loaded.unprotect(&TestIndex(-1, 0)));
+        assert!(!loaded.unprotect(&TestIndex(-1, 0)));
+        assert!(!loaded.is_protected(&TestIndex(-1, 0)));
+    }
+}
@@ -0,0 +1,149 @@
+use std::time::Duration;
+
+use hash::FxHashMap;
+use traits::Index;
+
+/// Where a loaded chunk came from.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum ChunkOrigin {
+    /// Read from its region file.
+    Loaded,
+    /// Generated because it wasn't saved yet, was reset, or couldn't be read.
+    Generated,
+}
+
+/// How a loaded chunk got into the world and how long that took.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ChunkProvenance {
+    pub origin: ChunkOrigin,
+    pub elapsed: Duration,
+    /// The world tick the chunk was loaded or generated at.
+    pub tick: u64,
+}
+
+/// The number, total and longest time of the chunks loaded or generated.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct TimingStats {
+    pub count: u64,
+    pub total: Duration,
+    pub max: Duration,
+}
+
+impl TimingStats {
+    pub fn record(&mut self, elapsed: Duration) {
+        self.count += 1;
+        self.total += elapsed;
+        self.max = self.max.max(elapsed);
+    }
+
+    /// Returns the average time, or zero if nothing was recorded.
+    pub fn mean(&self) -> Duration {
+        if self.count == 0 {
+            return Duration::from_secs(0);
+        }
+        let nanos = (self.total.as_secs() * 1_000_000_000 + self.total.subsec_nanos() as u64) / self.count;
+        Duration::new(nanos / 1_000_000_000, (nanos % 1_000_000_000) as u32)
+    }
+}
+
+/// Records whether every loaded chunk was read from disk or generated and
+/// how long it took, along with totals for each, so a game can tell at
+/// runtime whether pre-generating the world or keeping more chunks loaded
+/// would help, and show it in a debug display. See
+/// `ChunkedWorld::load_timings`.
+///
+/// Loads are timed from the start of the read to the chunk being inserted.
+/// Chunks read on a background thread by `request_chunks` are timed from the
+/// request to `finish_chunk_load`, so waiting for the chunk to be finished
+/// counts as well.
+#[derive(Debug, Clone)]
+pub struct LoadTimings<I: Index> {
+    chunks: FxHashMap<I, ChunkProvenance>,
+    loaded: TimingStats,
+    generated: TimingStats,
+}
+
+impl<I: Index> LoadTimings<I> {
+    pub fn new() -> Self {
+        LoadTimings {
+            chunks: FxHashMap::default(),
+            loaded: TimingStats::default(),
+            generated: TimingStats::default(),
+        }
+    }
+
+    pub fn record(&mut self, index: &I, provenance: ChunkProvenance) {
+        match provenance.origin {
+            ChunkOrigin::Loaded    => self.loaded.record(provenance.elapsed),
+            ChunkOrigin::Generated => self.generated.record(provenance.elapsed),
+        }
+        self.chunks.insert(index.clone(), provenance);
+    }
+
+    /// Forgets a chunk that was unloaded. Its time stays in the totals.
+    pub fn forget(&mut self, index: &I) {
+        self.chunks.remove(index);
+    }
+
+    /// Returns how a loaded chunk got into the world.
+    pub fn provenance(&self, index: &I) -> Option<&ChunkProvenance> {
+        self.chunks.get(index)
+    }
+
+    pub fn loaded(&self) -> &TimingStats {
+        &self.loaded
+    }
+
+    pub fn generated(&self) -> &TimingStats {
+        &self.generated
+    }
+
+    /// Returns the share of chunks that had to be generated, between zero
+    /// and one.
+    pub fn generated_fraction(&self) -> f64 {
+        let total = self.loaded.count + self.generated.count;
+        if total == 0 {
+            return 0.0;
+        }
+        self.generated.count as f64 / total as f64
+    }
+
+    /// Clears the totals, such as to measure a new area of the world. The
+    /// provenance of loaded chunks is kept.
+    pub fn reset_stats(&mut self) {
+        self.loaded = TimingStats::default();
+        self.generated = TimingStats::default();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use traits::TestIndex;
+
+    #[test]
+    fn test_load_timings() {
+        let mut timings = LoadTimings::new();
+        let provenance = |origin, millis| ChunkProvenance {
+            origin: origin,
+            elapsed: Duration::from_millis(millis),
+            tick: 7,
+        };
+        timings.record(&TestIndex(0, 0), provenance(ChunkOrigin::Loaded, 2));
+        timings.record(&TestIndex(1, 0), provenance(ChunkOrigin::Generated, 10));
+        timings.record(&TestIndex(2, 0), provenance(ChunkOrigin::Generated, 20));
+
+        assert_eq!(timings.loaded().count, 1);
+        assert_eq!(timings.generated().mean(), Duration::from_millis(15));
+        assert_eq!(timings.generated().max, Duration::from_millis(20));
+        assert!((timings.generated_fraction() - 2.0 / 3.0).abs() < 1e-9);
+
+        timings.forget(&TestIndex(1, 0));
+        assert!(timings.provenance(&TestIndex(1, 0)).is_none());
+        assert_eq!(timings.provenance(&TestIndex(2, 0)).unwrap().origin, ChunkOrigin::Generated);
+
+        timings.reset_stats();
+        assert_eq!(timings.generated().count, 0);
+        assert_eq!(timings.generated_fraction(), 0.0);
+    }
+}
@@ -0,0 +1,347 @@
+use std::collections::{BTreeMap, HashMap};
+use std::fs::{File, OpenOptions};
+use std::hash::Hash;
+use std::path::Path;
+
+use bincode;
+use serde::Serialize;
+use serde::de::DeserializeOwned;
+
+use attachment::AttachedChunk;
+use error::{ErrorContext, FormatError, SerialResult, WithContext};
+use format::{self, RegionConfig};
+use layout::RegionLayout;
+use managed_region::ManagedRegion;
+use migrate::region_files;
+use region::{Region, RegionIndex, RegionLocalIndex};
+use traits::ManagedChunk;
+
+/// A collection of entities saved as a chunk attachment.
+pub trait EntityCollection {
+    type Entity;
+
+    /// Removes every entity `remove` returns true for. Returns the number of
+    /// entities removed.
+    fn remove_where<F: FnMut(&Self::Entity) -> bool>(&mut self, remove: F) -> usize;
+}
+
+impl<E> EntityCollection for Vec<E> {
+    type Entity = E;
+
+    fn remove_where<F: FnMut(&E) -> bool>(&mut self, mut remove: F) -> usize {
+        let before = self.len();
+        self.retain(|e| !remove(e));
+        before - self.len()
+    }
+}
+
+impl<K: Hash + Eq, E> EntityCollection for HashMap<K, E> {
+    type Entity = E;
+
+    fn remove_where<F: FnMut(&E) -> bool>(&mut self, mut remove: F) -> usize {
+        let before = self.len();
+        self.retain(|_, e| !remove(e));
+        before - self.len()
+    }
+}
+
+impl<K: Ord, E> EntityCollection for BTreeMap<K, E> {
+    type Entity = E;
+
+    fn remove_where<F: FnMut(&E) -> bool>(&mut self, mut remove: F) -> usize {
+        let before = self.len();
+        self.retain(|_, e| !remove(e));
+        before - self.len()
+    }
+}
+
+/// Calls `visit` on every chunk saved in a region file without changing the
+/// file. The chunk's position is given in chunk coordinates, assuming the
+/// default grid partition. Returns the number of chunks visited.
+pub fn visit_region<C, P, F>(path: P, index: &RegionIndex, mut visit: F) -> SerialResult<usize>
+    where C: ManagedChunk,
+          P: AsRef<Path>,
+          F: FnMut((i32, i32), C) -> SerialResult<()>
+{
+    let path = path.as_ref();
+    let context = || ErrorContext::new("read region").with_region(index.clone()).with_path(path);
+
+    let mut file = File::open(path).with_context(&context)?;
+    format::check_header(&mut file, &RegionConfig::of::<C>()).with_context(&context)?;
+    let mut region: Region<RegionLocalIndex> = Region::new(file);
+
+    let mut count = 0;
+    for ly in 0..C::REGION_WIDTH {
+        for lx in 0..C::REGION_WIDTH {
+            let local = RegionLocalIndex(lx, ly);
+            let chunk_context = || context().with_chunk(local.clone());
+            let read = <Region<RegionLocalIndex> as ManagedRegion<RegionLocalIndex, C>>::read_chunk_data(&mut region, &local);
+            let data = match read {
+                Ok((_, data)) => data,
+                Err(ref e) if e.is_missing_chunk() => continue,
+                Err(e) => return Err(e.context(chunk_context())),
+            };
+
+            let chunk: C = bincode::deserialize(&data).with_context(&chunk_context)?;
+            let pos = RegionConfig::of::<C>().chunk_position(index, &local).with_context(&chunk_context)?;
+            visit(pos, chunk).with_context(&chunk_context)?;
+            count += 1;
+        }
+    }
+    Ok(count)
+}
+
+/// Visits every chunk of a world. See `visit_region`.
+pub fn visit_layout<C, F>(layout: &RegionLayout, mut visit: F) -> SerialResult<usize>
+    where C: ManagedChunk,
+          F: FnMut((i32, i32), C) -> SerialResult<()>
+{
+    let mut count = 0;
+    for (index, path) in region_files(layout)?.into_iter() {
+        count += visit_region::<C, _, _>(path, &index, &mut visit)?;
+    }
+    Ok(count)
+}
+
+/// Calls `edit` on every chunk saved in a region file, and rewrites the
+/// chunks it returns true for in place. The chunk's position is given in
+/// chunk coordinates, assuming the default grid partition. Returns the number
+/// of chunks that were rewritten.
+///
+/// The region must not be loaded by a running world.
+pub fn edit_region<C, P, F>(path: P, index: &RegionIndex, mut edit: F) -> SerialResult<usize>
+    where C: ManagedChunk,
+          P: AsRef<Path>,
+          F: FnMut((i32, i32), &mut C) -> SerialResult<bool>
+{
+    let path = path.as_ref();
+    let context = || ErrorContext::new("edit region").with_region(index.clone()).with_path(path);
+
+    let mut file = OpenOptions::new().read(true).write(true).open(path).with_context(&context)?;
+    format::check_header(&mut file, &RegionConfig::of::<C>()).with_context(&context)?;
+    let mut region: Region<RegionLocalIndex> = Region::new(file);
+
+    let mut count = 0;
+    for ly in 0..C::REGION_WIDTH {
+        for lx in 0..C::REGION_WIDTH {
+            let local = RegionLocalIndex(lx, ly);
+            let chunk_context = || context().with_chunk(local.clone());
+            let read = <Region<RegionLocalIndex> as ManagedRegion<RegionLocalIndex, C>>::read_chunk_data(&mut region, &local);
+            let (meta, data) = match read {
+                Ok(read) => read,
+                Err(ref e) if e.is_missing_chunk() => continue,
+                Err(e) => return Err(e.context(chunk_context())),
+            };
+
+            let mut chunk: C = bincode::deserialize(&data).with_context(&chunk_context)?;
+            let pos = RegionConfig::of::<C>().chunk_position(index, &local).with_context(&chunk_context)?;
+            if !edit(pos, &mut chunk).with_context(&chunk_context)? {
+                continue;
+            }
+
+            let (compressed, _) = <Region<RegionLocalIndex> as ManagedRegion<RegionLocalIndex, C>>::encode_chunk(&chunk, &meta)
+                .with_context(&chunk_context)?;
+            <Region<RegionLocalIndex> as ManagedRegion<RegionLocalIndex, C>>::write_encoded(&mut region, compressed, &local)
+                .with_context(&chunk_context)?;
+            count += 1;
+        }
+    }
+    <Region<RegionLocalIndex> as ManagedRegion<RegionLocalIndex, C>>::handle(&mut region).sync_all()?;
+    Ok(count)
+}
+
+/// Edits every region file of a world. See `edit_region`.
+pub fn edit_layout<C, F>(layout: &RegionLayout, mut edit: F) -> SerialResult<usize>
+    where C: ManagedChunk,
+          F: FnMut((i32, i32), &mut C) -> SerialResult<bool>
+{
+    let mut count = 0;
+    for (index, path) in region_files(layout)?.into_iter() {
+        count += edit_region::<C, _, _>(path, &index, &mut edit)?;
+    }
+    Ok(count)
+}
+
+/// Rewrites every chunk saved in a region file as type `Old` into type `New`
+/// with `convert`, for when the saved form of a chunk changes in a way serde
+/// can't read back, like a field changing its type. Both types must use the
+/// same region width and sector size. Returns the number of chunks that were
+/// converted.
+///
+/// Bincode doesn't record which type a chunk was saved as, so the game has
+/// to know which files still hold `Old` chunks, for example from a version
+/// number kept in the world metadata. Converting a file twice fails or
+/// garbles its chunks. The region must not be loaded by a running world.
+pub fn convert_region<Old, New, P, F>(path: P, index: &RegionIndex, mut convert: F) -> SerialResult<usize>
+    where Old: ManagedChunk,
+          New: ManagedChunk,
+          P: AsRef<Path>,
+          F: FnMut((i32, i32), Old) -> SerialResult<New>
+{
+    let path = path.as_ref();
+    let context = || ErrorContext::new("convert region").with_region(index.clone()).with_path(path);
+    let config = RegionConfig::of::<Old>();
+    if RegionConfig::of::<New>() != config {
+        return Err(FormatError::LayoutMismatch {
+            expected: config,
+            found: RegionConfig::of::<New>(),
+        }.into());
+    }
+
+    let mut file = OpenOptions::new().read(true).write(true).open(path).with_context(&context)?;
+    format::check_header(&mut file, &config).with_context(&context)?;
+    let mut region: Region<RegionLocalIndex> = Region::new(file);
+
+    let mut count = 0;
+    for ly in 0..Old::REGION_WIDTH {
+        for lx in 0..Old::REGION_WIDTH {
+            let local = RegionLocalIndex(lx, ly);
+            let chunk_context = || context().with_chunk(local.clone());
+            let read = <Region<RegionLocalIndex> as ManagedRegion<RegionLocalIndex, Old>>::read_chunk_data(&mut region, &local);
+            let (meta, data) = match read {
+                Ok(read) => read,
+                Err(ref e) if e.is_missing_chunk() => continue,
+                Err(e) => return Err(e.context(chunk_context())),
+            };
+
+            let chunk: Old = bincode::deserialize(&data).with_context(&chunk_context)?;
+            let pos = config.chunk_position(index, &local).with_context(&chunk_context)?;
+            let chunk = convert(pos, chunk).with_context(&chunk_context)?;
+
+            let (compressed, _) = <Region<RegionLocalIndex> as ManagedRegion<RegionLocalIndex, New>>::encode_chunk(&chunk, &meta)
+                .with_context(&chunk_context)?;
+            <Region<RegionLocalIndex> as ManagedRegion<RegionLocalIndex, New>>::write_encoded(&mut region, compressed, &local)
+                .with_context(&chunk_context)?;
+            count += 1;
+        }
+    }
+    <Region<RegionLocalIndex> as ManagedRegion<RegionLocalIndex, New>>::handle(&mut region).sync_all()?;
+    Ok(count)
+}
+
+/// Converts every region file of a world. See `convert_region`.
+pub fn convert_layout<Old, New, F>(layout: &RegionLayout, mut convert: F) -> SerialResult<usize>
+    where Old: ManagedChunk,
+          New: ManagedChunk,
+          F: FnMut((i32, i32), Old) -> SerialResult<New>
+{
+    let mut count = 0;
+    for (index, path) in region_files(layout)?.into_iter() {
+        count += convert_region::<Old, New, _, _>(path, &index, &mut convert)?;
+    }
+    Ok(count)
+}
+
+/// Removes the entities saved under the attachment `key` that `remove`
+/// returns true for from every chunk of a world that isn't running, such as
+/// monsters that should be despawned or items left behind by a bug. Chunks
+/// without matching entities are left untouched. Returns the number of
+/// entities removed.
+pub fn prune_entities<C, T, F>(layout: &RegionLayout, key: &str, mut remove: F) -> SerialResult<usize>
+    where C: ManagedChunk,
+          T: EntityCollection + Serialize + DeserializeOwned,
+          F: FnMut(&T::Entity) -> bool
+{
+    let mut removed = 0;
+    edit_layout::<AttachedChunk<C>, _>(layout, |_, chunk| {
+        let mut entities: T = match chunk.attachments.get(key)? {
+            Some(entities) => entities,
+            None           => return Ok(false),
+        };
+        let count = entities.remove_where(&mut remove);
+        if count == 0 {
+            return Ok(false);
+        }
+        chunk.attachments.insert(key, &entities)?;
+        removed += count;
+        Ok(true)
+    })?;
+    Ok(removed)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::env;
+    use std::fs;
+    use attachment::Attachments;
+    use metadata::ChunkMeta;
+
+    #[derive(Debug, PartialEq, Serialize, Deserialize)]
+    struct TestChunk(u8);
+
+    impl ManagedChunk for TestChunk {
+        const SECTOR_SIZE: usize = 64;
+        const REGION_WIDTH: i32 = 4;
+    }
+
+    type Saved = AttachedChunk<TestChunk>;
+
+    #[test]
+    fn test_prune_entities() {
+        let root = env::temp_dir().join("infinigen-test-prune");
+        let _ = fs::remove_dir_all(&root);
+        let layout = RegionLayout::new(&root);
+        let path = layout.region_path(&RegionIndex(-1, 0));
+
+        {
+            let file = <Region<RegionLocalIndex> as ManagedRegion<RegionLocalIndex, Saved>>::get_region_file(&path).unwrap();
+            let mut region: Region<RegionLocalIndex> = Region::new(file);
+            for (i, monsters) in vec![vec!["rat", "orc", "rat"], vec!["orc"]].into_iter().enumerate() {
+                let mut attachments = Attachments::new();
+                attachments.insert("monsters", &monsters).unwrap();
+                let chunk = AttachedChunk { chunk: TestChunk(i as u8), attachments: attachments };
+                let (data, _) = <Region<RegionLocalIndex> as ManagedRegion<RegionLocalIndex, Saved>>::encode_chunk(&chunk, &ChunkMeta::default()).unwrap();
+                <Region<RegionLocalIndex> as ManagedRegion<RegionLocalIndex, Saved>>::write_encoded(&mut region, data, &RegionLocalIndex(i as i32, 2)).unwrap();
+            }
+        }
+
+        let removed = prune_entities::<TestChunk, Vec<String>, _>(&layout, "monsters", |m| m == "rat").unwrap();
+        assert_eq!(removed, 2);
+
+        let mut seen = Vec::new();
+        edit_layout::<Saved, _>(&layout, |pos, chunk| {
+            seen.push((pos, chunk.chunk.0, chunk.attachments.get::<Vec<String>>("monsters")?.unwrap()));
+            Ok(false)
+        }).unwrap();
+        assert_eq!(seen, vec![((-4, 2), 0, vec!["orc".to_string()]),
+                              ((-3, 2), 1, vec!["orc".to_string()])]);
+
+        fs::remove_dir_all(&root).unwrap();
+    }
+
+    #[derive(Debug, PartialEq, Serialize, Deserialize)]
+    struct WideChunk(u32);
+
+    impl ManagedChunk for WideChunk {
+        const SECTOR_SIZE: usize = 64;
+        const REGION_WIDTH: i32 = 4;
+    }
+
+    #[test]
+    fn test_convert_region() {
+        let root = env::temp_dir().join("infinigen-test-convert");
+        let _ = fs::remove_dir_all(&root);
+        let layout = RegionLayout::new(&root);
+        let path = layout.region_path(&RegionIndex(0, 0));
+
+        {
+            let file = <Region<RegionLocalIndex> as ManagedRegion<RegionLocalIndex, TestChunk>>::get_region_file(&path).unwrap();
+            let mut region: Region<RegionLocalIndex> = Region::new(file);
+            let (data, _) = <Region<RegionLocalIndex> as ManagedRegion<RegionLocalIndex, TestChunk>>::encode_chunk(&TestChunk(7), &ChunkMeta::default()).unwrap();
+            <Region<RegionLocalIndex> as ManagedRegion<RegionLocalIndex, TestChunk>>::write_encoded(&mut region, data, &RegionLocalIndex(1, 1)).unwrap();
+        }
+
+        let converted = convert_layout::<TestChunk, WideChunk, _>(&layout, |_, old| Ok(WideChunk(old.0 as u32 * 1000))).unwrap();
+        assert_eq!(converted, 1);
+
+        let mut seen = Vec::new();
+        visit_layout::<WideChunk, _>(&layout, |pos, chunk| {
+            seen.push((pos, chunk));
+            Ok(())
+        }).unwrap();
+        assert_eq!(seen, vec![((1, 1), WideChunk(7000))]);
+
+        fs::remove_dir_all(&root).unwrap();
+    }
+}
@@ -0,0 +1,24 @@
+/// What a world does when a chunk can't be read from its region file, such
+/// as when its data is corrupt or was written by an incompatible version of
+/// the game. Returned by `ChunkedWorld::recovery_policy`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RecoveryPolicy {
+    /// Panic with the error.
+    Panic,
+    /// Generate the chunk again as if it was never saved. Its broken copy is
+    /// overwritten the next time it is saved.
+    RegenerateChunk,
+    /// Report the error with `ChunkedWorld::on_chunk_skipped` and leave the
+    /// chunk unloaded. It isn't tried again until
+    /// `LoadRegistry::retry_failed` is called.
+    SkipAndLog,
+    /// Let `ChunkedWorld::choose_recovery` pick one of the other policies
+    /// for each failure.
+    AskCallback,
+}
+
+impl Default for RecoveryPolicy {
+    fn default() -> Self {
+        RecoveryPolicy::Panic
+    }
+}
@@ -1,45 +1,19 @@
-use std::collections::HashSet;
+use std::cmp::Ordering;
 use std::fmt;
-use std::io;
 use std::fs::File;
 
-use bincode;
-
-use traits::{Index, ManagedChunk};
+use hash::FxHashMap;
+use traits::{ChunkKey, ManagedChunk};
+use lifetime::ChunkLifetimeTracker;
 use managed_region::ManagedRegion;
 
-pub use self::SerialError::*;
-
-#[derive(Debug)]
-pub enum SerialError {
-    NoChunkInWorld(i32, i32),
-    NoChunkInSavefile(RegionLocalIndex),
-    ChunkAlreadyLoaded(i32, i32),
-    IoError(io::Error),
-    EncodingError(bincode::ErrorKind),
-}
-
-pub type SerialResult<T> = Result<T, SerialError>;
-
-impl From<io::Error> for SerialError {
-    fn from(e: io::Error) -> SerialError {
-        IoError(e)
-    }
-}
-
-impl From<Box<bincode::ErrorKind>> for SerialError {
-    fn from(e: Box<bincode::ErrorKind>) -> SerialError {
-        EncodingError(*e)
-    }
-}
-
 /// An index of a chunk inside a region's coordinate space.
 #[derive(Copy, Clone, Debug, Hash, Eq, PartialEq)]
 pub struct RegionLocalIndex(pub i32, pub i32);
 
 impl fmt::Display for RegionLocalIndex {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        write!(f, "{}", self.0)
+        write!(f, "({}, {})", self.0, self.1)
     }
 }
 
@@ -49,54 +23,142 @@ pub struct RegionIndex(pub i32, pub i32);
 
 impl fmt::Display for RegionIndex {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        write!(f, "{}", self.0)
+        write!(f, "({}, {})", self.0, self.1)
+    }
+}
+
+/// A change in the state of a region file, reported to
+/// `RegionManager::on_region_event` so applications can sync region files
+/// with other services, such as uploading them to cloud storage once they
+/// are closed.
+#[derive(Copy, Clone, Debug, Hash, Eq, PartialEq)]
+pub enum RegionEvent {
+    /// The region file didn't exist and was created.
+    Created,
+    /// An existing region file was opened.
+    Opened,
+    /// Every write made to the region file so far has reached the disk.
+    Flushed,
+    /// The region file was flushed and closed, so it won't change until it
+    /// is opened again.
+    Closed,
+}
+
+// Indices are ordered by row, then column, matching the order chunks are laid
+// out in a region's lookup table.
+macro_rules! impl_index_traits {
+    ($index:ident) => {
+        impl Ord for $index {
+            fn cmp(&self, other: &$index) -> Ordering {
+                (self.1, self.0).cmp(&(other.1, other.0))
+            }
+        }
+
+        impl PartialOrd for $index {
+            fn partial_cmp(&self, other: &$index) -> Option<Ordering> {
+                Some(self.cmp(other))
+            }
+        }
+
+        impl From<(i32, i32)> for $index {
+            fn from((x, y): (i32, i32)) -> $index {
+                $index(x, y)
+            }
+        }
+
+        impl From<$index> for (i32, i32) {
+            fn from(index: $index) -> (i32, i32) {
+                (index.0, index.1)
+            }
+        }
+    }
+}
+
+impl_index_traits!(RegionIndex);
+impl_index_traits!(RegionLocalIndex);
+
+/// Addresses a slot of a single region file directly, for tools that work on
+/// one region file at a time.
+impl ChunkKey for RegionLocalIndex {
+    fn partition(&self, _region_width: i32) -> (RegionIndex, RegionLocalIndex) {
+        (RegionIndex(0, 0), *self)
     }
 }
 
 /// Implementation of a region for on-disk serialization.
-pub struct Region<I: Index> {
+pub struct Region<I: ChunkKey> {
     pub handle: Box<File>,
-    pub unsaved_chunks: HashSet<I>,
+    pub lifetimes: ChunkLifetimeTracker<I>,
+    pub hashes: FxHashMap<I, u64>,
 }
 
-impl<'de: 'a, 'a, I: Index, C: ManagedChunk> ManagedRegion<'a, I, C> for Region<I> {
-    fn handle(&mut self) -> &mut File {
-        &mut self.handle
+impl<I: ChunkKey> Region<I> {
+    pub fn new(handle: File) -> Self {
+        Region {
+            handle: Box::new(handle),
+            lifetimes: ChunkLifetimeTracker::new(),
+            hashes: FxHashMap::default(),
+        }
     }
+}
 
-    fn mark_as_saved(&mut self, index: &I) {
-        self.unsaved_chunks.remove(index);
+impl<'de: 'a, 'a, I: ChunkKey, C: ManagedChunk> ManagedRegion<'a, I, C> for Region<I> {
+    fn handle(&mut self) -> &mut File {
+        &mut self.handle
     }
 
-    fn mark_as_unsaved(&mut self, index: &I) {
-        self.unsaved_chunks.insert(index.clone());
+    fn lifetimes(&self) -> &ChunkLifetimeTracker<I> {
+        &self.lifetimes
     }
 
-    fn chunk_unsaved(&self, index: &I) -> bool {
-        self.unsaved_chunks.contains(index)
+    fn lifetimes_mut(&mut self) -> &mut ChunkLifetimeTracker<I> {
+        &mut self.lifetimes
     }
 
-    fn receive_created_chunk(&mut self, index: &I) {
-        self.unsaved_chunks.insert(index.clone());
+    fn cached_hash(&self, index: &I) -> Option<u64> {
+        self.hashes.get(index).cloned()
     }
 
-    fn is_empty(&self) -> bool {
-        self.unsaved_chunks.len() == 0
+    fn cache_hash(&mut self, index: &I, hash: u64) {
+        self.hashes.insert(index.clone(), hash);
     }
 }
 
-#[cfg(never)]
 #[cfg(test)]
 mod tests {
     use super::*;
+    use traits::TestIndex;
+
     #[test]
     fn test_region_index() {
-        assert_eq!(util::get_region_index(&ChunkIndex::new(0, 0)), RegionIndex::new(0, 0));
-        assert_eq!(util::get_region_index(&ChunkIndex::new(0, 8)), RegionIndex::new(0, 0));
-        assert_eq!(util::get_region_index(&ChunkIndex::new(0, 17)), RegionIndex::new(0, 1));
-        assert_eq!(util::get_region_index(&ChunkIndex::new(0, 16)), RegionIndex::new(0, 1));
-        assert_eq!(util::get_region_index(&ChunkIndex::new(0, 15)), RegionIndex::new(0, 0));
-        assert_eq!(util::get_region_index(&ChunkIndex::new(0, -1)), RegionIndex::new(0, -1));
+        let get_region_index = |x, y| TestIndex(x, y).partition(16).0;
+        assert_eq!(get_region_index(0, 0), RegionIndex(0, 0));
+        assert_eq!(get_region_index(0, 8), RegionIndex(0, 0));
+        assert_eq!(get_region_index(0, 17), RegionIndex(0, 1));
+        assert_eq!(get_region_index(0, 16), RegionIndex(0, 1));
+        assert_eq!(get_region_index(0, 15), RegionIndex(0, 0));
+        assert_eq!(get_region_index(0, -1), RegionIndex(0, -1));
+        assert_eq!(get_region_index(0, -16), RegionIndex(0, -1));
+        assert_eq!(get_region_index(0, -17), RegionIndex(0, -2));
+    }
+
+    /// Rooms of a dungeon, one region per floor.
+    #[derive(Clone, Hash, Eq, PartialEq)]
+    struct Room {
+        floor: i32,
+        room: i32,
     }
 
+    impl ChunkKey for Room {
+        fn partition(&self, region_width: i32) -> (RegionIndex, RegionLocalIndex) {
+            (RegionIndex(self.floor, 0),
+             RegionLocalIndex(self.room % region_width, self.room / region_width))
+        }
+    }
+
+    #[test]
+    fn test_custom_partition() {
+        let room = Room { floor: 3, room: 37 };
+        assert_eq!(room.partition(16), (RegionIndex(3, 0), RegionLocalIndex(5, 2)));
+    }
 }
@@ -0,0 +1,181 @@
+use std::time::{Duration, Instant};
+
+use error::SerialResult;
+
+/// A world hosted in a `WorldRegistry`. Usually implemented by forwarding to
+/// the `ChunkedWorld` methods of the same purpose.
+pub trait HostedWorld {
+    /// Loads and unloads chunks for at most about `budget`, like
+    /// `ChunkedWorld::update_chunks_with_deadline`. Returns `true` if work
+    /// remains to be done on a later call.
+    fn update_within(&mut self, budget: Duration) -> SerialResult<bool>;
+
+    /// Saves the world while it keeps running, like
+    /// `ChunkedWorld::save_in_place`.
+    fn autosave(&mut self) -> SerialResult<()>;
+
+    /// Saves and unloads the whole world, like `ChunkedWorld::save`.
+    fn shut_down(&mut self) -> SerialResult<()>;
+}
+
+struct Hosted {
+    name: String,
+    world: Box<dyn HostedWorld>,
+    behind: bool,
+}
+
+/// Several independent worlds hosted by one process, like the dimensions of
+/// a server or the worlds of several players, each with its own save
+/// directory and seed.
+///
+/// The worlds are updated one after the other on the calling thread, so
+/// their loading and saving never compete for the disk. `update` shares a
+/// time budget between them fairly: every world gets an equal share of the
+/// time that is left when its turn comes, so time a quiet world doesn't use
+/// goes to the busy ones, and the world that goes first changes every call
+/// so none of them is always left with the remainder.
+pub struct WorldRegistry {
+    worlds: Vec<Hosted>,
+    next: usize,
+}
+
+impl WorldRegistry {
+    pub fn new() -> Self {
+        WorldRegistry {
+            worlds: Vec::new(),
+            next: 0,
+        }
+    }
+
+    /// Adds a world under a name, returning the world it replaced.
+    pub fn insert<W: HostedWorld + 'static>(&mut self, name: &str, world: W) -> Option<Box<dyn HostedWorld>> {
+        let world: Box<dyn HostedWorld> = Box::new(world);
+        if let Some(hosted) = self.worlds.iter_mut().find(|h| h.name == name) {
+            hosted.behind = false;
+            return Some(::std::mem::replace(&mut hosted.world, world));
+        }
+        self.worlds.push(Hosted {
+            name: name.to_string(),
+            world: world,
+            behind: false,
+        });
+        None
+    }
+
+    /// Removes a world without saving it.
+    pub fn remove(&mut self, name: &str) -> Option<Box<dyn HostedWorld>> {
+        let pos = self.worlds.iter().position(|h| h.name == name)?;
+        if pos < self.next {
+            self.next -= 1;
+        }
+        Some(self.worlds.remove(pos).world)
+    }
+
+    pub fn get_mut(&mut self, name: &str) -> Option<&mut dyn HostedWorld> {
+        match self.worlds.iter_mut().find(|h| h.name == name) {
+            Some(hosted) => Some(&mut *hosted.world),
+            None         => None,
+        }
+    }
+
+    pub fn names(&self) -> Vec<&str> {
+        self.worlds.iter().map(|h| h.name.as_str()).collect()
+    }
+
+    pub fn len(&self) -> usize {
+        self.worlds.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.worlds.is_empty()
+    }
+
+    /// Returns the names of the worlds that had work left after the last
+    /// `update`.
+    pub fn behind(&self) -> Vec<&str> {
+        self.worlds.iter().filter(|h| h.behind).map(|h| h.name.as_str()).collect()
+    }
+
+    /// Updates every world, taking about `budget` in total. Returns `true` if
+    /// any world has work left.
+    pub fn update(&mut self, budget: Duration) -> SerialResult<bool> {
+        let count = self.worlds.len();
+        if count == 0 {
+            return Ok(false);
+        }
+
+        let deadline = Instant::now() + budget;
+        let first = self.next % count;
+        self.next = (first + 1) % count;
+
+        let mut behind = false;
+        for turn in 0..count {
+            let hosted = &mut self.worlds[(first + turn) % count];
+            let left = deadline.saturating_duration_since(Instant::now());
+            let share = left / (count - turn) as u32;
+            hosted.behind = hosted.world.update_within(share)?;
+            behind |= hosted.behind;
+        }
+        Ok(behind)
+    }
+
+    /// Saves every world while they keep running.
+    pub fn autosave(&mut self) -> SerialResult<()> {
+        for hosted in self.worlds.iter_mut() {
+            hosted.world.autosave()?;
+        }
+        Ok(())
+    }
+
+    /// Saves and unloads every world.
+    pub fn shut_down(&mut self) -> SerialResult<()> {
+        for hosted in self.worlds.iter_mut() {
+            hosted.world.shut_down()?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::cell::RefCell;
+    use std::rc::Rc;
+
+    struct FakeWorld {
+        name: &'static str,
+        log: Rc<RefCell<Vec<(&'static str, Duration)>>>,
+        busy: bool,
+    }
+
+    impl HostedWorld for FakeWorld {
+        fn update_within(&mut self, budget: Duration) -> SerialResult<bool> {
+            self.log.borrow_mut().push((self.name, budget));
+            Ok(self.busy)
+        }
+
+        fn autosave(&mut self) -> SerialResult<()> { Ok(()) }
+        fn shut_down(&mut self) -> SerialResult<()> { Ok(()) }
+    }
+
+    #[test]
+    fn test_fair_shares() {
+        let log = Rc::new(RefCell::new(Vec::new()));
+        let mut registry = WorldRegistry::new();
+        for &(name, busy) in [("overworld", true), ("nether", false)].iter() {
+            registry.insert(name, FakeWorld { name: name, log: log.clone(), busy: busy });
+        }
+
+        let budget = Duration::from_secs(10);
+        assert!(registry.update(budget).unwrap());
+        assert!(registry.update(budget).unwrap());
+        assert_eq!(registry.behind(), vec!["overworld"]);
+
+        let log = log.borrow();
+        let order: Vec<_> = log.iter().map(|&(name, _)| name).collect();
+        assert_eq!(order, vec!["overworld", "nether", "nether", "overworld"]);
+        // The first world gets half, the second what is left of the budget.
+        assert!(log[0].1 <= budget / 2 && log[0].1 > budget / 3);
+        assert!(log[1].1 > budget / 2);
+    }
+}
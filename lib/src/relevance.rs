@@ -0,0 +1,352 @@
+use std::collections::HashMap;
+
+use grid::ChunkArrangement;
+use hash::FxHashSet;
+use traits::Index;
+
+/// The area of chunks around an observer that is kept loaded.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum InterestShape {
+    /// All chunks within the given Manhattan distance.
+    Diamond(i32),
+    /// All chunks within the given Chebyshev distance.
+    Square(i32),
+    /// All chunks within the given Euclidean distance.
+    Circle(i32),
+    /// All chunks within the given distances along the x and y axes, for
+    /// viewports that are wider than they are tall. See
+    /// `InterestShape::viewport`.
+    Rect(i32, i32),
+}
+
+impl InterestShape {
+    /// Returns the largest distance along either axis this shape covers.
+    pub fn radius(&self) -> i32 {
+        match *self {
+            InterestShape::Diamond(r) |
+            InterestShape::Square(r)  |
+            InterestShape::Circle(r)  => r,
+            InterestShape::Rect(rx, ry) => rx.max(ry),
+        }
+    }
+
+    /// Returns the rectangle of chunks that covers a viewport of `width` by
+    /// `height` cells centered on the observer, plus `margin` cells on every
+    /// side, wherever the observer stands inside its chunk.
+    pub fn viewport(width: i32, height: i32, margin: i32, chunk_width: i32) -> Self {
+        let reach = |size: i32| {
+            let cells = (size.max(0) + 1) / 2 + margin.max(0);
+            (cells + chunk_width - 1) / chunk_width
+        };
+        InterestShape::Rect(reach(width), reach(height))
+    }
+
+    /// Returns whether the chunk at the given offset from the center is
+    /// inside this shape.
+    pub fn contains(&self, dx: i32, dy: i32) -> bool {
+        match *self {
+            InterestShape::Diamond(r) => dx.abs() + dy.abs() <= r,
+            InterestShape::Square(r)  => dx.abs() <= r && dy.abs() <= r,
+            InterestShape::Circle(r)  => dx * dx + dy * dy <= r * r,
+            InterestShape::Rect(rx, ry) => dx.abs() <= rx && dy.abs() <= ry,
+        }
+    }
+
+    /// Returns the indices of all chunks inside this shape centered on the
+    /// chunk at `center`. For strips, only the row through the center is
+    /// used.
+    pub fn indices<I: Index>(&self, center: &I) -> Vec<I> {
+        let r = self.radius();
+        let rows = match I::ARRANGEMENT {
+            ChunkArrangement::Grid    => r,
+            ChunkArrangement::Columns => 0,
+        };
+        let mut indices = Vec::new();
+        for dy in -rows..rows + 1 {
+            for dx in -r..r + 1 {
+                if self.contains(dx, dy) {
+                    indices.push(I::from_xy(center.x() + dx, center.y() + dy));
+                }
+            }
+        }
+        indices
+    }
+}
+
+/// A set of chunks that is kept loaded regardless of where observers are,
+/// such as the area around a spawn point.
+#[derive(Debug, Clone)]
+pub struct PinnedSet<I: Index> {
+    pub chunks: FxHashSet<I>,
+    /// Number of ticks between saves of the set's chunks, or `None` to only
+    /// save them along with the rest of the world.
+    pub save_interval: Option<u64>,
+    last_saved: u64,
+}
+
+/// A chunk entering or leaving the active set.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ChunkActivity<I: Index> {
+    Activated(I),
+    Deactivated(I),
+}
+
+/// Decides which chunks should be loaded, based on the positions of observers
+/// and on sets of chunks that are always kept loaded.
+///
+/// It can also track a smaller set of active chunks around the observers,
+/// where the game simulates entities, while the rest of the loaded chunks
+/// are only kept around to be drawn. Likewise, only chunks inside a detail
+/// shape may need to be fully generated, leaving the fringe as stubs.
+#[derive(Debug, Clone)]
+pub struct RelevancePolicy<I: Index> {
+    pub shape: InterestShape,
+    pub active_shape: Option<InterestShape>,
+    pub detail_shape: Option<InterestShape>,
+    pinned: HashMap<String, PinnedSet<I>>,
+    active: FxHashSet<I>,
+    viewport: Option<(i32, i32)>,
+}
+
+impl<I: Index> RelevancePolicy<I> {
+    pub fn new(shape: InterestShape) -> Self {
+        RelevancePolicy {
+            shape: shape,
+            active_shape: None,
+            detail_shape: None,
+            pinned: HashMap::new(),
+            active: FxHashSet::default(),
+            viewport: None,
+        }
+    }
+
+    /// Makes the shape follow the viewport given to `set_viewport`, extended
+    /// by `margin` cells on every side, in place of the shape given to `new`.
+    /// `chunk_width` is the width of a chunk in cells.
+    pub fn with_viewport(mut self, margin: i32, chunk_width: i32) -> Self {
+        assert!(chunk_width > 0, "Chunk width must be positive!");
+        self.viewport = Some((margin, chunk_width));
+        self
+    }
+
+    /// Sets the size of the viewport in cells, such as after the window was
+    /// resized. Does nothing unless the policy was made with `with_viewport`.
+    pub fn set_viewport(&mut self, width: i32, height: i32) {
+        if let Some((margin, chunk_width)) = self.viewport {
+            self.shape = InterestShape::viewport(width, height, margin, chunk_width);
+        }
+    }
+
+    /// Sets the shape of the area around observers whose chunks are active.
+    pub fn with_active_shape(mut self, shape: InterestShape) -> Self {
+        self.active_shape = Some(shape);
+        self
+    }
+
+    /// Sets the shape of the area around observers whose chunks need full
+    /// detail. Without one, every chunk does.
+    pub fn with_detail_shape(mut self, shape: InterestShape) -> Self {
+        self.detail_shape = Some(shape);
+        self
+    }
+
+    /// Returns whether the chunk at `index` needs full detail given the
+    /// chunks observers are standing in. Pinned chunks always do.
+    pub fn needs_detail(&self, observers: &[I], index: &I) -> bool {
+        let shape = match self.detail_shape {
+            Some(shape) => shape,
+            None        => return true,
+        };
+        observers.iter().any(|c| shape.contains(index.x() - c.x(), index.y() - c.y()))
+            || self.pinned.values().any(|set| set.chunks.contains(index))
+    }
+
+    /// Keeps the given chunks loaded under a name, replacing any set
+    /// previously pinned under that name.
+    pub fn pin<T>(&mut self, name: &str, chunks: T, save_interval: Option<u64>)
+        where T: IntoIterator<Item = I>
+    {
+        self.pinned.insert(name.to_string(), PinnedSet {
+            chunks: chunks.into_iter().collect(),
+            save_interval: save_interval,
+            last_saved: 0,
+        });
+    }
+
+    /// Stops keeping a named set of chunks loaded.
+    pub fn unpin(&mut self, name: &str) -> Option<PinnedSet<I>> {
+        self.pinned.remove(name)
+    }
+
+    pub fn pinned_set(&self, name: &str) -> Option<&PinnedSet<I>> {
+        self.pinned.get(name)
+    }
+
+    pub fn is_pinned(&self, index: &I) -> bool {
+        self.pinned.values().any(|set| set.chunks.contains(index))
+    }
+
+    /// Returns the chunks that should be loaded given the chunks observers
+    /// are standing in.
+    pub fn relevant(&self, observers: &[I]) -> FxHashSet<I> {
+        let mut relevant = FxHashSet::default();
+        for center in observers.iter() {
+            relevant.extend(self.shape.indices(center));
+        }
+        for set in self.pinned.values() {
+            relevant.extend(set.chunks.iter().cloned());
+        }
+        relevant
+    }
+
+    /// Returns the chunks that should be active given the chunks observers
+    /// are standing in. Without an active shape, no chunk is ever active.
+    pub fn active(&self, observers: &[I]) -> FxHashSet<I> {
+        let mut active = FxHashSet::default();
+        if let Some(shape) = self.active_shape {
+            for center in observers.iter() {
+                active.extend(shape.indices(center));
+            }
+        }
+        active
+    }
+
+    pub fn is_active(&self, index: &I) -> bool {
+        self.active.contains(index)
+    }
+
+    pub fn active_chunks(&self) -> &FxHashSet<I> {
+        &self.active
+    }
+
+    /// Replaces the set of active chunks and returns the chunks that left
+    /// it, followed by the chunks that entered it.
+    pub fn update_active(&mut self, active: FxHashSet<I>) -> Vec<ChunkActivity<I>> {
+        let mut changes: Vec<ChunkActivity<I>> = self.active.difference(&active)
+            .map(|i| ChunkActivity::Deactivated(i.clone()))
+            .collect();
+        changes.extend(active.difference(&self.active).map(|i| ChunkActivity::Activated(i.clone())));
+        self.active = active;
+        changes
+    }
+
+    /// Returns the chunks of every pinned set whose save interval has elapsed
+    /// at tick `now`, and records that they were saved.
+    pub fn due_for_save(&mut self, now: u64) -> Vec<I> {
+        let mut due = Vec::new();
+        for set in self.pinned.values_mut() {
+            if let Some(interval) = set.save_interval {
+                if now >= set.last_saved + interval {
+                    set.last_saved = now;
+                    due.extend(set.chunks.iter().cloned());
+                }
+            }
+        }
+        due
+    }
+}
+
+/// Computes extra chunks to keep loaded for gameplay reasons, like a
+/// running machine or a projectile in flight.
+pub type RelevanceProvider<I> = Box<dyn Fn() -> FxHashSet<I> + Send + Sync>;
+
+/// Named providers of chunks that must stay loaded besides those around
+/// observers. `ChunkedWorld::update_chunks` asks every provider for its
+/// chunks each time it runs and keeps their union loaded along with the
+/// relevant chunks.
+///
+/// Providers are closures, so they usually share the state they look at with
+/// the game through an `Arc`.
+pub struct RelevanceProviders<I: Index> {
+    providers: HashMap<String, RelevanceProvider<I>>,
+}
+
+impl<I: Index> RelevanceProviders<I> {
+    pub fn new() -> Self {
+        RelevanceProviders {
+            providers: HashMap::new(),
+        }
+    }
+
+    /// Registers a provider under a name, replacing any provider previously
+    /// registered under that name.
+    pub fn register<F>(&mut self, name: &str, provider: F)
+        where F: Fn() -> FxHashSet<I> + Send + Sync + 'static
+    {
+        self.providers.insert(name.to_string(), Box::new(provider));
+    }
+
+    /// Removes a provider. Returns `false` if none was registered under that
+    /// name.
+    pub fn unregister(&mut self, name: &str) -> bool {
+        self.providers.remove(name).is_some()
+    }
+
+    pub fn is_registered(&self, name: &str) -> bool {
+        self.providers.contains_key(name)
+    }
+
+    /// Returns the union of the chunks of every provider.
+    pub fn chunks(&self) -> FxHashSet<I> {
+        let mut chunks = FxHashSet::default();
+        for provider in self.providers.values() {
+            chunks.extend(provider());
+        }
+        chunks
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use traits::TestIndex;
+
+    #[test]
+    fn test_shapes() {
+        assert_eq!(InterestShape::Diamond(2).indices(&TestIndex(0, 0)).len(), 13);
+        assert_eq!(InterestShape::Square(1).indices(&TestIndex(5, 5)).len(), 9);
+        assert_eq!(InterestShape::Circle(1).indices(&TestIndex(0, 0)).len(), 5);
+
+        // An 80 by 24 terminal with 8 cells of margin and 32 cell chunks.
+        assert_eq!(InterestShape::viewport(80, 24, 8, 32), InterestShape::Rect(2, 1));
+        assert_eq!(InterestShape::Rect(2, 1).indices(&TestIndex(0, 0)).len(), 15);
+
+        let mut policy: RelevancePolicy<TestIndex> = RelevancePolicy::new(InterestShape::Square(1)).with_viewport(0, 16);
+        policy.set_viewport(100, 20);
+        assert_eq!(policy.shape, InterestShape::Rect(4, 1));
+    }
+
+    #[test]
+    fn test_active_transitions() {
+        let mut policy = RelevancePolicy::new(InterestShape::Square(2))
+            .with_active_shape(InterestShape::Square(0));
+
+        let active = policy.active(&[TestIndex(0, 0)]);
+        assert_eq!(policy.update_active(active), vec![ChunkActivity::Activated(TestIndex(0, 0))]);
+        assert!(policy.is_active(&TestIndex(0, 0)));
+
+        let active = policy.active(&[TestIndex(1, 0)]);
+        assert_eq!(policy.update_active(active),
+                   vec![ChunkActivity::Deactivated(TestIndex(0, 0)),
+                        ChunkActivity::Activated(TestIndex(1, 0))]);
+        assert!(policy.relevant(&[TestIndex(1, 0)]).is_superset(policy.active_chunks()));
+    }
+
+    #[test]
+    fn test_providers() {
+        use std::sync::{Arc, Mutex};
+
+        let machines = Arc::new(Mutex::new(vec![TestIndex(3, 3)]));
+        let mut providers = RelevanceProviders::new();
+        let shared = machines.clone();
+        providers.register("machines", move || shared.lock().unwrap().iter().cloned().collect());
+        providers.register("projectile", || vec![TestIndex(0, 0), TestIndex(3, 3)].into_iter().collect());
+        assert_eq!(providers.chunks().len(), 2);
+
+        machines.lock().unwrap().push(TestIndex(-1, 5));
+        assert!(providers.chunks().contains(&TestIndex(-1, 5)));
+        assert!(providers.unregister("projectile"));
+        assert!(!providers.unregister("projectile"));
+        assert_eq!(providers.chunks().len(), 2);
+    }
+}
@@ -0,0 +1,142 @@
+use std::collections::HashMap;
+
+use seed::mix64;
+
+/// The names of the kinds of cells used in a saved chunk. Cells store an
+/// index into the palette instead of the kind itself, so kinds can be
+/// renamed or reordered by later versions of a game without breaking old
+/// saves.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Palette {
+    names: Vec<String>,
+}
+
+impl Palette {
+    pub fn new() -> Self {
+        Palette::default()
+    }
+
+    /// Returns the id of a kind, adding it to the palette if needed.
+    pub fn insert(&mut self, name: &str) -> u16 {
+        if let Some(id) = self.id(name) {
+            return id;
+        }
+        self.names.push(name.to_string());
+        (self.names.len() - 1) as u16
+    }
+
+    pub fn id(&self, name: &str) -> Option<u16> {
+        self.names.iter().position(|n| n == name).map(|i| i as u16)
+    }
+
+    pub fn name(&self, id: u16) -> Option<&str> {
+        self.names.get(id as usize).map(|n| n.as_str())
+    }
+
+    pub fn names(&self) -> &[String] {
+        &self.names
+    }
+
+    pub fn len(&self) -> usize {
+        self.names.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.names.is_empty()
+    }
+}
+
+/// Renames kinds of cells in chunks saved by an older version of a game as
+/// they are loaded.
+///
+/// A kind can be renamed, or split into several kinds, in which case each
+/// cell of the old kind picks one of the new kinds.
+#[derive(Debug, Clone, Default)]
+pub struct CellRemap {
+    rules: HashMap<String, Vec<String>>,
+}
+
+impl CellRemap {
+    pub fn new() -> Self {
+        CellRemap::default()
+    }
+
+    pub fn rename(self, old: &str, new: &str) -> Self {
+        self.split(old, &[new])
+    }
+
+    /// Replaces every cell of kind `old` with one of the kinds in `new`. The
+    /// choice depends only on the seed passed to `apply` and the cell's
+    /// position in the chunk, so it is the same on every load.
+    pub fn split(mut self, old: &str, new: &[&str]) -> Self {
+        assert!(!new.is_empty(), "A kind must be remapped to at least one kind!");
+        self.rules.insert(old.to_string(), new.iter().map(|n| n.to_string()).collect());
+        self
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.rules.is_empty()
+    }
+
+    /// Returns whether the remap changes any kind in the palette.
+    pub fn affects(&self, palette: &Palette) -> bool {
+        palette.names().iter().any(|n| self.rules.contains_key(n))
+    }
+
+    /// Rewrites a palette and the ids of the cells using it. `seed` picks
+    /// the new kind of cells whose kind was split; use a different seed for
+    /// each chunk, like one from `chunk_seed`.
+    pub fn apply(&self, palette: &mut Palette, ids: &mut [u16], seed: u64) {
+        if !self.affects(palette) {
+            return;
+        }
+
+        let mut remapped = Palette::new();
+        let targets: Vec<Vec<u16>> = palette.names().iter()
+            .map(|name| match self.rules.get(name) {
+                Some(new) => new.iter().map(|n| remapped.insert(n)).collect(),
+                None      => vec![remapped.insert(name)],
+            })
+            .collect();
+
+        for (i, id) in ids.iter_mut().enumerate() {
+            let choices = match targets.get(*id as usize) {
+                Some(c) => c,
+                None    => continue,
+            };
+            *id = if choices.len() == 1 {
+                choices[0]
+            } else {
+                choices[(mix64(seed ^ i as u64) % choices.len() as u64) as usize]
+            };
+        }
+        *palette = remapped;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_remap() {
+        let mut palette = Palette::new();
+        let floor = palette.insert("Floor");
+        let tree = palette.insert("Tree");
+        let mut ids = vec![tree; 64];
+        ids[0] = floor;
+
+        let remap = CellRemap::new()
+            .rename("Floor", "Grass")
+            .split("Tree", &["Oak", "Pine"]);
+        let mut again = (palette.clone(), ids.clone());
+        remap.apply(&mut palette, &mut ids, 7);
+        remap.apply(&mut again.0, &mut again.1, 7);
+
+        assert_eq!(palette.name(ids[0]), Some("Grass"));
+        assert!(ids[1..].iter().all(|&id| palette.name(id) == Some("Oak") || palette.name(id) == Some("Pine")));
+        assert!(ids.iter().any(|&id| palette.name(id) == Some("Pine")));
+        assert_eq!(ids, again.1);
+        assert!(!remap.affects(&palette));
+    }
+}
@@ -0,0 +1,218 @@
+use std::collections::HashMap;
+use std::fs::{self, File};
+use std::io::Write;
+use std::net::ToSocketAddrs;
+
+use error::{self, SerialResult};
+use layout::RegionLayout;
+use managed_region::{hash_bytes, ManagedRegion};
+use platform::replace_file;
+use region::{Region, RegionIndex};
+use server::{ChunkClient, ClientMessage, ServerMessage};
+use traits::{ChunkKey, ManagedChunk, RegionManager};
+
+/// A region manager that fetches region files from a `ChunkServer` serving
+/// regions, so a client can run the same `ChunkedWorld` code as the server
+/// with its chunks coming over the network.
+///
+/// Fetched regions are cached in the region files of a local layout, which
+/// the world then reads and writes as usual. A region is fetched again every
+/// time it is opened, unless the cached copy is unchanged on the server.
+/// Chunks saved by the client only go to its cache, and are lost if the
+/// region changed on the server by the next time it is opened.
+///
+/// If the server can't be reached, the backend goes offline and keeps using
+/// the cache. Regions that were never cached can't be opened then.
+pub struct RemoteRegionBackend<I: ChunkKey> {
+    client: Option<ChunkClient>,
+    layout: RegionLayout,
+    regions: HashMap<RegionIndex, Region<I>>,
+}
+
+impl<I: ChunkKey> RemoteRegionBackend<I> {
+    /// Connects to a server, caching its regions under `cache`.
+    pub fn connect<A: ToSocketAddrs>(addr: A, cache: RegionLayout) -> SerialResult<Self> {
+        Ok(RemoteRegionBackend {
+            client: Some(ChunkClient::connect(addr)?),
+            layout: cache,
+            regions: HashMap::new(),
+        })
+    }
+
+    /// Creates a backend that only uses the regions cached under `cache`,
+    /// such as for playing while the server is down.
+    pub fn offline(cache: RegionLayout) -> Self {
+        RemoteRegionBackend {
+            client: None,
+            layout: cache,
+            regions: HashMap::new(),
+        }
+    }
+
+    pub fn is_online(&self) -> bool {
+        self.client.is_some()
+    }
+
+    /// Updates the cached copy of a region from the server.
+    fn fetch(&mut self, index: &RegionIndex) -> SerialResult<()> {
+        let path = self.layout.region_path(index);
+        let cached = fs::read(&path).ok().map(|data| hash_bytes(&data));
+        let fetched = match self.client {
+            Some(ref mut client) => request_region(client, index, cached),
+            None                 => return Ok(()),
+        };
+
+        let data = match fetched {
+            Ok(Some(data)) => data,
+            Ok(None) => return Ok(()),
+            Err(e) => {
+                self.client = None;
+                return if cached.is_some() { Ok(()) } else { Err(e) };
+            },
+        };
+
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        let temp = path.with_extension("tmp");
+        {
+            let mut file = File::create(&temp)?;
+            let written = file.write_all(&data).and_then(|_| file.sync_all());
+            if let Err(e) = written {
+                let _ = fs::remove_file(&temp);
+                return Err(error::write_error(e));
+            }
+        }
+        replace_file(&temp, &path)?;
+        Ok(())
+    }
+}
+
+/// Asks the server for a region. Returns `None` if the cached copy should be
+/// kept, because it is unchanged or the server has no such region.
+fn request_region(client: &mut ChunkClient, index: &RegionIndex, cached: Option<u64>) -> SerialResult<Option<Vec<u8>>> {
+    let RegionIndex(x, y) = *index;
+    client.send(&ClientMessage::FetchRegion(x, y, cached))?;
+    loop {
+        match client.recv()? {
+            ServerMessage::Region(rx, ry, data) if (rx, ry) == (x, y) => return Ok(data),
+            ServerMessage::RegionUnchanged(rx, ry) if (rx, ry) == (x, y) => return Ok(None),
+            _ => (),
+        }
+    }
+}
+
+impl<'a, I, C> RegionManager<'a, I, C> for RemoteRegionBackend<I>
+    where I: ChunkKey,
+          C: ManagedChunk,
+          Region<I>: ManagedRegion<'a, I, C>
+{
+    fn load(&mut self, index: RegionIndex) -> SerialResult<()> {
+        self.fetch(&index)?;
+        let path = self.layout.region_path(&index);
+        let handle = <Region<I> as ManagedRegion<'a, I, C>>::get_region_file(path)?;
+        self.regions.insert(index, Region::new(handle));
+        Ok(())
+    }
+
+    fn get(&mut self, index: &RegionIndex) -> Option<&Region<I>> {
+        self.regions.get(index)
+    }
+
+    fn get_mut(&mut self, index: &RegionIndex) -> Option<&mut Region<I>> {
+        self.regions.get_mut(index)
+    }
+
+    fn remove(&mut self, index: &RegionIndex) {
+        self.regions.remove(index);
+    }
+
+    fn region_loaded(&self, index: &RegionIndex) -> bool {
+        self.regions.contains_key(index)
+    }
+
+    fn region_indices(&self) -> Vec<RegionIndex> {
+        self.regions.keys().cloned().collect()
+    }
+
+    fn layout(&self) -> &RegionLayout {
+        &self.layout
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::env;
+    use std::sync::Arc;
+    use std::sync::atomic::{AtomicBool, Ordering};
+    use std::thread;
+    use std::time::Duration;
+    use attachment::{AttachedChunk, Attachments};
+    use diff::DiffableChunk;
+    use metadata::ChunkMeta;
+    use region::RegionLocalIndex;
+    use server::ChunkServer;
+    use traits::TestIndex;
+
+    #[derive(Debug, PartialEq, Serialize, Deserialize)]
+    struct TestChunk(u8);
+
+    impl ManagedChunk for TestChunk {
+        const SECTOR_SIZE: usize = 64;
+        const REGION_WIDTH: i32 = 4;
+    }
+
+    impl DiffableChunk for TestChunk {
+        type Cell = u8;
+        fn cells(&self) -> &[u8] { &[] }
+        fn cells_mut(&mut self) -> &mut [u8] { &mut [] }
+    }
+
+    type Saved = AttachedChunk<TestChunk>;
+    type R = Region<RegionLocalIndex>;
+
+    #[test]
+    fn test_fetch_region() {
+        let root = env::temp_dir().join("infinigen-test-remote");
+        let _ = fs::remove_dir_all(&root);
+        let (served, cache) = (RegionLayout::new(root.join("server")), RegionLayout::new(root.join("client")));
+        let slot = RegionLocalIndex(1, 2);
+        {
+            let file = <R as ManagedRegion<RegionLocalIndex, Saved>>::get_region_file(served.region_path(&RegionIndex(0, 0))).unwrap();
+            let mut region: R = Region::new(file);
+            let chunk = AttachedChunk { chunk: TestChunk(42), attachments: Attachments::new() };
+            let (data, _) = <R as ManagedRegion<RegionLocalIndex, Saved>>::encode_chunk(&chunk, &ChunkMeta::default()).unwrap();
+            <R as ManagedRegion<RegionLocalIndex, Saved>>::write_encoded(&mut region, data, &slot).unwrap();
+        }
+
+        let server: ChunkServer<TestIndex, TestChunk> = ChunkServer::bind("127.0.0.1:0").unwrap();
+        let mut server = server.with_regions(served);
+        let addr = server.local_addr().unwrap();
+        let done = Arc::new(AtomicBool::new(false));
+        let stop = done.clone();
+        let serving = thread::spawn(move || {
+            while !stop.load(Ordering::SeqCst) {
+                server.poll(|_| None).unwrap();
+                thread::sleep(Duration::from_millis(1));
+            }
+        });
+
+        let mut backend = RemoteRegionBackend::connect(addr, cache.clone()).unwrap();
+        for _ in 0..2 {
+            {
+                let region = <RemoteRegionBackend<RegionLocalIndex> as RegionManager<RegionLocalIndex, Saved>>::get_for_chunk(&mut backend, &slot).unwrap();
+                let (chunk, _) = <R as ManagedRegion<RegionLocalIndex, Saved>>::read_chunk(region, &slot).unwrap();
+                assert_eq!(chunk.chunk, TestChunk(42));
+            }
+            // Opened again, the cached copy is kept.
+            <RemoteRegionBackend<RegionLocalIndex> as RegionManager<RegionLocalIndex, Saved>>::remove(&mut backend, &RegionIndex(0, 0));
+        }
+        assert!(backend.is_online());
+        assert!(cache.region_path(&RegionIndex(0, 0)).exists());
+
+        done.store(true, Ordering::SeqCst);
+        serving.join().unwrap();
+        fs::remove_dir_all(&root).unwrap();
+    }
+}
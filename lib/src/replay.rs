@@ -0,0 +1,112 @@
+use std::fs::{File, OpenOptions};
+use std::io::{BufReader, BufWriter, Write};
+use std::path::Path;
+
+use bincode::{self, Infinite};
+
+use error::SerialResult;
+use traits::Index;
+
+/// What a world did with a chunk.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum ResidencyAction {
+    /// The chunk was read from its region file.
+    Load,
+    /// The chunk didn't exist and was generated.
+    Generate,
+    /// The chunk was saved and removed from the world.
+    Unload,
+}
+
+/// A single residency decision, made at a tick of the world clock.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ResidencyRecord {
+    pub tick: u64,
+    pub action: ResidencyAction,
+    pub chunk: (i32, i32),
+}
+
+impl ResidencyRecord {
+    pub fn index<I: Index>(&self) -> I {
+        I::from_xy(self.chunk.0, self.chunk.1)
+    }
+}
+
+/// The size of a serialized `ResidencyRecord`.
+const RECORD_SIZE: u64 = 20;
+
+/// Appends every chunk a world loads, generates or unloads to a file, so the
+/// exact sequence can be replayed against a fresh world with
+/// `ChunkedWorld::replay` when debugging.
+#[derive(Debug)]
+pub struct ResidencyLog {
+    writer: BufWriter<File>,
+}
+
+impl ResidencyLog {
+    /// Starts a new log at the given path, replacing any existing one.
+    pub fn create<P: AsRef<Path>>(path: P) -> SerialResult<Self> {
+        let file = OpenOptions::new().write(true).create(true).truncate(true).open(path)?;
+        Ok(ResidencyLog {
+            writer: BufWriter::new(file),
+        })
+    }
+
+    pub fn record<I: Index>(&mut self, tick: u64, action: ResidencyAction, index: &I) -> SerialResult<()> {
+        let record = ResidencyRecord {
+            tick: tick,
+            action: action,
+            chunk: (index.x(), index.y()),
+        };
+        bincode::serialize_into(&mut self.writer, &record, Infinite)?;
+        Ok(())
+    }
+
+    /// Writes buffered records to the file.
+    pub fn flush(&mut self) -> SerialResult<()> {
+        self.writer.flush()?;
+        Ok(())
+    }
+
+    /// Reads every record of a log, in the order they were made.
+    pub fn read<P: AsRef<Path>>(path: P) -> SerialResult<Vec<ResidencyRecord>> {
+        let file = File::open(path)?;
+        let len = file.metadata()?.len();
+        let mut reader = BufReader::new(file);
+
+        // A record cut off by a crash is ignored.
+        let count = len / RECORD_SIZE;
+        let mut records = Vec::with_capacity(count as usize);
+        for _ in 0..count {
+            records.push(bincode::deserialize_from(&mut reader, Infinite)?);
+        }
+        Ok(records)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::env;
+    use std::fs;
+    use traits::TestIndex;
+
+    #[test]
+    fn test_round_trip() {
+        let path = env::temp_dir().join("infinigen-test-residency");
+        {
+            let mut log = ResidencyLog::create(&path).unwrap();
+            log.record(0, ResidencyAction::Generate, &TestIndex(1, -2)).unwrap();
+            log.record(3, ResidencyAction::Unload, &TestIndex(1, -2)).unwrap();
+            log.record(5, ResidencyAction::Load, &TestIndex(1, -2)).unwrap();
+            log.flush().unwrap();
+        }
+        assert_eq!(fs::metadata(&path).unwrap().len(), 3 * RECORD_SIZE);
+
+        let records = ResidencyLog::read(&path).unwrap();
+        assert_eq!(records.len(), 3);
+        assert_eq!(records[1], ResidencyRecord { tick: 3, action: ResidencyAction::Unload, chunk: (1, -2) });
+        assert_eq!(records[2].index::<TestIndex>(), TestIndex(1, -2));
+        fs::remove_file(&path).unwrap();
+    }
+}
@@ -0,0 +1,130 @@
+use hash::FxHashMap;
+use traits::ChunkKey;
+
+/// An event that should happen at a position inside a chunk once the world
+/// reaches a certain tick.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ScheduledEvent<P, E> {
+    pub tick: u64,
+    pub position: P,
+    pub payload: E,
+}
+
+/// The events of a chunk as they are saved alongside it, usually through an
+/// `Attachment`.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SavedEvents<P, E> {
+    /// The tick the chunk was unloaded at.
+    pub saved_at: u64,
+    pub events: Vec<ScheduledEvent<P, E>>,
+}
+
+/// What to do with the events of a chunk that is reloaded after time has
+/// passed.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CatchUp {
+    /// Keep the original ticks, so events that became due while the chunk was
+    /// unloaded are delivered on the next call to `due`, in tick order.
+    FastForward,
+    /// Delay every event by the time the chunk spent unloaded, as if time
+    /// stood still inside it.
+    Defer,
+}
+
+/// Per-chunk queues of scheduled events.
+///
+/// Events are only delivered for loaded chunks. When a chunk unloads, its
+/// queue is detached and saved with it; when it is loaded again, the queue is
+/// attached and caught up according to the `CatchUp` policy.
+pub struct EventScheduler<I: ChunkKey, P, E> {
+    queues: FxHashMap<I, Vec<ScheduledEvent<P, E>>>,
+    catch_up: CatchUp,
+}
+
+impl<I: ChunkKey, P, E> EventScheduler<I, P, E> {
+    pub fn new(catch_up: CatchUp) -> Self {
+        EventScheduler {
+            queues: FxHashMap::default(),
+            catch_up: catch_up,
+        }
+    }
+
+    /// Schedules an event in the chunk at `index`. If the chunk isn't loaded,
+    /// the event waits until the chunk's saved events are attached.
+    pub fn schedule(&mut self, index: I, tick: u64, position: P, payload: E) {
+        let queue = self.queues.entry(index).or_insert_with(Vec::new);
+        let at = queue.iter().position(|e| e.tick > tick).unwrap_or(queue.len());
+        queue.insert(at, ScheduledEvent {
+            tick: tick,
+            position: position,
+            payload: payload,
+        });
+    }
+
+    /// Returns the number of events pending for the chunk at `index`.
+    pub fn pending(&self, index: &I) -> usize {
+        self.queues.get(index).map_or(0, |q| q.len())
+    }
+
+    /// Removes and returns every event that is due at tick `now` in a chunk
+    /// for which `loaded` returns true, ordered by tick.
+    pub fn due<F>(&mut self, now: u64, loaded: F) -> Vec<(I, ScheduledEvent<P, E>)>
+        where F: Fn(&I) -> bool
+    {
+        let mut due = Vec::new();
+        for (index, queue) in self.queues.iter_mut() {
+            if !loaded(index) {
+                continue;
+            }
+            let count = queue.iter().take_while(|e| e.tick <= now).count();
+            for event in queue.drain(..count) {
+                due.push((index.clone(), event));
+            }
+        }
+        self.queues.retain(|_, q| !q.is_empty());
+        due.sort_by_key(|&(_, ref e)| e.tick);
+        due
+    }
+
+    /// Removes the queue of a chunk that is being unloaded at tick `now`.
+    pub fn detach(&mut self, index: &I, now: u64) -> Option<SavedEvents<P, E>> {
+        self.queues.remove(index).map(|events| SavedEvents {
+            saved_at: now,
+            events: events,
+        })
+    }
+
+    /// Restores the saved queue of a chunk that is being loaded at tick `now`.
+    pub fn attach(&mut self, index: &I, saved: SavedEvents<P, E>, now: u64) {
+        let elapsed = now.saturating_sub(saved.saved_at);
+        for mut event in saved.events.into_iter() {
+            if self.catch_up == CatchUp::Defer {
+                event.tick += elapsed;
+            }
+            self.schedule(index.clone(), event.tick, event.position, event.payload);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use traits::TestIndex as Idx;
+
+    #[test]
+    fn test_catch_up() {
+        for &(catch_up, expected) in [(CatchUp::FastForward, 1), (CatchUp::Defer, 0)].iter() {
+            let mut scheduler = EventScheduler::new(catch_up);
+            scheduler.schedule(Idx(0, 0), 50, (1, 1), "lava");
+            scheduler.schedule(Idx(0, 0), 10, (2, 2), "water");
+            assert_eq!(scheduler.due(10, |_| true).len(), 1);
+
+            let saved = scheduler.detach(&Idx(0, 0), 20).unwrap();
+            assert_eq!(scheduler.due(100, |_| true).len(), 0);
+
+            scheduler.attach(&Idx(0, 0), saved, 60);
+            assert_eq!(scheduler.due(60, |_| true).len(), expected);
+            assert_eq!(scheduler.pending(&Idx(0, 0)), 1 - expected);
+        }
+    }
+}
@@ -0,0 +1,212 @@
+use std::fmt;
+
+use error::SerialResult;
+use metadata::WorldMetadata;
+use traits::Index;
+
+const GOLDEN_GAMMA: u64 = 0x9e37_79b9_7f4a_7c15;
+
+/// Key the world seed is stored under in the world metadata.
+const SEED_KEY: &'static str = "seed";
+
+/// Scrambles a 64-bit value with the SplitMix64 finalizer.
+pub fn mix64(mut z: u64) -> u64 {
+    z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+    z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+    z ^ (z >> 31)
+}
+
+/// Hashes a feature tag like `"trees"` with 64-bit FNV-1a, which gives the
+/// same result on every platform and Rust version, unlike `DefaultHasher`.
+pub fn hash_tag(tag: &str) -> u64 {
+    let mut hash: u64 = 0xcbf2_9ce4_8422_2325;
+    for byte in tag.bytes() {
+        hash ^= byte as u64;
+        hash = hash.wrapping_mul(0x0000_0100_0000_01b3);
+    }
+    hash
+}
+
+/// Derives the seed of a generation pass in one chunk from the world seed,
+/// the chunk's position and a tag naming the pass.
+///
+/// Different tags give independent streams for the same chunk, so adding a
+/// pass doesn't change what the existing passes generate.
+pub fn chunk_seed(seed: u64, x: i32, y: i32, tag: &str) -> u64 {
+    let mut h = mix64(seed ^ hash_tag(tag));
+    h = mix64(h ^ (x as u32 as u64));
+    mix64(h ^ ((y as u32 as u64) << 32))
+}
+
+/// The seed of a world, from which every generation pass derives its own.
+///
+/// Passes that seed themselves with the same raw integer produce correlated
+/// output, like caves that always follow the biome borders. Deriving a sub-seed
+/// by name for each pass, as in `seed.derive("caves")`, keeps them independent
+/// and doesn't change the others when a pass is added. Derivation hashes the
+/// name with `hash_tag`, so it gives the same seeds on every platform and
+/// build. Serialized as the plain integer.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(transparent)]
+pub struct WorldSeed(u64);
+
+impl WorldSeed {
+    pub fn new(seed: u64) -> Self {
+        WorldSeed(seed)
+    }
+
+    pub fn value(&self) -> u64 {
+        self.0
+    }
+
+    /// Derives the seed of a sub-generator. Names can be chained, like
+    /// `seed.derive("caves").derive("ores")`.
+    pub fn derive(&self, name: &str) -> WorldSeed {
+        // Mixed once more than `chunk_seed`, so a derived seed is never the
+        // seed of a chunk pass with the same name.
+        WorldSeed(mix64(mix64(self.0) ^ hash_tag(name)))
+    }
+
+    /// Derives the seed of a generation pass in one chunk. See `chunk_seed`.
+    pub fn chunk<I: Index>(&self, index: &I, tag: &str) -> u64 {
+        chunk_seed(self.0, index.x(), index.y(), tag)
+    }
+
+    /// Creates the stream of a generation pass in one chunk. Same as
+    /// `SplitMix64::for_chunk` with the raw seed.
+    pub fn chunk_rng<I: Index>(&self, index: &I, tag: &str) -> SplitMix64 {
+        SplitMix64::for_chunk(self.0, index, tag)
+    }
+
+    /// Creates a stream seeded from this seed.
+    pub fn rng(&self) -> SplitMix64 {
+        SplitMix64::new(self.0)
+    }
+
+    /// Loads the seed stored in the world metadata, if any.
+    pub fn load(metadata: &WorldMetadata) -> SerialResult<Option<Self>> {
+        metadata.get(SEED_KEY)
+    }
+
+    /// Stores the seed in the world metadata.
+    pub fn save(&self, metadata: &mut WorldMetadata) -> SerialResult<()> {
+        metadata.insert(SEED_KEY, self)
+    }
+
+    /// Returns the seed stored in the world metadata, storing `seed` first
+    /// if the world doesn't have one yet.
+    pub fn resolve(metadata: &mut WorldMetadata, seed: WorldSeed) -> SerialResult<Self> {
+        if let Some(stored) = WorldSeed::load(metadata)? {
+            return Ok(stored);
+        }
+        seed.save(metadata)?;
+        Ok(seed)
+    }
+}
+
+impl From<u64> for WorldSeed {
+    fn from(seed: u64) -> Self {
+        WorldSeed(seed)
+    }
+}
+
+impl fmt::Display for WorldSeed {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+
+/// A small, fast random number generator with a fixed algorithm, so worlds
+/// generated from the same seed are identical across platforms and runs.
+///
+/// This is not suitable for cryptography.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SplitMix64 {
+    state: u64,
+}
+
+impl SplitMix64 {
+    pub fn new(seed: u64) -> Self {
+        SplitMix64 {
+            state: seed,
+        }
+    }
+
+    /// Creates the stream of a generation pass in a chunk. See `chunk_seed`.
+    pub fn for_chunk<I: Index>(seed: u64, index: &I, tag: &str) -> Self {
+        SplitMix64::new(chunk_seed(seed, index.x(), index.y(), tag))
+    }
+
+    pub fn next_u64(&mut self) -> u64 {
+        self.state = self.state.wrapping_add(GOLDEN_GAMMA);
+        mix64(self.state)
+    }
+
+    pub fn next_u32(&mut self) -> u32 {
+        (self.next_u64() >> 32) as u32
+    }
+
+    /// Returns a number in `[0, 1)`.
+    pub fn next_f64(&mut self) -> f64 {
+        (self.next_u64() >> 11) as f64 * (1.0 / (1u64 << 53) as f64)
+    }
+
+    /// Returns a number in `[low, high)`. Panics if the range is empty.
+    pub fn gen_range(&mut self, low: i32, high: i32) -> i32 {
+        assert!(low < high, "Empty range!");
+        let span = (high as i64 - low as i64) as u64;
+        (low as i64 + (self.next_u64() % span) as i64) as i32
+    }
+
+    /// Returns true with the given probability.
+    pub fn chance(&mut self, probability: f64) -> bool {
+        self.next_f64() < probability
+    }
+
+    /// Picks an element of a slice, or `None` if it is empty.
+    pub fn choose<'b, T>(&mut self, items: &'b [T]) -> Option<&'b T> {
+        if items.is_empty() {
+            return None;
+        }
+        let i = (self.next_u64() % items.len() as u64) as usize;
+        items.get(i)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use traits::TestIndex;
+
+    #[test]
+    fn test_stable_streams() {
+        assert_eq!(SplitMix64::new(0).next_u64(), 0xe220_a839_7b1d_cdaf);
+
+        let mut a = SplitMix64::for_chunk(42, &TestIndex(-3, 7), "trees");
+        let mut b = SplitMix64::for_chunk(42, &TestIndex(-3, 7), "trees");
+        let mut c = SplitMix64::for_chunk(42, &TestIndex(-3, 7), "ores");
+        let first = a.next_u64();
+        assert_eq!(first, b.next_u64());
+        assert!(first != c.next_u64());
+
+        for _ in 0..100 {
+            let n = a.gen_range(-5, 5);
+            assert!(n >= -5 && n < 5);
+        }
+    }
+
+    #[test]
+    fn test_world_seed() {
+        let seed = WorldSeed::new(42);
+        assert_eq!(seed.derive("caves"), WorldSeed::new(42).derive("caves"));
+        assert!(seed.derive("caves") != seed.derive("biomes"));
+        assert!(seed.derive("caves").derive("ores") != seed.derive("ores"));
+        assert!(seed.derive("caves").value() != chunk_seed(42, 0, 0, "caves"));
+        assert_eq!(seed.chunk(&TestIndex(-3, 7), "trees"), chunk_seed(42, -3, 7, "trees"));
+
+        let mut metadata = WorldMetadata::new();
+        assert_eq!(WorldSeed::resolve(&mut metadata, seed).unwrap(), seed);
+        assert_eq!(WorldSeed::resolve(&mut metadata, WorldSeed::new(7)).unwrap(), seed);
+        assert_eq!(metadata.get::<u64>(SEED_KEY).unwrap(), Some(42));
+    }
+}
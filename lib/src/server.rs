@@ -0,0 +1,575 @@
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::{self, Read, Write};
+use std::marker::PhantomData;
+use std::net::{SocketAddr, TcpListener, TcpStream, ToSocketAddrs};
+use std::sync::mpsc::{self, Receiver, Sender, TryRecvError};
+use std::thread;
+
+use bincode::{self, Infinite};
+use serde::Serialize;
+use serde::de::DeserializeOwned;
+
+use diff::{ChunkDiff, DiffableChunk};
+use error::SerialResult;
+use hash::FxHashSet;
+use layout::RegionLayout;
+use managed_region::hash_bytes;
+use region::RegionIndex;
+use traits::Index;
+
+/// The largest frame accepted from the other side, so a bad length prefix
+/// can't make the reader allocate gigabytes.
+pub const MAX_FRAME_SIZE: usize = 16 * 1024 * 1024;
+
+/// Clients that let this many bytes queue up unread are disconnected.
+const MAX_BACKLOG: usize = 4 * MAX_FRAME_SIZE;
+
+/// The largest request accepted from a client. Every `ClientMessage` is far
+/// smaller.
+const MAX_REQUEST_SIZE: usize = 256;
+
+/// The most bytes read from a client per poll. The rest is read on later
+/// polls.
+const MAX_INBOX: usize = 64 * 1024;
+
+/// Clients subscribing to more chunks than this are disconnected.
+pub const MAX_SUBSCRIPTIONS: usize = 4096;
+
+/// Clients asking for more region files than this before the earlier ones
+/// were sent are disconnected.
+const MAX_PENDING_REGIONS: usize = 8;
+
+/// A request sent to a `ChunkServer`. Chunks are addressed by the x and y of
+/// their index.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub enum ClientMessage {
+    /// Asks for a chunk once.
+    Fetch(i32, i32),
+    /// Asks for a chunk now and for a delta every time it changes.
+    Subscribe(i32, i32),
+    Unsubscribe(i32, i32),
+    /// Asks for the region file at a region index, unless its hash is the
+    /// given one. Used by `RemoteRegionBackend`.
+    FetchRegion(i32, i32, Option<u64>),
+}
+
+/// A reply or update sent by a `ChunkServer`.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub enum ServerMessage {
+    /// A whole chunk, encoded with bincode.
+    Chunk(i32, i32, Vec<u8>),
+    /// The cells of a subscribed chunk that changed since it was last sent.
+    Delta(i32, i32, ChunkDiff),
+    /// The chunk isn't loaded on the server. Subscribed chunks that are
+    /// unloaded are sent again in full once they are loaded.
+    NotLoaded(i32, i32),
+    /// A whole region file, or `None` if the server has no such region or
+    /// doesn't serve regions.
+    Region(i32, i32, Option<Vec<u8>>),
+    /// The region file has the hash the client asked with.
+    RegionUnchanged(i32, i32),
+}
+
+fn invalid_data(message: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message.to_string())
+}
+
+fn frame_len(prefix: &[u8]) -> io::Result<usize> {
+    let len = ((prefix[0] as usize) << 24) | ((prefix[1] as usize) << 16)
+        | ((prefix[2] as usize) << 8) | prefix[3] as usize;
+    if len > MAX_FRAME_SIZE {
+        return Err(invalid_data("frame too large"));
+    }
+    Ok(len)
+}
+
+/// Encodes a message as a frame: its length as a big-endian `u32`, followed
+/// by the message encoded with bincode.
+pub fn encode_frame<T: Serialize>(message: &T) -> SerialResult<Vec<u8>> {
+    let payload = bincode::serialize(message, Infinite)?;
+    if payload.len() > MAX_FRAME_SIZE {
+        return Err(invalid_data("frame too large").into());
+    }
+    let len = payload.len() as u32;
+    let mut frame = vec![(len >> 24) as u8, (len >> 16) as u8, (len >> 8) as u8, len as u8];
+    frame.extend(payload);
+    Ok(frame)
+}
+
+pub fn write_frame<W: Write, T: Serialize>(writer: &mut W, message: &T) -> SerialResult<()> {
+    writer.write_all(&encode_frame(message)?)?;
+    Ok(())
+}
+
+/// Reads a single frame, blocking until all of it arrived.
+pub fn read_frame<R: Read, T: DeserializeOwned>(reader: &mut R) -> SerialResult<T> {
+    let mut prefix = [0; 4];
+    reader.read_exact(&mut prefix)?;
+    let mut payload = vec![0; frame_len(&prefix)?];
+    reader.read_exact(&mut payload)?;
+    Ok(bincode::deserialize(&payload)?)
+}
+
+/// The cells of a chunk as last sent to a client, for diffing against.
+struct SentCells<T>(Vec<T>);
+
+impl<T: Serialize + DeserializeOwned> DiffableChunk for SentCells<T> {
+    type Cell = T;
+
+    fn cells(&self) -> &[T] { &self.0 }
+    fn cells_mut(&mut self) -> &mut [T] { &mut self.0 }
+}
+
+struct Client<I, T> {
+    id: u64,
+    stream: TcpStream,
+    inbox: Vec<u8>,
+    outbox: Vec<u8>,
+    /// The cells of every subscribed chunk when it was last sent, or `None`
+    /// if the client doesn't have it.
+    subscriptions: HashMap<I, Option<SentCells<T>>>,
+    /// The number of region files asked for that weren't read yet.
+    pending_regions: usize,
+}
+
+impl<I: Index, T> Client<I, T> {
+    fn new(id: u64, stream: TcpStream) -> io::Result<Self> {
+        stream.set_nonblocking(true)?;
+        stream.set_nodelay(true)?;
+        Ok(Client {
+            id: id,
+            stream: stream,
+            inbox: Vec::new(),
+            outbox: Vec::new(),
+            subscriptions: HashMap::new(),
+            pending_regions: 0,
+        })
+    }
+
+    /// Reads what the client sent so far, up to `MAX_INBOX` bytes, and
+    /// returns the complete messages.
+    fn receive(&mut self) -> io::Result<Vec<ClientMessage>> {
+        let mut buf = [0; 4096];
+        while self.inbox.len() < MAX_INBOX {
+            match self.stream.read(&mut buf) {
+                Ok(0) => return Err(io::ErrorKind::UnexpectedEof.into()),
+                Ok(n) => self.inbox.extend_from_slice(&buf[..n]),
+                Err(ref e) if e.kind() == io::ErrorKind::WouldBlock => break,
+                Err(ref e) if e.kind() == io::ErrorKind::Interrupted => continue,
+                Err(e) => return Err(e),
+            }
+        }
+
+        let mut messages = Vec::new();
+        while self.inbox.len() >= 4 {
+            let len = frame_len(&self.inbox[..4])?;
+            if len > MAX_REQUEST_SIZE {
+                return Err(invalid_data("request too large"));
+            }
+            if self.inbox.len() < 4 + len {
+                break;
+            }
+            let message = bincode::deserialize(&self.inbox[4..4 + len])
+                .map_err(|_| invalid_data("bad message"))?;
+            messages.push(message);
+            self.inbox.drain(..4 + len);
+        }
+        Ok(messages)
+    }
+
+    fn queue(&mut self, message: &ServerMessage) -> SerialResult<()> {
+        self.outbox.extend(encode_frame(message)?);
+        Ok(())
+    }
+
+    /// Writes as much of the queued messages as the client takes without
+    /// blocking.
+    fn flush(&mut self) -> io::Result<()> {
+        while !self.outbox.is_empty() {
+            match self.stream.write(&self.outbox) {
+                Ok(0) => return Err(io::ErrorKind::WriteZero.into()),
+                Ok(n) => { self.outbox.drain(..n); },
+                Err(ref e) if e.kind() == io::ErrorKind::WouldBlock => break,
+                Err(ref e) if e.kind() == io::ErrorKind::Interrupted => continue,
+                Err(e) => return Err(e),
+            }
+        }
+        if self.outbox.len() > MAX_BACKLOG {
+            return Err(io::Error::new(io::ErrorKind::Other, "client is reading too slowly"));
+        }
+        Ok(())
+    }
+}
+
+/// Reads region files on a thread of its own, so clients asking for them
+/// don't hold up `ChunkServer::poll`.
+struct RegionReader {
+    requests: Sender<(u64, RegionIndex, Option<u64>)>,
+    replies: Receiver<(u64, ServerMessage)>,
+}
+
+impl RegionReader {
+    fn spawn(layout: RegionLayout) -> Self {
+        let (request_tx, request_rx) = mpsc::channel::<(u64, RegionIndex, Option<u64>)>();
+        let (reply_tx, reply_rx) = mpsc::channel();
+        thread::spawn(move || {
+            for (client, index, hash) in request_rx.iter() {
+                let (x, y) = (index.0, index.1);
+                let reply = match read_region(&layout, &index) {
+                    Some(ref data) if hash == Some(hash_bytes(data)) => ServerMessage::RegionUnchanged(x, y),
+                    data => ServerMessage::Region(x, y, data),
+                };
+                if reply_tx.send((client, reply)).is_err() {
+                    break;
+                }
+            }
+        });
+        RegionReader {
+            requests: request_tx,
+            replies: reply_rx,
+        }
+    }
+}
+
+/// Serves the loaded chunks of a world to thin clients, like remote viewers,
+/// over TCP. Messages are sent as frames, see `encode_frame`.
+///
+/// Clients can fetch a chunk once or subscribe to it. Subscribed chunks are
+/// sent in full, then as a `ChunkDiff` of their cells every time they
+/// change. Deltas only cover the cells of chunks, not their entities.
+///
+/// The server never blocks and runs on the thread that owns the world:
+/// `poll` should be called once per tick. It only serves chunks that are
+/// loaded; use `subscribed_chunks` to keep the chunks clients watch loaded.
+/// Each client can subscribe to at most `MAX_SUBSCRIPTIONS` chunks.
+pub struct ChunkServer<I: Index, X: DiffableChunk> {
+    listener: TcpListener,
+    clients: Vec<Client<I, X::Cell>>,
+    next_client: u64,
+    regions: Option<RegionReader>,
+    _chunk: PhantomData<X>,
+}
+
+impl<I, X> ChunkServer<I, X>
+    where I: Index,
+          X: DiffableChunk + Serialize,
+          X::Cell: Clone
+{
+    pub fn bind<A: ToSocketAddrs>(addr: A) -> SerialResult<Self> {
+        let listener = TcpListener::bind(addr)?;
+        listener.set_nonblocking(true)?;
+        Ok(ChunkServer {
+            listener: listener,
+            clients: Vec::new(),
+            next_client: 0,
+            regions: None,
+            _chunk: PhantomData,
+        })
+    }
+
+    /// Also serves the region files under `layout`, for clients using a
+    /// `RemoteRegionBackend`. Region files are sent as they are on disk, so
+    /// chunks that are loaded are sent as they were last saved; save the
+    /// world in place now and then to keep them fresh. The files are read on
+    /// a thread of their own and sent on a later `poll`.
+    pub fn with_regions(mut self, layout: RegionLayout) -> Self {
+        self.regions = Some(RegionReader::spawn(layout));
+        self
+    }
+
+    /// Returns the address the server listens on, such as to find the port
+    /// picked when binding to port 0.
+    pub fn local_addr(&self) -> SerialResult<SocketAddr> {
+        Ok(self.listener.local_addr()?)
+    }
+
+    pub fn client_count(&self) -> usize {
+        self.clients.len()
+    }
+
+    /// Returns every chunk some client is subscribed to.
+    pub fn subscribed_chunks(&self) -> FxHashSet<I> {
+        self.clients.iter()
+            .flat_map(|c| c.subscriptions.keys().cloned())
+            .collect()
+    }
+
+    /// Accepts new clients, answers their requests and sends the changes to
+    /// their subscribed chunks. `chunk` returns the loaded chunk at an
+    /// index. Clients that disconnect, misbehave or can't be sent a reply are
+    /// dropped, without failing the poll.
+    ///
+    /// Finding the changes compares every cell of every subscribed chunk, so
+    /// keep the number of subscriptions reasonable.
+    pub fn poll<'c, F>(&mut self, chunk: F) -> SerialResult<()>
+        where F: Fn(&I) -> Option<&'c X>,
+              X: 'c
+    {
+        loop {
+            match self.listener.accept() {
+                Ok((stream, _)) => {
+                    if let Ok(client) = Client::new(self.next_client, stream) {
+                        self.next_client += 1;
+                        self.clients.push(client);
+                    }
+                },
+                Err(ref e) if e.kind() == io::ErrorKind::WouldBlock => break,
+                Err(ref e) if e.kind() == io::ErrorKind::Interrupted => continue,
+                Err(e) => return Err(e.into()),
+            }
+        }
+
+        let mut failed = Vec::new();
+        if let Some(ref reader) = self.regions {
+            loop {
+                match reader.replies.try_recv() {
+                    Ok((id, reply)) => {
+                        // Replies to clients that were dropped are discarded.
+                        if let Some(client) = self.clients.iter_mut().find(|c| c.id == id) {
+                            client.pending_regions -= 1;
+                            if client.queue(&reply).is_err() {
+                                failed.push(id);
+                            }
+                        }
+                    },
+                    Err(TryRecvError::Empty) => break,
+                    Err(TryRecvError::Disconnected) => return Err(io::Error::new(io::ErrorKind::Other, "the region reader stopped").into()),
+                }
+            }
+        }
+
+        let mut i = 0;
+        while i < self.clients.len() {
+            if failed.contains(&self.clients[i].id) {
+                self.clients.remove(i);
+                continue;
+            }
+            match Self::serve(&mut self.clients[i], &chunk, self.regions.as_ref()) {
+                Ok(true) => i += 1,
+                _        => { self.clients.remove(i); },
+            }
+        }
+        Ok(())
+    }
+
+    /// Serves one client. Returns `false` if it should be dropped.
+    fn serve<'c, F>(client: &mut Client<I, X::Cell>, chunk: &F, regions: Option<&RegionReader>) -> SerialResult<bool>
+        where F: Fn(&I) -> Option<&'c X>,
+              X: 'c
+    {
+        let messages = match client.receive() {
+            Ok(m) => m,
+            Err(_) => return Ok(false),
+        };
+        for message in messages.into_iter() {
+            match message {
+                ClientMessage::Fetch(x, y) => {
+                    let reply = match chunk(&I::from_xy(x, y)) {
+                        Some(c) => ServerMessage::Chunk(x, y, bincode::serialize(c, Infinite)?),
+                        None    => ServerMessage::NotLoaded(x, y),
+                    };
+                    client.queue(&reply)?;
+                },
+                ClientMessage::Subscribe(x, y) => {
+                    let index = I::from_xy(x, y);
+                    if client.subscriptions.len() >= MAX_SUBSCRIPTIONS && !client.subscriptions.contains_key(&index) {
+                        return Ok(false);
+                    }
+                    if chunk(&index).is_none() {
+                        client.queue(&ServerMessage::NotLoaded(x, y))?;
+                    }
+                    client.subscriptions.insert(index, None);
+                },
+                ClientMessage::Unsubscribe(x, y) => {
+                    client.subscriptions.remove(&I::from_xy(x, y));
+                },
+                ClientMessage::FetchRegion(x, y, hash) => {
+                    match regions {
+                        Some(reader) => {
+                            if client.pending_regions >= MAX_PENDING_REGIONS {
+                                return Ok(false);
+                            }
+                            client.pending_regions += 1;
+                            if reader.requests.send((client.id, RegionIndex(x, y), hash)).is_err() {
+                                return Ok(false);
+                            }
+                        },
+                        None => client.queue(&ServerMessage::Region(x, y, None))?,
+                    }
+                },
+            }
+        }
+
+        let indices: Vec<I> = client.subscriptions.keys().cloned().collect();
+        for index in indices.into_iter() {
+            let (x, y) = (index.x(), index.y());
+            let current = chunk(&index);
+            let sent = client.subscriptions.get_mut(&index).unwrap().take();
+            let (update, now) = match (current, sent) {
+                (Some(c), None) => {
+                    let data = bincode::serialize(c, Infinite)?;
+                    (Some(ServerMessage::Chunk(x, y, data)), Some(SentCells(c.cells().to_vec())))
+                },
+                (Some(c), Some(ref old)) if old.0.len() != c.cells().len() => {
+                    // Chunks whose size changed are sent again in full.
+                    let data = bincode::serialize(c, Infinite)?;
+                    (Some(ServerMessage::Chunk(x, y, data)), Some(SentCells(c.cells().to_vec())))
+                },
+                (Some(c), Some(old)) => {
+                    let new = SentCells(c.cells().to_vec());
+                    let diff = ChunkDiff::between(&old, &new)?;
+                    let update = if diff.is_empty() { None } else { Some(ServerMessage::Delta(x, y, diff)) };
+                    (update, Some(new))
+                },
+                (None, Some(_)) => (Some(ServerMessage::NotLoaded(x, y)), None),
+                (None, None)    => (None, None),
+            };
+            if let Some(update) = update {
+                client.queue(&update)?;
+            }
+            client.subscriptions.insert(index, now);
+        }
+
+        Ok(client.flush().is_ok())
+    }
+}
+
+/// Reads a region file to send it, unless it is missing or too large for a
+/// frame.
+fn read_region(layout: &RegionLayout, index: &RegionIndex) -> Option<Vec<u8>> {
+    let mut data = Vec::new();
+    File::open(layout.region_path(index)).and_then(|mut f| f.read_to_end(&mut data)).ok()?;
+    // Leaves room for the rest of the message.
+    if data.len() > MAX_FRAME_SIZE - 64 {
+        return None;
+    }
+    Some(data)
+}
+
+/// A blocking connection to a `ChunkServer`, for thin clients and tools.
+pub struct ChunkClient {
+    stream: TcpStream,
+}
+
+impl ChunkClient {
+    pub fn connect<A: ToSocketAddrs>(addr: A) -> SerialResult<Self> {
+        let stream = TcpStream::connect(addr)?;
+        stream.set_nodelay(true)?;
+        Ok(ChunkClient {
+            stream: stream,
+        })
+    }
+
+    pub fn send(&mut self, message: &ClientMessage) -> SerialResult<()> {
+        write_frame(&mut self.stream, message)
+    }
+
+    /// Waits for the next message from the server.
+    pub fn recv(&mut self) -> SerialResult<ServerMessage> {
+        read_frame(&mut self.stream)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::thread;
+    use std::time::{Duration, Instant};
+    use traits::TestIndex;
+
+    #[derive(Serialize, Deserialize)]
+    struct Cells(Vec<u8>);
+
+    impl DiffableChunk for Cells {
+        type Cell = u8;
+        fn cells(&self) -> &[u8] { &self.0 }
+        fn cells_mut(&mut self) -> &mut [u8] { &mut self.0 }
+    }
+
+    fn poll_until_sent(server: &mut ChunkServer<TestIndex, Cells>, chunk: &Cells) {
+        for _ in 0..20 {
+            server.poll(|i| if *i == TestIndex(1, 2) { Some(chunk) } else { None }).unwrap();
+            thread::sleep(Duration::from_millis(5));
+        }
+    }
+
+    /// Polls the server until a whole frame reached the client and reads it,
+    /// failing instead of blocking forever if none arrives.
+    fn recv(client: &mut ChunkClient, server: &mut ChunkServer<TestIndex, Cells>, chunk: &Cells) -> ServerMessage {
+        let deadline = Instant::now() + Duration::from_secs(10);
+        client.stream.set_read_timeout(Some(Duration::from_millis(5))).unwrap();
+        loop {
+            server.poll(|i| if *i == TestIndex(1, 2) { Some(chunk) } else { None }).unwrap();
+            let mut prefix = [0; 4];
+            if let Ok(4) = client.stream.peek(&mut prefix) {
+                let mut frame = vec![0; 4 + frame_len(&prefix).unwrap()];
+                if client.stream.peek(&mut frame).ok() == Some(frame.len()) {
+                    return client.recv().unwrap();
+                }
+            }
+            assert!(Instant::now() < deadline, "no message from the server");
+        }
+    }
+
+    #[test]
+    fn test_subscribe() {
+        let mut server: ChunkServer<TestIndex, Cells> = ChunkServer::bind("127.0.0.1:0").unwrap();
+        let mut client = ChunkClient::connect(server.local_addr().unwrap()).unwrap();
+        client.send(&ClientMessage::Fetch(0, 0)).unwrap();
+        client.send(&ClientMessage::Subscribe(1, 2)).unwrap();
+
+        let mut chunk = Cells(vec![1, 2, 3]);
+        poll_until_sent(&mut server, &chunk);
+        assert_eq!(server.client_count(), 1);
+        assert_eq!(recv(&mut client, &mut server, &chunk), ServerMessage::NotLoaded(0, 0));
+        let mut copy: Cells = match recv(&mut client, &mut server, &chunk) {
+            ServerMessage::Chunk(1, 2, data) => bincode::deserialize(&data).unwrap(),
+            other => panic!("unexpected {:?}", other),
+        };
+
+        chunk.0[1] = 7;
+        match recv(&mut client, &mut server, &chunk) {
+            ServerMessage::Delta(1, 2, diff) => diff.apply(&mut copy).unwrap(),
+            other => panic!("unexpected {:?}", other),
+        }
+        assert_eq!(copy.0, vec![1, 7, 3]);
+    }
+
+    #[test]
+    fn test_bad_clients_dropped() {
+        let mut server: ChunkServer<TestIndex, Cells> = ChunkServer::bind("127.0.0.1:0").unwrap();
+        let addr = server.local_addr().unwrap();
+        let mut good = ChunkClient::connect(addr).unwrap();
+        good.send(&ClientMessage::Fetch(1, 2)).unwrap();
+
+        // A frame larger than any request.
+        let mut large = ChunkClient::connect(addr).unwrap();
+        large.stream.write_all(&[0, 0, 4, 0]).unwrap();
+        large.stream.write_all(&[0; 1024]).unwrap();
+
+        let mut greedy = ChunkClient::connect(addr).unwrap();
+        for x in 0..MAX_SUBSCRIPTIONS as i32 + 1 {
+            greedy.send(&ClientMessage::Subscribe(x, 100)).unwrap();
+        }
+
+        let chunk = Cells(vec![1, 2, 3]);
+        poll_until_sent(&mut server, &chunk);
+        assert_eq!(server.client_count(), 1);
+        assert!(server.subscribed_chunks().is_empty());
+        match recv(&mut good, &mut server, &chunk) {
+            ServerMessage::Chunk(1, 2, _) => (),
+            other => panic!("unexpected {:?}", other),
+        }
+
+        // A chunk that changed size is sent again in full.
+        good.send(&ClientMessage::Subscribe(1, 2)).unwrap();
+        for chunk in [chunk, Cells(vec![1, 2])].iter() {
+            match recv(&mut good, &mut server, chunk) {
+                ServerMessage::Chunk(1, 2, _) => (),
+                other => panic!("unexpected {:?}", other),
+            }
+        }
+        assert_eq!(server.client_count(), 1);
+    }
+}
@@ -0,0 +1,217 @@
+use std::hash::Hasher;
+use std::ops::{Deref, DerefMut};
+use std::sync::{RwLock, RwLockReadGuard, RwLockWriteGuard};
+
+use hash::{FxHashMap, FxHasher};
+use traits::ChunkKey;
+
+/// Loaded chunks split across several `RwLock`ed maps, so worker threads,
+/// like those of a rayon pool running pathfinding or AI, can read chunks in
+/// parallel while the main thread loads and unloads others. Share it between
+/// threads with an `Arc` and use it to back a `ChunkedTerrain`.
+///
+/// # Locking order
+///
+/// A chunk's shard is locked for as long as a guard to it is held, and
+/// loading or unloading a chunk write-locks its shard. To avoid deadlocks:
+///
+/// 1. Don't hold a guard on the thread that loads and unloads chunks while
+///    calling `ChunkedWorld::update_chunks` or anything else that inserts or
+///    removes chunks. Workers may hold guards during an update; the update
+///    waits for them.
+/// 2. To read several chunks at once, use `read_many`, which locks their
+///    shards in ascending order. Never take a second guard while holding one.
+/// 3. Keep guards short. A worker holding a guard across a long computation
+///    stalls the unloading of every chunk in the same shard.
+///
+/// A lock poisoned by a panicking thread is recovered, since chunks are
+/// only ever replaced whole.
+pub struct ShardedChunks<I: ChunkKey, C> {
+    shards: Vec<RwLock<FxHashMap<I, C>>>,
+}
+
+impl<I: ChunkKey, C> ShardedChunks<I, C> {
+    /// Creates an empty map with the given number of shards. More shards
+    /// mean less contention, at the cost of slower iteration.
+    pub fn new(shards: usize) -> Self {
+        assert!(shards > 0, "Need at least one shard!");
+        ShardedChunks {
+            shards: (0..shards).map(|_| RwLock::new(FxHashMap::default())).collect(),
+        }
+    }
+
+    pub fn shard_count(&self) -> usize {
+        self.shards.len()
+    }
+
+    /// Returns the number of the shard holding the chunk at `index`.
+    pub fn shard_of(&self, index: &I) -> usize {
+        let mut hasher = FxHasher::default();
+        index.hash(&mut hasher);
+        (hasher.finish() % self.shards.len() as u64) as usize
+    }
+
+    /// Inserts a chunk, returning the chunk it replaced.
+    pub fn insert(&self, index: I, chunk: C) -> Option<C> {
+        let shard = self.shard_of(&index);
+        self.write_shard(shard).insert(index, chunk)
+    }
+
+    /// Removes a chunk, waiting for any guards to it to be dropped.
+    pub fn remove(&self, index: &I) -> Option<C> {
+        self.write_shard(self.shard_of(index)).remove(index)
+    }
+
+    pub fn contains(&self, index: &I) -> bool {
+        self.read_shard(self.shard_of(index)).contains_key(index)
+    }
+
+    pub fn len(&self) -> usize {
+        (0..self.shards.len()).map(|s| self.read_shard(s).len()).sum()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Returns the indices of every chunk. Chunks may be loaded or unloaded
+    /// by the time this returns.
+    pub fn indices(&self) -> Vec<I> {
+        let mut indices = Vec::new();
+        for s in 0..self.shards.len() {
+            indices.extend(self.read_shard(s).keys().cloned());
+        }
+        indices
+    }
+
+    /// Returns a guard for reading the chunk at `index`, if it is loaded.
+    pub fn read(&self, index: &I) -> Option<ChunkReadGuard<'_, I, C>> {
+        let guard = self.read_shard(self.shard_of(index));
+        if !guard.contains_key(index) {
+            return None;
+        }
+        Some(ChunkReadGuard {
+            guard: guard,
+            index: index.clone(),
+        })
+    }
+
+    /// Returns a guard for modifying the chunk at `index`, if it is loaded.
+    pub fn write(&self, index: &I) -> Option<ChunkWriteGuard<'_, I, C>> {
+        let guard = self.write_shard(self.shard_of(index));
+        if !guard.contains_key(index) {
+            return None;
+        }
+        Some(ChunkWriteGuard {
+            guard: guard,
+            index: index.clone(),
+        })
+    }
+
+    /// Locks the shards of every chunk in `indices` in ascending order and
+    /// returns a view for reading those that are loaded.
+    pub fn read_many(&self, indices: &[I]) -> ChunksReadGuard<'_, I, C> {
+        let mut shards: Vec<usize> = indices.iter().map(|i| self.shard_of(i)).collect();
+        shards.sort();
+        shards.dedup();
+
+        let guards = shards.into_iter().map(|s| (s, self.read_shard(s))).collect();
+        ChunksReadGuard {
+            chunks: self,
+            guards: guards,
+        }
+    }
+
+    fn read_shard(&self, shard: usize) -> RwLockReadGuard<'_, FxHashMap<I, C>> {
+        self.shards[shard].read().unwrap_or_else(|e| e.into_inner())
+    }
+
+    fn write_shard(&self, shard: usize) -> RwLockWriteGuard<'_, FxHashMap<I, C>> {
+        self.shards[shard].write().unwrap_or_else(|e| e.into_inner())
+    }
+}
+
+/// Read access to a single loaded chunk. The chunk can't be unloaded while
+/// this is held.
+pub struct ChunkReadGuard<'a, I: ChunkKey + 'a, C: 'a> {
+    guard: RwLockReadGuard<'a, FxHashMap<I, C>>,
+    index: I,
+}
+
+impl<'a, I: ChunkKey, C> Deref for ChunkReadGuard<'a, I, C> {
+    type Target = C;
+
+    fn deref(&self) -> &C {
+        &self.guard[&self.index]
+    }
+}
+
+/// Write access to a single loaded chunk.
+pub struct ChunkWriteGuard<'a, I: ChunkKey + 'a, C: 'a> {
+    guard: RwLockWriteGuard<'a, FxHashMap<I, C>>,
+    index: I,
+}
+
+impl<'a, I: ChunkKey, C> Deref for ChunkWriteGuard<'a, I, C> {
+    type Target = C;
+
+    fn deref(&self) -> &C {
+        &self.guard[&self.index]
+    }
+}
+
+impl<'a, I: ChunkKey, C> DerefMut for ChunkWriteGuard<'a, I, C> {
+    fn deref_mut(&mut self) -> &mut C {
+        self.guard.get_mut(&self.index).unwrap()
+    }
+}
+
+/// Read access to several chunks at once, returned by
+/// `ShardedChunks::read_many`.
+pub struct ChunksReadGuard<'a, I: ChunkKey + 'a, C: 'a> {
+    chunks: &'a ShardedChunks<I, C>,
+    guards: Vec<(usize, RwLockReadGuard<'a, FxHashMap<I, C>>)>,
+}
+
+impl<'a, I: ChunkKey, C> ChunksReadGuard<'a, I, C> {
+    /// Returns the chunk at `index`, if it was requested and is loaded.
+    pub fn get(&self, index: &I) -> Option<&C> {
+        let shard = self.chunks.shard_of(index);
+        self.guards.iter()
+            .find(|&&(s, _)| s == shard)
+            .and_then(|&(_, ref guard)| guard.get(index))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Arc;
+    use std::thread;
+    use traits::TestIndex;
+
+    #[test]
+    fn test_parallel_reads() {
+        let chunks = Arc::new(ShardedChunks::new(4));
+        for i in 0..16 {
+            chunks.insert(TestIndex(i, 0), vec![i; 8]);
+        }
+
+        let workers: Vec<_> = (0..4).map(|w| {
+            let chunks = chunks.clone();
+            thread::spawn(move || {
+                let wanted: Vec<_> = (0..16).filter(|i| i % 4 == w).map(|i| TestIndex(i, 0)).collect();
+                let view = chunks.read_many(&wanted);
+                wanted.iter().map(|i| view.get(i).unwrap()[0]).sum::<i32>()
+            })
+        }).collect();
+        let total: i32 = workers.into_iter().map(|w| w.join().unwrap()).sum();
+        assert_eq!(total, (0..16).sum::<i32>());
+
+        chunks.write(&TestIndex(3, 0)).unwrap()[0] = 100;
+        assert_eq!(chunks.read(&TestIndex(3, 0)).unwrap()[0], 100);
+        assert_eq!(chunks.remove(&TestIndex(3, 0)).map(|c| c.len()), Some(8));
+        assert!(chunks.read(&TestIndex(3, 0)).is_none());
+        assert_eq!(chunks.len(), 15);
+    }
+}
@@ -0,0 +1,38 @@
+/// How far `ChunkedWorld::save_for_shutdown` has come, passed to its callback
+/// after every chunk written, so a game can show a progress bar while it
+/// quits.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct SaveProgress {
+    /// Chunks written so far.
+    pub saved: usize,
+    /// Chunks still to be written.
+    pub remaining: usize,
+    /// Bytes of chunk data written so far, including padding.
+    pub bytes_written: u64,
+    /// Chunks written uncompressed because the time limit had passed.
+    pub uncompressed: usize,
+}
+
+impl SaveProgress {
+    /// Returns the fraction of the chunks written so far, from 0 to 1.
+    pub fn fraction(&self) -> f32 {
+        let total = self.saved + self.remaining;
+        if total == 0 {
+            1.0
+        } else {
+            self.saved as f32 / total as f32
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_fraction() {
+        assert_eq!(SaveProgress::default().fraction(), 1.0);
+        let progress = SaveProgress { saved: 1, remaining: 3, ..SaveProgress::default() };
+        assert_eq!(progress.fraction(), 0.25);
+    }
+}
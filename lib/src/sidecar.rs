@@ -0,0 +1,223 @@
+use std::collections::{BTreeMap, HashMap};
+use std::fs::{self, File};
+use std::io::{BufReader, Write};
+use std::path::{Path, PathBuf};
+
+use bincode::{self, Infinite};
+use serde::Serialize;
+use serde::de::DeserializeOwned;
+
+use error::{self, ErrorContext, SerialResult, WithContext};
+use inbox::INBOX_EXTENSION;
+use layout::RegionLayout;
+use occupancy::OCCUPANCY_EXTENSION;
+use platform::replace_file;
+use region::{RegionIndex, RegionLocalIndex};
+use timestamps::SAVE_TIMES_EXTENSION;
+use tombstone::TOMBSTONES_EXTENSION;
+use versions::VERSIONS_EXTENSION;
+use visits::VISITS_EXTENSION;
+
+/// The extensions of every kind of file the library keeps next to region
+/// files, for copying or moving them along with their regions.
+pub const SIDECAR_EXTENSIONS: [&'static str; 6] = [
+    OCCUPANCY_EXTENSION,
+    SAVE_TIMES_EXTENSION,
+    VISITS_EXTENSION,
+    TOMBSTONES_EXTENSION,
+    INBOX_EXTENSION,
+    VERSIONS_EXTENSION,
+];
+
+/// Returns the sidecar files with one of `extensions` in a layout's
+/// directory, along with the region and extension of each. Sidecars are
+/// found whether or not their region file exists, since a chunk that was
+/// never saved can still have messages or a tombstone.
+pub fn sidecar_files(layout: &RegionLayout, extensions: &[&'static str]) -> SerialResult<Vec<(RegionIndex, &'static str, PathBuf)>> {
+    let dir = layout.directory();
+    let dir = if dir.as_os_str().is_empty() { PathBuf::from(".") } else { dir };
+    if !dir.exists() {
+        return Ok(Vec::new());
+    }
+
+    let mut files = Vec::new();
+    for entry in fs::read_dir(&dir)? {
+        let path = entry?.path();
+        let name = match path.file_name().and_then(|n| n.to_str()) {
+            Some(name) if path.is_file() => name.to_string(),
+            _                            => continue,
+        };
+        for &extension in extensions.iter() {
+            let suffix = format!(".{}", extension);
+            if !name.ends_with(&suffix) {
+                continue;
+            }
+            if let Some(index) = layout.parse_path(&name[..name.len() - suffix.len()]) {
+                files.push((index, extension, path.clone()));
+            }
+        }
+    }
+    files.sort();
+    Ok(files)
+}
+
+/// Values kept for the chunks of one region, keyed by their position inside
+/// the region.
+pub type RegionEntries<T> = BTreeMap<(i32, i32), T>;
+
+/// Keeps a value for each saved chunk in a small file next to its region
+/// file, so it can be looked up without reading the region.
+///
+/// The files are read the first time a region is looked up and written by
+/// `flush`, and are named after the region file with `extension` appended.
+pub struct RegionSidecar<T> {
+    layout: RegionLayout,
+    extension: &'static str,
+    regions: HashMap<RegionIndex, RegionEntries<T>>,
+    dirty: Vec<RegionIndex>,
+}
+
+impl<T: Serialize + DeserializeOwned + PartialEq> RegionSidecar<T> {
+    pub fn new(layout: RegionLayout, extension: &'static str) -> Self {
+        RegionSidecar {
+            layout: layout,
+            extension: extension,
+            regions: HashMap::new(),
+            dirty: Vec::new(),
+        }
+    }
+
+    pub fn extension(&self) -> &'static str {
+        self.extension
+    }
+
+    /// Returns the path of the file holding the values of a region.
+    pub fn path(&self, index: &RegionIndex) -> PathBuf {
+        self.layout.sidecar_path(index, self.extension)
+    }
+
+    /// Returns the values of every chunk of a region.
+    pub fn entries(&mut self, index: &RegionIndex) -> SerialResult<&mut RegionEntries<T>> {
+        if !self.regions.contains_key(index) {
+            let path = self.path(index);
+            let entries = if path.exists() {
+                let context = || ErrorContext::new("read region sidecar").with_region(index.clone()).with_path(&path);
+                let mut reader = BufReader::new(File::open(&path).with_context(&context)?);
+                bincode::deserialize_from(&mut reader, Infinite).with_context(&context)?
+            } else {
+                RegionEntries::new()
+            };
+            self.regions.insert(index.clone(), entries);
+        }
+        Ok(self.regions.get_mut(index).unwrap())
+    }
+
+    pub fn get(&mut self, region: &RegionIndex, local: &RegionLocalIndex) -> SerialResult<Option<&T>> {
+        Ok(self.entries(region)?.get(&(local.0, local.1)))
+    }
+
+    /// Sets the value of a chunk, or removes it if `value` is `None`.
+    pub fn set(&mut self, region: &RegionIndex, local: &RegionLocalIndex, value: Option<T>) -> SerialResult<()> {
+        let key = (local.0, local.1);
+        let changed = {
+            let entries = self.entries(region)?;
+            if entries.get(&key) == value.as_ref() {
+                false
+            } else {
+                match value {
+                    Some(v) => entries.insert(key, v),
+                    None    => entries.remove(&key),
+                };
+                true
+            }
+        };
+        if changed && !self.dirty.contains(region) {
+            self.dirty.push(region.clone());
+        }
+        Ok(())
+    }
+
+    /// Writes the values of a region to a temporary file and syncs it, so
+    /// it can be renamed over the old file.
+    fn write(&self, index: &RegionIndex, temp: &Path) -> SerialResult<()> {
+        fs::create_dir_all(self.layout.directory())?;
+        let data = bincode::serialize(&self.regions[index], Infinite)?;
+        let mut file = File::create(temp)?;
+        file.write_all(&data).and_then(|_| file.sync_all()).map_err(error::write_error)
+    }
+
+    /// Writes the files of every region that changed since the last flush.
+    /// Each file is written to a temporary file first and renamed into
+    /// place, so a file that exists is always complete.
+    pub fn flush(&mut self) -> SerialResult<()> {
+        while let Some(index) = self.dirty.pop() {
+            let path = self.path(&index);
+            let context = || ErrorContext::new("write region sidecar").with_region(index.clone()).with_path(&path);
+            let temp = self.layout.sidecar_path(&index, &format!("{}.tmp", self.extension));
+            let result = self.write(&index, &temp).and_then(|_| Ok(replace_file(&temp, &path)?));
+            if let Err(e) = result {
+                let _ = fs::remove_file(&temp);
+                self.dirty.push(index.clone());
+                return Err(e).with_context(&context);
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use testing::{corrupt_sidecar, TempDir};
+
+    #[test]
+    fn test_sidecar() {
+        let dir = TempDir::new("sidecar");
+        let (region, local) = (RegionIndex(1, -1), RegionLocalIndex(2, 3));
+
+        let mut sidecar: RegionSidecar<u64> = RegionSidecar::new(dir.layout(), "test");
+        assert!(sidecar.entries(&region).unwrap().is_empty());
+        // Nothing changed, so nothing is written.
+        sidecar.set(&region, &local, None).unwrap();
+        sidecar.flush().unwrap();
+        assert!(!sidecar.path(&region).exists());
+
+        sidecar.set(&region, &local, Some(4)).unwrap();
+        sidecar.flush().unwrap();
+        let mut reread: RegionSidecar<u64> = RegionSidecar::new(dir.layout(), "test");
+        assert_eq!(reread.get(&region, &local).unwrap(), Some(&4));
+
+        sidecar.set(&region, &local, None).unwrap();
+        sidecar.flush().unwrap();
+        let mut reread: RegionSidecar<u64> = RegionSidecar::new(dir.layout(), "test");
+        assert_eq!(reread.get(&region, &local).unwrap(), None);
+    }
+
+    #[test]
+    fn test_sidecar_failures() {
+        let dir = TempDir::new("sidecar-failures");
+        let region = RegionIndex(0, 0);
+        corrupt_sidecar(&dir.layout(), &region, "test");
+
+        let mut sidecar: RegionSidecar<u64> = RegionSidecar::new(dir.layout(), "test");
+        let error = sidecar.entries(&region).unwrap_err();
+        assert_eq!(error.error_context().unwrap().path.as_ref(), Some(&sidecar.path(&region)));
+
+        // A region that couldn't be written is written by the next flush.
+        let other = RegionIndex(1, 0);
+        sidecar.set(&other, &RegionLocalIndex(0, 0), Some(1)).unwrap();
+        fs::create_dir(sidecar.path(&other)).unwrap();
+        assert!(sidecar.flush().is_err());
+        fs::remove_dir(sidecar.path(&other)).unwrap();
+        sidecar.flush().unwrap();
+        assert!(sidecar.path(&other).is_file());
+
+        // A write that fails leaves the old file as it was.
+        let temp = dir.layout().sidecar_path(&other, "test.tmp");
+        fs::create_dir(&temp).unwrap();
+        sidecar.set(&other, &RegionLocalIndex(0, 0), Some(2)).unwrap();
+        assert!(sidecar.flush().is_err());
+        let mut reread: RegionSidecar<u64> = RegionSidecar::new(dir.layout(), "test");
+        assert_eq!(reread.get(&other, &RegionLocalIndex(0, 0)).unwrap(), Some(&1));
+    }
+}
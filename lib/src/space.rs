@@ -0,0 +1,199 @@
+use std::fs::File;
+use std::io::prelude::*;
+use std::io::SeekFrom;
+use std::path::{Path, PathBuf};
+
+use error::{ErrorContext, SerialResult, WithContext};
+use format::{self, RegionConfig, HEADER_SIZE};
+use layout::RegionLayout;
+use managed_region::read_data_prefix;
+use migrate::region_files;
+use region::{RegionIndex, RegionLocalIndex};
+
+/// How the space of a single region file is used.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RegionSpace {
+    pub region: RegionIndex,
+    pub path: PathBuf,
+    pub file_size: u64,
+    /// Bytes taken up by the header and lookup table.
+    pub table_bytes: u64,
+    /// Bytes of chunk data, including their length prefixes.
+    pub live_bytes: u64,
+    /// Bytes between the end of a chunk's data and the end of its last
+    /// sector.
+    pub padding_bytes: u64,
+    /// Bytes of sectors set aside with `reserve` that hold no chunk yet.
+    pub reserved_bytes: u64,
+    /// Bytes of sectors no chunk points to, such as the ones left behind by
+    /// chunks that were moved.
+    pub dead_bytes: u64,
+    pub chunks: usize,
+}
+
+impl RegionSpace {
+    /// Measures the region file at `path`, which must have been written with
+    /// `config`.
+    pub fn measure<P: AsRef<Path>>(region: RegionIndex, path: P, config: &RegionConfig) -> SerialResult<Self> {
+        let path = path.as_ref();
+        let mut file = File::open(path)?;
+        format::check_header(&mut file, config)?;
+        let file_size = file.metadata()?.len();
+
+        let mut space = RegionSpace {
+            region: region,
+            path: path.to_path_buf(),
+            file_size: file_size,
+            table_bytes: config.data_start().min(file_size),
+            live_bytes: 0,
+            padding_bytes: 0,
+            reserved_bytes: 0,
+            dead_bytes: 0,
+            chunks: 0,
+        };
+
+        let mut used = 0;
+        for y in 0..config.region_width {
+            for x in 0..config.region_width {
+                let index = RegionLocalIndex(x, y);
+                let (offset, size) = match format::read_entry(&mut file, config, HEADER_SIZE, &index)? {
+                    Some(entry) => entry,
+                    None        => continue,
+                };
+                // The last chunk of a file that isn't padded may end before
+                // its last sector does.
+                let size = (offset + size as u64).min(file_size).saturating_sub(offset);
+                used += size;
+
+                let mut prefix = [0u8; 4];
+                file.seek(SeekFrom::Start(offset))?;
+                file.read_exact(&mut prefix)?;
+                let (_, len) = read_data_prefix(&prefix);
+                if len == 0 {
+                    space.reserved_bytes += size;
+                } else {
+                    let live = (4 + len as u64).min(size);
+                    space.live_bytes += live;
+                    space.padding_bytes += size - live;
+                    space.chunks += 1;
+                }
+            }
+        }
+
+        space.dead_bytes = (file_size - space.table_bytes).saturating_sub(used);
+        Ok(space)
+    }
+
+    /// Returns the number of bytes rewriting this file without any dead
+    /// sectors would save.
+    pub fn reclaimable_bytes(&self) -> u64 {
+        self.dead_bytes
+    }
+
+    /// Returns the number of bytes of the file that aren't chunk data: the
+    /// lookup table, padding, reserved and dead sectors.
+    pub fn overhead_bytes(&self) -> u64 {
+        self.table_bytes + self.padding_bytes + self.reserved_bytes + self.dead_bytes
+    }
+}
+
+/// Space accounting for every region file of a world, for seeing how much a
+/// compaction would reclaim and how well the sector size fits the chunks.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct SpaceReport {
+    pub regions: Vec<RegionSpace>,
+}
+
+impl SpaceReport {
+    /// Measures every region file in a layout's directory.
+    pub fn measure(layout: &RegionLayout, config: &RegionConfig) -> SerialResult<Self> {
+        let mut regions = Vec::new();
+        for (index, path) in region_files(layout)?.into_iter() {
+            let context = ErrorContext::new("measure region").with_region(index.clone()).with_path(&path);
+            regions.push(RegionSpace::measure(index, &path, config).with_context(|| context)?);
+        }
+        Ok(SpaceReport {
+            regions: regions,
+        })
+    }
+
+    pub fn file_size(&self) -> u64 {
+        self.regions.iter().map(|r| r.file_size).sum()
+    }
+
+    pub fn live_bytes(&self) -> u64 {
+        self.regions.iter().map(|r| r.live_bytes).sum()
+    }
+
+    pub fn padding_bytes(&self) -> u64 {
+        self.regions.iter().map(|r| r.padding_bytes).sum()
+    }
+
+    pub fn dead_bytes(&self) -> u64 {
+        self.regions.iter().map(|r| r.dead_bytes).sum()
+    }
+
+    pub fn reclaimable_bytes(&self) -> u64 {
+        self.regions.iter().map(|r| r.reclaimable_bytes()).sum()
+    }
+
+    pub fn overhead_bytes(&self) -> u64 {
+        self.regions.iter().map(|r| r.overhead_bytes()).sum()
+    }
+
+    /// Returns the fraction of the region files that isn't chunk data.
+    pub fn overhead_ratio(&self) -> f64 {
+        let size = self.file_size();
+        if size == 0 {
+            return 0.0;
+        }
+        self.overhead_bytes() as f64 / size as f64
+    }
+
+    /// Returns the fraction of chunk sectors taken up by padding. A high
+    /// ratio means the sector size is large for the chunks being saved.
+    pub fn padding_ratio(&self) -> f64 {
+        let allocated = self.live_bytes() + self.padding_bytes();
+        if allocated == 0 {
+            return 0.0;
+        }
+        self.padding_bytes() as f64 / allocated as f64
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::env;
+    use std::fs::{self, OpenOptions};
+    use format::CompressionLevel;
+    use managed_region::compress_data;
+
+    #[test]
+    fn test_measure_region() {
+        let root = env::temp_dir().join("infinigen-test-space");
+        let _ = fs::remove_dir_all(&root);
+        fs::create_dir_all(&root).unwrap();
+        let layout = RegionLayout::new(&root);
+        let config = RegionConfig { region_width: 4, sector_size: 64 };
+
+        let mut file = OpenOptions::new().read(true).write(true).create(true)
+            .open(layout.region_path(&RegionIndex(0, 0))).unwrap();
+        format::write_header(&mut file, &config).unwrap();
+        let mut payload = vec![0; 64];
+        payload.extend(compress_data(&vec![7, 8, 9], CompressionLevel::Stored).unwrap());
+        payload.resize(128, 0);
+        file.write_all(&payload).unwrap();
+        format::write_entry(&mut file, &config, &RegionLocalIndex(1, 0), config.data_start() + 64, 1).unwrap();
+
+        let report = SpaceReport::measure(&layout, &config).unwrap();
+        assert_eq!(report.regions.len(), 1);
+        assert_eq!(report.regions[0].chunks, 1);
+        assert_eq!(report.live_bytes(), 7);
+        assert_eq!(report.padding_bytes(), 57);
+        assert_eq!(report.dead_bytes(), 64);
+        assert_eq!(report.overhead_bytes(), report.file_size() - 7);
+
+        fs::remove_dir_all(&root).unwrap();
+    }
+}
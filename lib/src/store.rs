@@ -0,0 +1,225 @@
+use std::collections::BTreeMap;
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, BufReader, Read, Write};
+use std::path::{Path, PathBuf};
+
+use bincode::{self, Infinite};
+use serde::Serialize;
+use serde::de::DeserializeOwned;
+
+use error::{self, ErrorContext, SerialResult, WithContext};
+use platform::replace_file;
+
+/// The log is compacted once it holds this many more records than keys.
+const COMPACT_SLACK: usize = 256;
+
+/// A record of the log: a key and its new value, or `None` if it was removed.
+type Record = (String, Option<Vec<u8>>);
+
+/// A small store of values under string keys for game state that isn't tied
+/// to a chunk, like the player's inventory or quest flags, kept in a single
+/// file in the save directory. See `RegionLayout::store_path`.
+///
+/// Every change is appended to the file as a length-prefixed record, so
+/// changing one value doesn't rewrite the others. A record torn by a crash
+/// is dropped the next time the store is opened. The file is rewritten with
+/// only the current values once enough old ones have piled up, or when
+/// `compact` is called.
+pub struct GameStore {
+    path: PathBuf,
+    /// Closed while the file is being replaced by `compact`.
+    file: Option<File>,
+    values: BTreeMap<String, Vec<u8>>,
+    records: usize,
+}
+
+impl GameStore {
+    /// Opens the store at the given path, creating it and its parent
+    /// directories if needed.
+    pub fn open<P: AsRef<Path>>(path: P) -> SerialResult<Self> {
+        let path = path.as_ref().to_path_buf();
+        let context = || ErrorContext::new("open game store").with_path(&path);
+        if let Some(parent) = path.parent() {
+            if !parent.as_os_str().is_empty() {
+                fs::create_dir_all(parent).with_context(&context)?;
+            }
+        }
+
+        let mut file = OpenOptions::new().read(true).append(true).create(true).open(&path)
+            .with_context(&context)?;
+        let mut values = BTreeMap::new();
+        let mut records = 0;
+        let mut valid_len = 0;
+        {
+            let mut reader = BufReader::new(&mut file);
+            while let Some((len, (key, value))) = read_record(&mut reader) {
+                match value {
+                    Some(v) => values.insert(key, v),
+                    None    => values.remove(&key),
+                };
+                records += 1;
+                valid_len += len;
+            }
+        }
+        if file.metadata().with_context(&context)?.len() > valid_len {
+            file.set_len(valid_len).with_context(&context)?;
+        }
+
+        Ok(GameStore {
+            path: path,
+            file: Some(file),
+            values: values,
+            records: records,
+        })
+    }
+
+    /// Deserializes the value stored under a key.
+    pub fn get<T: DeserializeOwned>(&self, key: &str) -> SerialResult<Option<T>> {
+        match self.values.get(key) {
+            Some(bytes) => Ok(Some(bincode::deserialize(bytes)?)),
+            None        => Ok(None),
+        }
+    }
+
+    /// Serializes a value and stores it under a key.
+    pub fn put<T: Serialize>(&mut self, key: &str, value: &T) -> SerialResult<()> {
+        let encoded = bincode::serialize(value, Infinite)?;
+        if self.values.get(key) == Some(&encoded) {
+            return Ok(());
+        }
+        self.append(&(key.to_string(), Some(encoded.clone())))?;
+        self.values.insert(key.to_string(), encoded);
+        self.compact_if_needed()
+    }
+
+    pub fn remove(&mut self, key: &str) -> SerialResult<()> {
+        if !self.values.contains_key(key) {
+            return Ok(());
+        }
+        self.append(&(key.to_string(), None))?;
+        self.values.remove(key);
+        self.compact_if_needed()
+    }
+
+    pub fn contains_key(&self, key: &str) -> bool {
+        self.values.contains_key(key)
+    }
+
+    pub fn keys(&self) -> Vec<&str> {
+        self.values.keys().map(|k| k.as_str()).collect()
+    }
+
+    /// Makes every change so far durable.
+    pub fn sync(&mut self) -> SerialResult<()> {
+        let synced = self.file().and_then(|f| f.sync_all());
+        let path = &self.path;
+        synced.with_context(|| ErrorContext::new("sync game store").with_path(path))
+    }
+
+    /// Rewrites the file with only the current values.
+    pub fn compact(&mut self) -> SerialResult<()> {
+        let path = self.path.clone();
+        let context = || ErrorContext::new("compact game store").with_path(&path);
+        let temp = path.with_extension("tmp");
+        {
+            let mut file = File::create(&temp).with_context(&context)?;
+            let mut written = Ok(());
+            for (key, value) in self.values.iter() {
+                written = encode_record(&(key.clone(), Some(value.clone())))
+                    .and_then(|bytes| file.write_all(&bytes).map_err(error::write_error));
+                if written.is_err() {
+                    break;
+                }
+            }
+            if let Err(e) = written.and_then(|_| file.sync_all().map_err(error::write_error)) {
+                let _ = fs::remove_file(&temp);
+                return Err(e).with_context(&context);
+            }
+        }
+        // The file can't be replaced on Windows while it is open.
+        self.file = None;
+        let replaced = replace_file(&temp, &path);
+        self.file = Some(OpenOptions::new().read(true).append(true).open(&path).with_context(&context)?);
+        replaced.with_context(&context)?;
+        self.records = self.values.len();
+        Ok(())
+    }
+
+    fn file(&mut self) -> io::Result<&mut File> {
+        match self.file {
+            Some(ref mut file) => Ok(file),
+            None               => Err(io::Error::new(io::ErrorKind::NotFound, "game store file couldn't be reopened")),
+        }
+    }
+
+    fn compact_if_needed(&mut self) -> SerialResult<()> {
+        if self.records > self.values.len() + COMPACT_SLACK {
+            self.compact()
+        } else {
+            Ok(())
+        }
+    }
+
+    fn append(&mut self, record: &Record) -> SerialResult<()> {
+        let bytes = encode_record(record)?;
+        let written = self.file().and_then(|f| f.write_all(&bytes)).map_err(error::write_error);
+        let path = &self.path;
+        written.with_context(|| ErrorContext::new("write game store").with_path(path))?;
+        self.records += 1;
+        Ok(())
+    }
+}
+
+/// Encodes a record with its length in front, as a little-endian u32.
+fn encode_record(record: &Record) -> SerialResult<Vec<u8>> {
+    let body = bincode::serialize(record, Infinite)?;
+    let mut bytes = (body.len() as u32).to_le_bytes().to_vec();
+    bytes.extend(body);
+    Ok(bytes)
+}
+
+/// Reads the next record and the number of bytes it took up, or `None` at
+/// the end of the log or at a record that was torn.
+fn read_record<R: Read>(reader: &mut R) -> Option<(u64, Record)> {
+    let mut prefix = [0u8; 4];
+    reader.read_exact(&mut prefix).ok()?;
+    let len = u32::from_le_bytes(prefix) as usize;
+    let mut body = vec![0u8; len];
+    reader.read_exact(&mut body).ok()?;
+    let record = bincode::deserialize(&body).ok()?;
+    Some((4 + len as u64, record))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::env;
+
+    #[test]
+    fn test_game_store() {
+        let path = env::temp_dir().join("infinigen-test-store").join("game.kv");
+        let _ = fs::remove_dir_all(path.parent().unwrap());
+
+        {
+            let mut store = GameStore::open(&path).unwrap();
+            store.put("inventory", &vec!["sword".to_string()]).unwrap();
+            store.put("quest.rats", &true).unwrap();
+            store.put("quest.rats", &false).unwrap();
+            store.remove("inventory").unwrap();
+            store.sync().unwrap();
+        }
+        // A record torn by a crash is dropped.
+        OpenOptions::new().append(true).open(&path).unwrap().write_all(&[200, 0, 0, 0, 1]).unwrap();
+
+        let mut store = GameStore::open(&path).unwrap();
+        assert_eq!(store.keys(), vec!["quest.rats"]);
+        assert_eq!(store.get::<bool>("quest.rats").unwrap(), Some(false));
+
+        store.compact().unwrap();
+        store.put("gold", &25u32).unwrap();
+        let store = GameStore::open(&path).unwrap();
+        assert_eq!(store.get::<u32>("gold").unwrap(), Some(25));
+        assert_eq!(store.get::<bool>("quest.rats").unwrap(), Some(false));
+        fs::remove_dir_all(path.parent().unwrap()).unwrap();
+    }
+}
@@ -0,0 +1,104 @@
+use serde::Serialize;
+use serde::de::DeserializeOwned;
+
+use format::{CompressionLevel, Padding, Preallocation};
+use managed_region::UnsavedReadPolicy;
+use occupancy::Occupancy;
+use traits::ManagedChunk;
+
+/// A chunk that is either a lightweight stub, holding only what is needed to
+/// show or plan around it from afar like heights or biomes, or fully
+/// generated. Both are saved to the same region file, so stubs at the fringe
+/// of the loaded area don't have to be regenerated either.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub enum Staged<S, F> {
+    Stub(S),
+    Full(F),
+}
+
+impl<S, F> Staged<S, F> {
+    pub fn is_stub(&self) -> bool {
+        match *self {
+            Staged::Stub(_) => true,
+            Staged::Full(_) => false,
+        }
+    }
+
+    pub fn full(&self) -> Option<&F> {
+        match *self {
+            Staged::Full(ref full) => Some(full),
+            Staged::Stub(_)        => None,
+        }
+    }
+
+    pub fn full_mut(&mut self) -> Option<&mut F> {
+        match *self {
+            Staged::Full(ref mut full) => Some(full),
+            Staged::Stub(_)            => None,
+        }
+    }
+
+    pub fn stub(&self) -> Option<&S> {
+        match *self {
+            Staged::Stub(ref stub) => Some(stub),
+            Staged::Full(_)        => None,
+        }
+    }
+
+    /// Turns a stub into a full chunk with `generate`, which is given the
+    /// stub to build on. Full chunks are returned as they are.
+    pub fn promote<G: FnOnce(S) -> F>(self, generate: G) -> F {
+        match self {
+            Staged::Stub(stub) => generate(stub),
+            Staged::Full(full) => full,
+        }
+    }
+}
+
+impl<S, F> ManagedChunk for Staged<S, F>
+    where S: Serialize + DeserializeOwned,
+          F: ManagedChunk
+{
+    const SECTOR_SIZE: usize = F::SECTOR_SIZE;
+    const REGION_WIDTH: i32 = F::REGION_WIDTH;
+    const COMPRESSION_THRESHOLD: usize = F::COMPRESSION_THRESHOLD;
+    const UNSAVED_READ_POLICY: UnsavedReadPolicy = F::UNSAVED_READ_POLICY;
+    const PREALLOCATION: Preallocation = F::PREALLOCATION;
+    const PADDING: Padding = F::PADDING;
+    const VERIFY_WRITES: bool = F::VERIFY_WRITES;
+
+    fn compression_level(&self) -> CompressionLevel {
+        match *self {
+            Staged::Stub(_)        => CompressionLevel::Default,
+            Staged::Full(ref full) => full.compression_level(),
+        }
+    }
+
+    fn occupancy(&self) -> Option<Occupancy> {
+        self.full().and_then(|full| full.occupancy())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use bincode::{self, Infinite};
+    use relevance::{InterestShape, RelevancePolicy};
+    use traits::TestIndex;
+
+    #[test]
+    fn test_promote() {
+        let stub: Staged<Vec<u8>, Vec<u16>> = Staged::Stub(vec![3, 4]);
+        let encoded = bincode::serialize(&stub, Infinite).unwrap();
+        let decoded: Staged<Vec<u8>, Vec<u16>> = bincode::deserialize(&encoded).unwrap();
+        assert!(decoded.is_stub());
+
+        let full = decoded.promote(|heights| heights.iter().map(|&h| h as u16 * 100).collect());
+        assert_eq!(full, vec![300, 400]);
+
+        let policy = RelevancePolicy::new(InterestShape::Square(3))
+            .with_detail_shape(InterestShape::Square(1));
+        assert!(policy.needs_detail(&[TestIndex(0, 0)], &TestIndex(1, -1)));
+        assert!(!policy.needs_detail(&[TestIndex(0, 0)], &TestIndex(2, 0)));
+    }
+}
@@ -0,0 +1,158 @@
+use std::collections::BTreeMap;
+
+use traits::Index;
+
+/// A rectangle of cells in world coordinates.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct CellRect {
+    pub x: i32,
+    pub y: i32,
+    pub width: i32,
+    pub height: i32,
+}
+
+impl CellRect {
+    pub fn new(x: i32, y: i32, width: i32, height: i32) -> Self {
+        CellRect {
+            x: x,
+            y: y,
+            width: width,
+            height: height,
+        }
+    }
+
+    /// Returns the rectangle covered by the chunk at `index`.
+    pub fn of_chunk<I: Index>(index: &I, chunk_width: i32) -> Self {
+        CellRect::new(index.x() * chunk_width, index.y() * chunk_width, chunk_width, chunk_width)
+    }
+
+    pub fn contains(&self, x: i32, y: i32) -> bool {
+        x >= self.x && x < self.x + self.width && y >= self.y && y < self.y + self.height
+    }
+
+    /// Returns the part of this rectangle that is also inside `other`, if
+    /// they overlap.
+    pub fn intersection(&self, other: &CellRect) -> Option<CellRect> {
+        let x = self.x.max(other.x);
+        let y = self.y.max(other.y);
+        let right = (self.x + self.width).min(other.x + other.width);
+        let bottom = (self.y + self.height).min(other.y + other.height);
+        if x < right && y < bottom {
+            Some(CellRect::new(x, y, right - x, bottom - y))
+        } else {
+            None
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, Hash, PartialEq, Eq, PartialOrd, Ord)]
+pub struct SubscriptionId(u64);
+
+/// Something that happened to the cells watched by a subscription.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CellEvent {
+    /// The cell at a world position changed.
+    Changed(i32, i32),
+    /// The cells in a rectangle were loaded or generated.
+    Loaded(CellRect),
+    /// The cells in a rectangle were unloaded and can't be read anymore.
+    Unloaded(CellRect),
+}
+
+/// Subscriptions to the cells inside rectangles of the world, so things like
+/// maps and triggers can react to changes without scanning the cells
+/// themselves.
+///
+/// Events are queued until they are taken with `drain`.
+#[derive(Debug, Clone)]
+pub struct CellSubscriptions {
+    chunk_width: i32,
+    subscriptions: BTreeMap<SubscriptionId, CellRect>,
+    events: Vec<(SubscriptionId, CellEvent)>,
+    next_id: u64,
+}
+
+impl CellSubscriptions {
+    pub fn new(chunk_width: i32) -> Self {
+        CellSubscriptions {
+            chunk_width: chunk_width,
+            subscriptions: BTreeMap::new(),
+            events: Vec::new(),
+            next_id: 0,
+        }
+    }
+
+    pub fn subscribe(&mut self, rect: CellRect) -> SubscriptionId {
+        let id = SubscriptionId(self.next_id);
+        self.next_id += 1;
+        self.subscriptions.insert(id, rect);
+        id
+    }
+
+    /// Removes a subscription along with its queued events.
+    pub fn unsubscribe(&mut self, id: SubscriptionId) -> Option<CellRect> {
+        self.events.retain(|&(i, _)| i != id);
+        self.subscriptions.remove(&id)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.subscriptions.is_empty()
+    }
+
+    /// Notifies the subscriptions watching the cell at a world position that
+    /// it changed.
+    pub fn cell_changed(&mut self, x: i32, y: i32) {
+        for (&id, rect) in self.subscriptions.iter() {
+            if rect.contains(x, y) {
+                self.events.push((id, CellEvent::Changed(x, y)));
+            }
+        }
+    }
+
+    pub fn chunk_loaded<I: Index>(&mut self, index: &I) {
+        let chunk = CellRect::of_chunk(index, self.chunk_width);
+        self.notify_chunk(chunk, CellEvent::Loaded);
+    }
+
+    pub fn chunk_unloaded<I: Index>(&mut self, index: &I) {
+        let chunk = CellRect::of_chunk(index, self.chunk_width);
+        self.notify_chunk(chunk, CellEvent::Unloaded);
+    }
+
+    /// Takes every queued event, in the order they happened.
+    pub fn drain(&mut self) -> Vec<(SubscriptionId, CellEvent)> {
+        self.events.drain(..).collect()
+    }
+
+    fn notify_chunk<F>(&mut self, chunk: CellRect, event: F)
+        where F: Fn(CellRect) -> CellEvent {
+        for (&id, rect) in self.subscriptions.iter() {
+            if let Some(overlap) = rect.intersection(&chunk) {
+                self.events.push((id, event(overlap)));
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use traits::TestIndex;
+
+    #[test]
+    fn test_events() {
+        let mut subs = CellSubscriptions::new(16);
+        let map = subs.subscribe(CellRect::new(-4, -4, 8, 8));
+        let trigger = subs.subscribe(CellRect::new(2, 2, 1, 1));
+
+        subs.cell_changed(2, 2);
+        subs.cell_changed(10, 2);
+        subs.chunk_loaded(&TestIndex(-1, 0));
+        subs.chunk_unloaded(&TestIndex(1, 1));
+
+        assert_eq!(subs.drain(), vec![(map, CellEvent::Changed(2, 2)),
+                                      (trigger, CellEvent::Changed(2, 2)),
+                                      (map, CellEvent::Loaded(CellRect::new(-4, 0, 4, 4)))]);
+        assert!(subs.drain().is_empty());
+    }
+}
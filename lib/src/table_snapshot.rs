@@ -0,0 +1,315 @@
+use std::collections::BTreeMap;
+use std::fs::{File, OpenOptions};
+use std::io::{self, SeekFrom};
+use std::io::prelude::*;
+use std::path::Path;
+
+use serde::Serialize;
+use serde::de::DeserializeOwned;
+use serde_json::{self, Value};
+
+use error::{ErrorContext, FormatError, SerialResult, WithContext};
+use format::{self, RegionConfig, HEADER_SIZE, REGION_VERSION};
+use managed_region::read_data_prefix;
+use region::{RegionIndex, RegionLocalIndex};
+use sidecar::RegionSidecar;
+
+/// One used entry of a region's lookup table.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct TableEntry {
+    pub x: i32,
+    pub y: i32,
+    /// The sector the chunk's data starts at.
+    pub sector: u8,
+    pub sectors: u8,
+    /// Length of the chunk's data from the prefix in front of it, or `None`
+    /// if the entry points past the end of the file. Zero for sectors set
+    /// aside with `reserve`.
+    pub length: Option<usize>,
+}
+
+/// The value of one chunk in a sidecar table.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct SidecarEntry {
+    pub x: i32,
+    pub y: i32,
+    pub value: Value,
+}
+
+/// A copy of the lookup table of a region file, and optionally of its
+/// sidecar tables, that serializes to JSON.
+///
+/// Snapshots of the same region taken from two backups can be diffed to see
+/// which chunks moved or disappeared, and attached to bug reports without
+/// sending the save. If a lookup table gets corrupted while the chunk data
+/// behind it is intact, `restore` writes the table of an earlier snapshot
+/// back. Only available with the `table-snapshots` feature.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct RegionTableSnapshot {
+    pub region: (i32, i32),
+    pub version: u32,
+    pub region_width: i32,
+    pub sector_size: usize,
+    pub file_size: u64,
+    /// The used entries, in the order they are stored in the table.
+    pub entries: Vec<TableEntry>,
+    /// Sidecar tables added with `add_sidecar`, by file extension.
+    pub sidecars: BTreeMap<String, Vec<SidecarEntry>>,
+}
+
+fn json_error(e: serde_json::Error) -> io::Error {
+    io::Error::from(e)
+}
+
+impl RegionTableSnapshot {
+    /// Reads the lookup table of the region file at `path`, which must have
+    /// been written with `config`.
+    pub fn read<P: AsRef<Path>>(region: RegionIndex, path: P, config: &RegionConfig) -> SerialResult<Self> {
+        let path = path.as_ref();
+        let context = || ErrorContext::new("snapshot lookup table").with_region(region).with_path(path);
+        let mut file = File::open(path).with_context(&context)?;
+        format::check_header(&mut file, config).with_context(&context)?;
+        let file_size = file.metadata().with_context(&context)?.len();
+
+        let mut snapshot = RegionTableSnapshot {
+            region: (region.0, region.1),
+            version: REGION_VERSION,
+            region_width: config.region_width,
+            sector_size: config.sector_size,
+            file_size: file_size,
+            entries: Vec::new(),
+            sidecars: BTreeMap::new(),
+        };
+
+        let mut table = vec![0u8; config.lookup_table_size() as usize];
+        file.seek(SeekFrom::Start(HEADER_SIZE)).with_context(&context)?;
+        file.read_exact(&mut table).with_context(&context)?;
+        for (i, entry) in table.chunks(2).enumerate() {
+            if entry[1] == 0 {
+                continue;
+            }
+            let offset = config.data_start() + entry[0] as u64 * config.sector_size as u64;
+            snapshot.entries.push(TableEntry {
+                x: i as i32 % config.region_width,
+                y: i as i32 / config.region_width,
+                sector: entry[0],
+                sectors: entry[1],
+                length: read_length(&mut file, offset, file_size).with_context(&context)?,
+            });
+        }
+        Ok(snapshot)
+    }
+
+    pub fn region_index(&self) -> RegionIndex {
+        RegionIndex(self.region.0, self.region.1)
+    }
+
+    pub fn config(&self) -> RegionConfig {
+        RegionConfig {
+            region_width: self.region_width,
+            sector_size: self.sector_size,
+        }
+    }
+
+    pub fn entry(&self, index: &RegionLocalIndex) -> Option<&TableEntry> {
+        self.entries.iter().find(|e| e.x == index.0 && e.y == index.1)
+    }
+
+    /// Returns the chunks whose entries differ between two snapshots of a
+    /// region, including chunks only one of them has.
+    pub fn changed_entries(&self, other: &RegionTableSnapshot) -> Vec<RegionLocalIndex> {
+        let mut changed = Vec::new();
+        for entry in self.entries.iter() {
+            let index = RegionLocalIndex(entry.x, entry.y);
+            if other.entry(&index) != Some(entry) {
+                changed.push(index);
+            }
+        }
+        for entry in other.entries.iter() {
+            let index = RegionLocalIndex(entry.x, entry.y);
+            if self.entry(&index).is_none() {
+                changed.push(index);
+            }
+        }
+        changed
+    }
+
+    /// Adds the values a sidecar holds for this region.
+    pub fn add_sidecar<T>(&mut self, sidecar: &mut RegionSidecar<T>) -> SerialResult<()>
+        where T: Serialize + DeserializeOwned + PartialEq
+    {
+        let region = self.region_index();
+        let mut values = Vec::new();
+        for (&(x, y), value) in sidecar.entries(&region)?.iter() {
+            let value = serde_json::to_value(value).map_err(json_error)?;
+            values.push(SidecarEntry { x: x, y: y, value: value });
+        }
+        self.sidecars.insert(sidecar.extension().to_string(), values);
+        Ok(())
+    }
+
+    pub fn to_json(&self) -> SerialResult<String> {
+        Ok(serde_json::to_string_pretty(self).map_err(json_error)?)
+    }
+
+    pub fn from_json(json: &str) -> SerialResult<Self> {
+        Ok(serde_json::from_str(json).map_err(json_error)?)
+    }
+
+    pub fn save<P: AsRef<Path>>(&self, path: P) -> SerialResult<()> {
+        let json = self.to_json()?;
+        let mut file = File::create(path)?;
+        file.write_all(json.as_bytes())?;
+        Ok(())
+    }
+
+    pub fn load<P: AsRef<Path>>(path: P) -> SerialResult<Self> {
+        let mut json = String::new();
+        File::open(path)?.read_to_string(&mut json)?;
+        RegionTableSnapshot::from_json(&json)
+    }
+
+    /// Replaces the lookup table of the region file at `path` with the one
+    /// in this snapshot, for repairing a corrupted table when the chunk data
+    /// is intact.
+    ///
+    /// Every entry is checked against the data it points to before anything
+    /// is written, and a `FormatError::TableMismatch` is returned if the
+    /// data isn't there or differs in length from when the snapshot was
+    /// taken. Chunks saved after the snapshot are lost. The region must not
+    /// be open in a world.
+    pub fn restore<P: AsRef<Path>>(&self, path: P) -> SerialResult<()> {
+        let path = path.as_ref();
+        let config = self.config();
+        let context = || ErrorContext::new("restore lookup table").with_region(self.region_index()).with_path(path);
+        let mut file = OpenOptions::new().read(true).write(true).open(path).with_context(&context)?;
+        format::check_header(&mut file, &config).with_context(&context)?;
+        let file_size = file.metadata().with_context(&context)?.len();
+
+        let mut table = vec![0u8; config.lookup_table_size() as usize];
+        for entry in self.entries.iter() {
+            let index = RegionLocalIndex(entry.x, entry.y);
+            let offset = config.data_start() + entry.sector as u64 * config.sector_size as u64;
+            let length = read_length(&mut file, offset, file_size).with_context(&context)?;
+            let fits = length.map_or(false, |len| 4 + len <= entry.sectors as usize * config.sector_size);
+            if entry.sectors == 0 || length != entry.length || !fits {
+                return Err(FormatError::TableMismatch(index)).with_context(&context);
+            }
+            let at = (config.entry_offset(&index) - HEADER_SIZE) as usize;
+            table[at] = entry.sector;
+            table[at + 1] = entry.sectors;
+        }
+
+        file.seek(SeekFrom::Start(HEADER_SIZE)).with_context(&context)?;
+        file.write_all(&table).with_context(&context)?;
+        file.sync_all().with_context(&context)?;
+        Ok(())
+    }
+
+    /// Replaces the values a sidecar holds for this region with the ones in
+    /// this snapshot. Returns `false` if the snapshot doesn't have the
+    /// sidecar. Call `RegionSidecar::flush` to write them.
+    pub fn restore_sidecar<T>(&self, sidecar: &mut RegionSidecar<T>) -> SerialResult<bool>
+        where T: Serialize + DeserializeOwned + PartialEq
+    {
+        let values = match self.sidecars.get(sidecar.extension()) {
+            Some(values) => values,
+            None         => return Ok(false),
+        };
+        let region = self.region_index();
+        let stale: Vec<(i32, i32)> = sidecar.entries(&region)?.keys()
+            .filter(|&&(x, y)| !values.iter().any(|v| v.x == x && v.y == y))
+            .cloned()
+            .collect();
+        for (x, y) in stale {
+            sidecar.set(&region, &RegionLocalIndex(x, y), None)?;
+        }
+        for entry in values.iter() {
+            let value = serde_json::from_value(entry.value.clone()).map_err(json_error)?;
+            sidecar.set(&region, &RegionLocalIndex(entry.x, entry.y), Some(value))?;
+        }
+        Ok(true)
+    }
+}
+
+/// Reads the length prefix of the data at `offset`, or `None` if it lies
+/// past the end of the file.
+fn read_length(file: &mut File, offset: u64, file_size: u64) -> io::Result<Option<usize>> {
+    if offset + 4 > file_size {
+        return Ok(None);
+    }
+    let mut prefix = [0u8; 4];
+    file.seek(SeekFrom::Start(offset))?;
+    file.read_exact(&mut prefix)?;
+    Ok(Some(read_data_prefix(&prefix).1))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::env;
+    use std::fs;
+    use layout::RegionLayout;
+    use managed_region::ManagedRegion;
+    use region::Region;
+    use metadata::ChunkMeta;
+    use traits::ManagedChunk;
+
+    #[derive(Serialize, Deserialize)]
+    struct TestChunk(Vec<u8>);
+
+    impl ManagedChunk for TestChunk {
+        const SECTOR_SIZE: usize = 64;
+        const REGION_WIDTH: i32 = 4;
+    }
+
+    #[test]
+    fn test_snapshot_restore() {
+        let root = env::temp_dir().join("infinigen-test-table-snapshot");
+        let _ = fs::remove_dir_all(&root);
+        let layout = RegionLayout::new(&root);
+        let path = layout.region_path(&RegionIndex(0, 0));
+        let config = RegionConfig::of::<TestChunk>();
+
+        {
+            let file = <Region<RegionLocalIndex> as ManagedRegion<RegionLocalIndex, TestChunk>>::get_region_file(&path).unwrap();
+            let mut region: Region<RegionLocalIndex> = Region::new(file);
+            for &(x, y) in [(0, 0), (1, 2), (3, 3)].iter() {
+                let (data, _) = <Region<RegionLocalIndex> as ManagedRegion<RegionLocalIndex, TestChunk>>::encode_chunk(&TestChunk(vec![x as u8; 100]), &ChunkMeta::default()).unwrap();
+                <Region<RegionLocalIndex> as ManagedRegion<RegionLocalIndex, TestChunk>>::write_encoded(&mut region, data, &RegionLocalIndex(x, y)).unwrap();
+            }
+        }
+
+        let mut times: RegionSidecar<u64> = RegionSidecar::new(layout.clone(), "times");
+        times.set(&RegionIndex(0, 0), &RegionLocalIndex(1, 2), Some(42)).unwrap();
+
+        let mut snapshot = RegionTableSnapshot::read(RegionIndex(0, 0), &path, &config).unwrap();
+        snapshot.add_sidecar(&mut times).unwrap();
+        assert_eq!(snapshot.entries.len(), 3);
+        let snapshot = RegionTableSnapshot::from_json(&snapshot.to_json().unwrap()).unwrap();
+        assert!(snapshot.entry(&RegionLocalIndex(1, 2)).unwrap().length.unwrap() > 0);
+
+        // Wipe part of the table and put it back.
+        let mut file = OpenOptions::new().write(true).open(&path).unwrap();
+        file.seek(SeekFrom::Start(HEADER_SIZE)).unwrap();
+        file.write_all(&[0u8; 20]).unwrap();
+        drop(file);
+        let damaged = RegionTableSnapshot::read(RegionIndex(0, 0), &path, &config).unwrap();
+        assert_eq!(snapshot.changed_entries(&damaged), vec![RegionLocalIndex(0, 0), RegionLocalIndex(1, 2)]);
+
+        snapshot.restore(&path).unwrap();
+        let restored = RegionTableSnapshot::read(RegionIndex(0, 0), &path, &config).unwrap();
+        assert!(snapshot.changed_entries(&restored).is_empty());
+
+        let mut times: RegionSidecar<u64> = RegionSidecar::new(layout.clone(), "times");
+        assert!(snapshot.restore_sidecar(&mut times).unwrap());
+        assert_eq!(times.get(&RegionIndex(0, 0), &RegionLocalIndex(1, 2)).unwrap(), Some(&42));
+
+        // An entry that doesn't point at a chunk is refused.
+        let mut bad = snapshot.clone();
+        bad.entries[0].sector += 1;
+        assert!(bad.restore(&path).is_err());
+
+        fs::remove_dir_all(&root).unwrap();
+    }
+}
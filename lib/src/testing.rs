@@ -0,0 +1,303 @@
+//! Fixtures shared by the library's tests: temporary world directories and a
+//! small world implementing every required method of `ChunkedWorld`, so the
+//! default methods can be tested against real region files.
+
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::env;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use adaptive::AdaptiveCompression;
+use detail::DetailLevel;
+use error::*;
+use hash::{FxHashMap, FxHashSet};
+use layout::RegionLayout;
+use load_state::LoadRegistry;
+use managed_region::ManagedRegion;
+use metadata::WorldMetadata;
+use metrics::MetricsSink;
+use tombstone::ChunkTombstones;
+use traits::{ChunkedTerrain, ChunkedWorld, ManagedChunk, RegionManager, TestIndex};
+use recovery::RecoveryPolicy;
+use provenance::LoadTimings;
+use relevance::RelevancePolicy;
+use versions::ChunkVersions;
+use visits::ChunkVisits;
+use region::*;
+use world_lock::WorldLock;
+
+/// A directory for one test, removed when dropped. Every test needs its own
+/// name, since tests run at the same time.
+pub struct TempDir {
+    path: PathBuf,
+}
+
+impl TempDir {
+    pub fn new(name: &str) -> Self {
+        let path = env::temp_dir().join(format!("infinigen-test-{}", name));
+        let _ = fs::remove_dir_all(&path);
+        fs::create_dir_all(&path).unwrap();
+        TempDir {
+            path: path,
+        }
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    pub fn layout(&self) -> RegionLayout {
+        RegionLayout::new(&self.path)
+    }
+}
+
+/// Writes a file that can't be read in place of the sidecar of a region,
+/// such as one cut short by a crash.
+pub fn corrupt_sidecar(layout: &RegionLayout, region: &RegionIndex, extension: &str) {
+    fs::create_dir_all(layout.directory()).unwrap();
+    fs::write(layout.sidecar_path(region, extension), [0xff; 3]).unwrap();
+}
+
+impl Drop for TempDir {
+    fn drop(&mut self) {
+        let _ = fs::remove_dir_all(&self.path);
+    }
+}
+
+/// A chunk remembering where it was generated and how often it was changed,
+/// so tests can tell a saved chunk from a regenerated one.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct TestChunk {
+    pub pos: (i32, i32),
+    pub edits: u32,
+}
+
+impl ManagedChunk for TestChunk {
+    const SECTOR_SIZE: usize = 64;
+    const REGION_WIDTH: i32 = 4;
+}
+
+pub struct TestRegions {
+    regions: HashMap<RegionIndex, Region<TestIndex>>,
+    layout: RegionLayout,
+}
+
+impl<'a> RegionManager<'a, TestIndex, TestChunk> for TestRegions {
+    fn load(&mut self, index: RegionIndex) -> SerialResult<()> {
+        let file = <Region<TestIndex> as ManagedRegion<'a, TestIndex, TestChunk>>::get_region_file(self.layout.region_path(&index))?;
+        self.regions.insert(index, Region::new(file));
+        Ok(())
+    }
+
+    fn get(&mut self, index: &RegionIndex) -> Option<&Region<TestIndex>> {
+        self.regions.get(index)
+    }
+
+    fn get_mut(&mut self, index: &RegionIndex) -> Option<&mut Region<TestIndex>> {
+        self.regions.get_mut(index)
+    }
+
+    fn remove(&mut self, index: &RegionIndex) {
+        self.regions.remove(index);
+    }
+
+    fn region_loaded(&self, index: &RegionIndex) -> bool {
+        self.regions.contains_key(index)
+    }
+
+    fn region_indices(&self) -> Vec<RegionIndex> {
+        self.regions.keys().cloned().collect()
+    }
+
+    fn layout(&self) -> &RegionLayout {
+        &self.layout
+    }
+}
+
+/// Collects everything reported to it.
+#[derive(Default)]
+pub struct RecordingSink {
+    pub events: RefCell<Vec<(&'static str, f64)>>,
+}
+
+impl RecordingSink {
+    pub fn count(&self, name: &str) -> usize {
+        self.events.borrow().iter().filter(|e| e.0 == name).count()
+    }
+}
+
+impl MetricsSink for RecordingSink {
+    fn counter(&self, name: &'static str, value: u64) {
+        self.events.borrow_mut().push((name, value as f64));
+    }
+
+    fn histogram(&self, name: &'static str, value: f64) {
+        self.events.borrow_mut().push((name, value));
+    }
+}
+
+/// A world whose relevant chunks are set directly by the test. Optional
+/// subsystems are left out unless a test needs them.
+pub struct TestWorld {
+    regions: TestRegions,
+    pub chunks: FxHashMap<TestIndex, TestChunk>,
+    pub relevant: FxHashSet<TestIndex>,
+    load_registry: LoadRegistry<TestIndex>,
+    metadata: WorldMetadata,
+    pub metrics: RecordingSink,
+    /// Chunks it pins are relevant along with `relevant`.
+    pub relevance: Option<RelevancePolicy<TestIndex>>,
+    /// Chunks `unload_chunk_internal` fails for.
+    pub fail_unload: FxHashSet<TestIndex>,
+    /// Number of chunks generated so far.
+    pub generated: usize,
+    pub lock: Option<WorldLock>,
+    pub recovery: RecoveryPolicy,
+    timings: LoadTimings<TestIndex>,
+    pub adaptive: Option<AdaptiveCompression>,
+    pub tombstones: Option<ChunkTombstones>,
+    pub visits: Option<ChunkVisits>,
+    pub versions: Option<ChunkVersions>,
+    /// Chunks observers are in, for `update_visits`.
+    pub observed: Vec<TestIndex>,
+}
+
+impl TestWorld {
+    pub fn new(layout: RegionLayout) -> Self {
+        TestWorld {
+            regions: TestRegions {
+                regions: HashMap::new(),
+                layout: layout,
+            },
+            chunks: FxHashMap::default(),
+            relevant: FxHashSet::default(),
+            load_registry: LoadRegistry::new(),
+            metadata: WorldMetadata::new(),
+            metrics: RecordingSink::default(),
+            relevance: None,
+            fail_unload: FxHashSet::default(),
+            generated: 0,
+            lock: None,
+            recovery: RecoveryPolicy::Panic,
+            timings: LoadTimings::new(),
+            adaptive: None,
+            tombstones: None,
+            visits: None,
+            versions: None,
+            observed: Vec::new(),
+        }
+    }
+
+    /// Creates a world keeping tombstones, visits and versions of its
+    /// chunks next to its region files.
+    pub fn with_sidecars(layout: RegionLayout) -> Self {
+        let mut world = TestWorld::new(layout.clone());
+        world.tombstones = Some(ChunkTombstones::new(layout.clone()));
+        world.visits = Some(ChunkVisits::new(layout.clone()));
+        world.versions = Some(ChunkVersions::new(layout));
+        world
+    }
+
+    /// Loads a chunk and changes it, so its saved copy can be told apart
+    /// from a newly generated one.
+    pub fn edit(&mut self, index: TestIndex, edits: u32) {
+        self.load_chunk(&index).unwrap();
+        self.chunks.get_mut(&index).unwrap().edits = edits;
+    }
+
+    pub fn edits(&self, index: TestIndex) -> Option<u32> {
+        self.chunks.get(&index).map(|c| c.edits)
+    }
+}
+
+impl<'a> ChunkedTerrain<'a, TestIndex, TestChunk, TestRegions> for TestWorld {
+    type Chunk = TestChunk;
+
+    fn chunk_loaded(&self, index: &TestIndex) -> bool {
+        self.chunks.contains_key(index)
+    }
+
+    fn chunk_indices(&self) -> Vec<TestIndex> {
+        self.chunks.keys().cloned().collect()
+    }
+
+    fn chunk_count(&self) -> usize {
+        self.chunks.len()
+    }
+
+    fn chunk(&self, index: &TestIndex) -> Option<&TestChunk> {
+        self.chunks.get(index)
+    }
+
+    fn chunk_mut(&mut self, index: &TestIndex) -> Option<&mut TestChunk> {
+        self.chunks.get_mut(index)
+    }
+
+    fn regions_mut(&mut self) -> &mut TestRegions {
+        &mut self.regions
+    }
+}
+
+impl<'a> ChunkedWorld<'a, TestIndex, TestChunk, TestRegions, TestWorld> for TestWorld {
+    fn load_chunk_internal(&mut self, chunk: TestChunk, index: &TestIndex) -> SerialResult<()> {
+        self.chunks.insert(index.clone(), chunk);
+        Ok(())
+    }
+
+    fn unload_chunk_internal(&mut self, index: &TestIndex) -> SerialResult<TestChunk> {
+        if self.fail_unload.contains(index) {
+            return Err(NoChunkInWorld(index.0, index.1).into());
+        }
+        match self.chunks.remove(index) {
+            Some(chunk) => Ok(chunk),
+            None        => Err(NoChunkInWorld(index.0, index.1).into()),
+        }
+    }
+
+    fn snapshot_chunk(&mut self, index: &TestIndex) -> SerialResult<Option<TestChunk>> {
+        Ok(self.chunks.get(index).cloned())
+    }
+
+    fn generate_at(&mut self, index: &TestIndex, _level: DetailLevel) -> SerialResult<()> {
+        self.generated += 1;
+        self.chunks.insert(index.clone(), TestChunk { pos: (index.0, index.1), edits: 0 });
+        Ok(())
+    }
+
+    fn terrain(&self) -> &TestWorld { self }
+    fn terrain_mut(&mut self) -> &mut TestWorld { self }
+
+    fn load_registry(&self) -> &LoadRegistry<TestIndex> { &self.load_registry }
+    fn load_registry_mut(&mut self) -> &mut LoadRegistry<TestIndex> { &mut self.load_registry }
+
+    fn load_timings(&self) -> Option<&LoadTimings<TestIndex>> { Some(&self.timings) }
+    fn load_timings_mut(&mut self) -> Option<&mut LoadTimings<TestIndex>> { Some(&mut self.timings) }
+
+    fn metrics(&self) -> Option<&dyn MetricsSink> { Some(&self.metrics) }
+
+    fn adaptive_compression_mut(&mut self) -> Option<&mut AdaptiveCompression> { self.adaptive.as_mut() }
+
+    fn tombstones_mut(&mut self) -> Option<&mut ChunkTombstones> { self.tombstones.as_mut() }
+    fn visits_mut(&mut self) -> Option<&mut ChunkVisits> { self.visits.as_mut() }
+    fn chunk_versions_mut(&mut self) -> Option<&mut ChunkVersions> { self.versions.as_mut() }
+
+    fn observed_chunks(&self) -> Vec<TestIndex> { self.observed.clone() }
+
+    fn metadata(&self) -> &WorldMetadata { &self.metadata }
+    fn metadata_mut(&mut self) -> &mut WorldMetadata { &mut self.metadata }
+
+    fn world_lock(&self) -> Option<&WorldLock> { self.lock.as_ref() }
+
+    fn recovery_policy(&self) -> RecoveryPolicy { self.recovery }
+
+    fn relevance_policy_mut(&mut self) -> Option<&mut RelevancePolicy<TestIndex>> { self.relevance.as_mut() }
+
+    fn relevant_chunks(&self) -> FxHashSet<TestIndex> {
+        let mut relevant = self.relevant.clone();
+        if let Some(ref policy) = self.relevance {
+            relevant.extend(policy.relevant(&[]));
+        }
+        relevant
+    }
+}
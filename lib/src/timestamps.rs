@@ -0,0 +1,84 @@
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use error::SerialResult;
+use layout::RegionLayout;
+use region::{RegionIndex, RegionLocalIndex};
+use sidecar::RegionSidecar;
+
+/// Extension appended to region file names for the files holding the times
+/// their chunks were last saved.
+pub const SAVE_TIMES_EXTENSION: &'static str = "times";
+
+/// Returns the number of whole seconds from the Unix epoch to `time`, or zero
+/// for times before it.
+pub fn unix_seconds(time: SystemTime) -> u64 {
+    time.duration_since(UNIX_EPOCH).map(|d| d.as_secs()).unwrap_or(0)
+}
+
+pub fn from_unix_seconds(seconds: u64) -> SystemTime {
+    UNIX_EPOCH + Duration::from_secs(seconds)
+}
+
+/// Keeps the wall-clock time every chunk was last saved at in a small file
+/// next to each region file, for things like decaying abandoned areas or
+/// backing up only the regions that changed. Times are kept to the second.
+///
+/// The files are read the first time a region is looked up and written by
+/// `flush`.
+pub struct SaveTimes {
+    chunks: RegionSidecar<u64>,
+}
+
+impl SaveTimes {
+    pub fn new(layout: RegionLayout) -> Self {
+        SaveTimes {
+            chunks: RegionSidecar::new(layout, SAVE_TIMES_EXTENSION),
+        }
+    }
+
+    /// Records that a chunk was saved at `time`.
+    pub fn record(&mut self, region: &RegionIndex, local: &RegionLocalIndex, time: SystemTime) -> SerialResult<()> {
+        self.chunks.set(region, local, Some(unix_seconds(time)))
+    }
+
+    /// Returns when a chunk was last saved, if it was recorded.
+    pub fn chunk_saved_at(&mut self, region: &RegionIndex, local: &RegionLocalIndex) -> SerialResult<Option<SystemTime>> {
+        Ok(self.chunks.get(region, local)?.map(|&s| from_unix_seconds(s)))
+    }
+
+    /// Returns when any chunk of a region was last saved, if it was recorded.
+    pub fn region_saved_at(&mut self, region: &RegionIndex) -> SerialResult<Option<SystemTime>> {
+        Ok(self.chunks.entries(region)?.values().max().map(|&s| from_unix_seconds(s)))
+    }
+
+    /// Writes the times of every region that changed since the last flush.
+    pub fn flush(&mut self) -> SerialResult<()> {
+        self.chunks.flush()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::env;
+    use std::fs;
+
+    #[test]
+    fn test_save_times() {
+        let root = env::temp_dir().join("infinigen-test-save-times");
+        let _ = fs::remove_dir_all(&root);
+        let layout = RegionLayout::new(&root);
+        let region = RegionIndex(2, 0);
+
+        let mut times = SaveTimes::new(layout.clone());
+        times.record(&region, &RegionLocalIndex(0, 0), from_unix_seconds(100)).unwrap();
+        times.record(&region, &RegionLocalIndex(1, 0), from_unix_seconds(300)).unwrap();
+        times.flush().unwrap();
+
+        let mut times = SaveTimes::new(layout);
+        assert_eq!(times.chunk_saved_at(&region, &RegionLocalIndex(0, 0)).unwrap(), Some(from_unix_seconds(100)));
+        assert_eq!(times.region_saved_at(&region).unwrap(), Some(from_unix_seconds(300)));
+        assert_eq!(times.region_saved_at(&RegionIndex(0, 0)).unwrap(), None);
+        fs::remove_dir_all(&root).unwrap();
+    }
+}
@@ -0,0 +1,97 @@
+use error::SerialResult;
+use layout::RegionLayout;
+use region::{RegionIndex, RegionLocalIndex};
+use sidecar::RegionSidecar;
+
+/// Extension appended to region file names for the files holding which of
+/// their chunks are to be regenerated.
+pub const TOMBSTONES_EXTENSION: &'static str = "tomb";
+
+/// Marks saved chunks to be generated again the next time they are loaded,
+/// for resetting areas of a world without rewriting its region files right
+/// away. The stale copy of a chunk stays in its region file until the
+/// regenerated chunk is saved over it. See `ChunkedWorld::schedule_reset`.
+///
+/// Every tombstone keeps the world tick it was placed at, in a small file
+/// next to each region file. The files are read the first time a region is
+/// looked up and written by `flush`.
+pub struct ChunkTombstones {
+    chunks: RegionSidecar<u64>,
+}
+
+impl ChunkTombstones {
+    pub fn new(layout: RegionLayout) -> Self {
+        ChunkTombstones {
+            chunks: RegionSidecar::new(layout, TOMBSTONES_EXTENSION),
+        }
+    }
+
+    /// Returns the tick a chunk was marked for regeneration at, or `None` if
+    /// it isn't.
+    pub fn placed_at(&mut self, region: &RegionIndex, local: &RegionLocalIndex) -> SerialResult<Option<u64>> {
+        Ok(self.chunks.get(region, local)?.cloned())
+    }
+
+    /// Marks a chunk for regeneration, keeping the tick of an earlier mark.
+    pub fn place(&mut self, region: &RegionIndex, local: &RegionLocalIndex, tick: u64) -> SerialResult<()> {
+        if self.placed_at(region, local)?.is_some() {
+            return Ok(());
+        }
+        self.chunks.set(region, local, Some(tick))
+    }
+
+    /// Removes the mark of a chunk. Returns `true` if it was marked.
+    pub fn take(&mut self, region: &RegionIndex, local: &RegionLocalIndex) -> SerialResult<bool> {
+        if self.placed_at(region, local)?.is_none() {
+            return Ok(false);
+        }
+        self.chunks.set(region, local, None)?;
+        Ok(true)
+    }
+
+    /// Writes the tombstones of every region that changed since the last
+    /// flush.
+    pub fn flush(&mut self) -> SerialResult<()> {
+        self.chunks.flush()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use testing::{corrupt_sidecar, TempDir};
+
+    #[test]
+    fn test_tombstones() {
+        let dir = TempDir::new("tombstones");
+        let (region, local) = (RegionIndex(2, -3), RegionLocalIndex(0, 7));
+
+        // Regions without a file have no tombstones.
+        let mut tombstones = ChunkTombstones::new(dir.layout());
+        assert_eq!(tombstones.placed_at(&region, &local).unwrap(), None);
+        tombstones.place(&region, &local, 5).unwrap();
+        tombstones.place(&region, &local, 9).unwrap();
+        tombstones.flush().unwrap();
+
+        let mut tombstones = ChunkTombstones::new(dir.layout());
+        assert_eq!(tombstones.placed_at(&region, &local).unwrap(), Some(5));
+        assert!(tombstones.take(&region, &local).unwrap());
+        assert!(!tombstones.take(&region, &local).unwrap());
+        tombstones.flush().unwrap();
+
+        let mut tombstones = ChunkTombstones::new(dir.layout());
+        assert_eq!(tombstones.placed_at(&region, &local).unwrap(), None);
+    }
+
+    #[test]
+    fn test_corrupt_tombstones() {
+        let dir = TempDir::new("tombstones-corrupt");
+        let (region, local) = (RegionIndex(0, 0), RegionLocalIndex(1, 1));
+        corrupt_sidecar(&dir.layout(), &region, TOMBSTONES_EXTENSION);
+
+        let mut tombstones = ChunkTombstones::new(dir.layout());
+        assert!(tombstones.placed_at(&region, &local).is_err());
+        assert!(tombstones.take(&region, &local).is_err());
+        assert!(tombstones.place(&region, &local, 1).is_err());
+    }
+}
@@ -1,15 +1,112 @@
+use std::fs;
 use std::hash::Hash;
+use std::panic::{self, AssertUnwindSafe};
+use std::path::Path;
+use std::time::SystemTime;
+#[cfg(feature = "async")]
+use std::sync::mpsc;
+#[cfg(feature = "async")]
+use std::thread;
+use std::time::{Duration, Instant};
 
 use serde::Serialize;
 use serde::de::DeserializeOwned;
 
-use managed_region::ManagedRegion;
+use adaptive::{AdaptiveCompression, CompressionSample};
+use admission::{AdmissionPolicy, IoTracker};
+use archive::{ArchivePolicy, ColdArchive};
+use coalesce::WriteCoalescing;
+use crash::{panic_message, CrashMarker, CrashRecovery};
+use detail::{DetailLevel, DetailRules};
+use error::*;
+use fork;
+use format::{CompressionLevel, Padding, Preallocation, RegionConfig};
+use grid::{spiral_rank, split_coord, ChunkArrangement};
+use hash::FxHashSet;
+use inbox::{ChunkInbox, ChunkMessage};
+use journal::SaveJournal;
+use layout::RegionLayout;
+use migrate::{self, region_files};
+use lazy::LazyChunk;
+use lease::{ChunkLease, ChunkLeases};
+use lifetime::ChunkLifetime;
+#[cfg(feature = "async")]
+use load_handle::{self, ChunkLoadHandle};
+use load_state::{LoadRegistry, LoadState};
+use links::{LinkEnd, LinkId, LinkTable};
+use managed_region::{ManagedRegion, UnsavedReadPolicy};
+use metadata::{ChunkMeta, WorldMetadata};
+use metrics::{self, MetricsSink};
+use occupancy::{Occupancy, OccupancyStore};
+use overlay::DebugOverlay;
+use protection::ChunkProtection;
+use provenance::{ChunkOrigin, ChunkProvenance, LoadTimings};
+#[cfg(feature = "parallel")]
+use parallel;
+use timestamps::SaveTimes;
+use tombstone::ChunkTombstones;
+use versions::ChunkVersions;
+use visits::ChunkVisits;
+use world_lock::WorldLock;
+use recovery::RecoveryPolicy;
+use relevance::{ChunkActivity, RelevancePolicy, RelevanceProviders};
+use replay::{ResidencyAction, ResidencyLog, ResidencyRecord};
+use shutdown::SaveProgress;
+use space::SpaceReport;
+use subscribe::{CellRect, CellSubscriptions};
 use region::*;
 
+/// A key identifying a chunk in region files.
+///
+/// Every `Index` is a key that partitions the grid of chunks into square
+/// regions. Worlds that aren't a grid, like a dungeon of discrete floors and
+/// rooms, can implement this directly on their own identifiers to reuse the
+/// region file machinery.
+pub trait ChunkKey: Hash + Eq + PartialEq + Clone {
+    /// Returns the region that stores this chunk and the chunk's slot inside
+    /// it. Both coordinates of the slot must be in `0..region_width`, and no
+    /// two keys may map to the same region and slot.
+    fn partition(&self, region_width: i32) -> (RegionIndex, RegionLocalIndex);
+}
+
+impl<T: Index> ChunkKey for T {
+    fn partition(&self, region_width: i32) -> (RegionIndex, RegionLocalIndex) {
+        match T::ARRANGEMENT {
+            ChunkArrangement::Grid    => {
+                let (rx, lx) = split_coord(self.x(), region_width);
+                let (ry, ly) = split_coord(self.y(), region_width);
+                (RegionIndex(rx, ry), RegionLocalIndex(lx, ly))
+            },
+            ChunkArrangement::Columns => {
+                let (rx, slot) = split_coord(self.x(), region_width * region_width);
+                (RegionIndex(rx, 0), RegionLocalIndex(slot % region_width, slot / region_width))
+            },
+        }
+    }
+}
+
 /// A two-dimensional index into a grid, like those of chunks or regions.
 pub trait Index: Hash + Eq + PartialEq + Clone {
+    /// How the chunks this index addresses are arranged.
+    const ARRANGEMENT: ChunkArrangement = ChunkArrangement::Grid;
+
     fn x(&self) -> i32;
     fn y(&self) -> i32;
+
+    /// Creates the index at the given coordinates.
+    fn from_xy(x: i32, y: i32) -> Self;
+}
+
+/// A plain index used by the library's tests.
+#[cfg(test)]
+#[derive(Copy, Clone, Debug, Hash, Eq, PartialEq)]
+pub(crate) struct TestIndex(pub i32, pub i32);
+
+#[cfg(test)]
+impl Index for TestIndex {
+    fn x(&self) -> i32 { self.0 }
+    fn y(&self) -> i32 { self.1 }
+    fn from_xy(x: i32, y: i32) -> Self { TestIndex(x, y) }
 }
 
 /// Allows the user to specify the parameters of the region fil
@@ -20,44 +117,226 @@ pub trait ManagedChunk: Serialize + DeserializeOwned {
 
     /// The number of chunks per row inside regions.
     const REGION_WIDTH: i32 = 16;
+
+    /// Chunks whose serialized data is smaller than this many bytes are
+    /// stored without compression.
+    const COMPRESSION_THRESHOLD: usize = 128;
+
+    /// What happens when a chunk is read from its region while it has
+    /// unsaved changes.
+    const UNSAVED_READ_POLICY: UnsavedReadPolicy = UnsavedReadPolicy::Fail;
+
+    /// How new region files are sized. Bounded worlds can preallocate every
+    /// chunk's space to avoid fragmentation and make disk usage predictable.
+    const PREALLOCATION: Preallocation = Preallocation::None;
+
+    /// What fills the rest of a saved chunk's last sector.
+    const PADDING: Padding = Padding::ZeroFill;
+
+    /// Whether every chunk written to a region is read back and compared to
+    /// what was written, which doubles the I/O of saving. On by default in
+    /// debug builds only; turn it on when tracking down save corruption.
+    const VERIFY_WRITES: bool = cfg!(debug_assertions);
+
+    /// Returns the level this chunk should be compressed with when it is
+    /// saved, for example `Best` for large chunks that are mostly uniform.
+    fn compression_level(&self) -> CompressionLevel {
+        CompressionLevel::Default
+    }
+
+    /// Returns which cells of this chunk can be walked on, to be kept next to
+    /// the region file when the chunk is saved. See `OccupancyStore`.
+    fn occupancy(&self) -> Option<Occupancy> {
+        None
+    }
 }
 
 /// Describes a struct that is responsible for keeping track of multiple
 /// ManagedRegions and retrieving the correct region for a given chunk index.
 pub trait RegionManager<'a, I, C>
-    where I:Index,
+    where I: ChunkKey,
           C: ManagedChunk,
           Region<I>: ManagedRegion<'a, I, C> {
 
-    fn load(&mut self, index: RegionIndex);
+    fn load(&mut self, index: RegionIndex) -> SerialResult<()>;
     fn get(&mut self, index: &RegionIndex) -> Option<&Region<I>>;
     fn get_mut(&mut self, index: &RegionIndex) -> Option<&mut Region<I>>;
     fn remove(&mut self, index: &RegionIndex);
     fn region_loaded(&self, index: &RegionIndex) -> bool;
     fn region_indices(&self) -> Vec<RegionIndex>;
 
-    fn notify_chunk_creation(&mut self, chunk_index: &I) {
-        let region = self.get_for_chunk(chunk_index);
-        region.receive_created_chunk(chunk_index);
+    /// Returns where this manager's region files and world metadata live.
+    fn layout(&self) -> &RegionLayout;
+
+    /// Returns the indices of the regions saved in the world at `path`,
+    /// which uses this manager's naming scheme, by looking at the file names
+    /// and headers of its region files. No chunks are read, so this is cheap
+    /// enough for finding the extent of the explored world, such as for a
+    /// world map. Regions in the cold archive aren't included.
+    fn scan_directory<P: AsRef<Path>>(&self, path: P) -> SerialResult<Vec<RegionIndex>> {
+        migrate::scan_regions(&self.layout().with_root(path), &RegionConfig::of::<C>())
+    }
+
+    /// Returns the archive rarely visited regions are moved to, if this
+    /// manager uses one. Archived regions are restored automatically when
+    /// one of their chunks is needed.
+    fn cold_archive(&mut self) -> Option<&mut ColdArchive> { None }
+
+    /// Returns the tracker counting this manager's reads and writes in
+    /// progress, if it keeps one. See `ChunkedWorld::admission_policy`.
+    fn io_tracker(&self) -> Option<&IoTracker> { None }
+
+    /// Moves every region file that isn't loaded and matches the policy into
+    /// the cold archive. Returns the number of regions archived.
+    fn archive_idle_regions(&mut self, policy: &ArchivePolicy) -> SerialResult<usize> {
+        if self.cold_archive().is_none() {
+            return Ok(0);
+        }
+
+        let now = SystemTime::now();
+        let mut archived = 0;
+        for (index, path) in region_files(self.layout())?.into_iter() {
+            if self.region_loaded(&index) {
+                continue;
+            }
+            let meta = path.metadata()?;
+            if !policy.should_archive(&meta, now) {
+                continue;
+            }
+
+            let context = ErrorContext::new("archive region").with_region(index.clone()).with_path(&path);
+            if let Some(archive) = self.cold_archive() {
+                archive.archive(&index, &path).with_context(|| context)?;
+                archived += 1;
+            }
+        }
+        Ok(archived)
+    }
+
+    /// Moves a region out of the cold archive back into a region file, if
+    /// it was archived.
+    fn restore_archived(&mut self, index: &RegionIndex) -> SerialResult<bool> {
+        let path = self.layout().region_path(index);
+        match self.cold_archive() {
+            Some(archive) => archive.restore(index, &path)
+                .with_context(|| ErrorContext::new("restore region").with_region(index.clone()).with_path(&path)),
+            None          => Ok(false),
+        }
+    }
+
+    fn notify_chunk_creation(&mut self, chunk_index: &I) -> SerialResult<()> {
+        let context = self.error_context(chunk_index, "create chunk");
+        let region = self.get_for_chunk(chunk_index)?;
+        region.receive_created_chunk(chunk_index).with_context(|| context)
+    }
+
+    /// Called when a region file is created, opened, flushed or closed.
+    fn on_region_event(&mut self, _index: &RegionIndex, _event: RegionEvent) -> SerialResult<()> {
+        Ok(())
+    }
+
+    /// Syncs every open region file to disk.
+    fn flush_regions(&mut self) -> SerialResult<()> {
+        for idx in self.region_indices() {
+            let path = self.layout().region_path(&idx);
+            if let Some(region) = self.get(&idx) {
+                region.handle.sync_all().with_context(|| {
+                    ErrorContext::new("flush region").with_region(idx.clone()).with_path(path)
+                })?;
+            }
+            self.on_region_event(&idx, RegionEvent::Flushed)?;
+        }
+        Ok(())
+    }
+
+    /// Syncs a region file to disk and closes it. The region is closed even
+    /// if the sync fails.
+    fn close_region(&mut self, index: &RegionIndex) -> SerialResult<()> {
+        let synced = match self.get(index) {
+            Some(region) => region.handle.sync_all(),
+            None         => return Ok(()),
+        };
+        self.remove(index);
+        let path = self.layout().region_path(index);
+        synced.with_context(|| ErrorContext::new("close region").with_region(index.clone()).with_path(path))?;
+        self.on_region_event(index, RegionEvent::Closed)
     }
 
-    fn prune_empty(&mut self) {
+    /// Closes every region that has no chunks in memory.
+    fn prune_empty(&mut self) -> SerialResult<()> {
         let indices = self.region_indices();
         for idx in indices {
             if self.get(&idx).map_or(false, |r: &Region<I>| r.is_empty()) {
-                self.remove(&idx);
+                self.close_region(&idx)?;
             }
         }
+        Ok(())
+    }
+
+    /// Returns whether a chunk is in memory and unsaved, and why, without
+    /// loading its region if it isn't loaded.
+    fn chunk_lifetime(&mut self, chunk_index: &I) -> Option<ChunkLifetime> {
+        let region_index = Region::get_region_index(chunk_index);
+        self.get(&region_index).and_then(|r| r.lifetimes.state(chunk_index))
+    }
+
+    /// Returns the content hash of the saved data of a chunk. See
+    /// `ManagedRegion::chunk_hash`.
+    fn chunk_hash(&mut self, chunk_index: &I) -> SerialResult<Option<u64>> {
+        self.get_for_chunk(chunk_index)?.chunk_hash(chunk_index)
+    }
+
+    /// Summarizes how the space of every region file on disk is used, such
+    /// as how much a compaction would reclaim.
+    fn space_report(&self) -> SerialResult<SpaceReport> {
+        SpaceReport::measure(self.layout(), &RegionConfig::of::<C>())
+    }
+
+    /// Reserves space for a chunk in its region file. See
+    /// `ManagedRegion::reserve`.
+    fn reserve(&mut self, chunk_index: &I, sectors: usize) -> SerialResult<()> {
+        let context = self.error_context(chunk_index, "reserve space for chunk");
+        self.get_for_chunk(chunk_index)?.reserve(chunk_index, sectors).with_context(|| context)
+    }
+
+    /// Returns a description of the chunk and region file an operation on a
+    /// chunk involves, for attaching to its errors.
+    fn error_context(&self, chunk_index: &I, operation: &'static str) -> ErrorContext {
+        let (region_index, local_index) = chunk_index.partition(C::REGION_WIDTH);
+        ErrorContext::new(operation)
+            .with_path(self.layout().region_path(&region_index))
+            .with_region(region_index)
+            .with_chunk(local_index)
+    }
+
+    /// Checks that the world in this manager's directory was first saved
+    /// with the region parameters of `C`, if it was saved at all, so chunks
+    /// of another type can't mis-address its regions. Called before a region
+    /// is opened.
+    fn check_world_config(&self) -> SerialResult<()> {
+        let path = self.layout().metadata_path();
+        let context = || ErrorContext::new("check world region parameters").with_path(&path);
+        let mut metadata = WorldMetadata::load(&path).with_context(&context)?;
+        RegionConfig::of::<C>().check_world(&mut metadata).with_context(&context)
     }
 
-    fn get_for_chunk(&mut self, chunk_index: &I) -> &mut Region<I> {
+    fn get_for_chunk(&mut self, chunk_index: &I) -> SerialResult<&mut Region<I>> {
         let region_index = Region::get_region_index(chunk_index);
 
         if !self.region_loaded(&region_index) {
-            self.load(region_index);
+            self.check_world_config()?;
+            let path = self.layout().region_path(&region_index);
+            self.restore_archived(&region_index)?;
+            let event = if path.exists() { RegionEvent::Opened } else { RegionEvent::Created };
+            self.load(region_index.clone()).with_context(|| {
+                ErrorContext::new("load region")
+                    .with_region(region_index.clone())
+                    .with_path(path)
+            })?;
+            self.on_region_event(&region_index, event)?;
         }
 
-        self.get_mut(&region_index).unwrap()
+        Ok(self.get_mut(&region_index).unwrap())
     }
 }
 
@@ -67,11 +346,29 @@ pub trait ChunkedTerrain<'a, I, C, M>
     where I:Index,
           C: ManagedChunk,
           M: RegionManager<'a, I, C> {
+    /// A chunk as the world keeps it while loaded, as opposed to `C`, the
+    /// form it is saved in.
+    type Chunk;
 
     fn chunk_loaded(&self, index: &I) -> bool;
     fn chunk_indices(&self) -> Vec<I>;
     fn chunk_count(&self) -> usize;
 
+    /// Returns a loaded chunk, or `None` if it isn't loaded.
+    fn chunk(&self, index: &I) -> Option<&Self::Chunk>;
+
+    /// Returns a loaded chunk for changing it, or `None` if it isn't loaded.
+    /// Doesn't check protection; gameplay code should use
+    /// `ChunkedWorld::chunk_for_write` instead.
+    fn chunk_mut(&mut self, index: &I) -> Option<&mut Self::Chunk>;
+
+    /// Returns every loaded chunk with its index, in no particular order.
+    fn loaded_chunks(&self) -> Vec<(I, &Self::Chunk)> {
+        self.chunk_indices().into_iter()
+            .filter_map(|index| self.chunk(&index).map(|chunk| (index, chunk)))
+            .collect()
+    }
+
     fn regions_mut(&mut self) -> &mut M;
 }
 
@@ -83,68 +380,2357 @@ pub trait ChunkedWorld<'a, I, C, M, T>
     fn load_chunk_internal(&mut self, chunk: C, index: &I) -> SerialResult<()>;
     fn unload_chunk_internal(&mut self, index: &I) -> SerialResult<C>;
 
+    /// Returns a copy of a loaded chunk as `unload_chunk_internal` would
+    /// return it, leaving the chunk in the world. Worlds that return `None`
+    /// are saved in place by unloading and reloading each chunk.
+    fn snapshot_chunk(&mut self, _index: &I) -> SerialResult<Option<C>> {
+        Ok(None)
+    }
+
     fn load_chunk_from_region(&mut self, index: &I) -> SerialResult<()> {
         if self.terrain().chunk_loaded(index) {
             return Ok(());
         }
 
+        let start = Instant::now();
         let old_count = self.terrain().chunk_count();
-        let chunk: C;
+        let lazy = self.lazy_loading();
+        let context = self.terrain_mut().regions_mut().error_context(index, "load chunk");
+        let chunk: LazyChunk<C>;
+        let meta: ChunkMeta;
         {
-            let region = self.terrain_mut().regions_mut().get_for_chunk(index);
-            chunk = match region.read_chunk(index) {
-                Ok(c) => c,
-                Err(e) => return Err(e),
+            let region = self.terrain_mut().regions_mut().get_for_chunk(index)
+                .with_context(|| context.clone())?;
+            let read = if lazy {
+                region.read_chunk_lazy(index)
+            } else {
+                region.read_chunk(index).map(|(c, m)| (LazyChunk::new(c), m))
             };
+            let (c, m) = read.with_context(|| context.clone())?;
+            chunk = c;
+            meta = m;
         }
+        self.load_registry_mut().mark_loaded(index);
 
-        self.load_chunk_internal(chunk, index)?;
+        if lazy {
+            self.load_lazy_chunk_internal(chunk, index)?;
+        } else {
+            let chunk = chunk.into_inner().with_context(|| context)?;
+            self.load_chunk_internal(chunk, index)?;
+        }
 
         assert_eq!(self.terrain().chunk_count(), old_count + 1,
                    "Chunk wasn't inserted into world!");
+        if let Some(m) = self.metrics() { m.counter(metrics::CHUNKS_LOADED, 1); }
+        self.record_provenance(index, ChunkOrigin::Loaded, start.elapsed());
+        self.record_residency(ResidencyAction::Load, index)?;
+
+        let elapsed = self.current_tick().saturating_sub(meta.saved_at);
+        self.on_chunk_reloaded(index, elapsed)
+    }
+
+    /// Returns the record of where loaded chunks came from and how long they
+    /// took, if this world keeps one.
+    fn load_timings(&self) -> Option<&LoadTimings<I>> { None }
+    fn load_timings_mut(&mut self) -> Option<&mut LoadTimings<I>> { None }
+
+    /// Returns whether a loaded chunk was read from disk or generated, and
+    /// how long that took, if this world keeps `LoadTimings`.
+    fn chunk_provenance(&self, index: &I) -> Option<ChunkProvenance> {
+        if !self.terrain().chunk_loaded(index) || self.chunk_is_placeholder(index) {
+            return None;
+        }
+        self.load_timings().and_then(|t| t.provenance(index).cloned())
+    }
+
+    /// Records how a chunk that was just inserted got into the world.
+    fn record_provenance(&mut self, index: &I, origin: ChunkOrigin, elapsed: Duration) {
+        if origin == ChunkOrigin::Generated {
+            if let Some(m) = self.metrics() { m.histogram(metrics::CHUNK_GENERATE_SECONDS, metrics::seconds(elapsed)); }
+        }
+        let tick = self.current_tick();
+        if let Some(timings) = self.load_timings_mut() {
+            timings.record(index, ChunkProvenance {
+                origin: origin,
+                elapsed: elapsed,
+                tick: tick,
+            });
+        }
+    }
+
+    /// Whether chunks loaded from disk are passed to
+    /// `load_lazy_chunk_internal` still compressed, instead of being
+    /// deserialized up front.
+    fn lazy_loading(&self) -> bool { false }
+
+    /// Inserts a chunk loaded from disk that is deserialized on first access.
+    /// Only called when `lazy_loading` returns true. By default the chunk is
+    /// deserialized immediately and passed to `load_chunk_internal`.
+    fn load_lazy_chunk_internal(&mut self, chunk: LazyChunk<C>, index: &I) -> SerialResult<()> {
+        self.load_chunk_internal(chunk.into_inner()?, index)
+    }
+
+    /// Called after a chunk was loaded from disk with the number of ticks
+    /// that passed since it was saved, so the application can simulate what
+    /// happened while it was unloaded.
+    fn on_chunk_reloaded(&mut self, _index: &I, _elapsed_ticks: u64) -> SerialResult<()> {
+        Ok(())
+    }
+
+    /// Generates and inserts the chunk at `index` in full. This is
+    /// `generate_at` with `DetailLevel::Full`.
+    fn generate_chunk(&mut self, index: &I) -> SerialResult<()> {
+        self.generate_at(index, DetailLevel::Full)
+    }
+
+    /// Generates and inserts the chunk at `index` at a detail level, or
+    /// raises the level of the loaded chunk at `index` to it, building on
+    /// what was already generated. Chunks are only ever promoted, never
+    /// generated at a lower level than they have.
+    ///
+    /// Worlds without detail levels can ignore `level` and always generate
+    /// the chunk in full. Worlds with just stubs and full chunks insert a
+    /// stub below `DetailLevel::Full` and promote loaded stubs with
+    /// `promote_chunk`, and worlds with more levels also implement
+    /// `chunk_detail`.
+    fn generate_at(&mut self, index: &I, level: DetailLevel) -> SerialResult<()>;
+
+    /// Returns the rules for the detail level of chunks by their distance to
+    /// observers, if this world uses them.
+    fn detail_rules(&self) -> Option<&DetailRules> { None }
+
+    /// Returns the detail level the chunk at `index` should have now. With
+    /// detail rules it follows `observer_distance`, and otherwise chunks are
+    /// full if `needs_full_detail` says so and stubs if not.
+    fn detail_level(&self, index: &I) -> DetailLevel {
+        if let Some(rules) = self.detail_rules() {
+            return rules.level_at(self.observer_distance(index));
+        }
+        if self.needs_full_detail(index) { DetailLevel::Full } else { DetailLevel::Stub }
+    }
+
+    /// Returns the detail level of the loaded chunk at `index`. By default
+    /// chunks are full unless `chunk_is_stub` says otherwise.
+    fn chunk_detail(&self, index: &I) -> DetailLevel {
+        if self.chunk_is_stub(index) { DetailLevel::Stub } else { DetailLevel::Full }
+    }
+
+    /// Returns whether the chunk at `index` must be fully generated, for
+    /// worlds without detail rules. Chunks that don't are generated with
+    /// `generate_stub` and promoted once this returns true for them.
+    fn needs_full_detail(&self, _index: &I) -> bool {
+        true
+    }
+
+    /// Inserts a stub of the chunk at `index`, such as a `Staged::Stub`. By
+    /// default the chunk is fully generated with `generate_chunk`.
+    fn generate_stub(&mut self, index: &I) -> SerialResult<()> {
+        self.generate_chunk(index)
+    }
+
+    /// Returns whether the loaded chunk at `index` is a stub.
+    fn chunk_is_stub(&self, _index: &I) -> bool {
+        false
+    }
 
+    /// Fully generates the loaded stub at `index` in place.
+    fn promote_chunk(&mut self, _index: &I) -> SerialResult<()> {
         Ok(())
     }
 
-    fn generate_chunk(&mut self, index: &I) -> SerialResult<()>;
+    /// Promotes every loaded chunk among `relevant` whose detail level is
+    /// below `detail_level` with `generate_at`. Returns the number of chunks
+    /// promoted.
+    fn promote_stubs(&mut self, relevant: &FxHashSet<I>) -> SerialResult<usize> {
+        let mut promoted = 0;
+        for idx in relevant.iter() {
+            if !self.terrain().chunk_loaded(idx) || self.chunk_is_placeholder(idx) {
+                continue;
+            }
+            let level = self.detail_level(idx);
+            if level > self.chunk_detail(idx) {
+                self.generate_at(idx, level)?;
+                promoted += 1;
+            }
+        }
+        if promoted > 0 {
+            if let Some(m) = self.metrics() { m.counter(metrics::CHUNKS_PROMOTED, promoted as u64); }
+        }
+        Ok(promoted)
+    }
+
+    /// Inserts a cheap stand-in for the chunk at `index`, like a flat
+    /// pattern, to show while the real chunk is still being read or
+    /// generated. Returns whether one was inserted. By default no
+    /// placeholders are shown.
+    ///
+    /// Placeholders are never saved. They are removed with
+    /// `unload_chunk_internal`, whose result is dropped, so they shouldn't
+    /// hold anything worth keeping.
+    fn insert_placeholder(&mut self, _index: &I) -> SerialResult<bool> {
+        Ok(false)
+    }
+
+    /// Returns whether the loaded chunk at `index` is a placeholder.
+    fn chunk_is_placeholder(&self, index: &I) -> bool {
+        self.load_registry().is_placeholder(index)
+    }
+
+    /// Fills the empty slot of a chunk with a placeholder, if the world
+    /// supplies one. `request_chunks` does this for every chunk it starts
+    /// reading. Returns whether a placeholder was inserted.
+    fn show_placeholder(&mut self, index: &I) -> SerialResult<bool> {
+        if self.terrain().chunk_loaded(index) {
+            return Ok(false);
+        }
+        let old_count = self.terrain().chunk_count();
+        if !self.insert_placeholder(index)? {
+            return Ok(false);
+        }
+
+        assert_eq!(self.terrain().chunk_count(), old_count + 1,
+                   "Placeholder wasn't inserted into world!");
+        self.load_registry_mut().mark_placeholder(index);
+        Ok(true)
+    }
+
+    /// Removes the placeholder at `index` without saving it, making room for
+    /// the real chunk. Returns whether there was one.
+    fn remove_placeholder(&mut self, index: &I) -> SerialResult<bool> {
+        if !self.chunk_is_placeholder(index) {
+            return Ok(false);
+        }
+        let old_count = self.terrain().chunk_count();
+        self.unload_chunk_internal(index)?;
+
+        assert_eq!(self.terrain().chunk_count(), old_count - 1,
+                   "Placeholder wasn't removed from world!");
+        self.load_registry_mut().clear_placeholder(index);
+        Ok(true)
+    }
+
+    /// Removes every placeholder in the world. Returns how many there were.
+    fn remove_placeholders(&mut self) -> SerialResult<usize> {
+        let placeholders = self.load_registry().placeholders();
+        for index in placeholders.iter() {
+            self.remove_placeholder(index)?;
+        }
+        Ok(placeholders.len())
+    }
+
     fn terrain(&self) -> &T;
     fn terrain_mut(&mut self) -> &mut T;
-    fn save(&mut self) -> SerialResult<()>;
 
-    fn load_chunk(&mut self, index: &I) -> SerialResult<()> {
-        match self.load_chunk_from_region(index) {
-            Err(SerialError::NoChunkInSavefile(_)) => {
-                let old_count = self.terrain().chunk_count();
-                if self.terrain().chunk_loaded(index) {
-                    return Err(ChunkAlreadyLoaded(index.x(), index.y()));
+    /// The load state of every chunk, used to keep repeated or overlapping
+    /// loads of the same chunk from inserting it twice.
+    fn load_registry(&self) -> &LoadRegistry<I>;
+    fn load_registry_mut(&mut self) -> &mut LoadRegistry<I>;
+
+    /// Returns the sink this world reports its streaming counters to, if it
+    /// has one.
+    fn metrics(&self) -> Option<&dyn MetricsSink> { None }
+
+    /// Returns the log this world records its residency decisions to, if
+    /// recording is enabled.
+    fn residency_log(&mut self) -> Option<&mut ResidencyLog> { None }
+
+    /// Returns the subscriptions to cells of this world, if it supports
+    /// them. They are notified whenever a chunk is loaded or unloaded.
+    fn cell_subscriptions_mut(&mut self) -> Option<&mut CellSubscriptions> { None }
+
+    /// Returns the store the occupancy of saved chunks is kept in, if this
+    /// world keeps it. See `ManagedChunk::occupancy`.
+    fn occupancy_store_mut(&mut self) -> Option<&mut OccupancyStore> { None }
+
+    /// Returns which cells of a chunk could be walked on when it was last
+    /// saved, without reading the chunk. Returns `None` if the world keeps no
+    /// occupancy or none was recorded for the chunk.
+    fn saved_occupancy(&mut self, index: &I) -> SerialResult<Option<Occupancy>> {
+        let (region, local) = index.partition(C::REGION_WIDTH);
+        match self.occupancy_store_mut() {
+            Some(store) => store.get(&region, &local),
+            None        => Ok(None),
+        }
+    }
+
+    /// Records the occupancy of a chunk that was just saved.
+    fn record_occupancy(&mut self, index: &I, occupancy: Option<Occupancy>) -> SerialResult<()> {
+        let (region, local) = index.partition(C::REGION_WIDTH);
+        match self.occupancy_store_mut() {
+            Some(store) => store.record(&region, &local, occupancy),
+            None        => Ok(()),
+        }
+    }
+
+    /// Returns the times chunks were last saved at, if this world keeps them.
+    fn save_times_mut(&mut self) -> Option<&mut SaveTimes> { None }
+
+    /// Records that a chunk was just saved.
+    fn record_save_time(&mut self, index: &I) -> SerialResult<()> {
+        let (region, local) = index.partition(C::REGION_WIDTH);
+        match self.save_times_mut() {
+            Some(times) => times.record(&region, &local, SystemTime::now()),
+            None        => Ok(()),
+        }
+    }
+
+    /// Returns the wall-clock time a chunk was last saved at, if this world
+    /// keeps save times and the chunk was saved since it started to.
+    fn chunk_saved_at(&mut self, index: &I) -> SerialResult<Option<SystemTime>> {
+        let (region, local) = index.partition(C::REGION_WIDTH);
+        match self.save_times_mut() {
+            Some(times) => times.chunk_saved_at(&region, &local),
+            None        => Ok(None),
+        }
+    }
+
+    /// Returns the wall-clock time any chunk of a region was last saved at.
+    fn region_saved_at(&mut self, region: &RegionIndex) -> SerialResult<Option<SystemTime>> {
+        match self.save_times_mut() {
+            Some(times) => times.region_saved_at(region),
+            None        => Ok(None),
+        }
+    }
+
+    /// Returns the regions on disk with a chunk saved at or after `since`,
+    /// such as for backing up only what changed since the last backup.
+    /// Regions without recorded save times are included, since it isn't
+    /// known when they changed.
+    fn regions_saved_since(&mut self, since: SystemTime) -> SerialResult<Vec<RegionIndex>> {
+        let regions = {
+            let manager = self.terrain_mut().regions_mut();
+            let root = manager.layout().root().to_path_buf();
+            manager.scan_directory(root)?
+        };
+        let mut changed = Vec::new();
+        for region in regions.into_iter() {
+            match self.region_saved_at(&region)? {
+                Some(time) if time < since => (),
+                _                          => changed.push(region),
+            }
+        }
+        Ok(changed)
+    }
+
+    /// Returns the tombstones of chunks to regenerate, if this world keeps
+    /// them.
+    fn tombstones_mut(&mut self) -> Option<&mut ChunkTombstones> { None }
+
+    /// Marks chunks to be generated again instead of read from their region
+    /// files the next time they are loaded, such as to reset an area of a
+    /// server. Loaded chunks are regenerated after they are unloaded. Does
+    /// nothing if this world keeps no tombstones.
+    fn schedule_reset(&mut self, indices: &[I]) -> SerialResult<()> {
+        let tick = self.current_tick();
+        if let Some(tombstones) = self.tombstones_mut() {
+            for index in indices.iter() {
+                let (region, local) = index.partition(C::REGION_WIDTH);
+                tombstones.place(&region, &local, tick)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Removes the tombstone of a chunk. Returns `true` if the chunk had one
+    /// and should be regenerated.
+    fn take_tombstone(&mut self, index: &I) -> SerialResult<bool> {
+        let (region, local) = index.partition(C::REGION_WIDTH);
+        match self.tombstones_mut() {
+            Some(tombstones) => tombstones.take(&region, &local),
+            None             => Ok(false),
+        }
+    }
+
+    /// Generates a chunk again in place of its stale saved copy, which is
+    /// overwritten the next time the chunk is saved.
+    fn regenerate_chunk(&mut self, index: &I) -> SerialResult<()> {
+        self.generate_new_chunk(index)?;
+        let regions = self.terrain_mut().regions_mut();
+        let context = regions.error_context(index, "regenerate chunk");
+        let region = regions.get_for_chunk(index).with_context(|| context.clone())?;
+        <Region<I> as ManagedRegion<'a, I, C>>::mark_as_loaded(region, index).with_context(|| context)
+    }
+
+    /// Returns the lock this world holds on its save, if it takes one. The
+    /// world refuses to load or unload chunks, or to write anything to its
+    /// save, once another process took the lock over.
+    fn world_lock(&self) -> Option<&WorldLock> { None }
+
+    /// Fails with `LogicError::WorldLocked` if another process took over the
+    /// lock of this world.
+    fn check_world_lock(&self) -> SerialResult<()> {
+        match self.world_lock() {
+            Some(lock) => lock.check(),
+            None       => Ok(()),
+        }
+    }
+
+    /// Returns the chunks protected from changes by normal gameplay, if this
+    /// world keeps any.
+    fn chunk_protection(&self) -> Option<&ChunkProtection> { None }
+    fn chunk_protection_mut(&mut self) -> Option<&mut ChunkProtection> { None }
+
+    fn is_protected(&self, index: &I) -> bool {
+        self.chunk_protection().map_or(false, |p| p.is_protected(index))
+    }
+
+    /// Returns `LogicError::ChunkProtected` if normal gameplay may not change
+    /// a chunk. Gameplay code that changes chunks without going through
+    /// `chunk_for_write` should call this first.
+    fn check_writable(&self, index: &I) -> SerialResult<()> {
+        match self.chunk_protection() {
+            Some(protection) => protection.check(index),
+            None             => Ok(()),
+        }
+    }
+
+    /// Returns a loaded chunk for changing it by normal gameplay. Fails with
+    /// `LogicError::ChunkProtected` if the chunk is protected and with
+    /// `NoChunkInWorld` if it isn't loaded or is only a placeholder. Counts
+    /// as a change of the chunk's version, if the world keeps versions.
+    fn chunk_for_write<'w>(&'w mut self, index: &I) -> SerialResult<&'w mut T::Chunk>
+        where T: 'w
+    {
+        self.check_writable(index)?;
+        if self.chunk_is_placeholder(index) || !self.terrain().chunk_loaded(index) {
+            return Err(NoChunkInWorld(index.x(), index.y()).into());
+        }
+        self.bump_chunk_version(index)?;
+        match self.terrain_mut().chunk_mut(index) {
+            Some(chunk) => Ok(chunk),
+            None        => Err(NoChunkInWorld(index.x(), index.y()).into()),
+        }
+    }
+
+    /// Returns the versions of chunks, if this world keeps them.
+    fn chunk_versions_mut(&mut self) -> Option<&mut ChunkVersions> { None }
+
+    /// Returns the number of times a chunk was changed through
+    /// `chunk_for_write` or `edit_chunk`. Always zero if this world keeps no
+    /// versions.
+    fn chunk_version(&mut self, index: &I) -> SerialResult<u64> {
+        let (region, local) = index.partition(C::REGION_WIDTH);
+        match self.chunk_versions_mut() {
+            Some(versions) => versions.version(&region, &local),
+            None           => Ok(0),
+        }
+    }
+
+    /// Records a change to a chunk made without `chunk_for_write`, and
+    /// returns its new version.
+    fn bump_chunk_version(&mut self, index: &I) -> SerialResult<u64> {
+        let (region, local) = index.partition(C::REGION_WIDTH);
+        match self.chunk_versions_mut() {
+            Some(versions) => versions.bump(&region, &local),
+            None           => Ok(0),
+        }
+    }
+
+    /// Changes a loaded chunk only if it is still at the version the edit
+    /// was made against, such as the version a map editor client last saw,
+    /// and returns what `edit` returned along with the chunk's new version.
+    /// Fails with `LogicError::StaleVersion` if another edit came first, in
+    /// which case the editor should fetch the chunk again and retry. Checks
+    /// protection like `chunk_for_write`.
+    fn edit_chunk<F, R>(&mut self, index: &I, expected: u64, edit: F) -> SerialResult<(R, u64)>
+        where F: FnOnce(&mut T::Chunk) -> R
+    {
+        let current = self.chunk_version(index)?;
+        if current != expected {
+            return Err(StaleVersion(index.x(), index.y(), current).into());
+        }
+        let result = edit(self.chunk_for_write(index)?);
+        let version = self.chunk_version(index)?;
+        Ok((result, version))
+    }
+
+    /// Protects chunks from changes by normal gameplay, or lifts their
+    /// protection, such as from an admin command. The chunks don't need to
+    /// be loaded. The change is saved with the world metadata. Does nothing
+    /// if this world keeps no protection.
+    fn set_protected(&mut self, indices: &[I], protected: bool) {
+        if let Some(protection) = self.chunk_protection_mut() {
+            for index in indices.iter() {
+                if protected {
+                    protection.protect(index);
+                } else {
+                    protection.unprotect(index);
                 }
+            }
+        }
+    }
 
-                self.generate_chunk(index)?;
+    /// Returns the first visits of chunks, if this world tracks them.
+    fn visits_mut(&mut self) -> Option<&mut ChunkVisits> { None }
 
-                assert_eq!(self.terrain().chunk_count(), old_count + 1,
-                           "Chunk wasn't inserted into world!");
+    /// Returns the chunks that observers, like players, are in.
+    fn observed_chunks(&self) -> Vec<I> { Vec::new() }
 
-                // The region this chunk was created in needs to know of the chunk
-                // that was created in-game but nonexistent on disk.
-                self.terrain_mut().regions_mut().notify_chunk_creation(index);
-            },
-            Err(e) => panic!("{:?}", e),
-            Ok(()) => (),
+    /// Called the first time an observer is in a chunk, once the chunk is
+    /// loaded, for decorating it or triggering events that must happen only
+    /// once per chunk. Only called if the world tracks visits. The visit is
+    /// recorded after this returns, so a call that fails is retried on the
+    /// next update.
+    fn on_first_visit(&mut self, _index: &I) -> SerialResult<()> {
+        Ok(())
+    }
+
+    /// Returns the tick a chunk was first visited at, or `None` if it wasn't
+    /// visited yet or the world doesn't track visits.
+    fn first_visited_at(&mut self, index: &I) -> SerialResult<Option<u64>> {
+        let (region, local) = index.partition(C::REGION_WIDTH);
+        match self.visits_mut() {
+            Some(visits) => visits.first_visited_at(&region, &local),
+            None         => Ok(None),
+        }
+    }
+
+    /// Calls `on_first_visit` for every loaded chunk an observer is in that
+    /// wasn't visited before.
+    fn update_visits(&mut self) -> SerialResult<()> {
+        if self.visits_mut().is_none() {
+            return Ok(());
+        }
+        for index in self.observed_chunks().iter() {
+            if !self.terrain().chunk_loaded(index) || self.chunk_is_placeholder(index)
+                || self.first_visited_at(index)?.is_some()
+            {
+                continue;
+            }
+            self.on_first_visit(index)?;
+
+            let tick = self.current_tick();
+            let (region, local) = index.partition(C::REGION_WIDTH);
+            if let Some(visits) = self.visits_mut() {
+                visits.record(&region, &local, tick)?;
+            }
         }
         Ok(())
     }
 
-    fn unload_chunk(&mut self, index: &I) -> SerialResult<()> {
-        let old_count = self.terrain().chunk_count();
-        let chunk = match self.unload_chunk_internal(index) {
-            Ok(c) => c,
-            Err(e) => return Err(e),
+    /// Returns the inboxes of chunks, if this world keeps them.
+    fn chunk_inbox_mut(&mut self) -> Option<&mut ChunkInbox> { None }
+
+    /// Posts a message to a chunk, whether it is loaded or not, for effects
+    /// that reach across chunk borders. It is passed to `on_chunk_messages`
+    /// during the first `update_chunks` the chunk is loaded in, and saved
+    /// with the world until then. Returns `false` if this world keeps no
+    /// inboxes.
+    fn post_message<S: Serialize>(&mut self, index: &I, kind: &str, message: &S) -> SerialResult<bool> {
+        let message = ChunkMessage::new(kind, self.current_tick(), message)?;
+        let (region, local) = index.partition(C::REGION_WIDTH);
+        match self.chunk_inbox_mut() {
+            Some(inbox) => inbox.post(&region, &local, message).map(|_| true),
+            None        => Ok(false),
+        }
+    }
+
+    /// Called with the messages posted to a loaded chunk, oldest first. The
+    /// messages are removed after this returns, so a call that fails gets
+    /// them again on the next update.
+    fn on_chunk_messages(&mut self, _index: &I, _messages: &[ChunkMessage]) -> SerialResult<()> {
+        Ok(())
+    }
+
+    /// Calls `on_chunk_messages` for every loaded chunk with messages
+    /// waiting. Placeholders and stubs keep their messages until the chunk
+    /// is fully there.
+    fn deliver_messages(&mut self) -> SerialResult<()> {
+        if self.chunk_inbox_mut().is_none() {
+            return Ok(());
+        }
+        for index in self.terrain().chunk_indices().iter() {
+            if self.chunk_is_placeholder(index) || self.chunk_detail(index) < DetailLevel::Full {
+                continue;
+            }
+            let (region, local) = index.partition(C::REGION_WIDTH);
+            let messages = match self.chunk_inbox_mut() {
+                Some(inbox) => inbox.messages(&region, &local)?.to_vec(),
+                None        => return Ok(()),
+            };
+            if messages.is_empty() {
+                continue;
+            }
+            self.on_chunk_messages(index, &messages)?;
+            if let Some(inbox) = self.chunk_inbox_mut() {
+                inbox.remove(&region, &local, messages.len())?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Appends a residency decision to the log, if there is one, and
+    /// notifies the cell subscriptions.
+    fn record_residency(&mut self, action: ResidencyAction, index: &I) -> SerialResult<()> {
+        if let Some(subs) = self.cell_subscriptions_mut() {
+            match action {
+                ResidencyAction::Load |
+                ResidencyAction::Generate => subs.chunk_loaded(index),
+                ResidencyAction::Unload   => subs.chunk_unloaded(index),
+            }
+        }
+
+        let tick = self.current_tick();
+        match self.residency_log() {
+            Some(log) => log.record(tick, action, index),
+            None      => Ok(()),
+        }
+    }
+
+    /// Repeats the residency decisions of a log against this world, which
+    /// should be freshly created from the same seed, advancing the world
+    /// clock to the tick each decision was made at.
+    fn replay(&mut self, records: &[ResidencyRecord]) -> SerialResult<()> {
+        for record in records.iter() {
+            while self.current_tick() < record.tick {
+                self.advance_tick();
+            }
+
+            let index = record.index::<I>();
+            match record.action {
+                ResidencyAction::Load     => self.load_chunk_from_region(&index)?,
+                ResidencyAction::Generate => {
+                    self.generate_new_chunk(&index)?;
+                    if !self.is_transient() {
+                        self.terrain_mut().regions_mut().notify_chunk_creation(&index)?;
+                    }
+                },
+                ResidencyAction::Unload   => self.unload_chunk(&index)?,
+            }
+        }
+        Ok(())
+    }
+
+    /// Returns whether this world never touches the disk. Chunks in a
+    /// transient world are always generated, unloading simply drops them and
+    /// `save` does nothing. Useful for tests and for tuning generation.
+    fn is_transient(&self) -> bool { false }
+
+    /// Returns the world's metadata, which is saved along with the chunks.
+    fn metadata(&self) -> &WorldMetadata;
+    fn metadata_mut(&mut self) -> &mut WorldMetadata;
+
+    /// Returns the current tick of the world clock.
+    fn current_tick(&self) -> u64 {
+        self.metadata().tick
+    }
+
+    /// Advances the world clock by one tick.
+    fn advance_tick(&mut self) {
+        self.metadata_mut().tick += 1;
+    }
+
+    /// Registers a durable link between two positions, stored in the world
+    /// metadata.
+    fn register_link(&mut self, a: LinkEnd, b: LinkEnd) -> SerialResult<LinkId> {
+        let mut links = LinkTable::load(self.metadata())?;
+        let id = links.register(a, b);
+        links.store(self.metadata_mut())?;
+        Ok(id)
+    }
+
+    fn remove_link(&mut self, id: LinkId) -> SerialResult<bool> {
+        let mut links = LinkTable::load(self.metadata())?;
+        let removed = links.remove(id).is_some();
+        links.store(self.metadata_mut())?;
+        Ok(removed)
+    }
+
+    /// Returns the end of a link opposite to `from`, loading the chunk it
+    /// lies in if it isn't loaded yet. Unless that chunk is relevant, it is
+    /// unloaded again by the next `update_chunks`.
+    fn resolve_link(&mut self, id: LinkId, from: &LinkEnd) -> SerialResult<Option<LinkEnd>> {
+        let end = match LinkTable::load(self.metadata())?.other_end(id, from) {
+            Some(end) => end,
+            None      => return Ok(None),
         };
 
-        assert_eq!(self.terrain().chunk_count(), old_count - 1,
-                   "Chunk wasn't removed from world!");
+        let index = I::from_xy(end.chunk.0, end.chunk.1);
+        if !self.terrain().chunk_loaded(&index) {
+            self.load_chunk(&index)?;
+        }
+        Ok(Some(end))
+    }
+
+    /// Unloads every loaded chunk, writing them to disk, and saves the world
+    /// metadata. Use `save_in_place` to save a world that keeps running.
+    fn save(&mut self) -> SerialResult<()> {
+        if self.is_transient() {
+            return Ok(());
+        }
+        self.check_world_lock()?;
+
+        let indices = self.terrain().chunk_indices();
+        for index in indices.iter() {
+            self.unload_chunk(index)?;
+        }
+        self.terrain_mut().regions_mut().prune_empty()?;
+        self.save_metadata()
+    }
+
+    /// Like `save`, but reports its progress to a callback after every chunk
+    /// and stops compressing once `limit` has passed, writing the remaining
+    /// chunks uncompressed. Uncompressed chunks load like any other and are
+    /// compressed again the next time they are saved. Returns the final
+    /// progress.
+    ///
+    /// Meant for saving when the game quits, which can otherwise take long
+    /// enough for the game to look frozen.
+    fn save_for_shutdown<F>(&mut self, limit: Duration, mut progress: F) -> SerialResult<SaveProgress>
+        where F: FnMut(&SaveProgress)
+    {
+        if self.is_transient() {
+            return Ok(SaveProgress::default());
+        }
+        self.check_world_lock()?;
+
+        let deadline = Instant::now() + limit;
+        self.remove_placeholders()?;
+        let indices = self.terrain().chunk_indices();
+        let mut report = SaveProgress {
+            remaining: indices.len(),
+            ..SaveProgress::default()
+        };
+        progress(&report);
+
+        for index in indices.iter() {
+            let level = if Instant::now() >= deadline {
+                report.uncompressed += 1;
+                Some(CompressionLevel::Stored)
+            } else {
+                None
+            };
+            report.bytes_written += self.unload_chunk_at_level(index, level)? as u64;
+            report.saved += 1;
+            report.remaining -= 1;
+            progress(&report);
+        }
+        self.terrain_mut().regions_mut().prune_empty()?;
+        self.save_metadata()?;
+        Ok(report)
+    }
+
+    /// Like `save`, but serializes and compresses the chunks on the rayon
+    /// thread pool before writing them one region at a time, which is much
+    /// faster for large worlds on machines with several cores. Chunks are
+    /// compressed at the same levels as by `save`.
+    ///
+    /// If a chunk can't be encoded or written, it and every chunk not yet
+    /// written are put back into the world unsaved and the error is returned,
+    /// so the save can be retried.
+    #[cfg(feature = "parallel")]
+    fn save_parallel(&mut self) -> SerialResult<()>
+        where I: Send,
+              C: Send
+    {
+        if self.is_transient() {
+            return self.save();
+        }
+        self.check_world_lock()?;
+
+        self.remove_placeholders()?;
+        if let Some(coalescing) = self.write_coalescing_mut() {
+            coalescing.take_all();
+        }
+        let mut chunks = Vec::new();
+        for index in self.terrain().chunk_indices() {
+            match self.unload_chunk_internal(&index) {
+                Ok(chunk) => {
+                    self.load_registry_mut().mark_unloading(&index);
+                    let level = self.compression_level_for(&index);
+                    chunks.push((index, chunk, level));
+                },
+                Err(e) => {
+                    for (index, chunk, _) in chunks.into_iter() {
+                        self.load_chunk_internal(chunk, &index)?;
+                        self.load_registry_mut().mark_loaded(&index);
+                    }
+                    return Err(e);
+                },
+            }
+        }
+
+        let meta = ChunkMeta {
+            saved_at: self.current_tick(),
+        };
+        let encoded = {
+            profile_span!("encode_chunks", count = chunks.len());
+            parallel::encode_chunks::<I, C>(chunks, &meta)
+        };
+
+        let mut pending = encoded.into_iter();
+        while let Some(entry) = pending.next() {
+            let index = entry.index;
+            if let Some(ref sample) = entry.sample {
+                self.record_compression(&index, sample);
+            }
+            let result = {
+                let regions = self.terrain_mut().regions_mut();
+                let context = regions.error_context(&index, "save chunk");
+                let _io = regions.io_tracker().map(|t| t.begin());
+                entry.encoded
+                    .and_then(|(data, hash)| {
+                        let region = regions.get_for_chunk(&index)?;
+                        <Region<I> as ManagedRegion<'a, I, C>>::save_encoded(region, data, hash, &index)
+                    })
+                    .with_context(|| context)
+            };
+            if let Err(e) = result {
+                self.load_chunk_internal(entry.chunk, &index)?;
+                self.load_registry_mut().mark_loaded(&index);
+                for rest in pending {
+                    self.load_chunk_internal(rest.chunk, &rest.index)?;
+                    self.load_registry_mut().mark_loaded(&rest.index);
+                }
+                return Err(e);
+            }
+
+            if let Some(timings) = self.load_timings_mut() {
+                timings.forget(&index);
+            }
+            self.record_occupancy(&index, entry.chunk.occupancy())?;
+            self.record_save_time(&index)?;
+            self.load_registry_mut().mark_unloaded(&index);
+            if let Some(m) = self.metrics() {
+                m.counter(metrics::CHUNKS_SAVED, 1);
+            }
+            self.record_residency(ResidencyAction::Unload, &index)?;
+        }
+
+        self.terrain_mut().regions_mut().prune_empty()?;
+        self.save_metadata()
+    }
+
+    /// Writes every loaded chunk that changed since it was last saved and the
+    /// world metadata to disk, keeping the chunks loaded. This is the usual
+    /// way to save, such as for an autosave.
+    fn save_in_place(&mut self) -> SerialResult<()> {
+        if self.is_transient() {
+            return Ok(());
+        }
+        self.check_world_lock()?;
+
+        // Every loaded chunk is written below, including those held back.
+        if let Some(coalescing) = self.write_coalescing_mut() {
+            coalescing.take_all();
+        }
+        let indices = self.terrain().chunk_indices();
+        for index in indices.iter() {
+            self.save_chunk_in_place(index)?;
+        }
+        self.terrain_mut().regions_mut().flush_regions()?;
+        self.save_metadata()
+    }
+
+    /// Same as `save_in_place`.
+    fn flush(&mut self) -> SerialResult<()> {
+        self.save_in_place()
+    }
+
+    /// Flushes this world and copies its save into a new world directory,
+    /// such as for a "new game plus" or for experimenting on a branch of a
+    /// live world. Returns the layout of the copy, for creating a world that
+    /// uses it.
+    fn fork<P: AsRef<Path>>(&mut self, dest: P) -> SerialResult<RegionLayout> {
+        self.flush()?;
+        let layout = self.terrain_mut().regions_mut().layout().clone();
+        fork::copy_world(&layout, dest)
+    }
+
+    /// Writes the world metadata, with the time it was saved at and, on the
+    /// first save, the region parameters, along with the occupancy, save
+    /// times, first visits, tombstones, inboxes and versions of chunks to
+    /// disk.
+    fn save_metadata(&mut self) -> SerialResult<()> {
+        if self.is_transient() {
+            return Ok(());
+        }
+        self.check_world_lock()?;
+
+        if let Some(store) = self.occupancy_store_mut() {
+            store.flush()?;
+        }
+        if let Some(times) = self.save_times_mut() {
+            times.flush()?;
+        }
+        if let Some(visits) = self.visits_mut() {
+            visits.flush()?;
+        }
+        if let Some(tombstones) = self.tombstones_mut() {
+            tombstones.flush()?;
+        }
+        if let Some(inbox) = self.chunk_inbox_mut() {
+            inbox.flush()?;
+        }
+        if let Some(versions) = self.chunk_versions_mut() {
+            versions.flush()?;
+        }
+        if let Some(protection) = self.chunk_protection().cloned() {
+            protection.save(self.metadata_mut())?;
+        }
+        RegionConfig::of::<C>().check_world(self.metadata_mut())?;
+        self.metadata_mut().set_last_saved(SystemTime::now())?;
+
+        let path = self.terrain_mut().regions_mut().layout().metadata_path();
+        self.metadata().save(&path).with_context(|| {
+            ErrorContext::new("save world metadata").with_path(&path)
+        })
+    }
+
+    /// Returns the indices of all chunks that should currently be loaded.
+    fn relevant_chunks(&self) -> FxHashSet<I>;
+
+    /// Returns the providers of extra chunks this world keeps loaded, if it
+    /// has any.
+    fn relevance_providers(&self) -> Option<&RelevanceProviders<I>> { None }
+
+    /// Returns the leases held on the chunks of this world, if it hands any
+    /// out.
+    fn chunk_leases(&self) -> Option<&ChunkLeases<I>> { None }
+
+    /// Takes a lease keeping a loaded chunk from being unloaded by
+    /// `update_chunks` until it is dropped. Returns `None` if the chunk isn't
+    /// loaded or this world hands out no leases.
+    fn lease_chunk(&self, index: &I) -> Option<ChunkLease<I>> {
+        if !self.terrain().chunk_loaded(index) {
+            return None;
+        }
+        self.chunk_leases().map(|leases| leases.lease(index))
+    }
+
+    fn is_leased(&self, index: &I) -> bool {
+        self.chunk_leases().map_or(false, |leases| leases.is_leased(index))
+    }
+
+    /// Returns the relevant chunks together with the chunks of every
+    /// relevance provider. This is the set `update_chunks` keeps loaded.
+    fn all_relevant_chunks(&self) -> FxHashSet<I> {
+        let mut relevant = self.relevant_chunks();
+        if let Some(providers) = self.relevance_providers() {
+            relevant.extend(providers.chunks());
+        }
+        relevant
+    }
+
+    /// Returns the indices of all chunks that should currently be simulated.
+    /// Only chunks that are also relevant and loaded become active.
+    fn active_chunks(&self) -> FxHashSet<I> {
+        FxHashSet::default()
+    }
+
+    /// Called when a loaded chunk enters the active set.
+    fn on_chunk_activated(&mut self, _index: &I) -> SerialResult<()> {
+        Ok(())
+    }
+
+    /// Called when a chunk leaves the active set, before it is unloaded if it
+    /// is also no longer relevant.
+    fn on_chunk_deactivated(&mut self, _index: &I) -> SerialResult<()> {
+        Ok(())
+    }
+
+    /// Updates the active set of the relevance policy and calls the
+    /// activation hooks for every chunk that entered or left it.
+    fn update_active_chunks(&mut self, relevant: &FxHashSet<I>) -> SerialResult<()> {
+        let active = self.active_chunks().into_iter()
+            .filter(|idx| relevant.contains(idx) && self.terrain().chunk_loaded(idx))
+            .collect();
+        let changes = match self.relevance_policy_mut() {
+            Some(policy) => policy.update_active(active),
+            None         => return Ok(()),
+        };
+
+        for change in changes.into_iter() {
+            match change {
+                ChunkActivity::Activated(idx)   => self.on_chunk_activated(&idx)?,
+                ChunkActivity::Deactivated(idx) => self.on_chunk_deactivated(&idx)?,
+            }
+        }
+        Ok(())
+    }
+
+    /// Returns the relevance policy of this world, if it uses one. Chunks
+    /// pinned by the policy are saved on their own schedule during
+    /// `update_chunks` and `update_chunks_with_deadline`.
+    fn relevance_policy_mut(&mut self) -> Option<&mut RelevancePolicy<I>> {
+        None
+    }
+
+    /// Sets the size of the viewport in cells, for relevance policies made
+    /// with `RelevancePolicy::with_viewport`. Chunks that come into view are
+    /// loaded by the next `update_chunks`.
+    fn set_viewport(&mut self, width: i32, height: i32) {
+        if let Some(policy) = self.relevance_policy_mut() {
+            policy.set_viewport(width, height);
+        }
+    }
+
+    /// Saves the chunks of every pinned set of the relevance policy whose save
+    /// interval has elapsed, keeping them loaded. The saves go through
+    /// `save_chunk_coalesced`.
+    fn save_pinned_chunks(&mut self) -> SerialResult<()> {
+        if self.is_transient() {
+            return Ok(());
+        }
+
+        let now = self.current_tick();
+        let due = match self.relevance_policy_mut() {
+            Some(policy) => policy.due_for_save(now),
+            None         => return Ok(()),
+        };
+
+        for idx in due.iter() {
+            if self.terrain().chunk_loaded(idx) {
+                self.save_chunk_coalesced(idx)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Loads every relevant chunk that isn't loaded and unloads every loaded
+    /// chunk that is no longer relevant, unless it is leased.
+    fn update_chunks(&mut self) -> SerialResult<()> {
+        self.check_world_lock()?;
+        let relevant = self.all_relevant_chunks();
+
+        let mut to_load: Vec<I> = relevant.iter()
+            .filter(|idx| !self.terrain().chunk_loaded(idx) || self.chunk_is_placeholder(idx))
+            .cloned()
+            .collect();
+        self.sort_load_order(&mut to_load);
+        for idx in to_load.iter() {
+            if self.admit_load(idx) {
+                self.load_chunk(idx)?;
+            }
+        }
+
+        self.promote_stubs(&relevant)?;
+        self.update_active_chunks(&relevant)?;
+        self.update_visits()?;
+        self.deliver_messages()?;
+
+        let indices = self.terrain().chunk_indices();
+        for idx in indices.iter() {
+            if !relevant.contains(idx) && self.terrain().chunk_loaded(idx) && !self.is_leased(idx) {
+                self.unload_chunk(idx)?;
+            }
+        }
+
+        self.save_pinned_chunks()?;
+        self.flush_due_writes()?;
+        self.terrain_mut().regions_mut().prune_empty()?;
+        self.report_gauges();
+
+        Ok(())
+    }
+
+    /// Reports the number of resident chunks and open regions to the metrics
+    /// sink.
+    fn report_gauges(&mut self) {
+        let chunks = self.terrain().chunk_count();
+        let regions = self.terrain_mut().regions_mut().region_indices().len();
+        if let Some(m) = self.metrics() {
+            m.gauge(metrics::CHUNKS_RESIDENT, chunks as f64);
+            m.gauge(metrics::REGIONS_OPEN, regions as f64);
+        }
+    }
+
+    /// Describes the chunks and borders inside a viewport of world cells,
+    /// for drawing a debug overlay. `chunk_width` is the width of a chunk in
+    /// cells.
+    fn debug_overlay(&mut self, viewport: CellRect, chunk_width: i32) -> DebugOverlay<I> {
+        let mut overlay: DebugOverlay<I> = DebugOverlay::grid(viewport, chunk_width, C::REGION_WIDTH);
+        for chunk in overlay.chunks.iter_mut() {
+            let index = chunk.index.clone();
+            let loaded = self.terrain().chunk_loaded(&index);
+            chunk.state = match self.load_registry().state(&index) {
+                LoadState::NotLoaded if loaded => LoadState::Loaded,
+                state                          => state,
+            };
+            chunk.placeholder = self.chunk_is_placeholder(&index);
+            chunk.stub = loaded && !chunk.placeholder && self.chunk_detail(&index) < DetailLevel::Full;
+            chunk.leased = self.is_leased(&index);
+            chunk.protected = self.is_protected(&index);
+            if let Some(policy) = self.relevance_policy_mut() {
+                chunk.pinned = policy.is_pinned(&index);
+                chunk.active = policy.is_active(&index);
+            }
+            if let Some(region) = self.terrain_mut().regions_mut().get(&chunk.region) {
+                chunk.unsaved = <Region<I> as ManagedRegion<'a, I, C>>::lifetimes(region).state(&index) == Some(ChunkLifetime::Created);
+            }
+        }
+        overlay
+    }
+
+    /// Like `update_chunks`, but stops loading and unloading chunks once the
+    /// given amount of wall-clock time has passed. Returns `true` if work
+    /// remains to be done on a later call.
+    ///
+    /// At least one chunk is always loaded or unloaded if any work is pending,
+    /// so repeated calls with a small budget still make progress.
+    fn update_chunks_with_deadline(&mut self, budget: Duration) -> SerialResult<bool> {
+        self.check_world_lock()?;
+        let deadline = Instant::now() + budget;
+        let relevant = self.all_relevant_chunks();
+
+        let mut to_load: Vec<I> = relevant.iter()
+            .filter(|idx| !self.terrain().chunk_loaded(idx) || self.chunk_is_placeholder(idx))
+            .cloned()
+            .collect();
+        self.sort_load_order(&mut to_load);
+        let to_unload: Vec<I> = self.terrain().chunk_indices().into_iter()
+            .filter(|idx| !relevant.contains(idx) && !self.is_leased(idx))
+            .collect();
+
+        let mut done = 0;
+        let total = to_load.len() + to_unload.len();
+
+        for idx in to_load.iter() {
+            if done > 0 && Instant::now() >= deadline {
+                break;
+            }
+            if !self.admit_load(idx) {
+                continue;
+            }
+            self.load_chunk(idx)?;
+            done += 1;
+        }
+
+        self.promote_stubs(&relevant)?;
+        self.update_active_chunks(&relevant)?;
+        self.update_visits()?;
+        self.deliver_messages()?;
+
+        for idx in to_unload.iter() {
+            if done > 0 && Instant::now() >= deadline {
+                break;
+            }
+            if self.terrain().chunk_loaded(idx) {
+                self.unload_chunk(idx)?;
+            }
+            done += 1;
+        }
+
+        self.save_pinned_chunks()?;
+        self.flush_due_writes()?;
+        self.terrain_mut().regions_mut().prune_empty()?;
+        self.report_gauges();
+
+        Ok(done < total)
+    }
+
+    /// Generates a chunk that doesn't exist in the world yet.
+    fn generate_new_chunk(&mut self, index: &I) -> SerialResult<()> {
+        profile_span!("generate_chunk", x = index.x(), y = index.y());
+        self.remove_placeholder(index)?;
+        let old_count = self.terrain().chunk_count();
+        if self.terrain().chunk_loaded(index) {
+            return Err(ChunkAlreadyLoaded(index.x(), index.y()).into());
+        }
+
+        let start = Instant::now();
+        let level = self.detail_level(index);
+        self.generate_at(index, level)?;
+
+        assert_eq!(self.terrain().chunk_count(), old_count + 1,
+                   "Chunk wasn't inserted into world!");
+        self.load_registry_mut().mark_loaded(index);
+        if let Some(m) = self.metrics() { m.counter(metrics::CHUNKS_GENERATED, 1); }
+        self.record_provenance(index, ChunkOrigin::Generated, start.elapsed());
+        self.record_residency(ResidencyAction::Generate, index)
+    }
+
+    /// Returns the policy deferring chunk loads while the region manager's
+    /// `io_tracker` counts too many operations in flight, if this world uses
+    /// one. Operations on the updating thread are finished by the time a
+    /// load is admitted, so this throttles loads against background loads
+    /// and saves running on other threads.
+    fn admission_policy(&self) -> Option<AdmissionPolicy> { None }
+
+    /// Returns how many chunks away from the nearest observer a chunk is.
+    /// Loads of nearer chunks are more urgent.
+    fn observer_distance(&self, _index: &I) -> i32 { 0 }
+
+    /// Sorts chunks in the order they are loaded by `update_chunks`: nearest
+    /// to an observer first by `observer_distance`, and chunks at the same
+    /// distance in spiral order around the nearest of the `observed_chunks`,
+    /// so terrain fills in outward from the player. See `indices_spiral`.
+    fn sort_load_order(&self, indices: &mut Vec<I>) {
+        let observed = self.observed_chunks();
+        indices.sort_by_key(|idx| {
+            let rank = observed.iter().map(|center| spiral_rank(center, idx)).min().unwrap_or(0);
+            (self.observer_distance(idx), rank)
+        });
+    }
+
+    /// Returns whether a load of a chunk may start now according to the
+    /// admission policy. Deferred loads are retried on the next update.
+    fn admit_load(&mut self, index: &I) -> bool {
+        let policy = match self.admission_policy() {
+            Some(p) => p,
+            None    => return true,
+        };
+        let in_flight = self.terrain_mut().regions_mut().io_tracker().map_or(0, |t| t.in_flight());
+        let admitted = policy.admits(in_flight, self.observer_distance(index));
+        if !admitted {
+            if let Some(m) = self.metrics() { m.counter(metrics::LOADS_DEFERRED, 1); }
+        }
+        admitted
+    }
+
+    /// Returns what to do when a chunk can't be read from its region file.
+    fn recovery_policy(&self) -> RecoveryPolicy {
+        RecoveryPolicy::Panic
+    }
+
+    /// Called with the error of a chunk that couldn't be read when the
+    /// recovery policy is `AskCallback`. Returning `AskCallback` panics.
+    fn choose_recovery(&mut self, _index: &I, _error: &SerialError) -> RecoveryPolicy {
+        RecoveryPolicy::Panic
+    }
+
+    /// Called when a chunk that couldn't be read is left unloaded or
+    /// regenerated, such as to show the error to the player. The chunk is
+    /// also counted in `metrics::CHUNKS_SKIPPED`, and with the `tracing`
+    /// feature reported as a warning.
+    fn on_chunk_skipped(&mut self, _index: &I, _error: &SerialError) {}
+
+    /// Handles a chunk that couldn't be read according to the recovery
+    /// policy.
+    fn recover_chunk(&mut self, index: &I, error: SerialError) -> SerialResult<()> {
+        let mut policy = self.recovery_policy();
+        if policy == RecoveryPolicy::AskCallback {
+            policy = self.choose_recovery(index, &error);
+        }
+
+        let regenerate = match policy {
+            RecoveryPolicy::Panic |
+            RecoveryPolicy::AskCallback     => panic!("Failed to load chunk: {}", error),
+            RecoveryPolicy::RegenerateChunk => true,
+            RecoveryPolicy::SkipAndLog      => false,
+        };
+
+        #[cfg(feature = "tracing")]
+        warn!(x = index.x(), y = index.y(), "couldn't read chunk: {}", error);
+        if let Some(m) = self.metrics() { m.counter(metrics::CHUNKS_SKIPPED, 1); }
+        self.on_chunk_skipped(index, &error);
+
+        // A chunk that failed to decode after it was read is still tracked
+        // by its region.
+        if !self.is_transient() {
+            self.terrain_mut().regions_mut().get_for_chunk(index)?.lifetimes.forget(index);
+        }
+
+        if regenerate {
+            self.generate_new_chunk(index)?;
+            if !self.is_transient() {
+                self.terrain_mut().regions_mut().notify_chunk_creation(index)?;
+            }
+        } else {
+            self.load_registry_mut().mark_failed(index);
+        }
+        Ok(())
+    }
+
+    fn load_chunk(&mut self, index: &I) -> SerialResult<()> {
+        profile_span!("load_chunk", x = index.x(), y = index.y());
+        if self.load_registry().state(index) == LoadState::Failed {
+            return Ok(());
+        }
+        let loaded = self.terrain().chunk_loaded(index) && !self.chunk_is_placeholder(index);
+        if self.load_registry().state(index) == LoadState::Loaded || loaded {
+            if let Some(m) = self.metrics() { m.counter(metrics::CHUNK_CACHE_HITS, 1); }
+            return Ok(());
+        }
+        if let Some(m) = self.metrics() { m.counter(metrics::CHUNK_CACHE_MISSES, 1); }
+        let start = Instant::now();
+        self.remove_placeholder(index)?;
+
+        if self.is_transient() {
+            self.generate_new_chunk(index)?;
+        } else if self.take_tombstone(index)? {
+            self.regenerate_chunk(index)?;
+        } else {
+            match self.load_chunk_from_region(index) {
+                Err(ref e) if e.is_missing_chunk() => {
+                    self.generate_new_chunk(index)?;
+
+                    // The region this chunk was created in needs to know of the chunk
+                    // that was created in-game but nonexistent on disk.
+                    self.terrain_mut().regions_mut().notify_chunk_creation(index)?;
+                },
+                Err(e) => self.recover_chunk(index, e)?,
+                Ok(()) => (),
+            }
+        }
+
+        if let Some(m) = self.metrics() { m.histogram(metrics::CHUNK_LOAD_SECONDS, metrics::seconds(start.elapsed())); }
+        Ok(())
+    }
+
+    /// Starts loading the given chunks on a background thread and returns a
+    /// handle for each, so the application can keep running while they are
+    /// read. Chunks that are already loaded get a handle that is ready
+    /// immediately. Chunks being read are shown as placeholders in the
+    /// meantime, if the world supplies them.
+    ///
+    /// Requesting a chunk that is already being loaded supersedes the earlier
+    /// request, so only the newest handle of a chunk inserts it.
+    #[cfg(feature = "async")]
+    fn request_chunks(&mut self, indices: &[I]) -> Vec<ChunkLoadHandle<I, C>>
+        where I: Send + 'static,
+              C: Send + 'static
+    {
+        let mut handles = Vec::new();
+        let mut jobs = Vec::new();
+
+        for index in indices.iter() {
+            let ticket = match self.load_registry_mut().begin_load(index) {
+                Some(t) if !self.terrain().chunk_loaded(index) || self.chunk_is_placeholder(index) => t,
+                _ => {
+                    handles.push(ChunkLoadHandle::ready(index.clone(), None, Ok(None)));
+                    continue;
+                },
+            };
+
+            if self.is_transient() {
+                handles.push(ChunkLoadHandle::ready(index.clone(), Some(ticket), Ok(None)));
+                continue;
+            }
+
+            // Loading the region here makes sure its file exists and has a
+            // valid header before it is read from another thread.
+            if let Err(e) = self.terrain_mut().regions_mut().get_for_chunk(index) {
+                handles.push(ChunkLoadHandle::ready(index.clone(), Some(ticket), Err(e)));
+                continue;
+            }
+            if let Err(e) = self.show_placeholder(index) {
+                handles.push(ChunkLoadHandle::ready(index.clone(), Some(ticket), Err(e)));
+                continue;
+            }
+
+            let (region_index, local_index) = index.partition(C::REGION_WIDTH);
+            let path = self.terrain_mut().regions_mut().layout().region_path(&region_index);
+            let io = self.terrain_mut().regions_mut().io_tracker().map(|t| t.begin());
+            let (tx, rx) = mpsc::channel();
+            jobs.push((path, local_index, tx, io));
+            handles.push(ChunkLoadHandle::pending(index.clone(), ticket, rx));
+        }
+
+        if !jobs.is_empty() {
+            thread::spawn(move || {
+                for (path, local_index, tx, io) in jobs.into_iter() {
+                    let _ = tx.send(load_handle::read_chunk_at::<C, _>(path, &local_index));
+                    drop(io);
+                }
+            });
+        }
+
+        handles
+    }
+
+    /// Inserts a chunk requested with `request_chunks` into the world,
+    /// blocking until it has been read. Chunks that weren't saved are
+    /// generated instead. Handles that were superseded by a later request or
+    /// load of the same chunk are discarded.
+    #[cfg(feature = "async")]
+    fn finish_chunk_load(&mut self, handle: ChunkLoadHandle<I, C>) -> SerialResult<()> {
+        let index = handle.index().clone();
+        let start = handle.requested_at();
+        let ticket = match handle.ticket() {
+            Some(t) => t,
+            None    => return Ok(()),
+        };
+        let result = handle.into_result();
+        if !self.load_registry().is_current(&index, ticket) {
+            return Ok(());
+        }
+
+        let finished = if !self.is_transient() && self.take_tombstone(&index)? {
+            self.regenerate_chunk(&index)
+        } else {
+            match result {
+                Err(e) => {
+                    self.load_registry_mut().cancel_load(&index, ticket);
+                    self.remove_placeholder(&index)?;
+                    self.recover_chunk(&index, e)
+                },
+                Ok(Some((chunk, meta))) => {
+                    self.remove_placeholder(&index)?;
+                    let old_count = self.terrain().chunk_count();
+                    {
+                        let region = self.terrain_mut().regions_mut().get_for_chunk(&index)?;
+                        <Region<I> as ManagedRegion<'a, I, C>>::mark_as_loaded(region, &index)?;
+                    }
+                    self.load_chunk_internal(chunk, &index)?;
+
+                    assert_eq!(self.terrain().chunk_count(), old_count + 1,
+                               "Chunk wasn't inserted into world!");
+                    self.load_registry_mut().mark_loaded(&index);
+                    if let Some(m) = self.metrics() { m.counter(metrics::CHUNKS_LOADED, 1); }
+                    self.record_provenance(&index, ChunkOrigin::Loaded, start.elapsed());
+                    self.record_residency(ResidencyAction::Load, &index)?;
+
+                    let elapsed = self.current_tick().saturating_sub(meta.saved_at);
+                    self.on_chunk_reloaded(&index, elapsed)
+                },
+                Ok(None) => {
+                    self.generate_new_chunk(&index)?;
+                    if !self.is_transient() {
+                        self.terrain_mut().regions_mut().notify_chunk_creation(&index)?;
+                    }
+                    Ok(())
+                },
+            }
+        };
+
+        if let Some(m) = self.metrics() { m.histogram(metrics::CHUNK_LOAD_SECONDS, metrics::seconds(start.elapsed())); }
+        finished
+    }
+
+    /// Inserts every requested chunk that has finished reading and removes
+    /// its handle. Returns the number of chunks that were inserted.
+    #[cfg(feature = "async")]
+    fn finish_ready_loads(&mut self, handles: &mut Vec<ChunkLoadHandle<I, C>>) -> SerialResult<usize> {
+        let mut finished = 0;
+        let mut i = 0;
+        while i < handles.len() {
+            if handles[i].is_ready() {
+                let handle = handles.remove(i);
+                self.finish_chunk_load(handle)?;
+                finished += 1;
+            } else {
+                i += 1;
+            }
+        }
+        Ok(finished)
+    }
+
+    /// Unloads several chunks and saves them as a group, so either all of
+    /// them or none of them reach the region files even if the process dies
+    /// partway through. Use this for chunks that share state across their
+    /// border, like an entity moving from one to the other.
+    ///
+    /// Every chunk is encoded before any is removed. If removing or saving
+    /// them fails, the chunks that were removed are put back, so they stay
+    /// loaded for the save to be retried. A group that was only partly
+    /// written stays in the journal and is finished before anything else is
+    /// saved.
+    fn unload_chunks_atomically(&mut self, indices: &[I]) -> SerialResult<()> {
+        for index in indices.iter() {
+            if !self.terrain().chunk_loaded(index) || self.chunk_is_placeholder(index) {
+                return Err(NoChunkInWorld(index.x(), index.y()).into());
+            }
+        }
+        self.check_world_lock()?;
+        self.replay_journal()?;
+
+        let meta = ChunkMeta {
+            saved_at: self.current_tick(),
+        };
+        let mut journal = SaveJournal::new();
+        let mut occupancies = Vec::new();
+        if !self.is_transient() {
+            for index in indices.iter() {
+                let encode = |chunk: &C| {
+                    <Region<I> as ManagedRegion<'a, I, C>>::encode_chunk(chunk, &meta)
+                        .map(|encoded| (encoded, chunk.occupancy()))
+                };
+                let ((data, hash), occupancy) = match self.snapshot_chunk(index)? {
+                    Some(chunk) => encode(&chunk)?,
+                    None        => {
+                        let chunk = self.unload_chunk_internal(index)?;
+                        let encoded = encode(&chunk);
+                        self.load_chunk_internal(chunk, index)?;
+                        encoded?
+                    },
+                };
+                journal.push((index.x(), index.y()), data, hash);
+                occupancies.push((index.clone(), occupancy));
+            }
+        }
+
+        let mut chunks = Vec::new();
+        let mut result = Ok(());
+        for index in indices.iter() {
+            let old_count = self.terrain().chunk_count();
+            match self.unload_chunk_internal(index) {
+                Ok(chunk) => {
+                    assert_eq!(self.terrain().chunk_count(), old_count - 1,
+                               "Chunk wasn't removed from world!");
+                    self.load_registry_mut().mark_unloading(index);
+                    chunks.push((index.clone(), chunk));
+                },
+                Err(e) => {
+                    result = Err(e);
+                    break;
+                },
+            }
+        }
+
+        if result.is_ok() && !self.is_transient() {
+            let path = self.terrain_mut().regions_mut().layout().journal_path();
+            result = journal.commit(&path)
+                .with_context(|| ErrorContext::new("write save journal").with_path(&path))
+                .and_then(|_| self.apply_journal(journal))
+                .and_then(|_| SaveJournal::clear(&path));
+        }
+
+        // A committed journal is kept if writing the group failed, so the
+        // next save or `replay_journal` finishes it.
+        if let Err(e) = result {
+            for (index, chunk) in chunks.into_iter() {
+                match self.load_chunk_internal(chunk, &index) {
+                    Ok(()) => self.load_registry_mut().mark_loaded(&index),
+                    Err(_) => self.load_registry_mut().mark_unloaded(&index),
+                }
+            }
+            return Err(e);
+        }
+
+        for index in indices.iter() {
+            self.load_registry_mut().mark_unloaded(index);
+        }
+        if !self.is_transient() {
+            if let Some(m) = self.metrics() { m.counter(metrics::CHUNKS_SAVED, indices.len() as u64); }
+            for (index, occupancy) in occupancies.into_iter() {
+                self.record_occupancy(&index, occupancy)?;
+                self.record_save_time(&index)?;
+            }
+        }
+        for index in indices.iter() {
+            self.record_residency(ResidencyAction::Unload, index)?;
+        }
+        Ok(())
+    }
+
+    /// Finishes a save group that was interrupted by a crash or a failed
+    /// write, if there is one. Should be called once before any chunks are
+    /// loaded. Returns
+    /// whether a group was finished.
+    fn replay_journal(&mut self) -> SerialResult<bool> {
+        if self.is_transient() {
+            return Ok(false);
+        }
+        self.check_world_lock()?;
+
+        let path = self.terrain_mut().regions_mut().layout().journal_path();
+        let journal = SaveJournal::load(&path)
+            .with_context(|| ErrorContext::new("read save journal").with_path(&path))?;
+        match journal {
+            Some(journal) => {
+                self.apply_journal(journal)?;
+                SaveJournal::clear(&path)?;
+                Ok(true)
+            },
+            None => Ok(false),
+        }
+    }
+
+    /// Writes every chunk of a committed save group to its region file and
+    /// syncs the files. Chunks of the group that were put back into the
+    /// world stay tracked as loaded.
+    fn apply_journal(&mut self, journal: SaveJournal) -> SerialResult<()> {
+        for entry in journal.entries.into_iter() {
+            let index = I::from_xy(entry.chunk.0, entry.chunk.1);
+            let (_, local_index) = index.partition(C::REGION_WIDTH);
+            let loaded = self.terrain().chunk_loaded(&index);
+            let regions = self.terrain_mut().regions_mut();
+            let context = regions.error_context(&index, "save chunk");
+            let region = regions.get_for_chunk(&index).with_context(|| context.clone())?;
+
+            <Region<I> as ManagedRegion<'a, I, C>>::write_encoded(region, entry.data, &local_index)
+                .with_context(|| context.clone())?;
+            if loaded {
+                if !region.lifetimes.is_unsaved(&index) {
+                    <Region<I> as ManagedRegion<'a, I, C>>::mark_as_loaded(region, &index)
+                        .with_context(|| context.clone())?;
+                }
+            } else {
+                region.lifetimes.forget(&index);
+            }
+            <Region<I> as ManagedRegion<'a, I, C>>::cache_hash(region, &index, entry.hash);
+            region.handle.sync_all().with_context(|| context)?;
+        }
+        Ok(())
+    }
+
+    /// Writes every loaded chunk that changed since it was last saved to the
+    /// crash journal, uncompressed so it is quick, followed by a crash marker
+    /// naming `reason`. The region files aren't touched, since they may be
+    /// what failed. The chunks stay loaded. Returns the number of chunks
+    /// written.
+    ///
+    /// Meant for calling when the game is about to die, either through
+    /// `run_with_crash_save` or from the game's own handling of signals.
+    /// Signal handlers themselves can't safely touch the world, so they should
+    /// only set a flag that the main loop checks. The chunks are moved into
+    /// the region files by `recover_crash_save` on the next start.
+    fn crash_save(&mut self, reason: &str) -> SerialResult<usize> {
+        if self.is_transient() {
+            return Ok(0);
+        }
+        self.check_world_lock()?;
+
+        let meta = ChunkMeta {
+            saved_at: self.current_tick(),
+        };
+        let mut journal = SaveJournal::new();
+        for index in self.terrain().chunk_indices() {
+            if self.chunk_is_placeholder(&index) {
+                continue;
+            }
+            let encode = |chunk: &C| {
+                <Region<I> as ManagedRegion<'a, I, C>>::encode_chunk_with_level(chunk, &meta, CompressionLevel::Stored)
+            };
+            let (data, hash) = match self.snapshot_chunk(&index)? {
+                Some(chunk) => encode(&chunk)?,
+                None        => {
+                    let chunk = self.unload_chunk_internal(&index)?;
+                    let encoded = encode(&chunk);
+                    self.load_chunk_internal(chunk, &index)?;
+                    encoded?
+                },
+            };
+            // A chunk whose saved copy can't be read is written anyway.
+            let saved_hash = self.terrain_mut().regions_mut().chunk_hash(&index).unwrap_or(None);
+            if saved_hash != Some(hash) {
+                journal.push((index.x(), index.y()), data, hash);
+            }
+        }
+
+        let layout = self.terrain_mut().regions_mut().layout().clone();
+        let path = layout.crash_journal_path();
+        journal.commit(&path)
+            .with_context(|| ErrorContext::new("write crash journal").with_path(&path))?;
+
+        let marker = CrashMarker {
+            tick: self.current_tick(),
+            reason: reason.to_string(),
+            chunks: journal.entries.len(),
+        };
+        let path = layout.crash_marker_path();
+        marker.save(&path)
+            .with_context(|| ErrorContext::new("write crash marker").with_path(&path))?;
+        Ok(marker.chunks)
+    }
+
+    /// Runs `f` with this world, and if it panics, calls `crash_save` with
+    /// the panic message before letting the panic continue.
+    fn run_with_crash_save<F, R>(&mut self, f: F) -> R
+        where F: FnOnce(&mut Self) -> R,
+              Self: Sized
+    {
+        let result = panic::catch_unwind(AssertUnwindSafe(|| f(self)));
+        match result {
+            Ok(r) => r,
+            Err(payload) => {
+                let _ = self.crash_save(&panic_message(&payload));
+                panic::resume_unwind(payload)
+            },
+        }
+    }
+
+    /// Moves the chunks of a crash journal left by `crash_save` into the
+    /// region files and removes the journal and crash marker. Returns `None`
+    /// if the world didn't crash. Should be called when the world is opened,
+    /// before any chunks are loaded, after `replay_journal`.
+    fn recover_crash_save(&mut self) -> SerialResult<Option<CrashRecovery>> {
+        if self.is_transient() {
+            return Ok(None);
+        }
+        self.check_world_lock()?;
+
+        let layout = self.terrain_mut().regions_mut().layout().clone();
+        let (journal_path, marker_path) = (layout.crash_journal_path(), layout.crash_marker_path());
+        let marker = CrashMarker::load(&marker_path)
+            .with_context(|| ErrorContext::new("read crash marker").with_path(&marker_path))?;
+        let journal = SaveJournal::load(&journal_path)
+            .with_context(|| ErrorContext::new("read crash journal").with_path(&journal_path))?;
+        if marker.is_none() && journal.is_none() {
+            return Ok(None);
+        }
+
+        let chunks = match journal {
+            Some(journal) => {
+                let count = journal.entries.len();
+                self.apply_journal(journal)?;
+                SaveJournal::clear(&journal_path)?;
+                count
+            },
+            None => 0,
+        };
+        if marker.is_some() {
+            fs::remove_file(&marker_path)
+                .with_context(|| ErrorContext::new("remove crash marker").with_path(&marker_path))?;
+        }
+        Ok(Some(CrashRecovery {
+            marker: marker,
+            chunks: chunks,
+        }))
+    }
+
+    /// Returns the chooser of compression levels, if this world picks them
+    /// from measurements instead of `ManagedChunk::compression_level`.
+    fn adaptive_compression(&self) -> Option<&AdaptiveCompression> { None }
+    fn adaptive_compression_mut(&mut self) -> Option<&mut AdaptiveCompression> { None }
+
+    /// Encodes a chunk for saving. Worlds with `AdaptiveCompression` use the
+    /// level it picks for the chunk's region, and record what compressing
+    /// the chunk cost.
+    fn encode_for_save(&mut self, chunk: &C, index: &I, meta: &ChunkMeta) -> SerialResult<(Vec<u8>, u64)> {
+        let level = match self.compression_level_for(index) {
+            Some(level) => level,
+            None        => return <Region<I> as ManagedRegion<'a, I, C>>::encode_chunk(chunk, meta),
+        };
+
+        let (data, hash, sample) = <Region<I> as ManagedRegion<'a, I, C>>::encode_chunk_sampled(chunk, meta, level)?;
+        self.record_compression(index, &sample);
+        Ok((data, hash))
+    }
+
+    /// Returns the level `AdaptiveCompression` picks for the chunk at
+    /// `index`, or `None` if the world doesn't use it.
+    fn compression_level_for(&mut self, index: &I) -> Option<CompressionLevel> {
+        let (region, _) = index.partition(C::REGION_WIDTH);
+        self.adaptive_compression_mut().map(|adaptive| adaptive.level_for(&region))
+    }
+
+    /// Tells `AdaptiveCompression` and the metrics sink how compressing the
+    /// chunk at `index` went.
+    fn record_compression(&mut self, index: &I, sample: &CompressionSample) {
+        let (region, _) = index.partition(C::REGION_WIDTH);
+        if let Some(adaptive) = self.adaptive_compression_mut() {
+            adaptive.record(&region, sample);
+        }
+        if let Some(m) = self.metrics() {
+            m.histogram(metrics::CHUNK_COMPRESSION_RATIO, sample.compressed_bytes as f64 / sample.raw_bytes.max(1) as f64);
+            m.histogram(metrics::CHUNK_COMPRESS_SECONDS, metrics::seconds(sample.elapsed));
+        }
+    }
+
+    /// Removes a chunk from the world and saves it. If the disk is full, the
+    /// chunk is put back into the world unsaved and `SerialError::DiskFull`
+    /// is returned, so the save can be retried. Placeholders are removed
+    /// without being saved.
+    fn unload_chunk(&mut self, index: &I) -> SerialResult<()> {
+        self.unload_chunk_at_level(index, None).map(|_| ())
+    }
+
+    /// Like `unload_chunk`, but compresses the chunk at the given level
+    /// instead of its own if one is given. Returns the number of bytes
+    /// written.
+    fn unload_chunk_at_level(&mut self, index: &I, level: Option<CompressionLevel>) -> SerialResult<usize> {
+        profile_span!("save_chunk", x = index.x(), y = index.y());
+        if self.remove_placeholder(index)? {
+            return Ok(0);
+        }
+        self.check_world_lock()?;
+        self.replay_journal()?;
+        let start = Instant::now();
+        let (region, _) = index.partition(C::REGION_WIDTH);
+        if let Some(coalescing) = self.write_coalescing_mut() {
+            coalescing.cancel(&region, index);
+        }
+        if let Some(timings) = self.load_timings_mut() {
+            timings.forget(index);
+        }
+        let old_count = self.terrain().chunk_count();
+        let chunk = match self.unload_chunk_internal(index) {
+            Ok(c) => c,
+            Err(e) => return Err(e),
+        };
+
+        assert_eq!(self.terrain().chunk_count(), old_count - 1,
+                   "Chunk wasn't removed from world!");
+
+        if self.is_transient() {
+            self.load_registry_mut().mark_unloaded(index);
+            return self.record_residency(ResidencyAction::Unload, index).map(|_| 0);
+        }
+
+        self.load_registry_mut().mark_unloading(index);
+        let meta = ChunkMeta {
+            saved_at: self.current_tick(),
+        };
+        let encoded = match level {
+            Some(level) => <Region<I> as ManagedRegion<'a, I, C>>::encode_chunk_with_level(&chunk, &meta, level),
+            None        => self.encode_for_save(&chunk, index, &meta),
+        };
+        let result = {
+            let regions = self.terrain_mut().regions_mut();
+            let context = regions.error_context(index, "save chunk");
+            let _io = regions.io_tracker().map(|t| t.begin());
+            encoded
+                .and_then(|(data, hash)| {
+                    let len = data.len();
+                    let region = regions.get_for_chunk(index)?;
+                    <Region<I> as ManagedRegion<'a, I, C>>::save_encoded(region, data, hash, index).map(|_| len)
+                })
+                .with_context(|| context)
+        };
+        let written = match result {
+            Ok(written) => written,
+            Err(e) => {
+                if e.is_disk_full() {
+                    self.load_chunk_internal(chunk, index)?;
+                    self.load_registry_mut().mark_loaded(index);
+                } else {
+                    self.load_registry_mut().mark_unloaded(index);
+                }
+                return Err(e);
+            },
+        };
+        self.record_occupancy(index, chunk.occupancy())?;
+        self.record_save_time(index)?;
+        self.load_registry_mut().mark_unloaded(index);
+        if let Some(m) = self.metrics() {
+            m.counter(metrics::CHUNKS_SAVED, 1);
+            m.histogram(metrics::CHUNK_SAVE_SECONDS, metrics::seconds(start.elapsed()));
+        }
+        self.record_residency(ResidencyAction::Unload, index)?;
+        Ok(written)
+    }
+
+    /// Writes a loaded chunk to disk if it changed since it was last saved,
+    /// keeping it loaded. Returns whether the chunk was written.
+    fn save_chunk_in_place(&mut self, index: &I) -> SerialResult<bool> {
+        if self.is_transient() || self.chunk_is_placeholder(index) {
+            return Ok(false);
+        }
+        self.check_world_lock()?;
+        self.replay_journal()?;
+        let chunk = match self.snapshot_chunk(index)? {
+            Some(chunk) => chunk,
+            None        => {
+                self.unload_chunk(index)?;
+                self.load_chunk(index)?;
+                return Ok(true);
+            },
+        };
+
+        let start = Instant::now();
+        let meta = ChunkMeta {
+            saved_at: self.current_tick(),
+        };
+        let encoded = self.encode_for_save(&chunk, index, &meta);
+        {
+            let regions = self.terrain_mut().regions_mut();
+            let context = regions.error_context(index, "save chunk in place");
+            let _io = regions.io_tracker().map(|t| t.begin());
+            let (data, hash) = encoded.with_context(|| context.clone())?;
+            if regions.chunk_hash(index).with_context(|| context.clone())? == Some(hash) {
+                return Ok(false);
+            }
+
+            // The chunk stays in memory, so it is tracked as loaded from the
+            // copy just written.
+            let region = regions.get_for_chunk(index)?;
+            <Region<I> as ManagedRegion<'a, I, C>>::save_encoded(region, data, hash, index)
+                .and_then(|_| <Region<I> as ManagedRegion<'a, I, C>>::mark_as_loaded(region, index))
+                .with_context(|| context)?;
+        }
+        self.record_occupancy(index, chunk.occupancy())?;
+        self.record_save_time(index)?;
+        if let Some(m) = self.metrics() {
+            m.counter(metrics::CHUNKS_SAVED, 1);
+            m.histogram(metrics::CHUNK_SAVE_SECONDS, metrics::seconds(start.elapsed()));
+        }
+        Ok(true)
+    }
+
+    /// Returns the coalescer holding back in-place saves, if this world uses
+    /// one.
+    fn write_coalescing_mut(&mut self) -> Option<&mut WriteCoalescing<I>> { None }
+
+    /// Saves a loaded chunk like `save_chunk_in_place`, but through the
+    /// write coalescer if this world has one, so the chunk is written
+    /// together with the rest of its region once the coalescing window has
+    /// passed. Returns whether anything was written now.
+    fn save_chunk_coalesced(&mut self, index: &I) -> SerialResult<bool> {
+        if self.is_transient() || self.chunk_is_placeholder(index) {
+            return Ok(false);
+        }
+        let (region, _) = index.partition(C::REGION_WIDTH);
+        let flush_now = match self.write_coalescing_mut() {
+            Some(coalescing) => coalescing.defer(region.clone(), index.clone(), Instant::now()),
+            None             => return self.save_chunk_in_place(index),
+        };
+        if flush_now {
+            Ok(self.flush_region_writes(&region)? > 0)
+        } else {
+            Ok(false)
+        }
+    }
+
+    /// Writes every chunk of a region the write coalescer holds back. Chunks
+    /// that were unloaded since were written then. Returns the number of
+    /// chunks written.
+    fn flush_region_writes(&mut self, region: &RegionIndex) -> SerialResult<usize> {
+        let chunks = match self.write_coalescing_mut() {
+            Some(coalescing) => coalescing.take(region),
+            None             => return Ok(0),
+        };
+        let mut written = 0;
+        for idx in chunks.iter() {
+            if self.terrain().chunk_loaded(idx) && self.save_chunk_in_place(idx)? {
+                written += 1;
+            }
+        }
+        Ok(written)
+    }
+
+    /// Writes the chunks of every region whose coalescing window has passed.
+    /// Called by `update_chunks`. Returns the number of chunks written.
+    fn flush_due_writes(&mut self) -> SerialResult<usize> {
+        let due = match self.write_coalescing_mut() {
+            Some(coalescing) => coalescing.due(Instant::now()),
+            None             => return Ok(0),
+        };
+        let mut written = 0;
+        for region in due.iter() {
+            written += self.flush_region_writes(region)?;
+        }
+        Ok(written)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs::OpenOptions;
+    use std::io::{Seek, SeekFrom, Write};
+    use format::{self, HEADER_SIZE};
+    use links::LinkEnd;
+    use relevance::InterestShape;
+    use testing::{corrupt_sidecar, TempDir, TestChunk, TestWorld};
+    use tombstone::TOMBSTONES_EXTENSION;
+
+    #[test]
+    fn test_load_and_unload() {
+        let dir = TempDir::new("world-load-unload");
+        let mut world = TestWorld::new(dir.layout());
+        let (a, b) = (TestIndex(1, 2), TestIndex(-5, 3));
+        world.edit(a, 5);
+        world.edit(b, 6);
+        assert_eq!(world.generated, 2);
+        assert_eq!(world.load_registry().state(&a), LoadState::Loaded);
+
+        world.unload_chunk(&a).unwrap();
+        assert!(!world.terrain().chunk_loaded(&a));
+        assert_eq!(world.load_registry().state(&a), LoadState::NotLoaded);
+
+        world.load_chunk(&a).unwrap();
+        assert_eq!(world.edits(a), Some(5));
+        assert_eq!(world.generated, 2);
+        assert_eq!(world.metrics.count(metrics::CHUNKS_LOADED), 1);
+
+        // Loading a loaded chunk does nothing.
+        world.chunks.get_mut(&a).unwrap().edits = 7;
+        world.load_chunk(&a).unwrap();
+        assert_eq!(world.edits(a), Some(7));
+        assert!(world.unload_chunk(&TestIndex(9, 9)).is_err());
+    }
+
+    #[test]
+    fn test_unload_failure_keeps_chunk() {
+        let dir = TempDir::new("world-unload-failure");
+        let mut world = TestWorld::new(dir.layout());
+        let index = TestIndex(0, 0);
+        world.edit(index, 3);
+        world.fail_unload.insert(index);
+        assert!(world.unload_chunk(&index).is_err());
+        assert!(world.save().is_err());
+        assert_eq!(world.edits(index), Some(3));
+
+        world.fail_unload.clear();
+        world.save().unwrap();
+        assert_eq!(world.terrain().chunk_count(), 0);
+    }
+
+    #[test]
+    fn test_update_chunks() {
+        let dir = TempDir::new("world-update-chunks");
+        let mut world = TestWorld::new(dir.layout());
+        let (a, b) = (TestIndex(0, 0), TestIndex(4, 0));
+        world.relevant = vec![a, b].into_iter().collect();
+        world.update_chunks().unwrap();
+        assert_eq!(world.terrain().chunk_count(), 2);
+        world.chunks.get_mut(&a).unwrap().edits = 1;
+
+        world.relevant.remove(&a);
+        world.update_chunks().unwrap();
+        assert!(!world.terrain().chunk_loaded(&a));
+        assert!(world.terrain().chunk_loaded(&b));
+        // The region of `a` has nothing loaded anymore and was closed.
+        assert_eq!(world.terrain_mut().regions_mut().region_indices(), vec![RegionIndex(1, 0)]);
+
+        world.relevant.insert(a);
+        assert!(!world.update_chunks_with_deadline(Duration::from_secs(60)).unwrap());
+        assert_eq!(world.edits(a), Some(1));
+        assert_eq!(world.generated, 2);
+    }
+
+    #[test]
+    fn test_save_and_reopen() {
+        let dir = TempDir::new("world-save-reopen");
+        let mut world = TestWorld::new(dir.layout());
+        world.edit(TestIndex(0, 0), 1);
+        world.edit(TestIndex(-1, -1), 2);
+        world.advance_tick();
+        world.save().unwrap();
+        assert_eq!(world.terrain().chunk_count(), 0);
+        assert!(world.terrain_mut().regions_mut().region_indices().is_empty());
+
+        let metadata = WorldMetadata::load(dir.layout().metadata_path()).unwrap();
+        assert_eq!(metadata.tick, 1);
+        assert!(metadata.last_saved().unwrap().is_some());
+
+        let mut world = TestWorld::new(dir.layout());
+        world.load_chunk(&TestIndex(0, 0)).unwrap();
+        world.load_chunk(&TestIndex(-1, -1)).unwrap();
+        assert_eq!(world.edits(TestIndex(0, 0)), Some(1));
+        assert_eq!(world.edits(TestIndex(-1, -1)), Some(2));
+        assert_eq!(world.generated, 0);
+    }
+
+    #[test]
+    fn test_save_in_place() {
+        let dir = TempDir::new("world-save-in-place");
+        let mut world = TestWorld::new(dir.layout());
+        let index = TestIndex(2, 1);
+        world.edit(index, 4);
+        world.save_in_place().unwrap();
+        assert_eq!(world.edits(index), Some(4));
+        assert!(!world.save_chunk_in_place(&index).unwrap());
+
+        world.chunks.get_mut(&index).unwrap().edits = 8;
+        assert!(world.save_chunk_in_place(&index).unwrap());
+
+        let mut other = TestWorld::new(dir.layout());
+        other.load_chunk(&index).unwrap();
+        assert_eq!(other.edits(index), Some(8));
+    }
+
+    #[test]
+    fn test_resolve_link() {
+        let dir = TempDir::new("world-resolve-link");
+        let mut world = TestWorld::new(dir.layout());
+        let (a, b) = (LinkEnd::new((0, 0), (1, 1)), LinkEnd::new((6, -2), (3, 0)));
+        let id = world.register_link(a, b).unwrap();
+
+        assert_eq!(world.resolve_link(id, &a).unwrap(), Some(b));
+        assert!(world.terrain().chunk_loaded(&TestIndex(6, -2)));
+        assert_eq!(world.resolve_link(id, &LinkEnd::new((0, 0), (0, 0))).unwrap(), None);
+
+        // Links are kept in the metadata, so they survive a restart.
+        world.save().unwrap();
+        let mut world = TestWorld::new(dir.layout());
+        *world.metadata_mut() = WorldMetadata::load(dir.layout().metadata_path()).unwrap();
+        assert_eq!(world.resolve_link(id, &b).unwrap(), Some(a));
+        assert!(world.terrain().chunk_loaded(&TestIndex(0, 0)));
+
+        assert!(world.remove_link(id).unwrap());
+        assert!(!world.remove_link(id).unwrap());
+        assert_eq!(world.resolve_link(id, &a).unwrap(), None);
+    }
+
+    #[cfg(feature = "async")]
+    #[test]
+    fn test_request_chunks() {
+        let dir = TempDir::new("world-request-chunks");
+        let mut world = TestWorld::new(dir.layout());
+        let (saved, new) = (TestIndex(1, 0), TestIndex(9, 9));
+        world.edit(saved, 2);
+        world.save().unwrap();
+
+        let mut world = TestWorld::new(dir.layout());
+        let mut handles = world.request_chunks(&[saved, new]);
+        for handle in handles.iter_mut() {
+            handle.wait();
+        }
+        assert_eq!(world.finish_ready_loads(&mut handles).unwrap(), 2);
+        assert!(handles.is_empty());
+        assert_eq!(world.edits(saved), Some(2));
+        assert_eq!(world.edits(new), Some(0));
+        assert_eq!(world.generated, 1);
+        assert_eq!(world.chunk_provenance(&saved).unwrap().origin, ChunkOrigin::Loaded);
+        assert_eq!(world.chunk_provenance(&new).unwrap().origin, ChunkOrigin::Generated);
+        assert_eq!(world.load_timings().unwrap().loaded().count, 1);
+        assert_eq!(world.metrics.count(metrics::CHUNK_LOAD_SECONDS), 2);
+
+        // Only the newest request of a chunk inserts it.
+        world.unload_chunk(&saved).unwrap();
+        let first = world.request_chunks(&[saved]).pop().unwrap();
+        let second = world.request_chunks(&[saved]).pop().unwrap();
+        world.finish_chunk_load(first).unwrap();
+        assert!(!world.terrain().chunk_loaded(&saved));
+        world.finish_chunk_load(second).unwrap();
+        assert_eq!(world.edits(saved), Some(2));
+
+        // Requests of loaded chunks are ready at once and change nothing.
+        let mut handles = world.request_chunks(&[saved]);
+        assert!(handles[0].is_ready());
+        world.finish_ready_loads(&mut handles).unwrap();
+        assert_eq!(world.terrain().chunk_count(), 2);
+    }
+
+    #[cfg(feature = "parallel")]
+    #[test]
+    fn test_save_parallel() {
+        let dir = TempDir::new("world-save-parallel");
+        let mut world = TestWorld::new(dir.layout());
+        let indices: Vec<_> = (-3..3).map(|i| TestIndex(i * 3, i)).collect();
+        for (i, index) in indices.iter().enumerate() {
+            world.edit(*index, i as u32);
+        }
+
+        // A chunk that can't be removed leaves every chunk loaded.
+        world.fail_unload.insert(indices[4]);
+        assert!(world.save_parallel().is_err());
+        assert_eq!(world.terrain().chunk_count(), indices.len());
+        assert_eq!(world.load_registry().state(&indices[0]), LoadState::Loaded);
+
+        // Chunks are compressed at the level adaptive compression picks, and
+        // forgotten by the load timings once saved.
+        world.fail_unload.clear();
+        world.adaptive = Some(AdaptiveCompression::new(Duration::from_millis(1)));
+        world.save_parallel().unwrap();
+        assert_eq!(world.terrain().chunk_count(), 0);
+        assert_eq!(world.metrics.count(metrics::CHUNKS_SAVED), indices.len());
+        assert_eq!(world.metrics.count(metrics::CHUNK_COMPRESSION_RATIO), indices.len());
+        assert!(indices.iter().all(|i| world.load_timings().unwrap().provenance(i).is_none()));
+
+        let mut world = TestWorld::new(dir.layout());
+        for (i, index) in indices.iter().enumerate() {
+            world.load_chunk(index).unwrap();
+            assert_eq!(world.edits(*index), Some(i as u32));
+        }
+        assert_eq!(world.generated, 0);
+    }
+
+    #[test]
+    fn test_save_for_shutdown() {
+        let dir = TempDir::new("world-save-for-shutdown");
+        let mut world = TestWorld::new(dir.layout());
+        for i in 0..4 {
+            world.edit(TestIndex(i, -i), i as u32);
+        }
+
+        let mut reports = Vec::new();
+        let done = world.save_for_shutdown(Duration::from_secs(0), |p| reports.push(p.clone())).unwrap();
+        assert_eq!(reports.len(), 5);
+        assert_eq!(reports[0].remaining, 4);
+        assert!(reports.windows(2).all(|w| w[0].fraction() < w[1].fraction()));
+        assert_eq!(done, reports[4]);
+        assert_eq!((done.saved, done.remaining, done.uncompressed), (4, 0, 4));
+        assert!(done.bytes_written > 0);
+        assert_eq!(world.terrain().chunk_count(), 0);
+
+        // Chunks written uncompressed load like any other.
+        let mut world = TestWorld::new(dir.layout());
+        for i in 0..4 {
+            world.load_chunk(&TestIndex(i, -i)).unwrap();
+            assert_eq!(world.edits(TestIndex(i, -i)), Some(i as u32));
+        }
+        let done = world.save_for_shutdown(Duration::from_secs(60), |_| ()).unwrap();
+        assert_eq!((done.saved, done.uncompressed), (4, 0));
+    }
+
+    #[test]
+    fn test_pinned_saves() {
+        let dir = TempDir::new("world-pinned-saves");
+        let mut world = TestWorld::new(dir.layout());
+        let mut policy = RelevancePolicy::new(InterestShape::Square(0));
+        let (spawn, other) = (TestIndex(0, 0), TestIndex(1, 0));
+        policy.pin("spawn", vec![spawn], Some(10));
+        world.relevance = Some(policy);
+        world.relevant.insert(other);
+
+        // Both ways of updating save the pinned chunks once their interval
+        // has passed, and only those.
+        for &deadline in [false, true].iter() {
+            let update = |world: &mut TestWorld| {
+                if deadline {
+                    world.update_chunks_with_deadline(Duration::from_secs(60)).map(|_| ())
+                } else {
+                    world.update_chunks()
+                }
+            };
+            update(&mut world).unwrap();
+            world.chunks.get_mut(&spawn).unwrap().edits += 1;
+            world.chunks.get_mut(&other).unwrap().edits += 1;
+            let saved = world.metrics.count(metrics::CHUNKS_SAVED);
+
+            update(&mut world).unwrap();
+            assert_eq!(world.metrics.count(metrics::CHUNKS_SAVED), saved);
+            for _ in 0..10 {
+                world.advance_tick();
+            }
+            update(&mut world).unwrap();
+            assert_eq!(world.metrics.count(metrics::CHUNKS_SAVED), saved + 1);
+
+            let mut reader = TestWorld::new(dir.layout());
+            reader.load_chunk(&spawn).unwrap();
+            assert_eq!(reader.edits(spawn), world.edits(spawn));
+            reader.load_chunk(&other).unwrap();
+            assert_eq!(reader.edits(other), Some(0));
+        }
+    }
+
+    #[test]
+    fn test_unload_chunks_atomically() {
+        let dir = TempDir::new("world-unload-atomically");
+        let mut world = TestWorld::new(dir.layout());
+        let (a, b) = (TestIndex(0, 0), TestIndex(4, 0));
+        world.edit(a, 1);
+        world.edit(b, 2);
+
+        // A chunk that can't be removed puts back the ones removed before it.
+        world.fail_unload.insert(b);
+        assert!(world.unload_chunks_atomically(&[a, b]).is_err());
+        assert_eq!((world.edits(a), world.edits(b)), (Some(1), Some(2)));
+        world.fail_unload.clear();
+
+        // So does a journal that can't be written.
+        let journal = dir.layout().journal_path();
+        fs::create_dir_all(journal.with_extension("tmp")).unwrap();
+        assert!(world.unload_chunks_atomically(&[a, b]).is_err());
+        assert_eq!((world.edits(a), world.edits(b)), (Some(1), Some(2)));
+        assert_eq!(world.load_registry().state(&a), LoadState::Loaded);
+        assert!(!journal.exists());
+        fs::remove_dir(journal.with_extension("tmp")).unwrap();
+
+        // A group that was only partly written is kept in the journal and
+        // finished before the next save.
+        let region_b = RegionIndex(1, 0);
+        let region_path = dir.layout().region_path(&region_b);
+        world.terrain_mut().regions_mut().remove(&region_b);
+        fs::remove_file(&region_path).unwrap();
+        fs::create_dir(&region_path).unwrap();
+        assert!(world.unload_chunks_atomically(&[a, b]).is_err());
+        assert_eq!((world.edits(a), world.edits(b)), (Some(1), Some(2)));
+        assert!(journal.exists());
+        fs::remove_dir(&region_path).unwrap();
+        world.chunks.get_mut(&a).unwrap().edits = 3;
+        world.unload_chunk(&a).unwrap();
+        assert!(!journal.exists());
+        let mut reader = TestWorld::new(dir.layout());
+        reader.load_chunk(&a).unwrap();
+        reader.load_chunk(&b).unwrap();
+        assert_eq!((reader.edits(a), reader.edits(b)), (Some(3), Some(2)));
+        world.chunks.get_mut(&b).unwrap().edits = 4;
+
+        world.unload_chunks_atomically(&[b]).unwrap();
+        assert_eq!(world.terrain().chunk_count(), 0);
+        assert!(!journal.exists());
+        drop(reader);
+        let mut reader = TestWorld::new(dir.layout());
+        reader.load_chunk(&a).unwrap();
+        reader.load_chunk(&b).unwrap();
+        assert_eq!((reader.edits(a), reader.edits(b)), (Some(3), Some(4)));
+    }
+
+    #[test]
+    fn test_world_lock_taken_over() {
+        let dir = TempDir::new("world-lock-taken-over");
+        let mut world = TestWorld::new(dir.layout());
+        let index = TestIndex(0, 0);
+        world.edit(index, 1);
+        world.relevant.insert(TestIndex(4, 0));
+        world.lock = Some(WorldLock::acquire(&dir.layout()).unwrap());
+
+        // Another process took the lock over.
+        let owner = world.lock.as_ref().unwrap().owner().clone();
+        fs::write(dir.layout().lock_path(), format!("pid {}\nhost {}\nsince {}\n", owner.pid + 1, owner.host, owner.since)).unwrap();
+        assert!(world.update_chunks().unwrap_err().is_world_locked());
+        assert!(world.update_chunks_with_deadline(Duration::from_secs(1)).unwrap_err().is_world_locked());
+        assert!(world.unload_chunk(&index).unwrap_err().is_world_locked());
+        assert!(world.unload_chunks_atomically(&[index]).unwrap_err().is_world_locked());
+        assert!(world.save_chunk_in_place(&index).unwrap_err().is_world_locked());
+        assert_eq!(world.terrain().chunk_count(), 1);
+        assert_eq!(world.edits(index), Some(1));
+    }
+
+    #[test]
+    fn test_skip_truncated_chunk() {
+        let dir = TempDir::new("world-skip-truncated");
+        let mut world = TestWorld::new(dir.layout());
+        let index = TestIndex(1, 1);
+        world.edit(index, 3);
+        world.save().unwrap();
+
+        // A length prefix claiming more data than was stored.
+        let config = RegionConfig::of::<TestChunk>();
+        let mut file = OpenOptions::new().read(true).write(true)
+            .open(dir.layout().region_path(&RegionIndex(0, 0))).unwrap();
+        let (offset, _) = format::read_entry(&mut file, &config, HEADER_SIZE, &RegionLocalIndex(1, 1)).unwrap().unwrap();
+        file.seek(SeekFrom::Start(offset)).unwrap();
+        file.write_all(&[1, 1, 1, 1]).unwrap();
+        drop(file);
+
+        let mut reader = TestWorld::new(dir.layout());
+        reader.recovery = RecoveryPolicy::SkipAndLog;
+        reader.load_chunk(&index).unwrap();
+        assert!(!reader.terrain().chunk_loaded(&index));
+        assert_eq!(reader.load_registry().state(&index), LoadState::Failed);
+        assert_eq!(reader.metrics.count(metrics::CHUNKS_SKIPPED), 1);
+    }
+
+    #[test]
+    fn test_sidecars_across_saves() {
+        let dir = TempDir::new("sidecars-across-saves");
+        let mut world = TestWorld::with_sidecars(dir.layout());
+        world.edit(TestIndex(0, 0), 5);
+        world.edit(TestIndex(1, 0), 7);
+        world.save().unwrap();
+
+        // A saved chunk is regenerated the next time it is loaded.
+        world.schedule_reset(&[TestIndex(0, 0)]).unwrap();
+        world.load_chunk(&TestIndex(0, 0)).unwrap();
+        assert_eq!(world.edits(TestIndex(0, 0)), Some(0));
+
+        // A loaded chunk keeps its changes until it is unloaded.
+        world.load_chunk(&TestIndex(1, 0)).unwrap();
+        world.schedule_reset(&[TestIndex(1, 0)]).unwrap();
+        assert_eq!(world.edits(TestIndex(1, 0)), Some(7));
+        world.unload_chunk(&TestIndex(1, 0)).unwrap();
+        world.load_chunk(&TestIndex(1, 0)).unwrap();
+        assert_eq!(world.edits(TestIndex(1, 0)), Some(0));
+
+        world.advance_tick();
+        world.observed = vec![TestIndex(0, 0)];
+        world.update_visits().unwrap();
+        let (_, version) = world.edit_chunk(&TestIndex(0, 0), 0, |c| c.edits = 1).unwrap();
+        assert_eq!(version, 1);
+        world.chunk_for_write(&TestIndex(0, 0)).unwrap().edits = 2;
+        world.save().unwrap();
+
+        let mut world = TestWorld::with_sidecars(dir.layout());
+        assert_eq!(world.first_visited_at(&TestIndex(0, 0)).unwrap(), Some(1));
+        assert_eq!(world.first_visited_at(&TestIndex(1, 0)).unwrap(), None);
+        assert_eq!(world.chunk_version(&TestIndex(0, 0)).unwrap(), 2);
+        world.load_chunk(&TestIndex(0, 0)).unwrap();
+        assert_eq!(world.edits(TestIndex(0, 0)), Some(2));
+        match world.edit_chunk(&TestIndex(0, 0), 1, |c| c.edits = 3) {
+            Err(SerialError::Logic(StaleVersion(0, 0, 2))) => (),
+            other => panic!("expected a stale version, got {:?}", other.map(|r| r.1)),
+        }
+        assert_eq!(world.edits(TestIndex(0, 0)), Some(2));
+    }
+
+    #[test]
+    fn test_corrupt_tombstones_fail_load() {
+        let dir = TempDir::new("corrupt-tombstones-load");
+        let mut world = TestWorld::with_sidecars(dir.layout());
+        world.edit(TestIndex(0, 0), 5);
+        world.save().unwrap();
+        corrupt_sidecar(&dir.layout(), &RegionIndex(0, 0), TOMBSTONES_EXTENSION);
 
-        let region = self.terrain_mut().regions_mut().get_for_chunk(index);
-        region.write_chunk(chunk, index)
+        // The saved copy may have been reset, so it isn't loaded either.
+        let mut world = TestWorld::with_sidecars(dir.layout());
+        assert!(world.load_chunk(&TestIndex(0, 0)).is_err());
+        assert!(!world.chunk_loaded(&TestIndex(0, 0)));
+        assert_eq!(world.generated, 0);
     }
 }
@@ -0,0 +1,80 @@
+use error::SerialResult;
+use layout::RegionLayout;
+use region::{RegionIndex, RegionLocalIndex};
+use sidecar::RegionSidecar;
+
+/// Extension appended to region file names for the files holding the
+/// versions of their chunks.
+pub const VERSIONS_EXTENSION: &'static str = "ver";
+
+/// Keeps a version number for every chunk that is incremented each time it
+/// is changed, so editors working on the same world, like the clients of a
+/// collaborative map editor, can tell when their copy of a chunk went stale.
+/// See `ChunkedWorld::edit_chunk`.
+///
+/// Chunks that were never changed are at version zero. The versions are
+/// kept in a small file next to each region file, so they keep counting up
+/// across unloads and restarts. The files are read the first time a region
+/// is looked up and written by `flush`.
+pub struct ChunkVersions {
+    chunks: RegionSidecar<u64>,
+}
+
+impl ChunkVersions {
+    pub fn new(layout: RegionLayout) -> Self {
+        ChunkVersions {
+            chunks: RegionSidecar::new(layout, VERSIONS_EXTENSION),
+        }
+    }
+
+    pub fn version(&mut self, region: &RegionIndex, local: &RegionLocalIndex) -> SerialResult<u64> {
+        Ok(self.chunks.get(region, local)?.cloned().unwrap_or(0))
+    }
+
+    /// Records a change to a chunk and returns its new version.
+    pub fn bump(&mut self, region: &RegionIndex, local: &RegionLocalIndex) -> SerialResult<u64> {
+        let version = self.version(region, local)?.wrapping_add(1);
+        self.chunks.set(region, local, Some(version))?;
+        Ok(version)
+    }
+
+    /// Writes the versions of every region that changed since the last
+    /// flush.
+    pub fn flush(&mut self) -> SerialResult<()> {
+        self.chunks.flush()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use testing::{corrupt_sidecar, TempDir};
+
+    #[test]
+    fn test_versions() {
+        let dir = TempDir::new("versions");
+        let (region, local) = (RegionIndex(0, -1), RegionLocalIndex(2, 2));
+
+        let mut versions = ChunkVersions::new(dir.layout());
+        assert_eq!(versions.version(&region, &local).unwrap(), 0);
+        assert_eq!(versions.bump(&region, &local).unwrap(), 1);
+        assert_eq!(versions.bump(&region, &local).unwrap(), 2);
+        versions.flush().unwrap();
+
+        let mut versions = ChunkVersions::new(dir.layout());
+        assert_eq!(versions.version(&region, &local).unwrap(), 2);
+        assert_eq!(versions.version(&region, &RegionLocalIndex(0, 0)).unwrap(), 0);
+    }
+
+    #[test]
+    fn test_corrupt_versions() {
+        let dir = TempDir::new("versions-corrupt");
+        let (region, local) = (RegionIndex(0, 0), RegionLocalIndex(1, 0));
+        corrupt_sidecar(&dir.layout(), &region, VERSIONS_EXTENSION);
+
+        // Starting over at zero would let stale edits through.
+        let mut versions = ChunkVersions::new(dir.layout());
+        assert!(versions.version(&region, &local).is_err());
+        assert!(versions.bump(&region, &local).is_err());
+    }
+}
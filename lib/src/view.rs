@@ -0,0 +1,104 @@
+use std::slice;
+
+use grid::CellsInRect;
+use subscribe::CellRect;
+use traits::Index;
+
+/// A dense rectangle of cells copied out of the loaded chunks, such as the
+/// area visible on screen around an observer.
+#[derive(Debug, Clone)]
+pub struct CellView<T> {
+    origin: (i32, i32),
+    width: i32,
+    height: i32,
+    cells: Vec<T>,
+}
+
+impl<T: Clone> CellView<T> {
+    /// Copies the cells of a `width` by `height` rectangle centered on the
+    /// world position `observer`. `chunk_cells` returns the cells of a loaded
+    /// chunk in row-major order, or `None` if the chunk isn't loaded, in which
+    /// case its cells are filled with `default`. See `CellsInRect` for going
+    /// through the cells without copying them.
+    pub fn extract<'b, I, F>(observer: (i32, i32), width: i32, height: i32, chunk_width: i32,
+                             default: &T, chunk_cells: F) -> Self
+        where I: Index,
+              T: 'b,
+              F: FnMut(&I) -> Option<&'b [T]>
+    {
+        assert!(width >= 0 && height >= 0);
+
+        let origin = (observer.0 - width / 2, observer.1 - height / 2);
+        let rect = CellRect::new(origin.0, origin.1, width, height);
+        let cells = CellsInRect::<I, T, F>::new(rect, chunk_width, chunk_cells)
+            .map(|(_, cell)| cell.unwrap_or(default).clone())
+            .collect();
+
+        CellView {
+            origin: origin,
+            width: width,
+            height: height,
+            cells: cells,
+        }
+    }
+}
+
+impl<T> CellView<T> {
+    /// Returns the world position of the top left cell.
+    pub fn origin(&self) -> (i32, i32) {
+        self.origin
+    }
+
+    pub fn width(&self) -> i32 {
+        self.width
+    }
+
+    pub fn height(&self) -> i32 {
+        self.height
+    }
+
+    /// Returns the cell at a position relative to the top left of the view.
+    pub fn get(&self, x: i32, y: i32) -> Option<&T> {
+        if x < 0 || y < 0 || x >= self.width || y >= self.height {
+            return None;
+        }
+        self.cells.get((y * self.width + x) as usize)
+    }
+
+    /// Returns the cell at a world position, if it's inside the view.
+    pub fn get_world(&self, x: i32, y: i32) -> Option<&T> {
+        self.get(x - self.origin.0, y - self.origin.1)
+    }
+
+    /// Returns the rows of the view from top to bottom.
+    pub fn rows(&self) -> slice::Chunks<'_, T> {
+        self.cells.chunks(self.width.max(1) as usize)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashMap;
+    use traits::TestIndex;
+
+    #[test]
+    fn test_extract_across_chunks() {
+        let mut chunks = HashMap::new();
+        chunks.insert(TestIndex(0, 0), vec![1, 2, 3, 4]);
+        chunks.insert(TestIndex(-1, 0), vec![5, 6, 7, 8]);
+
+        let view = CellView::extract((0, 1), 4, 2, 2, &0, |i: &TestIndex| {
+            chunks.get(i).map(|c| c.as_slice())
+        });
+        assert_eq!(view.origin(), (-2, 0));
+        let rows: Vec<&[i32]> = view.rows().collect();
+        assert_eq!(rows, vec![&[5, 6, 1, 2][..], &[7, 8, 3, 4][..]]);
+
+        let view = CellView::extract((0, 0), 2, 2, 2, &0, |i: &TestIndex| {
+            chunks.get(i).map(|c| c.as_slice())
+        });
+        assert_eq!(view.get_world(-1, -1), Some(&0));
+        assert_eq!(view.get_world(0, 0), Some(&1));
+    }
+}
@@ -0,0 +1,82 @@
+use error::SerialResult;
+use layout::RegionLayout;
+use region::{RegionIndex, RegionLocalIndex};
+use sidecar::RegionSidecar;
+
+/// Extension appended to region file names for the files holding which of
+/// their chunks were visited.
+pub const VISITS_EXTENSION: &'static str = "visits";
+
+/// Keeps the world tick every chunk was first visited by an observer at, in a
+/// small file next to each region file, so things that must happen only once
+/// per chunk, like placing treasure or triggering a quest, stay done across
+/// sessions. See `ChunkedWorld::on_first_visit`.
+///
+/// The files are read the first time a region is looked up and written by
+/// `flush`.
+pub struct ChunkVisits {
+    chunks: RegionSidecar<u64>,
+}
+
+impl ChunkVisits {
+    pub fn new(layout: RegionLayout) -> Self {
+        ChunkVisits {
+            chunks: RegionSidecar::new(layout, VISITS_EXTENSION),
+        }
+    }
+
+    /// Returns the tick a chunk was first visited at, or `None` if it wasn't
+    /// visited yet.
+    pub fn first_visited_at(&mut self, region: &RegionIndex, local: &RegionLocalIndex) -> SerialResult<Option<u64>> {
+        Ok(self.chunks.get(region, local)?.cloned())
+    }
+
+    /// Records that a chunk was visited at `tick`. Returns `true` if this is
+    /// its first visit.
+    pub fn record(&mut self, region: &RegionIndex, local: &RegionLocalIndex, tick: u64) -> SerialResult<bool> {
+        if self.first_visited_at(region, local)?.is_some() {
+            return Ok(false);
+        }
+        self.chunks.set(region, local, Some(tick))?;
+        Ok(true)
+    }
+
+    /// Writes the visits of every region that changed since the last flush.
+    pub fn flush(&mut self) -> SerialResult<()> {
+        self.chunks.flush()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use testing::{corrupt_sidecar, TempDir};
+
+    #[test]
+    fn test_visits() {
+        let dir = TempDir::new("visits");
+        let (region, local) = (RegionIndex(-1, 0), RegionLocalIndex(3, 2));
+
+        let mut visits = ChunkVisits::new(dir.layout());
+        assert_eq!(visits.first_visited_at(&region, &local).unwrap(), None);
+        assert!(visits.record(&region, &local, 10).unwrap());
+        assert!(!visits.record(&region, &local, 20).unwrap());
+        visits.flush().unwrap();
+
+        let mut visits = ChunkVisits::new(dir.layout());
+        assert_eq!(visits.first_visited_at(&region, &local).unwrap(), Some(10));
+        assert!(!visits.record(&region, &local, 30).unwrap());
+    }
+
+    #[test]
+    fn test_corrupt_visits() {
+        let dir = TempDir::new("visits-corrupt");
+        let (region, local) = (RegionIndex(3, 3), RegionLocalIndex(0, 0));
+        corrupt_sidecar(&dir.layout(), &region, VISITS_EXTENSION);
+
+        // A chunk whose visits can't be read isn't taken as unvisited.
+        let mut visits = ChunkVisits::new(dir.layout());
+        assert!(visits.first_visited_at(&region, &local).is_err());
+        assert!(visits.record(&region, &local, 1).is_err());
+    }
+}
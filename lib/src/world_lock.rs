@@ -0,0 +1,380 @@
+use std::fs::{self, File};
+use std::io::{self, Read, Write};
+use std::path::{Path, PathBuf};
+#[cfg(not(target_os = "linux"))]
+use std::process::{Command, Stdio};
+use std::process;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use error::{self, LogicError, SerialResult};
+use layout::RegionLayout;
+
+/// The process holding a `WorldLock`, as written to the lock file.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct LockOwner {
+    pub pid: u32,
+    /// The name of the machine the process runs on, or an empty string if it
+    /// isn't known.
+    pub host: String,
+    /// When the lock was taken, in seconds since the Unix epoch.
+    pub since: u64,
+}
+
+impl LockOwner {
+    /// Returns the owner for the current process.
+    pub fn current() -> Self {
+        let since = SystemTime::now().duration_since(UNIX_EPOCH).map(|d| d.as_secs()).unwrap_or(0);
+        LockOwner {
+            pid: process::id(),
+            host: host_name(),
+            since: since,
+        }
+    }
+
+    fn to_text(&self) -> String {
+        format!("pid {}\nhost {}\nsince {}\n", self.pid, self.host, self.since)
+    }
+
+    fn from_text(text: &str) -> Option<Self> {
+        let mut owner = LockOwner { pid: 0, host: String::new(), since: 0 };
+        let mut found = 0;
+        for line in text.lines() {
+            let mut parts = line.splitn(2, ' ');
+            match (parts.next(), parts.next()) {
+                (Some("pid"), Some(pid))     => owner.pid = pid.parse().ok()?,
+                (Some("host"), Some(host))   => owner.host = host.to_string(),
+                (Some("since"), Some(since)) => owner.since = since.parse().ok()?,
+                _                            => continue,
+            }
+            found += 1;
+        }
+        if found == 3 { Some(owner) } else { None }
+    }
+
+    /// Returns whether the owner is a process on this machine that isn't
+    /// running anymore. Owners on other machines, and processes whose state
+    /// can't be told, count as alive.
+    fn is_dead(&self) -> bool {
+        self.host == host_name() && process_alive(self.pid) == Some(false)
+    }
+}
+
+/// Whether and by whom a world is locked. See `WorldLock::status`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum LockStatus {
+    Free,
+    /// Locked by a process that is running, or whose state can't be told.
+    Held(LockOwner),
+    /// Locked by a process on this machine that isn't running anymore, such
+    /// as a game that crashed.
+    Stale(LockOwner),
+    /// The lock file can't be read, such as because the machine lost power
+    /// while it was written.
+    Damaged,
+}
+
+/// A lock file in the root directory of a world, held by the process playing
+/// it, so two processes don't write to the same save at once. The file
+/// records the process ID, machine and time the lock was taken, and is
+/// removed when the lock is dropped.
+///
+/// A process that crashes leaves its lock file behind. `acquire` takes over
+/// locks of processes on the same machine that aren't running anymore, while
+/// locks it can't tell are stale, like those taken from another machine
+/// sharing the save or on platforms where processes can't be looked up, and
+/// damaged lock files have to be taken over explicitly with `take_over`,
+/// such as after asking the player. Process IDs are reused, so a crashed
+/// game's lock can also look held until then.
+///
+/// Worlds holding a lock return it from `ChunkedWorld::world_lock`, and
+/// refuse to write to their save once another process took it over.
+#[derive(Debug)]
+pub struct WorldLock {
+    path: PathBuf,
+    owner: LockOwner,
+    released: bool,
+}
+
+impl WorldLock {
+    /// Returns whether the world is locked, and whether the lock is stale.
+    pub fn status(layout: &RegionLayout) -> SerialResult<LockStatus> {
+        let owner = match read_owner(&layout.lock_path())? {
+            Some(Some(owner)) => owner,
+            Some(None)        => return Ok(LockStatus::Damaged),
+            None              => return Ok(LockStatus::Free),
+        };
+        if owner.is_dead() {
+            Ok(LockStatus::Stale(owner))
+        } else {
+            Ok(LockStatus::Held(owner))
+        }
+    }
+
+    /// Locks a world for this process. Fails with `LogicError::WorldLocked`
+    /// if another process that may still be running holds the lock, or this
+    /// process already does, and with `LogicError::WorldLockDamaged` if the
+    /// lock file can't be read.
+    pub fn acquire(layout: &RegionLayout) -> SerialResult<Self> {
+        match WorldLock::status(layout)? {
+            LockStatus::Held(owner)  => Err(LogicError::WorldLocked(owner).into()),
+            LockStatus::Free         => WorldLock::create(layout),
+            LockStatus::Stale(owner) => WorldLock::replace(layout, Some(&owner)),
+            LockStatus::Damaged      => Err(LogicError::WorldLockDamaged.into()),
+        }
+    }
+
+    /// Locks a world for this process even if another process holds the
+    /// lock or the lock file is damaged. That process can't write to the
+    /// world anymore, but nothing stops it from writing chunks it was
+    /// already writing, so only take over locks of processes that aren't
+    /// running.
+    pub fn take_over(layout: &RegionLayout) -> SerialResult<Self> {
+        WorldLock::replace(layout, None)
+    }
+
+    /// Moves the lock file aside under a name of its own and creates a new
+    /// one. Only one process can move a file, so two processes taking over
+    /// the same lock can't both succeed. If `expected` is given and the file
+    /// that was moved names another owner, another process took the lock
+    /// over since its status was read, and its file is put back.
+    fn replace(layout: &RegionLayout, expected: Option<&LockOwner>) -> SerialResult<Self> {
+        let path = layout.lock_path();
+        let nanos = SystemTime::now().duration_since(UNIX_EPOCH).map(|d| d.subsec_nanos()).unwrap_or(0);
+        let aside = path.with_extension(format!("{}.{}.stale", process::id(), nanos));
+        match fs::rename(&path, &aside) {
+            Ok(()) => (),
+            Err(ref e) if e.kind() == io::ErrorKind::NotFound => return WorldLock::create(layout),
+            Err(e) => return Err(e.into()),
+        }
+
+        if let Some(expected) = expected {
+            match read_owner(&aside)? {
+                Some(Some(ref owner)) if owner == expected => (),
+                found => {
+                    // Fails if yet another lock was created in the meantime,
+                    // whose owner then holds the world.
+                    let _ = fs::hard_link(&aside, &path);
+                    let _ = fs::remove_file(&aside);
+                    return match found {
+                        Some(Some(owner)) => Err(LogicError::WorldLocked(owner).into()),
+                        _                 => Err(LogicError::WorldLockDamaged.into()),
+                    };
+                },
+            }
+        }
+        let _ = fs::remove_file(&aside);
+        WorldLock::create(layout)
+    }
+
+    /// Writes the lock file, failing if it exists. The file is written under
+    /// another name and linked into place, so a lock file that exists is
+    /// always complete.
+    fn create(layout: &RegionLayout) -> SerialResult<Self> {
+        let path = layout.lock_path();
+        if let Some(parent) = path.parent() {
+            if !parent.as_os_str().is_empty() {
+                fs::create_dir_all(parent)?;
+            }
+        }
+        let owner = LockOwner::current();
+        let temp = path.with_extension(format!("{}.tmp", owner.pid));
+        {
+            let mut file = File::create(&temp)?;
+            let written = file.write_all(owner.to_text().as_bytes()).and_then(|_| file.sync_all());
+            if let Err(e) = written {
+                let _ = fs::remove_file(&temp);
+                return Err(error::write_error(e));
+            }
+        }
+        let linked = fs::hard_link(&temp, &path);
+        let _ = fs::remove_file(&temp);
+        match linked {
+            Ok(()) => (),
+            Err(ref e) if e.kind() == io::ErrorKind::AlreadyExists => {
+                return match read_owner(&path)? {
+                    Some(Some(other)) => Err(LogicError::WorldLocked(other).into()),
+                    _                 => Err(io::Error::new(io::ErrorKind::AlreadyExists, "the world was locked at the same time").into()),
+                };
+            },
+            // Some file systems, like FAT, have no hard links.
+            Err(_) => {
+                let mut file = fs::OpenOptions::new().write(true).create_new(true).open(&path)?;
+                file.write_all(owner.to_text().as_bytes())?;
+                file.sync_all()?;
+            },
+        }
+        Ok(WorldLock {
+            path: path,
+            owner: owner,
+            released: false,
+        })
+    }
+
+    pub fn owner(&self) -> &LockOwner {
+        &self.owner
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Fails with `LogicError::WorldLocked` if another process took over the
+    /// lock.
+    pub fn check(&self) -> SerialResult<()> {
+        match read_owner(&self.path)? {
+            Some(Some(ref owner)) if *owner == self.owner => Ok(()),
+            Some(Some(owner)) => Err(LogicError::WorldLocked(owner).into()),
+            _ => Err(io::Error::new(io::ErrorKind::NotFound, "the world lock file was removed").into()),
+        }
+    }
+
+    /// Removes the lock file, unless another process took over the lock.
+    /// Dropping the lock does the same but ignores errors.
+    pub fn release(mut self) -> SerialResult<()> {
+        self.released = true;
+        if self.check().is_ok() {
+            fs::remove_file(&self.path)?;
+        }
+        Ok(())
+    }
+}
+
+impl Drop for WorldLock {
+    fn drop(&mut self) {
+        if !self.released && self.check().is_ok() {
+            let _ = fs::remove_file(&self.path);
+        }
+    }
+}
+
+/// Reads a lock file. Returns `None` if it doesn't exist, and `Some(None)`
+/// if it can't be parsed.
+fn read_owner(path: &Path) -> SerialResult<Option<Option<LockOwner>>> {
+    let mut text = String::new();
+    match File::open(path) {
+        Ok(mut file) => {
+            if file.read_to_string(&mut text).is_err() {
+                return Ok(Some(None));
+            }
+        },
+        Err(ref e) if e.kind() == io::ErrorKind::NotFound => return Ok(None),
+        Err(e) => return Err(e.into()),
+    }
+    Ok(Some(LockOwner::from_text(&text)))
+}
+
+/// Returns the name of this machine, or an empty string if it isn't known.
+fn host_name() -> String {
+    if let Some(name) = ::std::env::var_os("COMPUTERNAME") {
+        return name.to_string_lossy().into_owned();
+    }
+    let mut name = String::new();
+    if File::open("/etc/hostname").and_then(|mut f| f.read_to_string(&mut name)).is_ok() {
+        return name.trim().to_string();
+    }
+    ::std::env::var("HOSTNAME").unwrap_or_default()
+}
+
+/// Returns whether a process is running on this machine, or `None` if that
+/// can't be told.
+#[cfg(target_os = "linux")]
+fn process_alive(pid: u32) -> Option<bool> {
+    Some(Path::new("/proc").join(pid.to_string()).exists())
+}
+
+#[cfg(all(unix, not(target_os = "linux")))]
+fn process_alive(pid: u32) -> Option<bool> {
+    Command::new("kill").arg("-0").arg(pid.to_string())
+        .stdout(Stdio::null())
+        .stderr(Stdio::null())
+        .status()
+        .ok()
+        .map(|status| status.success())
+}
+
+#[cfg(windows)]
+fn process_alive(pid: u32) -> Option<bool> {
+    let output = Command::new("tasklist")
+        .args(&["/FI", &format!("PID eq {}", pid), "/NH", "/FO", "CSV"])
+        .stderr(Stdio::null())
+        .output()
+        .ok()?;
+    let quoted = format!("\"{}\"", pid);
+    Some(String::from_utf8_lossy(&output.stdout).contains(&quoted))
+}
+
+#[cfg(not(any(unix, windows)))]
+fn process_alive(_pid: u32) -> Option<bool> {
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::env;
+
+    #[test]
+    fn test_world_lock() {
+        let root = env::temp_dir().join("infinigen-test-world-lock");
+        let _ = fs::remove_dir_all(&root);
+        let layout = RegionLayout::new(&root);
+        assert_eq!(WorldLock::status(&layout).unwrap(), LockStatus::Free);
+
+        let lock = WorldLock::acquire(&layout).unwrap();
+        assert_eq!(WorldLock::status(&layout).unwrap(), LockStatus::Held(lock.owner().clone()));
+        assert!(WorldLock::acquire(&layout).unwrap_err().is_world_locked());
+
+        // A lock left behind by a process that exited is taken over.
+        let dead = LockOwner { pid: u32::max_value() - 1, ..lock.owner().clone() };
+        fs::write(layout.lock_path(), dead.to_text()).unwrap();
+        assert!(lock.check().is_err());
+        drop(lock);
+        assert_eq!(WorldLock::status(&layout).unwrap(), LockStatus::Stale(dead));
+
+        // Locks taken over by another process are left alone.
+        let lock = WorldLock::acquire(&layout).unwrap();
+        let other = LockOwner { pid: lock.owner().pid + 1, ..lock.owner().clone() };
+        fs::write(layout.lock_path(), other.to_text()).unwrap();
+        assert!(lock.check().unwrap_err().is_world_locked());
+        drop(lock);
+        assert!(layout.lock_path().exists());
+
+        let taken = WorldLock::take_over(&layout).unwrap();
+        taken.check().unwrap();
+        taken.release().unwrap();
+        assert_eq!(WorldLock::status(&layout).unwrap(), LockStatus::Free);
+
+        fs::write(layout.lock_path(), "garbage").unwrap();
+        assert_eq!(WorldLock::status(&layout).unwrap(), LockStatus::Damaged);
+        assert!(WorldLock::acquire(&layout).unwrap_err().is_world_locked());
+        WorldLock::take_over(&layout).unwrap();
+
+        fs::remove_dir_all(&root).unwrap();
+    }
+
+    #[test]
+    fn test_stale_lock_race() {
+        let root = env::temp_dir().join("infinigen-test-world-lock-race");
+        let _ = fs::remove_dir_all(&root);
+        let layout = RegionLayout::new(&root);
+        fs::create_dir_all(&root).unwrap();
+        let current = LockOwner::current();
+        let dead = LockOwner { pid: u32::max_value() - 1, ..current.clone() };
+        let other = LockOwner { pid: current.pid + 1, ..current.clone() };
+
+        // Another process took over the stale lock after its status was
+        // read, so its lock is left in place.
+        fs::write(layout.lock_path(), other.to_text()).unwrap();
+        assert!(WorldLock::replace(&layout, Some(&dead)).unwrap_err().is_world_locked());
+        assert_eq!(read_owner(&layout.lock_path()).unwrap(), Some(Some(other)));
+        assert_eq!(fs::read_dir(&root).unwrap().count(), 1);
+
+        fs::write(layout.lock_path(), dead.to_text()).unwrap();
+        let lock = WorldLock::replace(&layout, Some(&dead)).unwrap();
+        lock.check().unwrap();
+        assert_eq!(fs::read_dir(&root).unwrap().count(), 1);
+        drop(lock);
+
+        fs::remove_dir_all(&root).unwrap();
+    }
+}